@@ -0,0 +1,72 @@
+//! Benchmarks of how cycling a large `FixedMapping` delays concurrent triggers, run with
+//! `cargo bench --bench cleanup`.
+//!
+//! A thread triggers the mapping's keys while the main thread cycles it, once walking every
+//! key and once with `FixedMapping::incremental_cleanup`. The keys are kept alive while idle,
+//! so walking every key checks all of them each cycle, while the incremental cleanup knows that
+//! none can be dropped yet. The latency percentiles of the triggers are printed for each; the
+//! difference shows in the highest ones.
+
+use std::{
+    hint::black_box,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use floodgate::{FixedMapping, ManualClock};
+
+const KEYS: u64 = 500_000;
+const CYCLES: u32 = 20;
+
+fn bench(name: &str, incremental: bool) {
+    let clock = ManualClock::new();
+    let period = Duration::from_secs(1);
+    let mut mapping = FixedMapping::with_clock(u64::MAX, period, clock.clone());
+    if incremental {
+        mapping = mapping.incremental_cleanup(1_024);
+    }
+    // keep every key around for the whole benchmark.
+    mapping.set_idle_periods(CYCLES * 10);
+    for key in 0..KEYS {
+        mapping.trigger(&key);
+    }
+
+    let done = AtomicBool::new(false);
+    let mut latencies = thread::scope(|scope| {
+        let triggers = scope.spawn(|| {
+            let mut latencies = Vec::new();
+            let mut key = 0;
+            while !done.load(Ordering::Relaxed) {
+                key = (key + 7_919) % KEYS;
+                let start = Instant::now();
+                black_box(mapping.trigger(&key));
+                latencies.push(start.elapsed());
+            }
+            latencies
+        });
+
+        for _ in 0..CYCLES {
+            clock.advance(period);
+            mapping.cycle();
+            thread::sleep(Duration::from_millis(5));
+        }
+        done.store(true, Ordering::Relaxed);
+        triggers.join().unwrap()
+    });
+
+    latencies.sort_unstable();
+    let percentile = |p: f64| latencies[((latencies.len() - 1) as f64 * p) as usize];
+    println!(
+        "{name:<12} p50 {:>10?}  p99 {:>10?}  p99.99 {:>10?}  max {:>10?}",
+        percentile(0.5),
+        percentile(0.99),
+        percentile(0.9999),
+        latencies[latencies.len() - 1],
+    );
+}
+
+fn main() {
+    bench("full", false);
+    bench("incremental", true);
+}
@@ -0,0 +1,113 @@
+//! Benchmarks of `FixedMapping::trigger`, run with `cargo bench --bench trigger`.
+//!
+//! Each benchmark measures the time per trigger. The hot key benchmarks trigger one key from
+//! `THREADS` threads at once, and are only meaningful with as many cores as threads, since
+//! otherwise the threads barely contend for the key.
+
+use std::{
+    hint::black_box,
+    thread,
+    time::{Duration, Instant},
+};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use floodgate::{AtomicJumpingWindow, CachedClock, FixedMapping, RateLimiter};
+
+const THREADS: usize = 8;
+
+/// Trigger one key of `mapping` from `THREADS` threads at once, returning how long all of
+/// their `triggers` took.
+fn hammer<L: RateLimiter + Send + Sync + 'static>(
+    mapping: FixedMapping<u64, L>,
+    triggers: u64,
+) -> Duration {
+    mapping.trigger(&0);
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for _ in 0..THREADS {
+            scope.spawn(|| {
+                for _ in 0..triggers.div_ceil(THREADS as u64) {
+                    black_box(mapping.trigger(black_box(&0)));
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+fn single_thread(c: &mut Criterion) {
+    let mut group = c.benchmark_group("trigger");
+    group.throughput(Throughput::Elements(1));
+
+    group.bench_function("existing key", |b| {
+        let mapping = FixedMapping::new(u64::MAX, Duration::from_secs(60));
+        mapping.trigger(&0u64);
+        b.iter(|| mapping.trigger(black_box(&0)));
+    });
+
+    group.bench_function("existing key, cached", |b| {
+        let clock = CachedClock::new();
+        let refresher = clock.start(Duration::from_millis(1));
+        let mapping = FixedMapping::with_clock(u64::MAX, Duration::from_secs(60), clock);
+        mapping.trigger(&0u64);
+        b.iter(|| mapping.trigger(black_box(&0)));
+        refresher.stop();
+    });
+
+    group.bench_function("existing keys", |b| {
+        let mapping = FixedMapping::new(u64::MAX, Duration::from_secs(60));
+        for key in 0..1_000u64 {
+            mapping.trigger(&key);
+        }
+        let mut i = 0u64;
+        b.iter(|| {
+            i = i.wrapping_add(1);
+            mapping.trigger(black_box(&(i % 1_000)))
+        });
+    });
+
+    // a fresh mapping for each sample, so that it doesn't grow with the number of iterations.
+    group.bench_function("new keys", |b| {
+        b.iter_custom(|triggers| {
+            let mapping = FixedMapping::new(1, Duration::from_secs(60));
+            let start = Instant::now();
+            for key in 0..triggers {
+                black_box(mapping.trigger(black_box(&key)));
+            }
+            start.elapsed()
+        });
+    });
+
+    group.finish();
+}
+
+fn hot_key(c: &mut Criterion) {
+    let mut group = c.benchmark_group(format!("hot key, {THREADS} threads"));
+    group.throughput(Throughput::Elements(1));
+
+    group.bench_function("locked", |b| {
+        b.iter_custom(|triggers| {
+            hammer(
+                FixedMapping::new(u64::MAX, Duration::from_secs(60)),
+                triggers,
+            )
+        });
+    });
+
+    group.bench_function("atomic", |b| {
+        b.iter_custom(|triggers| {
+            hammer(
+                FixedMapping::<u64, AtomicJumpingWindow>::with_limiter(
+                    AtomicJumpingWindow::MAX_CAPACITY,
+                    Duration::from_secs(60),
+                ),
+                triggers,
+            )
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, single_thread, hot_key);
+criterion_main!(benches);
@@ -0,0 +1,210 @@
+//! The attribute macros of `floodgate`, re-exported by it with the `macros` feature.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::{
+    parse_macro_input, parse_quote, Error, FnArg, Ident, ItemFn, LitBool, LitStr, Pat, Path, Type,
+};
+
+/// The options given to `#[cooldown]`.
+#[derive(Default)]
+struct Options {
+    rate: Option<LitStr>,
+    key: Option<LitStr>,
+    name: Option<LitStr>,
+    or_else: Option<Path>,
+    wait: bool,
+}
+
+/// Put a function on cooldown, returning early with an error while it is exhausted.
+///
+/// # Options
+/// * `rate` - The rate to allow, in the form parsed by `floodgate::Rate`, like `"3/10s"`.
+/// * `key` - The name of the parameter to limit by, such as `"user_id"`. Without it, every call
+///   shares one limit. The parameter must be an owned type or a reference, like `&str`.
+/// * `name` - Share the cooldown with every function that uses the same name, through the
+///   global `floodgate::registry`. Keys are converted to strings with `ToString`, and whichever
+///   function is called first decides the rate. Without it, each function has its own cooldown.
+/// * `or_else` - A function converting the `floodgate::CooldownError` into the function's
+///   error type. Without it, the error is converted with `From`.
+/// * `wait = true` - Wait for the cooldown instead of returning an error. This only works on
+///   async functions, and needs floodgate's `tokio` feature.
+///
+/// Unless waiting, the function has to return a `Result`. The rate is parsed the first time the
+/// function is called, which panics if it is invalid.
+///
+/// # Examples
+/// ```
+/// use floodgate::{cooldown, CooldownError};
+///
+/// #[cooldown(rate = "1/10s", key = "user_id")]
+/// fn daily(user_id: u64) -> Result<&'static str, CooldownError> {
+///     Ok("here's your reward")
+/// }
+///
+/// #[derive(Debug)]
+/// struct CommandError(String);
+///
+/// fn on_cooldown(err: CooldownError) -> CommandError {
+///     CommandError(err.to_string())
+/// }
+///
+/// #[cooldown(rate = "1/10s", key = "user", name = "greetings", or_else = on_cooldown)]
+/// fn greet(user: &str) -> Result<String, CommandError> {
+///     Ok(format!("hello {user}"))
+/// }
+///
+/// #[cooldown(rate = "1/10s", key = "user", name = "greetings", or_else = on_cooldown)]
+/// fn wave(user: &str) -> Result<String, CommandError> {
+///     Ok(format!("*waves at {user}*"))
+/// }
+///
+/// assert!(daily(1).is_ok());
+/// assert!(daily(1).is_err());
+/// assert!(daily(2).is_ok());
+///
+/// assert!(greet("alice").is_ok());
+/// // greet and wave share a cooldown.
+/// assert!(wave("alice").is_err());
+/// assert!(wave("bob").is_ok());
+/// ```
+#[proc_macro_attribute]
+pub fn cooldown(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut options = Options::default();
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("rate") {
+            options.rate = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("key") {
+            options.key = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("name") {
+            options.name = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("or_else") {
+            options.or_else = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("wait") {
+            options.wait = meta.value()?.parse::<LitBool>()?.value;
+        } else {
+            return Err(meta.error("expected `rate`, `key`, `name`, `or_else` or `wait`"));
+        }
+        Ok(())
+    });
+    parse_macro_input!(attr with parser);
+    let item = parse_macro_input!(item as ItemFn);
+
+    expand(options, item)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(options: Options, mut item: ItemFn) -> syn::Result<TokenStream2> {
+    let Some(rate) = options.rate else {
+        let message = "expected a rate, such as `rate = \"3/10s\"`";
+        return Err(Error::new(Span::call_site(), message));
+    };
+    if options.wait && item.sig.asyncness.is_none() {
+        let message = "`wait = true` can only be used on async functions";
+        return Err(Error::new_spanned(item.sig.fn_token, message));
+    }
+    if let (true, Some(or_else)) = (options.wait, &options.or_else) {
+        let message = "`or_else` has no effect with `wait = true`, which never returns an error";
+        return Err(Error::new_spanned(or_else, message));
+    }
+
+    let function = item.sig.ident.to_string();
+    let parse_rate = quote! {
+        #rate.parse::<::floodgate::Rate>().unwrap_or_else(|err| {
+            panic!("invalid rate `{}` for `{}`: {}", #rate, #function, err)
+        })
+    };
+
+    // the type of the mapping's keys, the key to trigger, and the key to wait for.
+    let key = options
+        .key
+        .as_ref()
+        .map(|key| find_param(&item, key))
+        .transpose()?;
+    let (key_type, trigger_key, wait_key) = match (&key, &options.name) {
+        (None, None) => (quote!(()), quote!(&()), quote!(&())),
+        (None, Some(_)) => (quote!(), quote!(""), quote!(&::std::string::String::new())),
+        (Some((ident, _)), Some(_)) => {
+            let key = quote!(::std::string::ToString::to_string(&#ident));
+            (quote!(), quote!(&*#key), quote!(&#key))
+        }
+        (Some((ident, Type::Reference(reference))), None) => {
+            let elem = &reference.elem;
+            (
+                quote!(<#elem as ::std::borrow::ToOwned>::Owned),
+                quote!(#ident),
+                quote!(&::std::borrow::ToOwned::to_owned(#ident)),
+            )
+        }
+        (Some((ident, ty)), None) => (quote!(#ty), quote!(&#ident), quote!(&#ident)),
+    };
+
+    let cooldown = match &options.name {
+        Some(name) => quote! {
+            static COOLDOWN: ::std::sync::LazyLock<
+                ::std::sync::Arc<::floodgate::registry::Cooldown<::std::string::String>>,
+            > = ::std::sync::LazyLock::new(|| {
+                let policy = ::floodgate::Policy::new([#parse_rate]);
+                ::floodgate::registry::global()
+                    .get_or_register(#name, &policy)
+                    .unwrap_or_else(|err| panic!("{}", err))
+            });
+        },
+        None => quote! {
+            static COOLDOWN: ::std::sync::LazyLock<::floodgate::FixedMapping<#key_type>> =
+                ::std::sync::LazyLock::new(|| ::floodgate::FixedMapping::from_rate(#parse_rate));
+        },
+    };
+
+    let check = match (options.wait, &options.or_else) {
+        (true, _) => quote!(COOLDOWN.acquire(#wait_key).await;),
+        (false, or_else) => {
+            let convert = match or_else {
+                Some(or_else) => quote!(#or_else),
+                None => quote!(::core::convert::From::from),
+            };
+            quote! {
+                if let ::core::option::Option::Some(retry_after) = COOLDOWN.trigger(#trigger_key) {
+                    let err = ::floodgate::CooldownError { retry_after };
+                    return ::core::result::Result::Err(#convert(err));
+                }
+            }
+        }
+    };
+
+    let block = &item.block;
+    item.block = parse_quote!({
+        {
+            #cooldown
+            #check
+        }
+        #block
+    });
+    Ok(quote!(#item))
+}
+
+/// The name and type of the parameter called `key`.
+fn find_param(item: &ItemFn, key: &LitStr) -> syn::Result<(Ident, Type)> {
+    item.sig
+        .inputs
+        .iter()
+        .find_map(|input| match input {
+            FnArg::Typed(param) => match &*param.pat {
+                Pat::Ident(pat) if pat.ident == key.value() => {
+                    Some((pat.ident.clone(), (*param.ty).clone()))
+                }
+                _ => None,
+            },
+            FnArg::Receiver(_) => None,
+        })
+        .ok_or_else(|| {
+            let message = format!(
+                "`{}` has no parameter named `{}`",
+                item.sig.ident,
+                key.value()
+            );
+            Error::new_spanned(key, message)
+        })
+}
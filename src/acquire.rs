@@ -0,0 +1,490 @@
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+    time::Duration,
+};
+
+use tokio::time::sleep;
+
+use crate::{
+    clock::{self, Instant},
+    sleeper,
+    wait_queue::WaitQueue,
+    Clock, ConcurrencyLimit, ConcurrencyMapping, ConcurrencyPermit, DynamicMapping, Elapsed,
+    FixedMapping, JumpingWindow, KeyedPermit, RateLimiter, ReserveError, SharedJumpingWindow,
+};
+
+/// Trigger with `trigger`, sleeping with tokio for the returned retry-after until it
+/// succeeds. See `sleeper::acquire`.
+async fn acquire(
+    trigger: impl FnMut() -> Option<Duration>,
+    deadline: Option<Instant>,
+) -> Result<(), Elapsed> {
+    sleeper::acquire(trigger, deadline, &sleep).await
+}
+
+/// Like `acquire`, but waits for its turn in `queue` first.
+async fn acquire_queued(
+    queue: &WaitQueue,
+    trigger: impl FnMut() -> Option<Duration>,
+    deadline: Option<Instant>,
+) -> Result<(), Elapsed> {
+    let _turn = queue.enter(deadline).await?;
+    acquire(trigger, deadline).await
+}
+
+/// Take a permit with `take`, waiting for one to be released, or for the returned
+/// retry-after, until it succeeds. If `fair`, waits for its turn in `queue` first.
+async fn acquire_permit<P>(
+    queue: &WaitQueue,
+    fair: bool,
+    mut take: impl FnMut() -> Result<P, Option<Duration>>,
+    deadline: Option<Instant>,
+) -> Result<P, Elapsed> {
+    let _turn = match fair {
+        true => Some(queue.enter(deadline).await?),
+        false => None,
+    };
+    loop {
+        let seen = queue.resets();
+        let retry_after = match take() {
+            Ok(permit) => return Ok(permit),
+            Err(retry_after) => retry_after,
+        };
+
+        let now = clock::now();
+        let ready_at = retry_after.and_then(|retry_after| now.checked_add(retry_after));
+        if let Some(deadline) = deadline {
+            let too_late = ready_at.is_some_and(|ready_at| ready_at > deadline);
+            if now >= deadline || (retry_after.is_some() && too_late) {
+                return Err(Elapsed);
+            }
+        }
+
+        let wake_at = match (ready_at, deadline) {
+            (Some(ready_at), Some(deadline)) => Some(ready_at.min(deadline)),
+            (ready_at, deadline) => ready_at.or(deadline),
+        };
+        queue.reset_notified(seen, wake_at).await;
+    }
+}
+
+fn deadline(timeout: Duration) -> Option<Instant> {
+    clock::now().checked_add(timeout)
+}
+
+impl JumpingWindow {
+    /// Trigger the cooldown, waiting until the next reset if there are no triggers left.
+    ///
+    /// The future is cancellation-safe: if it is dropped while waiting, no token is consumed.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::Duration;
+    ///
+    /// # #[cfg_attr(feature = "tokio-time", tokio::main(flavor = "current_thread", start_paused = true))]
+    /// # #[cfg_attr(not(feature = "tokio-time"), tokio::main(flavor = "current_thread"))]
+    /// # async fn main() {
+    /// let mut cooldown = JumpingWindow::new(1, Duration::from_millis(10));
+    ///
+    /// cooldown.acquire().await;
+    /// // waits for the next window instead of failing.
+    /// cooldown.acquire().await;
+    /// # }
+    /// ```
+    pub async fn acquire(&mut self) {
+        let _ = acquire(|| self.trigger(None), None).await;
+    }
+
+    /// Like `acquire`, but gives up with `Err(Elapsed)` if the wait would exceed `timeout`.
+    ///
+    /// # Arguments
+    /// * `timeout` - The longest time to wait.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{Elapsed, JumpingWindow};
+    /// use std::time::Duration;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let mut cooldown = JumpingWindow::new(1, Duration::from_secs(10));
+    ///
+    /// assert_eq!(cooldown.acquire_timeout(Duration::from_secs(1)).await, Ok(()));
+    /// assert_eq!(cooldown.acquire_timeout(Duration::from_secs(1)).await, Err(Elapsed));
+    /// # }
+    /// ```
+    pub async fn acquire_timeout(&mut self, timeout: Duration) -> Result<(), Elapsed> {
+        acquire(|| self.trigger(None), deadline(timeout)).await
+    }
+
+    /// Consume `n` triggers at once, waiting until they are all available. See
+    /// `JumpingWindow::wait_for`.
+    ///
+    /// The future is cancellation-safe: nothing is consumed until all `n` can be.
+    ///
+    /// # Arguments
+    /// * `n` - How many triggers to consume.
+    ///
+    /// # Panics
+    /// Panics if `n` exceeds the burst capacity, since it would wait forever.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::Duration;
+    ///
+    /// # #[cfg_attr(feature = "tokio-time", tokio::main(flavor = "current_thread", start_paused = true))]
+    /// # #[cfg_attr(not(feature = "tokio-time"), tokio::main(flavor = "current_thread"))]
+    /// # async fn main() {
+    /// let mut cooldown = JumpingWindow::new(3, Duration::from_millis(10));
+    ///
+    /// cooldown.acquire_n(2).await;
+    /// // waits for the next window, when all 3 are available.
+    /// cooldown.acquire_n(3).await;
+    /// assert_eq!(cooldown.tokens(None), 0);
+    /// # }
+    /// ```
+    pub async fn acquire_n(&mut self, n: u64) {
+        assert!(n <= self.burst_capacity(), "n exceeds the capacity");
+        let _ = acquire(|| self.trigger_n(n, None).err(), None).await;
+    }
+}
+
+impl SharedJumpingWindow {
+    /// Trigger the cooldown, waiting until the next reset if there are no triggers left. See
+    /// `floodgate::JumpingWindow::acquire`.
+    ///
+    /// Waiters are served in the order they started waiting. Triggers that don't go through
+    /// `acquire` don't wait in line.
+    pub async fn acquire(&self) {
+        let _ = acquire_queued(&self.waiters, || self.trigger(None), None).await;
+    }
+
+    /// Like `acquire`, but gives up with `Err(Elapsed)` if the wait would exceed `timeout`.
+    /// See `floodgate::JumpingWindow::acquire_timeout`.
+    pub async fn acquire_timeout(&self, timeout: Duration) -> Result<(), Elapsed> {
+        acquire_queued(&self.waiters, || self.trigger(None), deadline(timeout)).await
+    }
+
+    /// Consume `n` triggers at once, waiting until they are all available. See
+    /// `floodgate::JumpingWindow::acquire_n`.
+    ///
+    /// # Panics
+    /// Panics if `n` exceeds the capacity.
+    pub async fn acquire_n(&self, n: u64) {
+        assert!(n <= self.capacity(), "n exceeds the capacity");
+        let trigger = || self.trigger_n(n, None).err();
+        let _ = acquire_queued(&self.waiters, trigger, None).await;
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync + 'static, L: RateLimiter> FixedMapping<K, L> {
+    /// Trigger the cooldown for `key`, waiting until it can be triggered. See
+    /// `floodgate::JumpingWindow::acquire`.
+    ///
+    /// Waiters on the same key are served in the order they started waiting.
+    pub async fn acquire(&self, key: &K) {
+        let waiters = self.waiters.get(key);
+        let _ = acquire_queued(waiters.queue(), || self.trigger(key), None).await;
+    }
+
+    /// Like `acquire`, but gives up with `Err(Elapsed)` if the wait would exceed `timeout`.
+    /// See `floodgate::JumpingWindow::acquire_timeout`.
+    pub async fn acquire_timeout(&self, key: &K, timeout: Duration) -> Result<(), Elapsed> {
+        let waiters = self.waiters.get(key);
+        acquire_queued(waiters.queue(), || self.trigger(key), deadline(timeout)).await
+    }
+
+    /// Consume `n` triggers of `key` at once, waiting until they are all available. See
+    /// `floodgate::JumpingWindow::acquire_n`.
+    ///
+    /// # Panics
+    /// Panics if `key` can never be triggered `n` times at once. See `FixedMapping::wait_for`.
+    pub async fn acquire_n(&self, key: &K, n: u64) {
+        assert!(self.wait_for(key, n).is_some(), "n exceeds the capacity");
+        let waiters = self.waiters.get(key);
+        let trigger = || self.trigger_n(key, n).err();
+        let _ = acquire_queued(waiters.queue(), trigger, None).await;
+    }
+}
+
+impl<K, C, S> FixedMapping<K, JumpingWindow, C, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    /// Reserve a token of `key` and wait until it may be used, to pace calls instead of
+    /// rejecting them. See `floodgate::FixedMapping::reserve`.
+    ///
+    /// If the future is dropped while waiting, the token is released.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{Clock, FixedMapping};
+    /// use std::time::Duration;
+    ///
+    /// # #[cfg_attr(feature = "tokio-time", tokio::main(flavor = "current_thread", start_paused = true))]
+    /// # #[cfg_attr(not(feature = "tokio-time"), tokio::main(flavor = "current_thread"))]
+    /// # async fn main() {
+    /// let mapping = FixedMapping::<String>::new(2, Duration::from_millis(10));
+    /// let start = mapping.clock().now();
+    /// for _ in 0..5 {
+    ///     mapping.pace("example.com").await.unwrap();
+    /// }
+    /// // the last call went to the third window.
+    /// assert!(mapping.clock().now() - start >= Duration::from_millis(20));
+    /// # }
+    /// ```
+    pub async fn pace<Q>(&self, key: &Q) -> Result<(), ReserveError>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.pace_with(key, sleep).await
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync + 'static, L: RateLimiter> DynamicMapping<K, L> {
+    /// Trigger the cooldown for `key`, waiting until it can be triggered. See
+    /// `floodgate::JumpingWindow::acquire`.
+    ///
+    /// Waiters on the same key are served in the order they started waiting.
+    pub async fn acquire(&self, key: &K, capacity: u64, period: Duration) {
+        let waiters = self.waiters.get(key);
+        let trigger = || self.trigger(key, capacity, period);
+        let _ = acquire_queued(waiters.queue(), trigger, None).await;
+    }
+
+    /// Like `acquire`, but gives up with `Err(Elapsed)` if the wait would exceed `timeout`.
+    /// See `floodgate::JumpingWindow::acquire_timeout`.
+    pub async fn acquire_timeout(
+        &self,
+        key: &K,
+        capacity: u64,
+        period: Duration,
+        timeout: Duration,
+    ) -> Result<(), Elapsed> {
+        let waiters = self.waiters.get(key);
+        let trigger = || self.trigger(key, capacity, period);
+        acquire_queued(waiters.queue(), trigger, deadline(timeout)).await
+    }
+
+    /// Consume `n` triggers of `key` at once, waiting until they are all available. See
+    /// `floodgate::JumpingWindow::acquire_n`.
+    ///
+    /// # Panics
+    /// Panics if `key` can never be triggered `n` times at once. See
+    /// `DynamicMapping::wait_for`.
+    pub async fn acquire_n(&self, key: &K, capacity: u64, period: Duration, n: u64) {
+        assert!(
+            self.wait_for(key, capacity, period, n).is_some(),
+            "n exceeds the capacity"
+        );
+        let waiters = self.waiters.get(key);
+        let trigger = || self.trigger_n(key, capacity, period, n).err();
+        let _ = acquire_queued(waiters.queue(), trigger, None).await;
+    }
+}
+
+impl ConcurrencyLimit {
+    /// Take a permit, waiting for one to be released, and for the window if there is one.
+    ///
+    /// The future is cancellation-safe: nothing is held if it is dropped while waiting.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::ConcurrencyLimit;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let renders = ConcurrencyLimit::new(1);
+    ///
+    /// let permit = renders.acquire().await;
+    /// assert!(renders.try_acquire().is_none());
+    /// drop(permit);
+    /// let _permit = renders.acquire().await;
+    /// # }
+    /// ```
+    pub async fn acquire(&self) -> ConcurrencyPermit<'_> {
+        acquire_permit(&self.waiters, self.fair, || self.take(), None)
+            .await
+            .expect("there is no deadline")
+    }
+
+    /// Like `acquire`, but gives up with `Err(Elapsed)` if the wait would exceed `timeout`.
+    pub async fn acquire_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<ConcurrencyPermit<'_>, Elapsed> {
+        acquire_permit(&self.waiters, self.fair, || self.take(), deadline(timeout)).await
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync + 'static> ConcurrencyMapping<K> {
+    /// Take a permit for `key`, waiting for one of its permits to be released, and for its
+    /// rate if there is one. See `floodgate::ConcurrencyLimit::acquire`.
+    pub async fn acquire(&self, key: &K) -> KeyedPermit<'_, K> {
+        let waiters = self.waiters.get(key);
+        acquire_permit(waiters.queue(), self.fair, || self.take(key), None)
+            .await
+            .expect("there is no deadline")
+    }
+
+    /// Like `acquire`, but gives up with `Err(Elapsed)` if the wait would exceed `timeout`.
+    pub async fn acquire_timeout(
+        &self,
+        key: &K,
+        timeout: Duration,
+    ) -> Result<KeyedPermit<'_, K>, Elapsed> {
+        let waiters = self.waiters.get(key);
+        let take = || self.take(key);
+        acquire_permit(waiters.queue(), self.fair, take, deadline(timeout)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use tokio::{
+        task::yield_now,
+        time::{sleep, timeout},
+    };
+
+    use crate::{
+        Clock, ConcurrencyMapping, FixedMapping, ManualClock, ReserveError, SharedJumpingWindow,
+    };
+
+    #[tokio::test]
+    async fn cancelled_acquire_consumes_nothing() {
+        let cooldown = SharedJumpingWindow::new(1, Duration::from_millis(100));
+        cooldown.acquire().await;
+
+        // give up half way through the wait.
+        let result = timeout(Duration::from_millis(50), cooldown.acquire()).await;
+        assert!(result.is_err());
+
+        sleep(Duration::from_millis(60)).await;
+        assert_eq!(cooldown.tokens(None), 1);
+    }
+
+    #[tokio::test]
+    async fn mapping_acquire_waits_for_reset() {
+        let period = Duration::from_millis(50);
+        let mapping = FixedMapping::new(2, period);
+        let start = Instant::now();
+
+        for _ in 0..5 {
+            mapping.acquire(&1).await;
+        }
+
+        // two windows had to pass for the last trigger.
+        assert!(start.elapsed() >= period * 2);
+        assert_eq!(mapping.tokens(&1), 1);
+    }
+
+    #[tokio::test]
+    async fn acquire_n_waits_as_long_as_wait_for() {
+        let period = Duration::from_millis(50);
+        let mapping = FixedMapping::new(3, period);
+        assert_eq!(mapping.wait_for(&1, 3), Some(Duration::ZERO));
+        assert!(!mapping.contains_key(&1));
+
+        mapping.acquire_n(&1, 2).await;
+        let wait = mapping.wait_for(&1, 3).unwrap();
+        assert!(wait > Duration::ZERO && wait <= period);
+
+        let start = Instant::now();
+        mapping.acquire_n(&1, 3).await;
+        assert!(start.elapsed() >= wait);
+        assert_eq!(mapping.tokens(&1), 0);
+    }
+
+    #[tokio::test]
+    async fn waiters_are_served_in_order() {
+        let mapping = Arc::new(FixedMapping::new(2, Duration::from_millis(50)));
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        let mut tasks = Vec::new();
+        for i in 0..7 {
+            tasks.push(tokio::spawn({
+                let mapping = mapping.clone();
+                let order = order.clone();
+                async move {
+                    mapping.acquire(&1).await;
+                    order.lock().unwrap().push(i);
+                }
+            }));
+            // let the task join the queue before spawning the next one.
+            yield_now().await;
+        }
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), (0..7).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn cancelled_waiters_leave_the_queue() {
+        let mapping = FixedMapping::new(1, Duration::from_millis(50));
+        mapping.acquire(&1).await;
+
+        // this waiter gives up while at the front of the queue...
+        let result = mapping.acquire_timeout(&1, Duration::from_millis(10)).await;
+        assert!(result.is_err());
+        // ...and this one while queued behind another waiter.
+        let first = mapping.acquire(&1);
+        let second = timeout(Duration::from_millis(10), mapping.acquire(&1));
+        let (_, second) = tokio::join!(first, second);
+        assert!(second.is_err());
+
+        assert!(mapping.waiters.is_empty());
+        timeout(Duration::from_millis(200), mapping.acquire(&1))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn cancelled_pacing_releases_the_booking() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(60);
+        let mapping = FixedMapping::with_clock(1, period, clock.clone()).max_queue(1);
+        mapping.pace(&1).await.unwrap();
+
+        let result = timeout(Duration::from_millis(10), mapping.pace(&1)).await;
+        assert!(result.is_err());
+        let reservation = mapping.reserve(&1).unwrap();
+        assert!(reservation.is_booked());
+        assert_eq!(reservation.at(), clock.now() + period);
+        reservation.commit();
+
+        assert_eq!(mapping.reserve(&1).err(), Some(ReserveError::QueueFull));
+        clock.advance(period);
+        assert_eq!(mapping.tokens(&1), 0);
+    }
+
+    #[tokio::test]
+    async fn released_permits_wake_waiters() {
+        let renders = Arc::new(ConcurrencyMapping::new(1).fair());
+        let permit = renders.acquire(&1).await;
+
+        let waiter = tokio::spawn({
+            let renders = renders.clone();
+            async move { renders.acquire(&1).await.key().to_owned() }
+        });
+        yield_now().await;
+        let timed_out = renders.acquire_timeout(&1, Duration::from_millis(10)).await;
+        assert!(timed_out.is_err());
+
+        drop(permit);
+        assert_eq!(waiter.await.unwrap(), 1);
+        assert_eq!(renders.in_flight(&1), 0);
+    }
+}
@@ -0,0 +1,389 @@
+use std::time::Duration;
+
+use crate::{
+    clock::{self, Instant},
+    error::validate,
+    InvalidWindow, JumpingWindow, RateLimitInfo, RateLimiter,
+};
+
+/// A `floodgate::JumpingWindow` whose capacity adapts to feedback from what it protects, with
+/// additive increase and multiplicative decrease (AIMD).
+///
+/// Every `AdaptiveWindow::record_success` grows the capacity by the increase step, and every
+/// `AdaptiveWindow::record_failure` halves it, always staying between the floor and the
+/// ceiling. When the upstream says how long to back off, for example with the `Retry-After`
+/// of a 429, `AdaptiveWindow::record_retry_after` also exhausts the window until then.
+///
+/// When used as a `floodgate::RateLimiter`, the capacity is the ceiling, so that a
+/// `floodgate::FixedMapping` doesn't undo the adaptation. To adapt each key of a mapping
+/// separately, use `FixedMapping::with_template`.
+///
+/// # Examples
+/// ```
+/// use floodgate::AdaptiveWindow;
+/// use std::time::{Duration, Instant};
+///
+/// let now = Instant::now();
+/// let mut upstream = AdaptiveWindow::new(1, 8, Duration::from_secs(1));
+/// upstream.reset(Some(now));
+///
+/// upstream.record_failure();
+/// assert_eq!(upstream.effective_capacity(), 4);
+/// upstream.record_success();
+/// assert_eq!(upstream.effective_capacity(), 5);
+///
+/// upstream.record_retry_after(Duration::from_secs(30), Some(now));
+/// assert_eq!(upstream.trigger(Some(now)), Some(Duration::from_secs(30)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct AdaptiveWindow {
+    /// Holds the effective capacity.
+    window: JumpingWindow,
+    floor: u64,
+    ceiling: u64,
+    step: u64,
+    /// When the upstream asked to be left alone until.
+    blocked_until: Option<Instant>,
+}
+
+impl AdaptiveWindow {
+    /// Create a new AdaptiveWindow, starting at the ceiling.
+    ///
+    /// # Arguments
+    /// * `floor` - The capacity never drops below this.
+    /// * `ceiling` - The capacity never grows above this.
+    /// * `period` - How long the window is.
+    ///
+    /// # Panics
+    /// Panics if `floor` or `period` is zero, or if `floor` exceeds `ceiling`. See
+    /// `AdaptiveWindow::try_new`.
+    pub fn new(floor: u64, ceiling: u64, period: Duration) -> Self {
+        Self::try_new(floor, ceiling, period).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Create a new AdaptiveWindow, returning an error if `floor` or `period` is zero, or if
+    /// `floor` exceeds `ceiling`.
+    ///
+    /// # Arguments
+    /// * `floor` - The capacity never drops below this.
+    /// * `ceiling` - The capacity never grows above this.
+    /// * `period` - How long the window is.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{AdaptiveWindow, InvalidWindow};
+    /// use std::time::Duration;
+    ///
+    /// let result = AdaptiveWindow::try_new(5, 2, Duration::from_secs(1));
+    /// assert_eq!(
+    ///     result.err(),
+    ///     Some(InvalidWindow::FloorAboveCeiling { floor: 5, ceiling: 2 })
+    /// );
+    /// ```
+    pub fn try_new(floor: u64, ceiling: u64, period: Duration) -> Result<Self, InvalidWindow> {
+        validate(floor, period)?;
+        if floor > ceiling {
+            return Err(InvalidWindow::FloorAboveCeiling { floor, ceiling });
+        }
+
+        Ok(Self {
+            window: JumpingWindow::new(ceiling, period),
+            floor,
+            ceiling,
+            step: 1,
+            blocked_until: None,
+        })
+    }
+
+    /// Grow the capacity by `step` on every success, instead of by one.
+    pub fn increase_step(mut self, step: u64) -> Self {
+        self.step = step;
+        self
+    }
+
+    pub fn floor(&self) -> u64 {
+        self.floor
+    }
+
+    pub fn ceiling(&self) -> u64 {
+        self.ceiling
+    }
+
+    pub fn period(&self) -> Duration {
+        self.window.period()
+    }
+
+    /// The capacity currently allowed, between the floor and the ceiling.
+    pub fn effective_capacity(&self) -> u64 {
+        self.window.capacity()
+    }
+
+    /// Report that a call went through, growing the capacity by the increase step. The extra
+    /// tokens are added to the current window.
+    pub fn record_success(&mut self) {
+        let capacity = self.effective_capacity().saturating_add(self.step);
+        self.window.set_capacity(capacity.min(self.ceiling));
+    }
+
+    /// Report that a call failed or was throttled, halving the capacity. The tokens left in
+    /// the current window are clamped to it.
+    pub fn record_failure(&mut self) {
+        let capacity = self.effective_capacity() / 2;
+        self.window.set_capacity(capacity.max(self.floor));
+    }
+
+    /// Report that the upstream asked to retry after `retry_after`, halving the capacity and
+    /// rejecting every trigger until then.
+    ///
+    /// # Arguments
+    /// * `retry_after` - How long the upstream asked to wait.
+    /// * `now` - Optionally specify the current time.
+    pub fn record_retry_after(&mut self, retry_after: Duration, now: Option<Instant>) {
+        let now = now.unwrap_or_else(clock::now);
+        self.record_failure();
+        let until = now + retry_after;
+        self.blocked_until = Some(
+            self.blocked_until
+                .map_or(until, |blocked| blocked.max(until)),
+        );
+    }
+
+    /// How long the upstream is still being left alone for, if at all.
+    fn blocked(&self, now: Instant) -> Option<Duration> {
+        self.blocked_until
+            .filter(|&until| until > now)
+            .map(|until| until.saturating_duration_since(now))
+    }
+
+    /// How many triggers are left in the current window. Always zero while backing off after
+    /// `AdaptiveWindow::record_retry_after`.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    pub fn tokens(&mut self, now: Option<Instant>) -> u64 {
+        let now = now.unwrap_or_else(clock::now);
+        match self.blocked(now) {
+            Some(_) => 0,
+            None => self.window.tokens(Some(now)),
+        }
+    }
+
+    /// Return the time until the next reset, or until the back-off ends if that is later.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    pub fn next_reset(&mut self, now: Option<Instant>) -> Duration {
+        let now = now.unwrap_or_else(clock::now);
+        let next_reset = self.window.next_reset(Some(now));
+        self.blocked(now)
+            .map_or(next_reset, |blocked| blocked.max(next_reset))
+    }
+
+    /// Like `next_reset`, except that it returns `None` if you still have triggers.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    pub fn retry_after(&mut self, now: Option<Instant>) -> Option<Duration> {
+        let now = now.unwrap_or_else(clock::now);
+        match self.blocked(now) {
+            Some(blocked) => Some(blocked),
+            None => self.window.retry_after(Some(now)),
+        }
+    }
+
+    /// How long until `n` triggers can be made at once, or `None` if `n` exceeds the
+    /// effective capacity. See `floodgate::JumpingWindow::wait_for`.
+    ///
+    /// # Arguments
+    /// * `n` - How many triggers to wait for.
+    /// * `now` - Optionally specify the current time.
+    pub fn wait_for(&self, n: u64, now: Option<Instant>) -> Option<Duration> {
+        let now = now.unwrap_or_else(clock::now);
+        let wait = self.window.wait_for(n, Some(now))?;
+        Some(self.blocked(now).map_or(wait, |blocked| blocked.max(wait)))
+    }
+
+    /// Returns whether or not there are still available triggers.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    pub fn can_trigger(&mut self, now: Option<Instant>) -> bool {
+        self.tokens(now) != 0
+    }
+
+    /// Trigger the window. While backing off, nothing is consumed and the rest of the back-off
+    /// is returned.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    pub fn trigger(&mut self, now: Option<Instant>) -> Option<Duration> {
+        self.trigger_n(1, now).err()
+    }
+
+    /// Trigger the window, consuming `cost` tokens at once. See
+    /// `floodgate::JumpingWindow::trigger_n`.
+    ///
+    /// # Arguments
+    /// * `cost` - How many tokens to consume.
+    /// * `now` - Optionally specify the current time.
+    pub fn trigger_n(&mut self, cost: u64, now: Option<Instant>) -> Result<(), Duration> {
+        let now = now.unwrap_or_else(clock::now);
+        match self.blocked(now) {
+            Some(blocked) => Err(blocked),
+            None => self.window.trigger_n(cost, Some(now)),
+        }
+    }
+
+    /// Trigger the window, returning its resulting state. The limit is the effective
+    /// capacity.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    pub fn trigger_info(&mut self, now: Option<Instant>) -> RateLimitInfo {
+        let now = now.unwrap_or_else(clock::now);
+        let retry_after = self.trigger(Some(now));
+
+        RateLimitInfo {
+            allowed: retry_after.is_none(),
+            limit: self.effective_capacity(),
+            remaining: self.tokens(Some(now)),
+            retry_after,
+            reset_after: self.next_reset(Some(now)),
+        }
+    }
+
+    /// Reset the window and end any back-off. The effective capacity is kept.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    pub fn reset(&mut self, now: Option<Instant>) {
+        self.blocked_until = None;
+        self.window.reset(now);
+    }
+
+    /// Give back `n` tokens taken from the window `epoch` identifies. See
+    /// `floodgate::JumpingWindow::refund`.
+    ///
+    /// # Arguments
+    /// * `n` - How many tokens to give back.
+    /// * `epoch` - The window the tokens were taken from.
+    /// * `now` - Optionally specify the current time.
+    pub fn refund(&mut self, n: u64, epoch: u64, now: Option<Instant>) {
+        self.window.refund(n, epoch, now);
+    }
+
+    /// A number identifying the current window. See `floodgate::JumpingWindow::epoch`.
+    pub fn epoch(&self, now: Option<Instant>) -> u64 {
+        self.window.epoch(now)
+    }
+}
+
+impl RateLimiter for AdaptiveWindow {
+    fn new(capacity: u64, period: Duration) -> Self {
+        Self::new(1, capacity, period)
+    }
+
+    /// The ceiling, rather than the effective capacity.
+    fn capacity(&self) -> u64 {
+        self.ceiling
+    }
+
+    fn period(&self) -> Duration {
+        self.period()
+    }
+
+    /// Change the ceiling and the period. The floor and the effective capacity are lowered to
+    /// the new ceiling if needed.
+    fn set_rate(&mut self, capacity: u64, period: Duration) {
+        self.ceiling = capacity;
+        self.floor = self.floor.min(capacity);
+        let effective = self.effective_capacity().min(capacity);
+        self.window.set_rate(effective, period);
+    }
+
+    fn tokens(&mut self, now: Option<Instant>) -> u64 {
+        self.tokens(now)
+    }
+
+    fn next_reset(&mut self, now: Option<Instant>) -> Duration {
+        self.next_reset(now)
+    }
+
+    fn retry_after(&mut self, now: Option<Instant>) -> Option<Duration> {
+        self.retry_after(now)
+    }
+
+    fn wait_for(&mut self, n: u64, now: Option<Instant>) -> Option<Duration> {
+        AdaptiveWindow::wait_for(self, n, now)
+    }
+
+    fn can_trigger(&mut self, now: Option<Instant>) -> bool {
+        self.can_trigger(now)
+    }
+
+    fn trigger(&mut self, now: Option<Instant>) -> Option<Duration> {
+        self.trigger(now)
+    }
+
+    fn trigger_n(&mut self, cost: u64, now: Option<Instant>) -> Result<(), Duration> {
+        self.trigger_n(cost, now)
+    }
+
+    fn trigger_info(&mut self, now: Option<Instant>) -> RateLimitInfo {
+        self.trigger_info(now)
+    }
+
+    fn reset(&mut self, now: Option<Instant>) {
+        self.reset(now)
+    }
+
+    fn refund(&mut self, n: u64, epoch: u64, now: Option<Instant>) {
+        self.refund(n, epoch, now)
+    }
+
+    fn epoch(&mut self, now: Option<Instant>) -> u64 {
+        AdaptiveWindow::epoch(self, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::AdaptiveWindow;
+
+    #[test]
+    fn capacity_stays_between_floor_and_ceiling() {
+        let now = Instant::now();
+        let mut window = AdaptiveWindow::new(2, 10, Duration::from_secs(1)).increase_step(4);
+        window.reset(Some(now));
+
+        for _ in 0..5 {
+            window.record_failure();
+        }
+        assert_eq!(window.effective_capacity(), 2);
+        assert_eq!(window.tokens(Some(now)), 2);
+
+        window.record_success();
+        assert_eq!(window.tokens(Some(now)), 6);
+        window.record_success();
+        window.record_success();
+        assert_eq!(window.effective_capacity(), 10);
+    }
+
+    #[test]
+    fn retry_after_exhausts_until_the_deadline() {
+        let now = Instant::now();
+        let secs = Duration::from_secs;
+        let mut window = AdaptiveWindow::new(1, 4, secs(1));
+        window.reset(Some(now));
+
+        window.record_retry_after(secs(10), Some(now));
+        window.record_retry_after(secs(5), Some(now));
+        assert_eq!(window.effective_capacity(), 1);
+        assert_eq!(window.tokens(Some(now + secs(3))), 0);
+        assert_eq!(window.wait_for(1, Some(now + secs(3))), Some(secs(7)));
+        assert_eq!(window.trigger(Some(now + secs(3))), Some(secs(7)));
+        assert_eq!(window.trigger(Some(now + secs(10))), None);
+    }
+}
@@ -0,0 +1,471 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    clock::{self, Instant},
+    error::{validate, validate_resolution},
+    InvalidWindow, RateLimiter,
+};
+
+const TOKEN_BITS: u32 = 24;
+const TOKEN_MASK: u64 = (1 << TOKEN_BITS) - 1;
+const NANOS_PER_MILLI: u128 = 1_000_000;
+/// The precision window starts are stored with.
+const RESOLUTION: Duration = Duration::from_millis(1);
+
+/// A lock-free version of `floodgate::JumpingWindow`, for cooldowns under heavy contention.
+///
+/// The whole state is packed into a single `AtomicU64` and updated with a compare-and-swap
+/// loop, so every method takes `&self` and never blocks. Even when many threads race the
+/// rollover of a window, at most `capacity` triggers succeed per window.
+///
+/// Packing the state comes with two limitations: the capacity can be at most
+/// `AtomicJumpingWindow::MAX_CAPACITY`, and window starts are stored with millisecond
+/// precision, so the period must be a whole number of milliseconds, and a window can end up
+/// to a millisecond early.
+///
+/// It also implements `floodgate::RateLimiter`, so hot mappings can opt in to it. Mappings
+/// of it make plain triggers of a key that already has a limiter at the mapping's rate under
+/// a shared lock, so such triggers of a single key, such as a global announcement channel,
+/// don't block each other. Everything else, like creating a key's limiter, changing its rate,
+/// or triggering several tokens at once, still locks the key exclusively, as do mappings of
+/// other limiters, including the default `floodgate::JumpingWindow`.
+///
+/// For method documentation, please see `floodgate::JumpingWindow`.
+///
+/// # Examples
+/// ```
+/// use floodgate::{AtomicJumpingWindow, FixedMapping};
+/// use std::time::Duration;
+///
+/// let mapping =
+///     FixedMapping::<u64, AtomicJumpingWindow>::with_limiter(1, Duration::from_secs(10));
+/// assert_eq!(mapping.trigger(&1), None);
+/// assert!(mapping.trigger(&1).is_some());
+/// ```
+#[derive(Debug)]
+pub struct AtomicJumpingWindow {
+    capacity: u64,
+    period: Duration,
+
+    base: Instant,
+    state: AtomicU64,
+}
+
+impl AtomicJumpingWindow {
+    /// The largest capacity an `AtomicJumpingWindow` can have.
+    pub const MAX_CAPACITY: u64 = TOKEN_MASK;
+
+    /// Create a new AtomicJumpingWindow.
+    ///
+    /// # Arguments
+    /// * `capacity` - How many triggers can occur per window.
+    /// * `period` - How long the window is.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::AtomicJumpingWindow;
+    /// use std::time::Duration;
+    ///
+    /// let cooldown = AtomicJumpingWindow::new(2, Duration::from_secs(10));
+    ///
+    /// assert_eq!(cooldown.trigger(None), None);
+    /// assert_eq!(cooldown.trigger(None), None);
+    /// assert!(matches!(cooldown.trigger(None), Some(_)));
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `period` is zero, if `capacity` is greater than
+    /// `AtomicJumpingWindow::MAX_CAPACITY`, or if `period` isn't a whole number of
+    /// milliseconds. See `AtomicJumpingWindow::try_new`.
+    pub fn new(capacity: u64, period: Duration) -> Self {
+        Self::try_new(capacity, period).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Create a new AtomicJumpingWindow, returning an error if `capacity` or `period` is zero,
+    /// if `capacity` is greater than `AtomicJumpingWindow::MAX_CAPACITY`, or if `period` isn't
+    /// a whole number of milliseconds.
+    ///
+    /// # Arguments
+    /// * `capacity` - How many triggers can occur per window.
+    /// * `period` - How long the window is.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{AtomicJumpingWindow, InvalidWindow};
+    /// use std::time::Duration;
+    ///
+    /// let result = AtomicJumpingWindow::try_new(u64::MAX, Duration::from_secs(10));
+    /// assert!(matches!(result, Err(InvalidWindow::CapacityTooLarge { .. })));
+    ///
+    /// let result = AtomicJumpingWindow::try_new(1, Duration::from_micros(500));
+    /// assert!(matches!(result, Err(InvalidWindow::PeriodResolution { .. })));
+    /// ```
+    pub fn try_new(capacity: u64, period: Duration) -> Result<Self, InvalidWindow> {
+        <Self as RateLimiter>::validate(capacity, period)?;
+        Ok(Self {
+            capacity,
+            period,
+            base: clock::now(),
+            state: AtomicU64::new(pack(0, capacity)),
+        })
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    pub fn tokens(&self, now: Option<Instant>) -> u64 {
+        let now = self.offset(now);
+        let (start, tokens) = unpack(self.state.load(Ordering::Acquire));
+
+        if self.is_expired(start, now) {
+            self.capacity
+        } else {
+            tokens
+        }
+    }
+
+    pub fn next_reset(&self, now: Option<Instant>) -> Duration {
+        let now = self.offset(now);
+        let (start, _) = unpack(self.state.load(Ordering::Acquire));
+        self.remaining(start, now)
+    }
+
+    pub fn retry_after(&self, now: Option<Instant>) -> Option<Duration> {
+        let now = self.offset(now);
+        let (start, tokens) = unpack(self.state.load(Ordering::Acquire));
+
+        if self.is_expired(start, now) || tokens != 0 {
+            None
+        } else {
+            Some(self.remaining(start, now))
+        }
+    }
+
+    pub fn wait_for(&self, n: u64, now: Option<Instant>) -> Option<Duration> {
+        let now = self.offset(now);
+        let (start, tokens) = unpack(self.state.load(Ordering::Acquire));
+
+        if n > self.capacity {
+            None
+        } else if self.is_expired(start, now) || tokens >= n {
+            Some(Duration::ZERO)
+        } else {
+            Some(self.remaining(start, now))
+        }
+    }
+
+    pub fn can_trigger(&self, now: Option<Instant>) -> bool {
+        self.tokens(now) != 0
+    }
+
+    pub fn trigger(&self, now: Option<Instant>) -> Option<Duration> {
+        self.trigger_n(1, now).err()
+    }
+
+    /// Trigger the cooldown, consuming `cost` tokens at once. See
+    /// `floodgate::JumpingWindow::trigger_n`.
+    pub fn trigger_n(&self, cost: u64, now: Option<Instant>) -> Result<(), Duration> {
+        if cost > self.capacity {
+            return Err(Duration::MAX);
+        }
+
+        let now = self.offset(now);
+        let mut current = self.state.load(Ordering::Acquire);
+        loop {
+            let (mut start, mut tokens) = unpack(current);
+            if self.is_expired(start, now) {
+                start = now.millis();
+                tokens = self.capacity;
+            }
+
+            if tokens < cost {
+                return Err(self.remaining(start, now));
+            }
+
+            let new = pack(start, tokens - cost);
+            match self.state.compare_exchange_weak(
+                current,
+                new,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return Ok(()),
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    pub fn reset(&self, now: Option<Instant>) {
+        let now = self.offset(now);
+        self.state
+            .store(pack(now.millis(), self.capacity), Ordering::Release);
+    }
+
+    /// Give back `n` tokens taken from the window `epoch` identifies, or nothing if it has
+    /// ended. See `floodgate::JumpingWindow::refund`.
+    pub fn refund(&self, n: u64, epoch: u64, now: Option<Instant>) {
+        let now = self.offset(now);
+        let _ = self
+            .state
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                let (start, tokens) = unpack(current);
+                if start != epoch || self.is_expired(start, now) {
+                    return None;
+                }
+                Some(pack(start, tokens.saturating_add(n).min(self.capacity)))
+            });
+    }
+
+    /// A number identifying the window that is current at `now`: the millisecond it started
+    /// at, or `now` if it has expired, which is when the next trigger starts the next one. See
+    /// `floodgate::JumpingWindow::epoch`.
+    pub fn epoch(&self, now: Option<Instant>) -> u64 {
+        let now = self.offset(now);
+        let (start, _) = unpack(self.state.load(Ordering::Acquire));
+        if self.is_expired(start, now) {
+            now.millis()
+        } else {
+            start
+        }
+    }
+
+    fn offset(&self, now: Option<Instant>) -> Offset {
+        let now = now.unwrap_or_else(clock::now);
+        Offset(now.saturating_duration_since(self.base).as_nanos())
+    }
+
+    fn elapsed(&self, start: u64, now: Offset) -> Duration {
+        let start = start as u128 * NANOS_PER_MILLI;
+        nanos(now.0.saturating_sub(start))
+    }
+
+    fn is_expired(&self, start: u64, now: Offset) -> bool {
+        self.elapsed(start, now) >= self.period
+    }
+
+    fn remaining(&self, start: u64, now: Offset) -> Duration {
+        self.period.saturating_sub(self.elapsed(start, now))
+    }
+}
+
+/// A point in time, as nanoseconds since the window's base.
+#[derive(Clone, Copy)]
+struct Offset(u128);
+
+impl Offset {
+    /// Round down to whole milliseconds, for storing as a window start.
+    fn millis(self) -> u64 {
+        (self.0 / NANOS_PER_MILLI) as u64
+    }
+}
+
+fn pack(start: u64, tokens: u64) -> u64 {
+    (start << TOKEN_BITS) | tokens
+}
+
+fn unpack(state: u64) -> (u64, u64) {
+    (state >> TOKEN_BITS, state & TOKEN_MASK)
+}
+
+fn nanos(nanos: u128) -> Duration {
+    Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
+}
+
+impl RateLimiter for AtomicJumpingWindow {
+    fn new(capacity: u64, period: Duration) -> Self {
+        Self::new(capacity, period)
+    }
+
+    fn validate(capacity: u64, period: Duration) -> Result<(), InvalidWindow> {
+        validate(capacity, period)?;
+        validate_resolution(period, RESOLUTION)?;
+        if capacity > Self::MAX_CAPACITY {
+            return Err(InvalidWindow::CapacityTooLarge {
+                capacity,
+                max: Self::MAX_CAPACITY,
+            });
+        }
+        Ok(())
+    }
+
+    fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    fn period(&self) -> Duration {
+        self.period
+    }
+
+    fn set_rate(&mut self, capacity: u64, period: Duration) {
+        let capacity = capacity.min(Self::MAX_CAPACITY);
+        let (start, mut tokens) = unpack(*self.state.get_mut());
+        if capacity > self.capacity {
+            tokens += capacity - self.capacity;
+        }
+        *self.state.get_mut() = pack(start, tokens.min(capacity));
+        self.capacity = capacity;
+        self.period = period;
+    }
+
+    fn tokens(&mut self, now: Option<Instant>) -> u64 {
+        AtomicJumpingWindow::tokens(self, now)
+    }
+
+    fn next_reset(&mut self, now: Option<Instant>) -> Duration {
+        AtomicJumpingWindow::next_reset(self, now)
+    }
+
+    fn retry_after(&mut self, now: Option<Instant>) -> Option<Duration> {
+        AtomicJumpingWindow::retry_after(self, now)
+    }
+
+    fn wait_for(&mut self, n: u64, now: Option<Instant>) -> Option<Duration> {
+        AtomicJumpingWindow::wait_for(self, n, now)
+    }
+
+    fn can_trigger(&mut self, now: Option<Instant>) -> bool {
+        AtomicJumpingWindow::can_trigger(self, now)
+    }
+
+    fn trigger(&mut self, now: Option<Instant>) -> Option<Duration> {
+        AtomicJumpingWindow::trigger(self, now)
+    }
+
+    fn trigger_n(&mut self, cost: u64, now: Option<Instant>) -> Result<(), Duration> {
+        AtomicJumpingWindow::trigger_n(self, cost, now)
+    }
+
+    fn trigger_shared(&self, now: Option<Instant>) -> Option<Option<Duration>> {
+        Some(AtomicJumpingWindow::trigger(self, now))
+    }
+
+    fn shares_triggers() -> bool {
+        true
+    }
+
+    fn reset(&mut self, now: Option<Instant>) {
+        AtomicJumpingWindow::reset(self, now)
+    }
+
+    fn refund(&mut self, n: u64, epoch: u64, now: Option<Instant>) {
+        AtomicJumpingWindow::refund(self, n, epoch, now)
+    }
+
+    fn epoch(&mut self, now: Option<Instant>) -> u64 {
+        AtomicJumpingWindow::epoch(self, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Barrier,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::AtomicJumpingWindow;
+    use crate::InvalidWindow;
+
+    #[test]
+    fn racing_rollovers_never_exceed_capacity() {
+        const THREADS: usize = 8;
+        const CAPACITY: u64 = 50;
+
+        let period = Duration::from_secs(1);
+        let cooldown = AtomicJumpingWindow::new(CAPACITY, period);
+        let start = Instant::now();
+        cooldown.reset(Some(start));
+
+        let barrier = Barrier::new(THREADS);
+        let accepted: Vec<AtomicU64> = (0..100).map(|_| AtomicU64::new(0)).collect();
+
+        thread::scope(|scope| {
+            for _ in 0..THREADS {
+                scope.spawn(|| {
+                    for (window, accepted) in accepted.iter().enumerate() {
+                        // every thread triggers right as the window rolls over.
+                        let now = start + period * window as u32;
+                        barrier.wait();
+                        for _ in 0..CAPACITY {
+                            if cooldown.trigger(Some(now)).is_none() {
+                                accepted.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        for accepted in accepted {
+            assert_eq!(accepted.load(Ordering::Relaxed), CAPACITY);
+        }
+    }
+
+    #[test]
+    fn rejects_periods_finer_than_a_millisecond() {
+        for period in [Duration::from_micros(500), Duration::from_micros(1_500)] {
+            assert_eq!(
+                AtomicJumpingWindow::try_new(1, period).unwrap_err(),
+                InvalidWindow::PeriodResolution {
+                    period,
+                    resolution: Duration::from_millis(1),
+                }
+            );
+        }
+
+        let cooldown = AtomicJumpingWindow::new(1, Duration::from_millis(1));
+        let now = Instant::now();
+        assert_eq!(cooldown.trigger(Some(now)), None);
+        assert!(cooldown.trigger(Some(now)).is_some());
+    }
+
+    #[test]
+    fn refund_saturates_at_capacity() {
+        let period = Duration::from_secs(1);
+        let cooldown = AtomicJumpingWindow::new(1, period);
+        let start = Instant::now();
+        cooldown.reset(Some(start));
+
+        let epoch = cooldown.epoch(Some(start));
+        assert_eq!(cooldown.trigger(Some(start)), None);
+        cooldown.refund(1, epoch, Some(start));
+        assert_eq!(cooldown.trigger(Some(start)), None);
+
+        let later = start + period;
+        let epoch = cooldown.epoch(Some(later));
+        assert_eq!(cooldown.trigger(Some(later)), None);
+        cooldown.refund(5, epoch, Some(later));
+        assert_eq!(cooldown.tokens(Some(later)), 1);
+    }
+
+    #[test]
+    fn refunds_to_an_ended_window_do_nothing() {
+        let period = Duration::from_secs(10);
+        let cooldown = AtomicJumpingWindow::new(2, period);
+        let start = Instant::now();
+        cooldown.reset(Some(start));
+
+        let epoch = cooldown.epoch(Some(start));
+        assert_eq!(cooldown.trigger(Some(start)), None);
+        let later = start + Duration::from_secs(11);
+        let current = cooldown.epoch(Some(later));
+        assert_eq!(cooldown.trigger(Some(later)), None);
+
+        cooldown.refund(1, epoch, Some(later));
+        assert_eq!(cooldown.tokens(Some(later)), 1);
+        cooldown.refund(1, current, Some(later));
+        assert_eq!(cooldown.tokens(Some(later)), 2);
+    }
+}
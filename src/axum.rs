@@ -0,0 +1,475 @@
+//! An `axum` middleware that ratelimits requests by client IP.
+
+use std::{
+    convert::Infallible,
+    fmt,
+    future::Future,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, OptionalFromRequestParts, Request},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{tower::too_many_requests, EnforcementMode, FixedMapping, RateLimitInfo, RateLimited};
+
+/// Where to find the client IP of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeySource {
+    /// The peer address from `axum::extract::ConnectInfo<SocketAddr>`. The server must be
+    /// started with `into_make_service_with_connect_info`.
+    #[default]
+    ConnectInfo,
+    /// The client address in the `X-Forwarded-For` header, for servers behind
+    /// `trusted_proxies` proxies that each append the address they received the request from.
+    ///
+    /// The address appended by the outermost trusted proxy is used, which is the
+    /// `trusted_proxies`th from the right. The entries to the left of it were sent by the
+    /// client, and can be anything, so they are ignored. If the header has fewer entries, or
+    /// `trusted_proxies` is zero, the client IP is missing.
+    ///
+    /// This trusts that every request reaches the server through the proxies, since a client
+    /// connecting directly can send any header.
+    XForwardedFor {
+        /// How many proxies in front of the server append to the header.
+        trusted_proxies: usize,
+    },
+}
+
+impl KeySource {
+    fn extract<B>(self, request: &Request<B>) -> Option<IpAddr> {
+        match self {
+            Self::ConnectInfo => request
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|info| info.0.ip()),
+            Self::XForwardedFor { trusted_proxies } => {
+                // proxies append to the last header, or add another one, so the entries are
+                // read from the end.
+                let mut entries = request
+                    .headers()
+                    .get_all("x-forwarded-for")
+                    .iter()
+                    .rev()
+                    .flat_map(|value| value.to_str().unwrap_or_default().rsplit(','));
+                entries
+                    .nth(trusted_proxies.checked_sub(1)?)?
+                    .trim()
+                    .parse()
+                    .ok()
+            }
+        }
+    }
+}
+
+/// What to do with a request whose client IP can't be found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissingKey {
+    /// Forward the request without ratelimiting it.
+    PassThrough,
+    /// Ratelimit the request as if it came from this address. All such requests share the
+    /// same cooldown.
+    Fallback(IpAddr),
+}
+
+/// A fallback key of `0.0.0.0`. With `KeySource::ConnectInfo`, a server that isn't started
+/// with `into_make_service_with_connect_info` has no client IP for any request, so every
+/// request shares this one cooldown.
+impl Default for MissingKey {
+    fn default() -> Self {
+        Self::Fallback(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+    }
+}
+
+type Reject = Arc<dyn Fn(RateLimitInfo) -> Response + Send + Sync>;
+
+/// A layer that ratelimits requests by client IP. Construct one per router to give each
+/// route its own limits.
+///
+/// Allowed requests get a `floodgate::axum::RateLimitStatus` extension, which handlers can
+/// extract. Ratelimited requests get a plain text `429 Too Many Requests`, unless
+/// customized with `RateLimit::reject_with`.
+///
+/// # Examples
+/// ```
+/// use axum::{routing::get, Router};
+/// use floodgate::axum::{KeySource, RateLimit, RateLimitStatus};
+/// use std::time::Duration;
+///
+/// async fn login(status: RateLimitStatus) -> String {
+///     format!("{} attempts left", status.remaining)
+/// }
+///
+/// let app: Router = Router::new()
+///     .route("/login", get(login))
+///     .route_layer(
+///         // behind a single load balancer.
+///         RateLimit::new(5, Duration::from_secs(60))
+///             .key(KeySource::XForwardedFor { trusted_proxies: 1 }),
+///     );
+/// ```
+#[derive(Clone)]
+pub struct RateLimit {
+    mapping: Arc<FixedMapping<IpAddr>>,
+    key: KeySource,
+    missing_key: MissingKey,
+    reject: Reject,
+}
+
+impl RateLimit {
+    /// Create a new RateLimit with its own mapping, and start the mapping's cycler.
+    ///
+    /// # Arguments
+    /// * `capacity` - How many requests each client can make per period.
+    /// * `period` - How long the period is.
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `period` is zero.
+    pub fn new(capacity: u64, period: Duration) -> Self {
+        let mapping = Arc::new(FixedMapping::new(capacity, period));
+        FixedMapping::start(mapping.clone(), None);
+        Self::with_mapping(mapping)
+    }
+
+    /// Create a new RateLimit using an existing mapping, for example to share limits between
+    /// several routes. The mapping's cycler must be started separately.
+    ///
+    /// # Arguments
+    /// * `mapping` - The mapping to trigger for each request.
+    pub fn with_mapping(mapping: Arc<FixedMapping<IpAddr>>) -> Self {
+        Self {
+            mapping,
+            key: KeySource::default(),
+            missing_key: MissingKey::default(),
+            reject: Arc::new(too_many_requests),
+        }
+    }
+
+    /// Set where to find the client IP. Defaults to `KeySource::ConnectInfo`.
+    pub fn key(mut self, key: KeySource) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Set what to do when the client IP can't be found. Defaults to a fallback key of
+    /// `0.0.0.0`, shared by every such request, so a server started without connect info
+    /// limits all of its clients together.
+    pub fn on_missing_key(mut self, missing_key: MissingKey) -> Self {
+        self.missing_key = missing_key;
+        self
+    }
+
+    /// Set the response for ratelimited requests.
+    ///
+    /// # Examples
+    /// ```
+    /// use axum::{response::IntoResponse, Json};
+    /// use floodgate::axum::RateLimit;
+    /// use std::time::Duration;
+    ///
+    /// let layer = RateLimit::new(5, Duration::from_secs(60)).reject_with(|info| {
+    ///     let retry_after = info.retry_after.unwrap_or_default().as_secs();
+    ///     Json(format!("retry in {retry_after}s")).into_response()
+    /// });
+    /// ```
+    pub fn reject_with(
+        mut self,
+        reject: impl Fn(RateLimitInfo) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.reject = Arc::new(reject);
+        self
+    }
+
+    /// The mapping used by this layer.
+    pub fn mapping(&self) -> &Arc<FixedMapping<IpAddr>> {
+        &self.mapping
+    }
+}
+
+impl fmt::Debug for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimit")
+            .field("key", &self.key)
+            .field("missing_key", &self.missing_key)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S> Layer<S> for RateLimit {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// The service created by `floodgate::axum::RateLimit`.
+#[derive(Debug, Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    layer: RateLimit,
+}
+
+impl<S, B> Service<Request<B>> for RateLimitService<S>
+where
+    S: Service<Request<B>, Response = Response, Error = Infallible>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        let key = match (self.layer.key.extract(&request), self.layer.missing_key) {
+            (Some(key), _) | (None, MissingKey::Fallback(key)) => key,
+            (None, MissingKey::PassThrough) => return Box::pin(self.inner.call(request)),
+        };
+
+        // in a dry run, the request is let through but still reports the would-be state.
+        let enforce = self.layer.mapping.mode() == EnforcementMode::Enforce;
+        let info = self.layer.mapping.trigger_info(&key);
+        if !info.allowed && enforce {
+            let response = (self.layer.reject)(info);
+            return Box::pin(async move { Ok(response) });
+        }
+
+        request.extensions_mut().insert(RateLimitStatus(info));
+        Box::pin(self.inner.call(request))
+    }
+}
+
+/// An extractor for the ratelimit state of the current request's client, set by
+/// `floodgate::axum::RateLimit`.
+///
+/// Extracting it fails with a `500 Internal Server Error` if the route isn't ratelimited, or
+/// if the request was passed through because its client IP couldn't be found. Use
+/// `Option<RateLimitStatus>` to handle that case.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitStatus(pub RateLimitInfo);
+
+impl std::ops::Deref for RateLimitStatus {
+    type Target = RateLimitInfo;
+
+    fn deref(&self) -> &RateLimitInfo {
+        &self.0
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for RateLimitStatus {
+    type Rejection = MissingRateLimitStatus;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Self>()
+            .copied()
+            .ok_or(MissingRateLimitStatus)
+    }
+}
+
+impl<S: Send + Sync> OptionalFromRequestParts<S> for RateLimitStatus {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        Ok(parts.extensions.get::<Self>().copied())
+    }
+}
+
+/// The rejection returned when extracting a `floodgate::axum::RateLimitStatus` from a request
+/// that wasn't ratelimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MissingRateLimitStatus;
+
+/// Responds with `429 Too Many Requests` and a `Retry-After` header, so that handlers can
+/// return a `floodgate::RateLimited` with `?`.
+impl<K> IntoResponse for RateLimited<K> {
+    fn into_response(self) -> Response {
+        Response::from(self)
+    }
+}
+
+impl IntoResponse for MissingRateLimitStatus {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "the route is not ratelimited",
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use ::tower::ServiceExt;
+    use axum::{
+        body::{to_bytes, Body},
+        extract::{ConnectInfo, Request},
+        http::StatusCode,
+        response::IntoResponse,
+        routing::get,
+        Json, Router,
+    };
+
+    use super::{KeySource, MissingKey, RateLimit, RateLimitStatus};
+
+    async fn remaining(status: Option<RateLimitStatus>) -> String {
+        match status {
+            Some(status) => status.remaining.to_string(),
+            None => "unlimited".to_owned(),
+        }
+    }
+
+    fn app(layer: RateLimit) -> Router {
+        Router::new().route("/", get(remaining)).route_layer(layer)
+    }
+
+    fn from(ip: &str) -> Request {
+        Request::builder()
+            .uri("/")
+            .header("x-forwarded-for", format!("6.6.6.6, {ip}"))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    async fn body(response: axum::response::Response) -> String {
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn limits_per_forwarded_ip() {
+        let forwarded = KeySource::XForwardedFor { trusted_proxies: 1 };
+        let app = app(RateLimit::new(2, Duration::from_secs(60)).key(forwarded));
+
+        let response = app.clone().oneshot(from("1.1.1.1")).await.unwrap();
+        assert_eq!(body(response).await, "1");
+        let response = app.clone().oneshot(from("1.1.1.1")).await.unwrap();
+        assert_eq!(body(response).await, "0");
+
+        let response = app.clone().oneshot(from("1.1.1.1")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "60");
+        assert_eq!(body(response).await, "Too Many Requests");
+
+        let response = app.oneshot(from("2.2.2.2")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn uses_connect_info() {
+        let app = app(RateLimit::new(1, Duration::from_secs(60)));
+        let request = || {
+            let mut request = from("1.1.1.1");
+            let peer: SocketAddr = "3.3.3.3:1234".parse().unwrap();
+            request.extensions_mut().insert(ConnectInfo(peer));
+            request
+        };
+
+        let response = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        // the forwarded address isn't trusted by default.
+        let response = app.oneshot(from("1.1.1.1")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn forwarded_ip_ignores_entries_sent_by_the_client() {
+        let forwarded = KeySource::XForwardedFor { trusted_proxies: 2 };
+        let limited = app(RateLimit::new(1, Duration::from_secs(60)).key(forwarded));
+        let request = |header: &str| {
+            let request = Request::builder().uri("/");
+            let request = header.split(';').fold(request, |request, value| {
+                request.header("x-forwarded-for", value)
+            });
+            request.body(Body::empty()).unwrap()
+        };
+
+        let response = limited
+            .clone()
+            .oneshot(request("1.1.1.1, 10.0.0.1"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        // spoofed entries in front of the client's, or a second header added by the proxy.
+        for header in ["6.6.6.6, 1.1.1.1, 10.0.0.1", "7.7.7.7, 1.1.1.1;10.0.0.1"] {
+            let response = limited.clone().oneshot(request(header)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        }
+
+        // too few entries to have come through both proxies.
+        let layer = RateLimit::new(1, Duration::from_secs(60))
+            .key(forwarded)
+            .on_missing_key(MissingKey::PassThrough);
+        let response = app(layer).oneshot(request("10.0.0.1")).await.unwrap();
+        assert_eq!(body(response).await, "unlimited");
+    }
+
+    #[tokio::test]
+    async fn missing_key_passes_through() {
+        let layer =
+            RateLimit::new(1, Duration::from_secs(60)).on_missing_key(MissingKey::PassThrough);
+        let app = app(layer);
+
+        for _ in 0..3 {
+            let request = Request::builder().uri("/").body(Body::empty()).unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(body(response).await, "unlimited");
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_rejection() {
+        let layer = RateLimit::new(1, Duration::from_secs(60))
+            .key(KeySource::XForwardedFor { trusted_proxies: 1 })
+            .reject_with(|info| {
+                let body = format!("{{\"limit\":{}}}", info.limit);
+                (StatusCode::TOO_MANY_REQUESTS, Json(body)).into_response()
+            });
+        let app = app(layer);
+
+        app.clone().oneshot(from("1.1.1.1")).await.unwrap();
+        let response = app.oneshot(from("1.1.1.1")).await.unwrap();
+        assert_eq!(response.headers()["content-type"], "application/json");
+        assert_eq!(body(response).await, "\"{\\\"limit\\\":1}\"");
+    }
+
+    #[tokio::test]
+    async fn handlers_can_return_rate_limited() {
+        let mapping = std::sync::Arc::new(crate::FixedMapping::new(1, Duration::from_secs(60)));
+        let handler = move || async move {
+            mapping.try_trigger(&1)?;
+            Ok::<_, crate::RateLimited<u64>>("hello")
+        };
+        let app = Router::new().route("/", get(handler));
+
+        let request = || Request::builder().uri("/").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(body(response).await, "hello");
+
+        let response = app.oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "60");
+    }
+}
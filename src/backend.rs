@@ -0,0 +1,420 @@
+use std::{
+    collections::{hash_map::RandomState, VecDeque},
+    convert::Infallible,
+    future::{ready, Future},
+    hash::{BuildHasher, Hash},
+    marker::PhantomData,
+    time::Duration,
+};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+
+use crate::{
+    clock::{SystemTime, UNIX_EPOCH},
+    error::{validate, validate_resolution},
+    InvalidWindow, JumpingWindowCore,
+};
+
+/// The stored state of one key's window in a `floodgate::Backend`.
+///
+/// Times are whole milliseconds since the unix epoch, so that every process sharing the
+/// backend reads them the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WindowState {
+    /// How many triggers are left in the window.
+    pub tokens: u64,
+    /// When the window started.
+    pub window_start: u64,
+}
+
+/// Where a `floodgate::BackendMapping` stores the state of its keys, such as an in-process map
+/// or a database shared between processes.
+///
+/// Triggers are made by reading a key's state, computing the new one, and writing it with
+/// `Backend::compare_and_set`, retrying if the state changed in the meantime. A backend only
+/// has to make `compare_and_set` atomic for the mapping to never allow more triggers than its
+/// capacity.
+pub trait Backend<K> {
+    /// The error returned when the backend can't be reached.
+    type Error;
+
+    /// The state of `key`, or `None` if it has none.
+    fn get(&self, key: &K)
+        -> impl Future<Output = Result<Option<WindowState>, Self::Error>> + Send;
+
+    /// Replace the state of `key` with `new`, only if it is still `current`. `None` means
+    /// that the key has no state. Returns whether the state was replaced.
+    ///
+    /// The state is no longer needed once `ttl` has passed, so the backend may drop it then.
+    fn compare_and_set(
+        &self,
+        key: &K,
+        current: Option<WindowState>,
+        new: WindowState,
+        ttl: Duration,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send;
+
+    /// Drop the state of `key`. Returns whether it had one.
+    fn remove(&self, key: &K) -> impl Future<Output = Result<bool, Self::Error>> + Send;
+}
+
+/// A `floodgate::Backend` that keeps the state in memory, in a `DashMap`.
+///
+/// States aren't dropped when their ttl passes, but by `MemoryBackend::cleanup`.
+pub struct MemoryBackend<K, S = RandomState> {
+    states: DashMap<K, (WindowState, u64), S>,
+}
+
+impl<K: Eq + Hash + Clone> MemoryBackend<K> {
+    /// Create an empty MemoryBackend.
+    pub fn new() -> Self {
+        Self {
+            states: DashMap::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone> Default for MemoryBackend<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Eq + Hash + Clone, S: BuildHasher + Clone> MemoryBackend<K, S> {
+    /// How many keys have a state.
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    /// Whether no key has a state.
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    /// Drop the states whose ttl has passed, returning how many were dropped.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    pub fn cleanup(&self, now: Option<SystemTime>) -> usize {
+        let now = millis(now.unwrap_or_else(SystemTime::now));
+        let before = self.states.len();
+        self.states.retain(|_, (_, expires_at)| *expires_at > now);
+        before - self.states.len()
+    }
+}
+
+impl<K, S> Backend<K> for MemoryBackend<K, S>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    S: BuildHasher + Clone + Send + Sync,
+{
+    type Error = Infallible;
+
+    fn get(&self, key: &K) -> impl Future<Output = Result<Option<WindowState>, Infallible>> + Send {
+        ready(Ok(self.states.get(key).map(|entry| entry.0)))
+    }
+
+    fn compare_and_set(
+        &self,
+        key: &K,
+        current: Option<WindowState>,
+        new: WindowState,
+        ttl: Duration,
+    ) -> impl Future<Output = Result<bool, Infallible>> + Send {
+        let expires_at = millis(SystemTime::now()).saturating_add(ttl.as_millis() as u64);
+        let replaced = match self.states.entry(key.clone()) {
+            Entry::Occupied(mut entry) if Some(entry.get().0) == current => {
+                entry.insert((new, expires_at));
+                true
+            }
+            Entry::Vacant(entry) if current.is_none() => {
+                entry.insert((new, expires_at));
+                true
+            }
+            _ => false,
+        };
+        ready(Ok(replaced))
+    }
+
+    fn remove(&self, key: &K) -> impl Future<Output = Result<bool, Infallible>> + Send {
+        ready(Ok(self.states.remove(key).is_some()))
+    }
+}
+
+/// A keyed cooldown like `floodgate::FixedMapping`, whose state is kept in a
+/// `floodgate::Backend`, so that it can be shared between processes.
+///
+/// Windows are aligned to multiples of the period since the unix epoch, and times are
+/// rounded down to whole milliseconds, so that every process agrees on when a window starts
+/// and ends. Every method is async, and fails if the backend does.
+///
+/// For in-process cooldowns, `floodgate::FixedMapping` is faster, since it doesn't have to go
+/// through the backend's compare-and-set.
+///
+/// # Examples
+/// ```
+/// use floodgate::{BackendMapping, MemoryBackend};
+/// use std::time::Duration;
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let mapping = BackendMapping::new(1, Duration::from_secs(10), MemoryBackend::new());
+///
+/// assert_eq!(mapping.trigger(&"user", None).await, Ok(None));
+/// assert!(mapping.trigger(&"user", None).await.unwrap().is_some());
+/// assert_eq!(mapping.trigger(&"other", None).await, Ok(None));
+/// # }
+/// ```
+pub struct BackendMapping<K, B> {
+    capacity: u64,
+    period: u64,
+    backend: B,
+    key: PhantomData<fn(&K)>,
+}
+
+impl<K, B: Backend<K>> BackendMapping<K, B> {
+    /// Create a new BackendMapping.
+    ///
+    /// # Arguments
+    /// * `capacity` - How many triggers can occur per window.
+    /// * `period` - How long the window is, in whole milliseconds.
+    /// * `backend` - Where to store the state of the keys.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is zero, or if `period` isn't a whole number of milliseconds. See
+    /// `BackendMapping::try_new`.
+    pub fn new(capacity: u64, period: Duration, backend: B) -> Self {
+        Self::try_new(capacity, period, backend).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Create a new BackendMapping, returning an error if `capacity` is zero, or if `period`
+    /// isn't a whole number of milliseconds.
+    ///
+    /// # Arguments
+    /// * `capacity` - How many triggers can occur per window.
+    /// * `period` - How long the window is, in whole milliseconds.
+    /// * `backend` - Where to store the state of the keys.
+    pub fn try_new(capacity: u64, period: Duration, backend: B) -> Result<Self, InvalidWindow> {
+        validate(capacity, period)?;
+        validate_resolution(period, Duration::from_millis(1))?;
+        let period = period.as_millis().min(u64::MAX as u128) as u64;
+        Ok(Self {
+            capacity,
+            period,
+            backend,
+            key: PhantomData,
+        })
+    }
+
+    /// The backend the state is kept in.
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    /// The period, in whole milliseconds.
+    pub fn period(&self) -> Duration {
+        Duration::from_millis(self.period)
+    }
+
+    pub async fn tokens(&self, key: &K, now: Option<SystemTime>) -> Result<u64, B::Error> {
+        let (window, now) = self.read(key, now).await?;
+        Ok(window.peek_tokens(now))
+    }
+
+    pub async fn retry_after(
+        &self,
+        key: &K,
+        now: Option<SystemTime>,
+    ) -> Result<Option<Duration>, B::Error> {
+        let (window, now) = self.read(key, now).await?;
+        Ok((window.peek_tokens(now) == 0).then(|| Duration::from_millis(window.next_reset(now))))
+    }
+
+    pub async fn trigger(
+        &self,
+        key: &K,
+        now: Option<SystemTime>,
+    ) -> Result<Option<Duration>, B::Error> {
+        Ok(self.trigger_n(key, 1, now).await?.err())
+    }
+
+    /// Consume `cost` triggers for `key` at once. See `floodgate::JumpingWindow::trigger_n`.
+    pub async fn trigger_n(
+        &self,
+        key: &K,
+        cost: u64,
+        now: Option<SystemTime>,
+    ) -> Result<Result<(), Duration>, B::Error> {
+        let now = millis(now.unwrap_or_else(SystemTime::now));
+        loop {
+            let current = self.backend.get(key).await?;
+            let mut window = self.window(current, now);
+            if let Err(retry_after) = window.trigger_n(cost, now) {
+                let retry_after = match retry_after {
+                    u64::MAX => Duration::MAX,
+                    millis => Duration::from_millis(millis),
+                };
+                return Ok(Err(retry_after));
+            }
+
+            let new = WindowState {
+                tokens: window.tokens,
+                window_start: window.last_reset,
+            };
+            let ttl = Duration::from_millis(window.next_reset(now));
+            if self.backend.compare_and_set(key, current, new, ttl).await? {
+                return Ok(Ok(()));
+            }
+        }
+    }
+
+    /// Reset the cooldown for `key`. Since a key without a state has a full window, this is
+    /// the same as `BackendMapping::remove`.
+    pub async fn reset(&self, key: &K) -> Result<(), B::Error> {
+        self.remove(key).await.map(|_| ())
+    }
+
+    /// Drop the state of `key`. Returns whether it had one.
+    pub async fn remove(&self, key: &K) -> Result<bool, B::Error> {
+        self.backend.remove(key).await
+    }
+
+    /// The window of `key`, along with the current time in milliseconds.
+    async fn read(
+        &self,
+        key: &K,
+        now: Option<SystemTime>,
+    ) -> Result<(JumpingWindowCore<u64>, u64), B::Error> {
+        let now = millis(now.unwrap_or_else(SystemTime::now));
+        let state = self.backend.get(key).await?;
+        Ok((self.window(state, now), now))
+    }
+
+    /// The window stored as `state`, or a new one aligned to the period if there is none.
+    fn window(&self, state: Option<WindowState>, now: u64) -> JumpingWindowCore<u64> {
+        let (tokens, window_start) = match state {
+            Some(state) => (state.tokens.min(self.capacity), state.window_start),
+            None => (self.capacity, now - now % self.period),
+        };
+        JumpingWindowCore {
+            capacity: self.capacity,
+            period: self.period,
+            last_reset: window_start,
+            tokens,
+            aligned: true,
+            penalty: None,
+            extension: 0,
+            carry_over: None,
+            jitter: None,
+            skew: (0, 0),
+            bookings: VecDeque::new(),
+            epoch: 0,
+        }
+    }
+}
+
+/// `time` in whole milliseconds since the unix epoch, or zero if it is earlier.
+pub(crate) fn millis(time: SystemTime) -> u64 {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    since.as_millis().min(u64::MAX as u128) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::Future,
+        sync::atomic::{AtomicU64, Ordering},
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use super::{Backend, BackendMapping, MemoryBackend, WindowState};
+    use crate::InvalidWindow;
+
+    /// A backend whose first compare-and-sets fail, as if another process got there first.
+    struct Contended {
+        inner: MemoryBackend<u64>,
+        conflicts: AtomicU64,
+    }
+
+    impl Backend<u64> for Contended {
+        type Error = std::convert::Infallible;
+
+        fn get(
+            &self,
+            key: &u64,
+        ) -> impl Future<Output = Result<Option<WindowState>, Self::Error>> + Send {
+            self.inner.get(key)
+        }
+
+        fn compare_and_set(
+            &self,
+            key: &u64,
+            current: Option<WindowState>,
+            new: WindowState,
+            ttl: Duration,
+        ) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+            let conflict = self
+                .conflicts
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+                .is_ok();
+            let mut set = None;
+            if !conflict {
+                set = Some(self.inner.compare_and_set(key, current, new, ttl));
+            }
+            async move {
+                match set {
+                    Some(set) => set.await,
+                    None => Ok(false),
+                }
+            }
+        }
+
+        fn remove(&self, key: &u64) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+            self.inner.remove(key)
+        }
+    }
+
+    #[tokio::test]
+    async fn triggers_retry_until_the_state_is_set() {
+        let backend = Contended {
+            inner: MemoryBackend::new(),
+            conflicts: AtomicU64::new(3),
+        };
+        let mapping = BackendMapping::new(2, Duration::from_secs(10), backend);
+        let now = Some(UNIX_EPOCH + Duration::from_millis(1_000_004_500));
+
+        assert_eq!(mapping.trigger(&1, now).await, Ok(None));
+        assert_eq!(mapping.backend().conflicts.load(Ordering::Relaxed), 0);
+        assert_eq!(mapping.trigger(&1, now).await, Ok(None));
+
+        // windows are aligned to the period, so this one ends at 1_000_010_000ms.
+        let retry_after = Duration::from_millis(5_500);
+        assert_eq!(mapping.trigger(&1, now).await, Ok(Some(retry_after)));
+        assert_eq!(mapping.retry_after(&1, now).await, Ok(Some(retry_after)));
+        assert_eq!(mapping.tokens(&2, now).await, Ok(2));
+
+        mapping.reset(&1).await.unwrap();
+        assert_eq!(mapping.tokens(&1, now).await, Ok(2));
+        assert!(mapping.backend().inner.is_empty());
+    }
+
+    #[test]
+    fn periods_must_be_whole_milliseconds() {
+        let mapping =
+            BackendMapping::<u64, _>::new(1, Duration::from_millis(2), MemoryBackend::new());
+        assert_eq!(mapping.period(), Duration::from_millis(2));
+
+        for period in [Duration::from_micros(1500), Duration::from_micros(999)] {
+            let result = BackendMapping::<u64, _>::try_new(1, period, MemoryBackend::new());
+            assert!(matches!(
+                result.err(),
+                Some(InvalidWindow::PeriodResolution { .. })
+            ));
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use std::{fmt, time::Duration};
+
+use crate::{FixedMapping, Rate, RateLimitInfo, SharedJumpingWindow};
+
+/// The ids a `floodgate::BucketedCooldown` can be keyed by, such as those of a chat command's
+/// author, channel and guild.
+///
+/// This is implemented by `floodgate::BucketIds`, but can also be implemented by a framework's
+/// own context or message types, to pass them to the cooldown directly.
+pub trait BucketContext {
+    /// The id of the user.
+    fn user_id(&self) -> u64;
+    /// The id of the channel.
+    fn channel_id(&self) -> u64;
+    /// The id of the guild, or `None` outside of one, such as in direct messages.
+    fn guild_id(&self) -> Option<u64>;
+}
+
+/// The ids of a user, and the channel and guild they are in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct BucketIds {
+    pub user: u64,
+    pub channel: u64,
+    pub guild: Option<u64>,
+}
+
+impl BucketContext for BucketIds {
+    fn user_id(&self) -> u64 {
+        self.user
+    }
+
+    fn channel_id(&self) -> u64 {
+        self.channel
+    }
+
+    fn guild_id(&self) -> Option<u64> {
+        self.guild
+    }
+}
+
+/// What a `floodgate::BucketedCooldown` gives a separate cooldown to.
+pub enum Bucket<Ctx = BucketIds> {
+    /// One cooldown shared by everyone.
+    Global,
+    /// A cooldown for each user, across every channel and guild.
+    PerUser,
+    /// A cooldown for each channel.
+    PerChannel,
+    /// A cooldown for each guild. Outside of a guild, each user has their own.
+    PerGuild,
+    /// A cooldown for each user in each guild. Outside of a guild, each user has their own.
+    PerMember,
+    /// A cooldown for each id returned by the function.
+    Custom(fn(&Ctx) -> u64),
+}
+
+impl<Ctx: BucketContext> Bucket<Ctx> {
+    /// The key of `ctx` in the cooldown's mapping, or `None` for `Bucket::Global`.
+    fn key(&self, ctx: &Ctx) -> Option<(u64, u64)> {
+        let key = match self {
+            Self::Global => return None,
+            Self::PerUser => (ctx.user_id(), 0),
+            Self::PerChannel => (ctx.channel_id(), 0),
+            Self::PerGuild => (ctx.guild_id().unwrap_or_else(|| ctx.user_id()), 0),
+            Self::PerMember => (ctx.guild_id().unwrap_or(0), ctx.user_id()),
+            Self::Custom(id) => (id(ctx), 0),
+        };
+        Some(key)
+    }
+}
+
+// implemented by hand, since the derives would require `Ctx` to implement them too.
+impl<Ctx> Clone for Bucket<Ctx> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<Ctx> Copy for Bucket<Ctx> {}
+
+impl<Ctx> fmt::Debug for Bucket<Ctx> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Global => "Global",
+            Self::PerUser => "PerUser",
+            Self::PerChannel => "PerChannel",
+            Self::PerGuild => "PerGuild",
+            Self::PerMember => "PerMember",
+            Self::Custom(_) => "Custom",
+        })
+    }
+}
+
+/// Where a `BucketedCooldown` keeps its state.
+enum Limiter {
+    Global(SharedJumpingWindow),
+    Keyed(Box<FixedMapping<(u64, u64)>>),
+}
+
+/// A cooldown that picks which window to trigger from a context, such as per user or per
+/// channel, the way chat bots usually limit their commands.
+///
+/// # Examples
+/// ```
+/// use floodgate::{Bucket, BucketIds, BucketedCooldown};
+/// use std::time::Duration;
+///
+/// let cooldown = BucketedCooldown::new(Bucket::PerMember, 1, Duration::from_secs(10));
+/// let alice = BucketIds { user: 1, channel: 10, guild: Some(100) };
+///
+/// assert_eq!(cooldown.trigger(&alice), None);
+/// assert!(cooldown.trigger(&alice).is_some());
+///
+/// // alice in another guild, and bob in the same one, have their own cooldowns.
+/// assert_eq!(cooldown.trigger(&BucketIds { guild: Some(200), ..alice }), None);
+/// assert_eq!(cooldown.trigger(&BucketIds { user: 2, ..alice }), None);
+/// assert_eq!(cooldown.status(&alice).unwrap().remaining, 0);
+/// ```
+pub struct BucketedCooldown<Ctx = BucketIds> {
+    bucket: Bucket<Ctx>,
+    limiter: Limiter,
+}
+
+impl<Ctx: BucketContext> BucketedCooldown<Ctx> {
+    /// Create a new BucketedCooldown.
+    ///
+    /// # Arguments
+    /// * `bucket` - What each window is for.
+    /// * `capacity` - How many triggers can occur per window.
+    /// * `period` - How long each window is.
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `period` is zero.
+    pub fn new(bucket: Bucket<Ctx>, capacity: u64, period: Duration) -> Self {
+        let limiter = match bucket {
+            Bucket::Global => Limiter::Global(SharedJumpingWindow::new(capacity, period)),
+            _ => Limiter::Keyed(Box::new(FixedMapping::new(capacity, period))),
+        };
+        Self { bucket, limiter }
+    }
+
+    /// Create a new BucketedCooldown that allows `rate` for each window.
+    pub fn from_rate(bucket: Bucket<Ctx>, rate: Rate) -> Self {
+        Self::new(bucket, rate.capacity(), rate.period())
+    }
+
+    pub fn bucket(&self) -> Bucket<Ctx> {
+        self.bucket
+    }
+
+    /// Trigger the window of `ctx`. Returns how long until it can be triggered again if it's
+    /// exhausted, like `floodgate::FixedMapping::trigger`.
+    ///
+    /// # Arguments
+    /// * `ctx` - The context to pick the window from.
+    pub fn trigger(&self, ctx: &Ctx) -> Option<Duration> {
+        match (&self.limiter, self.bucket.key(ctx)) {
+            (Limiter::Keyed(mapping), Some(key)) => mapping.trigger(&key),
+            (Limiter::Global(window), _) => window.trigger(None),
+            (Limiter::Keyed(_), None) => unreachable!("only global cooldowns have no keys"),
+        }
+    }
+
+    /// The state of the window of `ctx`, without triggering it. Like
+    /// `floodgate::FixedMapping::status`, this is `None` if the window hasn't been triggered
+    /// yet. The window of `Bucket::Global` always exists.
+    ///
+    /// # Arguments
+    /// * `ctx` - The context to pick the window from.
+    pub fn status(&self, ctx: &Ctx) -> Option<RateLimitInfo> {
+        match (&self.limiter, self.bucket.key(ctx)) {
+            (Limiter::Keyed(mapping), Some(key)) => mapping.status(&key, None),
+            (Limiter::Global(window), _) => Some(window.with(|window| {
+                let remaining = window.tokens(None);
+                RateLimitInfo {
+                    allowed: remaining != 0,
+                    limit: window.capacity(),
+                    remaining,
+                    retry_after: window.retry_after(None),
+                    reset_after: window.next_reset(None),
+                }
+            })),
+            (Limiter::Keyed(_), None) => unreachable!("only global cooldowns have no keys"),
+        }
+    }
+
+    /// Drop the windows that are full again, returning how many were dropped. See
+    /// `floodgate::FixedMapping::cleanup`. The window of `Bucket::Global` is never dropped.
+    pub fn cleanup(&self) -> usize {
+        match &self.limiter {
+            Limiter::Global(_) => 0,
+            Limiter::Keyed(mapping) => mapping.cleanup(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Bucket, BucketIds, BucketedCooldown};
+
+    const IDS: BucketIds = BucketIds {
+        user: 1,
+        channel: 10,
+        guild: Some(100),
+    };
+
+    #[test]
+    fn buckets_route_contexts_to_their_windows() {
+        let period = Duration::from_secs(60);
+        let expected = [
+            (Bucket::PerUser, [true, false, false, false]),
+            (Bucket::PerChannel, [false, true, true, true]),
+            (Bucket::PerGuild, [true, true, false, false]),
+            (Bucket::PerMember, [true, false, false, false]),
+            (
+                Bucket::Custom(|ids: &BucketIds| ids.user % 2),
+                [true, false, true, true],
+            ),
+        ];
+        for (bucket, shared) in expected {
+            let cooldown = BucketedCooldown::new(bucket, 1, period);
+            let others = [
+                BucketIds { channel: 11, ..IDS },
+                BucketIds { user: 2, ..IDS },
+                BucketIds {
+                    user: 3,
+                    guild: None,
+                    ..IDS
+                },
+                BucketIds {
+                    user: 4,
+                    channel: 10,
+                    guild: Some(200),
+                },
+            ];
+            for (other, shared) in others.iter().zip(shared) {
+                cooldown.trigger(&IDS);
+                let rejected = cooldown.trigger(other).is_some();
+                assert_eq!(rejected, shared, "{bucket:?} with {other:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn global_cooldowns_share_one_window() {
+        let cooldown = BucketedCooldown::new(Bucket::Global, 2, Duration::from_secs(60));
+        assert_eq!(cooldown.status(&IDS).unwrap().remaining, 2);
+        assert_eq!(cooldown.trigger(&IDS), None);
+        assert_eq!(cooldown.trigger(&BucketIds::default()), None);
+        assert!(cooldown.trigger(&BucketIds { user: 2, ..IDS }).is_some());
+        assert!(!cooldown.status(&IDS).unwrap().allowed);
+        assert_eq!(cooldown.cleanup(), 0);
+    }
+}
@@ -0,0 +1,134 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// A handle to the background cycler of a `floodgate::FixedMapping` or
+/// `floodgate::DynamicMapping`, returned by their `start` methods, or to the cleanup thread
+/// started by `floodgate::FixedMapping::start_thread`.
+///
+/// The cycler only holds a weak reference to the mapping, so it also stops on its own once the
+/// mapping is dropped. Dropping the handle doesn't stop the cycler.
+#[derive(Debug)]
+pub struct CleanupHandle {
+    stopped: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl CleanupHandle {
+    /// Spawn a thread that waits for `first`, then calls `tick` every time the duration it
+    /// returned has passed, until it returns `None` or the handle is stopped.
+    pub(crate) fn spawn(
+        first: Duration,
+        mut tick: impl FnMut() -> Option<Duration> + Send + 'static,
+    ) -> Self {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread = thread::spawn({
+            let stopped = stopped.clone();
+            move || {
+                let mut wait = Some(first);
+                while let Some(duration) = wait {
+                    if !sleep_unless_stopped(duration, &stopped) {
+                        break;
+                    }
+                    wait = tick();
+                }
+            }
+        });
+
+        Self { stopped, thread }
+    }
+
+    /// Stop the cycler, blocking until its thread has exited. If the cycler is in the middle
+    /// of cycling the mapping, that cycle is finished first.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::{sync::Arc, time::Duration};
+    ///
+    /// let mapping = Arc::new(FixedMapping::<u64>::new(1, Duration::from_secs(3600)));
+    /// let cycler = FixedMapping::start(mapping.clone(), None);
+    ///
+    /// // returns right away, without waiting for the next cycle.
+    /// cycler.stop();
+    /// ```
+    pub fn stop(self) {
+        self.stopped.store(true, Ordering::Release);
+        self.thread.thread().unpark();
+        // the thread can only have panicked inside the mapping, which has already reported it.
+        let _ = self.thread.join();
+    }
+
+    /// Whether the cycler has exited, either because it was stopped or because the mapping was
+    /// dropped.
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+}
+
+/// Sleep the current thread for `duration`, waking early if `stopped` is set and the thread is
+/// unparked. Returns `false` if it was stopped.
+fn sleep_unless_stopped(duration: Duration, stopped: &AtomicBool) -> bool {
+    let deadline = Instant::now() + duration;
+
+    loop {
+        if stopped.load(Ordering::Acquire) {
+            return false;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        thread::park_timeout(deadline - now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use crate::{DynamicMapping, FixedMapping};
+
+    fn wait_until(mut done: impl FnMut() -> bool) {
+        let start = Instant::now();
+        while !done() {
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn cycler_exits_when_the_mapping_is_dropped() {
+        let mapping = Arc::new(FixedMapping::<u64>::new(1, Duration::from_millis(10)));
+        let cycler = FixedMapping::start(mapping.clone(), None);
+
+        drop(mapping);
+        wait_until(|| cycler.is_finished());
+
+        let mapping = Arc::new(DynamicMapping::<u64>::new(Duration::from_millis(10)));
+        let cycler = DynamicMapping::start(mapping.clone());
+
+        drop(mapping);
+        wait_until(|| cycler.is_finished());
+    }
+
+    #[test]
+    fn stop_does_not_wait_for_the_next_cycle() {
+        let mapping = Arc::new(FixedMapping::<u64>::new(1, Duration::from_secs(3600)));
+        let cycler = FixedMapping::start(mapping.clone(), None);
+
+        let start = Instant::now();
+        cycler.stop();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(Arc::strong_count(&mapping), 1);
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use crate::cleanup::CleanupHandle;
+
+/// `std::time::Instant::now` panics on `wasm32-unknown-unknown`, so the time there comes from
+/// `web-time`, which uses the browser's `performance.now()`. Everywhere else, these are the
+/// `std::time` types.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub(crate) use std::time::{Instant, SystemTime, UNIX_EPOCH};
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub(crate) use web_time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// A source of the current time, used whenever `now` isn't given explicitly.
+///
+/// `floodgate::JumpingWindow`, `floodgate::FixedMapping` and `floodgate::DynamicMapping` are
+/// generic over their clock, which defaults to `floodgate::MonotonicClock`. Swapping in a
+/// `floodgate::ManualClock` makes time, including when mappings cycle, fully controllable in
+/// tests.
+pub trait Clock {
+    /// The current time.
+    fn now(&self) -> Instant;
+}
+
+/// The system's monotonic clock, `std::time::Instant::now`.
+///
+/// On `wasm32-unknown-unknown`, this is `web_time::Instant::now` instead, which reads the
+/// browser's `performance.now()`.
+///
+/// With the `tokio-time` feature, this is `tokio::time::Instant::now` instead, so that time
+/// can be paused and advanced with `tokio::time::pause` and `tokio::time::advance`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MonotonicClock;
+
+impl Clock for MonotonicClock {
+    fn now(&self) -> Instant {
+        now()
+    }
+}
+
+/// The current time, used everywhere the time isn't given explicitly.
+#[cfg(not(feature = "tokio-time"))]
+pub(crate) fn now() -> Instant {
+    Instant::now()
+}
+
+/// The current time from tokio's clock, which follows `tokio::time::pause`.
+#[cfg(feature = "tokio-time")]
+pub(crate) fn now() -> Instant {
+    tokio::time::Instant::now().into_std()
+}
+
+/// A clock that only moves when it is told to, for tests.
+///
+/// Clones share the same time, so a clone can be given to a window or mapping while the test
+/// keeps another to advance it.
+///
+/// # Examples
+/// ```
+/// use floodgate::{FixedMapping, ManualClock};
+/// use std::time::Duration;
+///
+/// let clock = ManualClock::new();
+/// let mapping = FixedMapping::with_clock(1, Duration::from_secs(60), clock.clone());
+///
+/// assert_eq!(mapping.trigger(&1), None);
+/// assert_eq!(mapping.trigger(&1), Some(Duration::from_secs(60)));
+///
+/// clock.advance(Duration::from_secs(45));
+/// assert_eq!(mapping.trigger(&1), Some(Duration::from_secs(15)));
+///
+/// clock.advance(Duration::from_secs(15));
+/// assert_eq!(mapping.trigger(&1), None);
+/// ```
+#[derive(Clone)]
+pub struct ManualClock {
+    start: Instant,
+    elapsed: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    /// Create a new ManualClock, starting at the current time.
+    pub fn new() -> Self {
+        Self {
+            start: now(),
+            elapsed: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Move the clock, and every clone of it, forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        let nanos = duration.as_nanos().min(u64::MAX as u128) as u64;
+        self.elapsed.fetch_add(nanos, Ordering::AcqRel);
+    }
+
+    /// How far the clock has been advanced since it was created.
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.elapsed.load(Ordering::Acquire))
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for ManualClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ManualClock")
+            .field("elapsed", &self.elapsed())
+            .finish()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+}
+
+/// A clock that reads the time once and keeps returning it until it is refreshed, so that each
+/// trigger doesn't have to read the system's clock.
+///
+/// Refresh it with `CachedClock::refresh`, or every `interval` from a background thread with
+/// `CachedClock::start`. Between refreshes, the time is stale by up to `interval`, so a window
+/// may reset up to `interval` late, and triggers can be rejected for that long after they
+/// should have been allowed. Since windows are measured from the same stale time, a trigger
+/// can likewise be accepted up to `interval` after it should have been rejected. That is
+/// negligible for periods of seconds or more with an interval of a few milliseconds.
+///
+/// Clones share the same time, so one clone can be refreshed for every window or mapping
+/// using the others. `floodgate::MonotonicClock` remains the default.
+///
+/// # Examples
+/// ```
+/// use floodgate::{CachedClock, Clock, FixedMapping};
+/// use std::time::Duration;
+///
+/// let clock = CachedClock::new();
+/// let cycler = clock.start(Duration::from_millis(5));
+/// let mapping = FixedMapping::with_clock(1, Duration::from_secs(60), clock.clone());
+///
+/// let before = clock.now();
+/// assert_eq!(mapping.trigger(&1), None);
+/// assert!(mapping.trigger(&1).is_some());
+///
+/// std::thread::sleep(Duration::from_millis(50));
+/// assert!(clock.now() > before);
+/// cycler.stop();
+/// ```
+#[derive(Clone)]
+pub struct CachedClock {
+    start: Instant,
+    elapsed: Arc<AtomicU64>,
+}
+
+impl CachedClock {
+    /// Create a new CachedClock, set to the current time.
+    pub fn new() -> Self {
+        Self {
+            start: now(),
+            elapsed: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Set the clock, and every clone of it, to the current time. The clock never goes back,
+    /// even if refreshes race.
+    pub fn refresh(&self) {
+        refresh(self.start, &self.elapsed);
+    }
+
+    /// Spawn a thread that refreshes the clock every `interval`, returning a handle to stop it
+    /// with. The thread exits on its own once every clone of the clock is dropped.
+    ///
+    /// Not available on `wasm32-unknown-unknown`, which has no threads.
+    ///
+    /// # Arguments
+    /// * `interval` - How often to refresh the clock, which is also how stale it can get.
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn start(&self, interval: Duration) -> CleanupHandle {
+        let start = self.start;
+        let elapsed = Arc::downgrade(&self.elapsed);
+        CleanupHandle::spawn(interval, move || {
+            let elapsed = elapsed.upgrade()?;
+            refresh(start, &elapsed);
+            Some(interval)
+        })
+    }
+}
+
+/// Move `elapsed` forward to the time since `start`.
+fn refresh(start: Instant, elapsed: &AtomicU64) {
+    let nanos = now().saturating_duration_since(start).as_nanos();
+    elapsed.fetch_max(nanos.min(u64::MAX as u128) as u64, Ordering::AcqRel);
+}
+
+impl Default for CachedClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for CachedClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachedClock")
+            .field(
+                "elapsed",
+                &Duration::from_nanos(self.elapsed.load(Ordering::Acquire)),
+            )
+            .finish()
+    }
+}
+
+impl Clock for CachedClock {
+    fn now(&self) -> Instant {
+        self.start + Duration::from_nanos(self.elapsed.load(Ordering::Acquire))
+    }
+}
@@ -0,0 +1,495 @@
+use std::{
+    borrow::Borrow,
+    fmt,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use dashmap::DashMap;
+
+#[cfg(feature = "tokio")]
+use crate::wait_queue::{WaitQueue, WaitQueues};
+use crate::{
+    clock::{self, Instant},
+    FixedMapping, JumpingWindow,
+};
+
+/// A hook called with how long a permit was held, once it is released after being held for
+/// too long.
+type HoldHook<T> = (Duration, Box<T>);
+
+/// At most `limit` things in flight at once, such as concurrent renders. A permit is taken
+/// with `ConcurrencyLimit::try_acquire`, or waited for with `ConcurrencyLimit::acquire` with
+/// the `tokio` feature, and is given back when it is dropped.
+///
+/// The limit can be combined with a `floodgate::JumpingWindow` with
+/// `ConcurrencyLimit::with_window`, so that taking a permit enforces both "N at once" and "M
+/// per period".
+///
+/// # Examples
+/// ```
+/// use floodgate::ConcurrencyLimit;
+///
+/// let renders = ConcurrencyLimit::new(2);
+///
+/// let first = renders.try_acquire().unwrap();
+/// let _second = renders.try_acquire().unwrap();
+/// assert!(renders.try_acquire().is_none());
+///
+/// drop(first);
+/// assert_eq!(renders.available(), 1);
+/// ```
+pub struct ConcurrencyLimit {
+    limit: u64,
+    in_flight: AtomicU64,
+    window: Option<Mutex<JumpingWindow>>,
+    pub(crate) fair: bool,
+    on_long_hold: Option<HoldHook<dyn Fn(Duration) + Send + Sync>>,
+    #[cfg(feature = "tokio")]
+    pub(crate) waiters: WaitQueue,
+}
+
+impl ConcurrencyLimit {
+    /// Create a new ConcurrencyLimit.
+    ///
+    /// # Arguments
+    /// * `limit` - How many permits can be held at once.
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            in_flight: AtomicU64::new(0),
+            window: None,
+            fair: false,
+            on_long_hold: None,
+            #[cfg(feature = "tokio")]
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Also limit how often permits are taken, by triggering `window` for every one.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{ConcurrencyLimit, JumpingWindow};
+    /// use std::time::Duration;
+    ///
+    /// let window = JumpingWindow::new(2, Duration::from_secs(60));
+    /// let renders = ConcurrencyLimit::new(5).with_window(window);
+    ///
+    /// drop(renders.try_acquire().unwrap());
+    /// drop(renders.try_acquire().unwrap());
+    /// // nothing is in flight, but the window is exhausted.
+    /// assert!(renders.try_acquire().is_none());
+    /// ```
+    pub fn with_window(mut self, window: JumpingWindow) -> Self {
+        self.window = Some(Mutex::new(window));
+        self
+    }
+
+    /// Hand out permits to async waiters in the order they started waiting. Without this, the
+    /// first waiter to notice a released permit gets it. Permits taken with `try_acquire`
+    /// never wait in line.
+    pub fn fair(mut self) -> Self {
+        self.fair = true;
+        self
+    }
+
+    /// Call `hook` whenever a permit is released after being held for longer than `held`,
+    /// with how long it was held. This helps find permits that are kept by mistake.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::ConcurrencyLimit;
+    /// use std::time::Duration;
+    ///
+    /// let renders = ConcurrencyLimit::new(2).on_long_hold(Duration::from_secs(30), |held| {
+    ///     eprintln!("a render permit was held for {held:?}");
+    /// });
+    /// ```
+    pub fn on_long_hold(
+        mut self,
+        held: Duration,
+        hook: impl Fn(Duration) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_long_hold = Some((held, Box::new(hook)));
+        self
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// How many permits are currently held.
+    pub fn in_flight(&self) -> u64 {
+        self.in_flight.load(Ordering::Acquire)
+    }
+
+    /// How many permits can be taken right now, counting the window's tokens if there is one.
+    pub fn available(&self) -> u64 {
+        let free = self.limit.saturating_sub(self.in_flight());
+        match &self.window {
+            Some(window) => free.min(window.lock().unwrap().tokens(None)),
+            None => free,
+        }
+    }
+
+    /// Take a permit, or return `None` if `limit` are already held or the window is exhausted.
+    pub fn try_acquire(&self) -> Option<ConcurrencyPermit<'_>> {
+        self.take().ok()
+    }
+
+    /// Take a permit. If none is free, returns `Err(None)`. If the window is exhausted,
+    /// returns its retry-after, and the permit is given back.
+    pub(crate) fn take(&self) -> Result<ConcurrencyPermit<'_>, Option<Duration>> {
+        if !take_slot(&self.in_flight, self.limit) {
+            return Err(None);
+        }
+        let permit = ConcurrencyPermit {
+            limit: self,
+            acquired_at: clock::now(),
+        };
+        if let Some(window) = &self.window {
+            // dropping the permit gives the slot back.
+            if let Some(retry_after) = window.lock().unwrap().trigger(None) {
+                return Err(Some(retry_after));
+            }
+        }
+        Ok(permit)
+    }
+
+    fn release(&self, acquired_at: Instant) {
+        self.in_flight.fetch_sub(1, Ordering::AcqRel);
+        if let Some((held, hook)) = &self.on_long_hold {
+            let elapsed = clock::now().saturating_duration_since(acquired_at);
+            if elapsed > *held {
+                hook(elapsed);
+            }
+        }
+        #[cfg(feature = "tokio")]
+        self.waiters.notify_reset();
+    }
+}
+
+impl fmt::Debug for ConcurrencyLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConcurrencyLimit")
+            .field("limit", &self.limit)
+            .field("in_flight", &self.in_flight())
+            .field("fair", &self.fair)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A permit taken from a `floodgate::ConcurrencyLimit`, which is given back when dropped.
+#[must_use = "dropping the permit immediately gives it back"]
+pub struct ConcurrencyPermit<'a> {
+    limit: &'a ConcurrencyLimit,
+    acquired_at: Instant,
+}
+
+impl ConcurrencyPermit<'_> {
+    /// When the permit was taken.
+    pub fn acquired_at(&self) -> Instant {
+        self.acquired_at
+    }
+}
+
+impl fmt::Debug for ConcurrencyPermit<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConcurrencyPermit")
+            .field("acquired_at", &self.acquired_at)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for ConcurrencyPermit<'_> {
+    fn drop(&mut self) {
+        self.limit.release(self.acquired_at);
+    }
+}
+
+/// Take one of `limit` slots counted by `in_flight`, returning whether there was one.
+fn take_slot(in_flight: &AtomicU64, limit: u64) -> bool {
+    in_flight
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |held| {
+            (held < limit).then_some(held + 1)
+        })
+        .is_ok()
+}
+
+/// A `floodgate::ConcurrencyLimit` for each key, such as at most 2 renders in flight per user.
+///
+/// Keys only take up memory while they hold permits, so the mapping never has to be cleaned
+/// up. With `ConcurrencyMapping::with_rate`, every permit also triggers the key in a
+/// `floodgate::FixedMapping`.
+///
+/// # Examples
+/// ```
+/// use floodgate::ConcurrencyMapping;
+///
+/// let renders = ConcurrencyMapping::new(2);
+///
+/// let _first = renders.try_acquire(&"alice").unwrap();
+/// let _second = renders.try_acquire(&"alice").unwrap();
+/// assert!(renders.try_acquire(&"alice").is_none());
+/// assert!(renders.try_acquire(&"bob").is_some());
+/// assert_eq!(renders.available(&"alice"), 0);
+/// ```
+pub struct ConcurrencyMapping<K: Eq + Hash + Clone + Send + Sync + 'static> {
+    limit: u64,
+    in_flight: DashMap<K, u64>,
+    rate: Option<FixedMapping<K>>,
+    pub(crate) fair: bool,
+    #[allow(clippy::type_complexity)]
+    on_long_hold: Option<HoldHook<dyn Fn(&K, Duration) + Send + Sync>>,
+    #[cfg(feature = "tokio")]
+    pub(crate) waiters: WaitQueues<K>,
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync + 'static> ConcurrencyMapping<K> {
+    /// Create a new ConcurrencyMapping.
+    ///
+    /// # Arguments
+    /// * `limit` - How many permits each key can hold at once.
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            in_flight: DashMap::new(),
+            rate: None,
+            fair: false,
+            on_long_hold: None,
+            #[cfg(feature = "tokio")]
+            waiters: WaitQueues::new(),
+        }
+    }
+
+    /// Also limit how often each key takes permits, to `capacity` per `period`.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::ConcurrencyMapping;
+    /// use std::time::Duration;
+    ///
+    /// let renders = ConcurrencyMapping::new(2).with_rate(3, Duration::from_secs(60));
+    /// for _ in 0..3 {
+    ///     drop(renders.try_acquire(&"alice").unwrap());
+    /// }
+    /// assert!(renders.try_acquire(&"alice").is_none());
+    /// assert!(renders.rate().unwrap().retry_after(&"alice").is_some());
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `period` is zero.
+    pub fn with_rate(mut self, capacity: u64, period: Duration) -> Self {
+        self.rate = Some(FixedMapping::new(capacity, period));
+        self
+    }
+
+    /// Hand out each key's permits to async waiters in the order they started waiting. See
+    /// `floodgate::ConcurrencyLimit::fair`.
+    pub fn fair(mut self) -> Self {
+        self.fair = true;
+        self
+    }
+
+    /// Call `hook` whenever a permit is released after being held for longer than `held`. See
+    /// `floodgate::ConcurrencyLimit::on_long_hold`.
+    pub fn on_long_hold(
+        mut self,
+        held: Duration,
+        hook: impl Fn(&K, Duration) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_long_hold = Some((held, Box::new(hook)));
+        self
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// The mapping that limits how often permits are taken, set with
+    /// `ConcurrencyMapping::with_rate`.
+    pub fn rate(&self) -> Option<&FixedMapping<K>> {
+        self.rate.as_ref()
+    }
+
+    /// How many permits `key` currently holds.
+    pub fn in_flight<Q>(&self, key: &Q) -> u64
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.in_flight.get(key).map_or(0, |held| *held)
+    }
+
+    /// How many permits `key` can take right now, counting its tokens if there is a rate.
+    pub fn available<Q>(&self, key: &Q) -> u64
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let free = self.limit.saturating_sub(self.in_flight(key));
+        match &self.rate {
+            Some(rate) => free.min(rate.tokens(key)),
+            None => free,
+        }
+    }
+
+    /// Take a permit for `key`, or return `None` if it already holds `limit` or its rate is
+    /// exhausted.
+    pub fn try_acquire<Q>(&self, key: &Q) -> Option<KeyedPermit<'_, K>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.take(key).ok()
+    }
+
+    /// Take a permit for `key`. See `floodgate::ConcurrencyLimit::take`.
+    pub(crate) fn take<Q>(&self, key: &Q) -> Result<KeyedPermit<'_, K>, Option<Duration>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let taken = match self.in_flight.get_mut(key) {
+            Some(mut held) => {
+                let taken = *held < self.limit;
+                *held += u64::from(taken);
+                taken
+            }
+            None => {
+                let mut held = self.in_flight.entry(key.to_owned()).or_insert(0);
+                let taken = *held < self.limit;
+                *held += u64::from(taken);
+                taken
+            }
+        };
+        if !taken {
+            return Err(None);
+        }
+        let permit = KeyedPermit {
+            mapping: self,
+            key: key.to_owned(),
+            acquired_at: clock::now(),
+        };
+        if let Some(rate) = &self.rate {
+            // dropping the permit gives the slot back.
+            if let Some(retry_after) = rate.trigger(key) {
+                return Err(Some(retry_after));
+            }
+        }
+        Ok(permit)
+    }
+
+    fn release(&self, key: &K, acquired_at: Instant) {
+        if let Some(mut held) = self.in_flight.get_mut(key) {
+            *held = held.saturating_sub(1);
+        }
+        self.in_flight.remove_if(key, |_, held| *held == 0);
+        if let Some((held, hook)) = &self.on_long_hold {
+            let elapsed = clock::now().saturating_duration_since(acquired_at);
+            if elapsed > *held {
+                hook(key, elapsed);
+            }
+        }
+        #[cfg(feature = "tokio")]
+        self.waiters.notify_reset(key);
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync + fmt::Debug + 'static> fmt::Debug
+    for ConcurrencyMapping<K>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConcurrencyMapping")
+            .field("limit", &self.limit)
+            .field("keys", &self.in_flight.len())
+            .field("fair", &self.fair)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A permit taken from a `floodgate::ConcurrencyMapping` for one key, which is given back when
+/// dropped.
+#[must_use = "dropping the permit immediately gives it back"]
+pub struct KeyedPermit<'a, K: Eq + Hash + Clone + Send + Sync + 'static> {
+    mapping: &'a ConcurrencyMapping<K>,
+    key: K,
+    acquired_at: Instant,
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync + 'static> KeyedPermit<'_, K> {
+    /// The key this permit was taken for.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// When the permit was taken.
+    pub fn acquired_at(&self) -> Instant {
+        self.acquired_at
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync + fmt::Debug + 'static> fmt::Debug for KeyedPermit<'_, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyedPermit")
+            .field("key", &self.key)
+            .field("acquired_at", &self.acquired_at)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync + 'static> Drop for KeyedPermit<'_, K> {
+    fn drop(&mut self) {
+        self.mapping.release(&self.key, self.acquired_at);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use super::{ConcurrencyLimit, ConcurrencyMapping};
+
+    #[test]
+    fn released_permits_free_their_keys() {
+        let long_holds = Arc::new(AtomicU64::new(0));
+        let renders = ConcurrencyMapping::new(1).on_long_hold(Duration::ZERO, {
+            let long_holds = long_holds.clone();
+            move |_: &u64, _| {
+                long_holds.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+
+        let permit = renders.try_acquire(&1).unwrap();
+        assert!(renders.try_acquire(&1).is_none());
+        assert_eq!(renders.in_flight(&1), 1);
+        std::thread::sleep(Duration::from_millis(1));
+        drop(permit);
+
+        assert_eq!(renders.in_flight(&1), 0);
+        assert!(renders.in_flight.is_empty());
+        assert_eq!(long_holds.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn window_rejections_give_the_slot_back() {
+        let window = crate::JumpingWindow::new(1, Duration::from_secs(60));
+        let limit = ConcurrencyLimit::new(3).with_window(window);
+
+        let _permit = limit.try_acquire().unwrap();
+        assert!(limit.try_acquire().is_none());
+        assert_eq!(limit.in_flight(), 1);
+        assert_eq!(limit.available(), 0);
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    future::{self, Future},
+    hash::{BuildHasher, Hash},
+    pin::pin,
+    sync::{Arc, Weak},
+    task::Poll,
+    time::Duration,
+};
+
+use tokio::{task::JoinHandle, time::sleep};
+
+use crate::{sleeper, Clock, DynamicMapping, FixedMapping, RateLimiter};
+
+/// A handle to a cycler task, returned by `floodgate::FixedMapping::start_task` and
+/// `floodgate::DynamicMapping::start_task`.
+///
+/// Like `floodgate::CleanupHandle`, the task only holds a weak reference to the mapping and
+/// exits on its own once the mapping is dropped. Dropping the handle doesn't stop the task.
+#[derive(Debug)]
+pub struct CleanupTask {
+    handle: JoinHandle<()>,
+}
+
+impl CleanupTask {
+    /// Stop the task, waiting until it has exited.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::{sync::Arc, time::Duration};
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let mapping = Arc::new(FixedMapping::<u64>::new(1, Duration::from_secs(3600)));
+    /// let cycler = FixedMapping::start_task(mapping.clone(), None);
+    ///
+    /// cycler.stop().await;
+    /// assert_eq!(Arc::strong_count(&mapping), 1);
+    /// # }
+    /// ```
+    pub async fn stop(self) {
+        self.handle.abort();
+        // the task can only have panicked inside the mapping, which has already reported it.
+        let _ = self.handle.await;
+    }
+
+    /// Wait for the task to exit on its own, such as after the shutdown signal given to
+    /// `floodgate::FixedMapping::start_task_until`, without stopping it.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::{sync::Arc, time::Duration};
+    /// use tokio::sync::oneshot;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let mapping = Arc::new(FixedMapping::<u64>::new(1, Duration::from_secs(3600)));
+    /// let (shutdown, signal) = oneshot::channel::<()>();
+    /// let cycler = FixedMapping::start_task_until(
+    ///     mapping.clone(),
+    ///     async move {
+    ///         let _ = signal.await;
+    ///     },
+    ///     true,
+    /// );
+    ///
+    /// shutdown.send(()).unwrap();
+    /// cycler.stopped().await;
+    /// # }
+    /// ```
+    pub async fn stopped(self) {
+        let _ = self.handle.await;
+    }
+
+    /// Whether the task has exited, either because it was stopped or because the mapping was
+    /// dropped.
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+}
+
+/// Spawn a task that sleeps for the duration returned by `wait`, then calls `cycle`, for as
+/// long as `mapping` is alive, or until `shutdown` completes. After a shutdown, `drain` is
+/// called once, if given.
+fn spawn<M: Send + Sync + 'static>(
+    mapping: &Arc<M>,
+    wait: impl Fn(&M) -> Duration + Send + 'static,
+    cycle: impl Fn(&M) + Send + 'static,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+    drain: Option<fn(&M)>,
+) -> CleanupTask {
+    let mapping: Weak<M> = Arc::downgrade(mapping);
+    let handle = tokio::spawn(async move {
+        let cycles = sleeper::cycles(mapping.clone(), wait, cycle, sleep);
+        if until(cycles, shutdown).await {
+            if let (Some(drain), Some(mapping)) = (drain, mapping.upgrade()) {
+                drain(&mapping);
+            }
+        }
+    });
+
+    CleanupTask { handle }
+}
+
+/// Run `task` until it completes or `shutdown` does, returning whether it was shut down.
+///
+/// `task` is only dropped between polls, so a cycle it has started is always finished, which
+/// an abort can't promise.
+async fn until(task: impl Future<Output = ()>, shutdown: impl Future<Output = ()>) -> bool {
+    let mut task = pin!(task);
+    let mut shutdown = pin!(shutdown);
+    future::poll_fn(|cx| {
+        if shutdown.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(true);
+        }
+        task.as_mut().poll(cx).map(|()| false)
+    })
+    .await
+}
+
+impl<K, L, C, S> FixedMapping<K, L, C, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    L: RateLimiter + Send + Sync + 'static,
+    C: Clock + Send + Sync + 'static,
+    S: BuildHasher + Clone + Send + Sync + 'static,
+{
+    /// Like `FixedMapping::start`, but cycles the mapping from a tokio task instead of a
+    /// thread. The task sleeps with `tokio::time::sleep`, so with the `tokio-time` feature it
+    /// follows paused time in tests.
+    ///
+    /// # Arguments
+    /// * `mapping` - The FixedMapping, wrapped in an Arc.
+    /// * `cycle_period` - How often to cycle the mapping. If specified, must be greater than the
+    ///   mapping's period.
+    ///
+    /// # Panics
+    /// Panics if called outside of a tokio runtime.
+    pub fn start_task(mapping: Arc<Self>, cycle_period: Option<Duration>) -> CleanupTask {
+        if let Some(cycle_period) = cycle_period {
+            mapping.set_cycle_period(cycle_period);
+        }
+        spawn(
+            &mapping,
+            Self::cycle_period,
+            Self::cycle_or_warn,
+            future::pending(),
+            None,
+        )
+    }
+
+    /// Like `FixedMapping::start_task`, but the task also exits once `shutdown` completes,
+    /// such as `CancellationToken::cancelled_owned` from `tokio_util`. A cycle that has already
+    /// started is finished first, and if `final_cleanup` is set, `FixedMapping::cleanup` is
+    /// called once more before exiting. Wait for that with `CleanupTask::stopped`.
+    ///
+    /// # Arguments
+    /// * `mapping` - The FixedMapping, wrapped in an Arc.
+    /// * `shutdown` - A future that completes when the task should exit.
+    /// * `final_cleanup` - Whether to clean the mapping up one last time before exiting.
+    ///
+    /// # Panics
+    /// Panics if called outside of a tokio runtime.
+    pub fn start_task_until(
+        mapping: Arc<Self>,
+        shutdown: impl Future<Output = ()> + Send + 'static,
+        final_cleanup: bool,
+    ) -> CleanupTask {
+        let drain: Option<fn(&Self)> = final_cleanup.then_some(|mapping| {
+            mapping.cleanup(None);
+        });
+        spawn(
+            &mapping,
+            Self::cycle_period,
+            Self::cycle_or_warn,
+            shutdown,
+            drain,
+        )
+    }
+}
+
+impl<K, L, C, S> DynamicMapping<K, L, C, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    L: RateLimiter + Send + Sync + 'static,
+    C: Clock + Send + Sync + 'static,
+    S: BuildHasher + Clone + Send + Sync + 'static,
+{
+    /// Like `DynamicMapping::start`, but cycles the mapping from a tokio task instead of a
+    /// thread. See `FixedMapping::start_task`.
+    ///
+    /// # Arguments
+    /// * `mapping` - The DynamicMapping, wrapped in an Arc.
+    ///
+    /// # Panics
+    /// Panics if called outside of a tokio runtime.
+    pub fn start_task(mapping: Arc<Self>) -> CleanupTask {
+        spawn(
+            &mapping,
+            Self::cycle_period,
+            |mapping| {
+                mapping.cycle();
+            },
+            future::pending(),
+            None,
+        )
+    }
+
+    /// Like `DynamicMapping::start_task`, but the task also exits once `shutdown` completes.
+    /// See `FixedMapping::start_task_until`.
+    ///
+    /// # Arguments
+    /// * `mapping` - The DynamicMapping, wrapped in an Arc.
+    /// * `shutdown` - A future that completes when the task should exit.
+    /// * `final_cleanup` - Whether to clean the mapping up one last time before exiting.
+    ///
+    /// # Panics
+    /// Panics if called outside of a tokio runtime.
+    pub fn start_task_until(
+        mapping: Arc<Self>,
+        shutdown: impl Future<Output = ()> + Send + 'static,
+        final_cleanup: bool,
+    ) -> CleanupTask {
+        let drain: Option<fn(&Self)> = final_cleanup.then_some(|mapping| {
+            mapping.cleanup(None);
+        });
+        spawn(
+            &mapping,
+            Self::cycle_period,
+            |mapping| {
+                mapping.cycle();
+            },
+            shutdown,
+            drain,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use tokio::time::{sleep, timeout};
+
+    use crate::{DynamicMapping, FixedMapping};
+
+    #[tokio::test]
+    async fn task_exits_when_the_mapping_is_dropped() {
+        let mapping = Arc::new(FixedMapping::<u64>::new(1, Duration::from_millis(10)));
+        let fixed = FixedMapping::start_task(mapping.clone(), None);
+        drop(mapping);
+
+        let mapping = Arc::new(DynamicMapping::<u64>::new(Duration::from_millis(10)));
+        let dynamic = DynamicMapping::start_task(mapping.clone());
+        drop(mapping);
+
+        timeout(Duration::from_secs(5), async {
+            while !fixed.is_finished() || !dynamic.is_finished() {
+                sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn shutdown_runs_a_final_cleanup() {
+        use tokio::sync::oneshot;
+
+        use crate::ManualClock;
+
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(3600);
+        for final_cleanup in [false, true] {
+            let mapping = Arc::new(FixedMapping::with_clock(1, period, clock.clone()));
+            let (shutdown, signal) = oneshot::channel::<()>();
+            let signal = async move {
+                let _ = signal.await;
+            };
+            let task = FixedMapping::start_task_until(mapping.clone(), signal, final_cleanup);
+
+            mapping.trigger(&1);
+            clock.advance(period);
+            shutdown.send(()).unwrap();
+            timeout(Duration::from_secs(5), task.stopped())
+                .await
+                .unwrap();
+            assert_eq!(mapping.is_empty(), final_cleanup);
+        }
+    }
+}
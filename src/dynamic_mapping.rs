@@ -1,21 +1,80 @@
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::sync::Arc;
 use std::{
-    hash::Hash,
-    sync::Arc,
-    thread::{self, sleep},
+    borrow::Borrow,
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hash},
+    sync::atomic::{AtomicU64, Ordering},
     time::Duration,
 };
 
-use dashmap::mapref::one::RefMut;
+use dashmap::DashMap;
 
-use crate::{mapping::Mapping, JumpingWindow};
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use crate::cleanup::CleanupHandle;
+#[cfg(feature = "tokio")]
+use crate::wait_queue::WaitQueues;
+use crate::{
+    clock::Instant,
+    error::validate,
+    mapping::{nanos, Mapping},
+    mode::ModeCell,
+    warmup::SharedWarmup,
+    Algorithm, Clock, EnforcementMode, InvalidWindow, Jitter, JumpingWindow, Limiter, MappingStats,
+    MonotonicClock, Penalty, RateLimitInfo, RateLimited, RateLimiter, UtilizationStats, Warmup,
+};
 
 /// Similar to `floodgate::FixedMapping`, except that each cooldown can have
 /// a different capacity and/or period.
 ///
+/// Like `floodgate::FixedMapping`, the time is read from a `floodgate::Clock`. See
+/// `DynamicMapping::with_clock`.
+///
+/// A mapping can also be given a default rate, or a policy computing each key's rate, for keys
+/// that are triggered without one. See `DynamicMapping::with_default_rate` and
+/// `DynamicMapping::with_policy`.
+///
+/// Methods taking a key accept any borrowed form of it, like those of `floodgate::FixedMapping`.
+///
 /// For some method documentation, please see `floodgate::JumpingWindow`.
-pub struct DynamicMapping<K: Eq + Hash + Clone + Send + Sync + 'static> {
-    mapping: Mapping<K>,
-    cycle_period: Duration,
+pub struct DynamicMapping<
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    L = JumpingWindow,
+    C = MonotonicClock,
+    S = RandomState,
+> {
+    mapping: Mapping<K, L, S>,
+    #[cfg(feature = "tokio")]
+    pub(crate) waiters: WaitQueues<K>,
+    cycle_period: AtomicU64,
+    default_rate: Option<(u64, Duration)>,
+    #[allow(clippy::type_complexity)]
+    policy: Option<Box<dyn Fn(&K) -> (u64, Duration) + Send + Sync>>,
+    rates: DashMap<K, (u64, Duration), S>,
+    mode: ModeCell,
+    name: Option<String>,
+    warmup: Option<SharedWarmup>,
+    clock: C,
+}
+
+/// The rate of a key in a `floodgate::DynamicMapping`, returned by `DynamicMapping::get_rate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyRate {
+    /// The key was given its own capacity and period.
+    Explicit(u64, Duration),
+    /// The key uses the mapping's default rate, or the rate its policy returned.
+    Default(u64, Duration),
+}
+
+impl KeyRate {
+    /// The capacity and period, whether they are explicit or not.
+    pub fn rate(self) -> (u64, Duration) {
+        match self {
+            Self::Explicit(capacity, period) | Self::Default(capacity, period) => {
+                (capacity, period)
+            }
+        }
+    }
 }
 
 impl<K: Eq + Hash + Clone + Send + Sync + 'static> DynamicMapping<K> {
@@ -23,59 +82,1342 @@ impl<K: Eq + Hash + Clone + Send + Sync + 'static> DynamicMapping<K> {
     ///
     /// # Arguments
     /// * `cycle_period` - How often to cycle the mapping. Must be greater than
-    /// the period of any cooldown this mapping contains.
+    ///   the period of any cooldown this mapping contains.
+    ///
+    /// # Panics
+    /// Panics if `cycle_period` is zero. See `DynamicMapping::try_new`.
     pub fn new(cycle_period: Duration) -> Self {
+        Self::with_limiter(cycle_period)
+    }
+
+    /// Create a new DynamicMapping, returning an error if `cycle_period` is zero.
+    ///
+    /// # Arguments
+    /// * `cycle_period` - How often to cycle the mapping. Must be greater than
+    ///   the period of any cooldown this mapping contains.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{DynamicMapping, InvalidWindow};
+    /// use std::time::Duration;
+    ///
+    /// let result = DynamicMapping::<u64>::try_new(Duration::ZERO);
+    /// assert_eq!(result.err(), Some(InvalidWindow::ZeroPeriod));
+    /// ```
+    pub fn try_new(cycle_period: Duration) -> Result<Self, InvalidWindow> {
+        if cycle_period.is_zero() {
+            return Err(InvalidWindow::ZeroPeriod);
+        }
+        Ok(Self::with_limiter(cycle_period))
+    }
+
+    /// Create a new DynamicMapping with a name. See `floodgate::FixedMapping::named`.
+    ///
+    /// # Arguments
+    /// * `name` - The name of the mapping.
+    /// * `cycle_period` - How often to cycle the mapping. Must be greater than
+    ///   the period of any cooldown this mapping contains.
+    ///
+    /// # Panics
+    /// Panics if `cycle_period` is zero.
+    pub fn named(name: impl Into<String>, cycle_period: Duration) -> Self {
+        let mut mapping = Self::new(cycle_period);
+        let name = name.into();
+        #[cfg(feature = "metrics")]
+        mapping.mapping.enable_metrics(&name);
+        mapping.name = Some(name);
+        mapping
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync + 'static, C: Clock> DynamicMapping<K, JumpingWindow, C> {
+    /// Create a new DynamicMapping that reads the time from `clock`.
+    ///
+    /// # Arguments
+    /// * `cycle_period` - How often to cycle the mapping. Must be greater than
+    ///   the period of any cooldown this mapping contains.
+    /// * `clock` - The clock to read the time from.
+    ///
+    /// # Panics
+    /// Panics if `cycle_period` is zero.
+    pub fn with_clock(cycle_period: Duration, clock: C) -> Self {
+        Self::from_parts(cycle_period, clock, 0, RandomState::new())
+    }
+}
+
+impl<K, S> DynamicMapping<K, JumpingWindow, MonotonicClock, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    S: BuildHasher + Clone,
+{
+    /// Create a new DynamicMapping that hashes keys with `hasher`. See
+    /// `floodgate::FixedMapping::with_hasher`.
+    ///
+    /// # Arguments
+    /// * `cycle_period` - How often to cycle the mapping. Must be greater than
+    ///   the period of any cooldown this mapping contains.
+    /// * `hasher` - The hasher to hash keys with.
+    ///
+    /// # Panics
+    /// Panics if `cycle_period` is zero.
+    pub fn with_hasher(cycle_period: Duration, hasher: S) -> Self {
+        Self::from_parts(cycle_period, MonotonicClock, 0, hasher)
+    }
+
+    /// Create a new DynamicMapping that hashes keys with `hasher`, and has room for
+    /// `map_capacity` keys before reallocating. See
+    /// `floodgate::FixedMapping::with_capacity_and_hasher`.
+    ///
+    /// # Arguments
+    /// * `cycle_period` - How often to cycle the mapping. Must be greater than
+    ///   the period of any cooldown this mapping contains.
+    /// * `map_capacity` - How many keys to allocate room for.
+    /// * `hasher` - The hasher to hash keys with.
+    ///
+    /// # Panics
+    /// Panics if `cycle_period` is zero.
+    pub fn with_capacity_and_hasher(
+        cycle_period: Duration,
+        map_capacity: usize,
+        hasher: S,
+    ) -> Self {
+        Self::from_parts(cycle_period, MonotonicClock, map_capacity, hasher)
+    }
+}
+
+impl<K, C, S> DynamicMapping<K, JumpingWindow, C, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    /// Extend the window of a key whenever one of its triggers is rejected. See
+    /// `floodgate::FixedMapping::punitive`.
+    pub fn punitive(mut self, penalty: Penalty) -> Self {
+        self.mapping
+            .add_configure(move |window: &mut JumpingWindow| window.set_penalty(Some(penalty)));
+        self
+    }
+
+    /// Carry the unused tokens of a key over into its next window. See
+    /// `floodgate::FixedMapping::carry_over`.
+    pub fn carry_over(mut self, burst: u64) -> Self {
+        self.mapping
+            .add_configure(move |window: &mut JumpingWindow| window.set_carry_over(Some(burst)));
+        self
+    }
+
+    /// Randomize the length of each key's windows. See `floodgate::FixedMapping::jittered`.
+    pub fn jittered(mut self, jitter: Jitter) -> Self {
+        let windows = AtomicU64::new(0);
+        self.mapping
+            .add_configure(move |window: &mut JumpingWindow| {
+                let n = windows.fetch_add(1, Ordering::Relaxed);
+                window.set_jitter(Some(jitter.fork(n)));
+            });
+        self
+    }
+}
+
+impl<K, C, S> DynamicMapping<K, Limiter, C, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    /// Like `DynamicMapping::add_key`, but also picks the algorithm of `key`'s limiters. Keys
+    /// that aren't added this way use `floodgate::JumpingWindow`.
+    ///
+    /// If `key` already has a limiter using another algorithm, it is replaced by a new one.
+    /// The algorithm is kept until `DynamicMapping::remove` is called for `key`.
+    ///
+    /// # Arguments
+    /// * `key` - The key to add.
+    /// * `algorithm` - The algorithm for `key`.
+    /// * `capacity` - The capacity for `key`.
+    /// * `period` - The period for `key`.
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `period` is zero.
+    pub fn add_limiter(&self, key: K, algorithm: Algorithm, capacity: u64, period: Duration) {
+        if let Err(err) = validate(capacity, period) {
+            panic!("{err}");
+        }
+
+        self.mapping
+            .set_template(key.clone(), algorithm.constructor());
+        let current = self
+            .mapping
+            .with_existing(&key, |limiter| limiter.algorithm());
+        if current.is_some_and(|current| current != algorithm) {
+            self.mapping.remove(&key);
+            #[cfg(feature = "tokio")]
+            self.waiters.notify_reset(&key);
+        }
+        self.add_key(key, capacity, period);
+    }
+
+    /// The algorithm of `key`'s limiter, or `None` if it has none.
+    ///
+    /// # Arguments
+    /// * `key` - The key to look up.
+    pub fn algorithm<Q>(&self, key: &Q) -> Option<Algorithm>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.mapping
+            .with_existing(key, |limiter| limiter.algorithm())
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync + 'static, L: RateLimiter> DynamicMapping<K, L> {
+    /// Create a new DynamicMapping using `L` as the limiter for each key.
+    ///
+    /// # Arguments
+    /// * `cycle_period` - How often to cycle the mapping. Must be greater than
+    ///   the period of any cooldown this mapping contains.
+    ///
+    /// # Panics
+    /// Panics if `cycle_period` is zero.
+    pub fn with_limiter(cycle_period: Duration) -> Self {
+        Self::from_parts(cycle_period, MonotonicClock, 0, RandomState::new())
+    }
+}
+
+impl<K, L, C, S> DynamicMapping<K, L, C, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    L: RateLimiter,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    fn from_parts(cycle_period: Duration, clock: C, map_capacity: usize, hasher: S) -> Self {
+        assert!(!cycle_period.is_zero(), "{}", InvalidWindow::ZeroPeriod);
+
+        let now = clock.now();
         Self {
-            mapping: Mapping::new(cycle_period),
-            cycle_period,
+            #[cfg(feature = "tokio")]
+            waiters: WaitQueues::new(),
+            mapping: Mapping::with_capacity_and_hasher(
+                cycle_period,
+                now,
+                map_capacity,
+                hasher.clone(),
+            ),
+            cycle_period: AtomicU64::new(nanos(cycle_period)),
+            default_rate: None,
+            policy: None,
+            rates: DashMap::with_hasher(hasher),
+            mode: ModeCell::default(),
+            name: None,
+            warmup: None,
+            clock,
+        }
+    }
+
+    /// The clock the mapping reads the time from.
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    /// The name the mapping was created with. See `DynamicMapping::named`.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// How often the mapping is cycled.
+    pub fn cycle_period(&self) -> Duration {
+        Duration::from_nanos(self.cycle_period.load(Ordering::Relaxed))
+    }
+
+    /// Change how often the mapping is cycled. A running cycler picks up the new cycle period
+    /// after its current wait.
+    ///
+    /// # Arguments
+    /// * `cycle_period` - How often to cycle the mapping. Must be greater than the period of
+    ///   any cooldown this mapping contains.
+    ///
+    /// # Panics
+    /// Panics if `cycle_period` is zero.
+    pub fn set_cycle_period(&self, cycle_period: Duration) {
+        assert!(!cycle_period.is_zero(), "{}", InvalidWindow::ZeroPeriod);
+        self.cycle_period
+            .store(nanos(cycle_period), Ordering::Relaxed);
+        self.mapping.set_cycle_period(cycle_period);
+    }
+
+    /// How many periods a key has to go unused before its limiter is dropped. See
+    /// `DynamicMapping::set_idle_periods`.
+    pub fn idle_periods(&self) -> u32 {
+        self.mapping.idle_periods()
+    }
+
+    /// Keep each key's limiter until it has gone unused for `periods` times that key's own
+    /// period, so keys with longer periods are kept for longer. Defaults to 0. See
+    /// `floodgate::FixedMapping::set_idle_periods`.
+    ///
+    /// # Arguments
+    /// * `periods` - The idle time to keep limiters for, in multiples of each key's period.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::DynamicMapping;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let mapping = DynamicMapping::new(Duration::from_secs(600));
+    /// mapping.set_idle_periods(2);
+    /// mapping.trigger(&1, 1, Duration::from_secs(10));
+    /// mapping.trigger(&2, 1, Duration::from_secs(60));
+    ///
+    /// let later = Instant::now() + Duration::from_secs(30);
+    /// assert_eq!(mapping.cleanup(Some(later)), 1);
+    /// assert!(mapping.contains_key(&2));
+    /// ```
+    pub fn set_idle_periods(&self, periods: u32) {
+        self.mapping.set_idle_periods(periods);
+    }
+
+    /// Bound the number of keys the mapping stores a limiter for, dropping the least recently
+    /// used limiters to make room. See `floodgate::FixedMapping::max_keys`.
+    ///
+    /// # Arguments
+    /// * `max_keys` - The most keys to store a limiter for.
+    ///
+    /// # Panics
+    /// Panics if `max_keys` is zero.
+    pub fn max_keys(mut self, max_keys: usize) -> Self {
+        assert!(max_keys > 0, "max_keys must be greater than zero");
+        self.mapping.set_max_keys(max_keys);
+        self
+    }
+
+    /// How many limiters have been dropped to stay under the bound set with
+    /// `DynamicMapping::max_keys`.
+    pub fn forced_evictions(&self) -> u64 {
+        self.mapping.forced_evictions()
+    }
+
+    /// Keep counters of the mapping's triggers, new keys and evictions, read with
+    /// `DynamicMapping::stats`. See `floodgate::FixedMapping::with_stats`.
+    pub fn with_stats(mut self) -> Self {
+        self.mapping.enable_stats();
+        self
+    }
+
+    /// Ramp the capacity of every key up from a fraction of its rate, starting now. See
+    /// `floodgate::FixedMapping::slow_start`.
+    pub fn slow_start(mut self, warmup: Warmup) -> Self {
+        self.warmup = Some(SharedWarmup::new(warmup, self.clock.now()));
+        self
+    }
+
+    /// Start the warm-up set with `DynamicMapping::slow_start` again from now. See
+    /// `floodgate::FixedMapping::begin_warmup`.
+    pub fn begin_warmup(&self) {
+        if let Some(warmup) = &self.warmup {
+            warmup.begin(self.clock.now());
+        }
+    }
+
+    /// The mapping's counters. See `floodgate::MappingStats`.
+    pub fn stats(&self) -> MappingStats {
+        let dry_run_rejected = self.mode.dry_run_rejections();
+        match self.mapping.counters() {
+            Some(counters) => counters.snapshot(dry_run_rejected),
+            None => MappingStats {
+                dry_run_rejected,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Set every counter of `DynamicMapping::stats` back to zero.
+    pub fn reset_stats(&self) {
+        if let Some(counters) = self.mapping.counters() {
+            counters.reset();
+        }
+        self.mode.reset_dry_run_rejections();
+    }
+
+    /// How many keys the mapping is storing a limiter for. See `floodgate::FixedMapping::len`.
+    pub fn len(&self) -> usize {
+        self.mapping.len()
+    }
+
+    /// Whether the mapping isn't storing any limiters.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the mapping is storing a limiter for `key`, without creating one. See
+    /// `floodgate::FixedMapping::contains_key`.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.mapping.contains_key(key)
+    }
+
+    /// Give keys without a rate of their own a default capacity and period, so that they can
+    /// be used with `DynamicMapping::trigger_key`.
+    ///
+    /// # Arguments
+    /// * `capacity` - The default capacity.
+    /// * `period` - The default period.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{DynamicMapping, KeyRate};
+    /// use std::time::Duration;
+    ///
+    /// let period = Duration::from_secs(60);
+    /// let mapping = DynamicMapping::new(period).with_default_rate(1, period);
+    /// mapping.add_key(2, 5, period);
+    ///
+    /// assert!(mapping.trigger_key(&1).is_none());
+    /// assert!(mapping.trigger_key(&1).is_some());
+    /// assert!(mapping.trigger_key(&2).is_none());
+    /// assert!(mapping.trigger_key(&2).is_none());
+    ///
+    /// assert_eq!(mapping.get_rate(&1), Some(KeyRate::Default(1, period)));
+    /// assert_eq!(mapping.get_rate(&2), Some(KeyRate::Explicit(5, period)));
+    /// assert_eq!(mapping.get_rate(&3), Some(KeyRate::Default(1, period)));
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `period` is invalid for `L`. See
+    /// `DynamicMapping::try_with_default_rate`.
+    pub fn with_default_rate(self, capacity: u64, period: Duration) -> Self {
+        self.try_with_default_rate(capacity, period)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Like `DynamicMapping::with_default_rate`, but returns an error if `capacity` or
+    /// `period` is invalid for `L`, such as a period that isn't a whole number of milliseconds
+    /// for `floodgate::AtomicJumpingWindow`.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{DynamicMapping, InvalidWindow};
+    /// use std::time::Duration;
+    ///
+    /// let result = DynamicMapping::<u64>::try_new(Duration::from_secs(60))
+    ///     .and_then(|mapping| mapping.try_with_default_rate(0, Duration::from_secs(10)));
+    /// assert_eq!(result.err(), Some(InvalidWindow::ZeroCapacity));
+    /// ```
+    pub fn try_with_default_rate(
+        mut self,
+        capacity: u64,
+        period: Duration,
+    ) -> Result<Self, InvalidWindow> {
+        L::validate(capacity, period)?;
+        self.default_rate = Some((capacity, period));
+        Ok(self)
+    }
+
+    /// The default capacity and period, if the mapping has one. See
+    /// `DynamicMapping::with_default_rate`.
+    pub fn default_rate(&self) -> Option<(u64, Duration)> {
+        self.default_rate
+    }
+
+    /// Compute the rate of keys without a rate of their own with `policy`, instead of using a
+    /// single default rate. If the mapping also has a default rate, the policy takes priority.
+    ///
+    /// The policy is only called when a key is first used, or used again after its limiter was
+    /// dropped; the rate it returns is kept in the key's limiter. To call it again for a key
+    /// that has a limiter, use `DynamicMapping::invalidate`.
+    ///
+    /// Policies aren't serialized, so they have to be set again after deserializing.
+    ///
+    /// # Arguments
+    /// * `policy` - Returns the capacity and period for a key.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::DynamicMapping;
+    /// use std::time::Duration;
+    ///
+    /// let period = Duration::from_secs(60);
+    /// let mapping = DynamicMapping::new(period).with_policy(move |user: &u64| match user {
+    ///     1 => (2, period),
+    ///     _ => (1, period),
+    /// });
+    ///
+    /// assert!(mapping.trigger_key(&1).is_none());
+    /// assert!(mapping.trigger_key(&1).is_none());
+    /// assert!(mapping.trigger_key(&2).is_none());
+    /// assert!(mapping.trigger_key(&2).is_some());
+    /// ```
+    pub fn with_policy(
+        mut self,
+        policy: impl Fn(&K) -> (u64, Duration) + Send + Sync + 'static,
+    ) -> Self {
+        self.policy = Some(Box::new(policy));
+        self
+    }
+
+    /// Call the policy for `key` again, and update its limiter to the new rate like with
+    /// `DynamicMapping::update_rate`. Returns whether `key` had a limiter to update.
+    ///
+    /// Keys given a rate with `DynamicMapping::add_key` keep that rate, so nothing is done for
+    /// them.
+    ///
+    /// # Arguments
+    /// * `key` - The key to re-evaluate.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::DynamicMapping;
+    /// use std::{
+    ///     sync::atomic::{AtomicU64, Ordering},
+    ///     sync::Arc,
+    ///     time::Duration,
+    /// };
+    ///
+    /// let period = Duration::from_secs(60);
+    /// let tier = Arc::new(AtomicU64::new(1));
+    /// let mapping = DynamicMapping::new(period).with_policy({
+    ///     let tier = tier.clone();
+    ///     move |_: &u64| (tier.load(Ordering::Relaxed), period)
+    /// });
+    /// mapping.trigger_key(&1);
+    /// assert!(mapping.trigger_key(&1).is_some());
+    ///
+    /// tier.store(5, Ordering::Relaxed);
+    /// assert!(mapping.invalidate(&1));
+    /// assert_eq!(mapping.tokens(&1, 5, period), 4);
+    /// ```
+    pub fn invalidate<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        if self.rates.contains_key(key) {
+            return false;
+        }
+        match self.fallback_rate(key) {
+            Some((capacity, period)) => self.rescale(key, capacity, period),
+            None => false,
+        }
+    }
+
+    /// The rate for a key without a rate of its own: the policy's, or else the default.
+    fn fallback_rate<Q>(&self, key: &Q) -> Option<(u64, Duration)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        match &self.policy {
+            Some(policy) => {
+                let (capacity, period) = policy(&key.to_owned());
+                if let Err(err) = L::validate(capacity, period) {
+                    panic!("the policy returned an invalid rate: {err}");
+                }
+                Some((capacity, period))
+            }
+            None => self.default_rate,
+        }
+    }
+
+    /// Give `key` its own capacity and period, to be used by `DynamicMapping::trigger_key`
+    /// instead of the default rate. If `key` already has a limiter, it is updated like with
+    /// `DynamicMapping::update_rate`.
+    ///
+    /// The rate is kept after the limiter is cycled out, until `DynamicMapping::remove` is
+    /// called for `key`.
+    ///
+    /// # Arguments
+    /// * `key` - The key to add.
+    /// * `capacity` - The capacity for `key`.
+    /// * `period` - The period for `key`.
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `period` is invalid for `L`. See `DynamicMapping::try_add_key`.
+    pub fn add_key(&self, key: K, capacity: u64, period: Duration) {
+        self.try_add_key(key, capacity, period)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Like `DynamicMapping::add_key`, but returns an error instead of registering the rate if
+    /// `capacity` or `period` is invalid for `L`.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{AtomicJumpingWindow, DynamicMapping, InvalidWindow};
+    /// use std::time::Duration;
+    ///
+    /// let mapping = DynamicMapping::<u64, AtomicJumpingWindow>::with_limiter(Duration::from_secs(60));
+    /// let result = mapping.try_add_key(1, 5, Duration::from_micros(1500));
+    /// assert!(matches!(result, Err(InvalidWindow::PeriodResolution { .. })));
+    /// assert_eq!(mapping.get_rate(&1), None);
+    /// ```
+    pub fn try_add_key(
+        &self,
+        key: K,
+        capacity: u64,
+        period: Duration,
+    ) -> Result<(), InvalidWindow> {
+        L::validate(capacity, period)?;
+        self.update_rate(&key, capacity, period);
+        self.rates.insert(key, (capacity, period));
+        Ok(())
+    }
+
+    /// The rate of `key`: the rate it was given with `DynamicMapping::add_key`, or else the
+    /// rate of its limiter, or else the policy's or default rate. Returns `None` if none of
+    /// those exist.
+    ///
+    /// The rate of a limiter is reported as the default if it is the mapping's default rate.
+    /// With a policy, it is always reported as the default: the limiter holds the rate the
+    /// policy returned for it, so the policy is only called for keys without a limiter.
+    ///
+    /// # Arguments
+    /// * `key` - The key to look up.
+    pub fn get_rate<Q>(&self, key: &Q) -> Option<KeyRate>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        if let Some(rate) = self.rates.get(key) {
+            let (capacity, period) = *rate;
+            return Some(KeyRate::Explicit(capacity, period));
+        }
+
+        let current = self
+            .mapping
+            .with_existing(key, |bucket| (bucket.capacity(), bucket.period()));
+        match current {
+            Some(rate) if self.policy.is_some() || self.default_rate == Some(rate) => {
+                Some(KeyRate::Default(rate.0, rate.1))
+            }
+            Some((capacity, period)) => Some(KeyRate::Explicit(capacity, period)),
+            None => self
+                .fallback_rate(key)
+                .map(|(capacity, period)| KeyRate::Default(capacity, period)),
+        }
+    }
+
+    /// How the mapping treats triggers. See `floodgate::EnforcementMode`.
+    pub fn mode(&self) -> EnforcementMode {
+        self.mode.get()
+    }
+
+    /// Switch how the mapping treats triggers. See `floodgate::FixedMapping::set_mode`.
+    ///
+    /// # Arguments
+    /// * `mode` - The new mode.
+    pub fn set_mode(&self, mode: EnforcementMode) {
+        self.mode.set(mode);
+    }
+
+    /// How many triggers were let through in `EnforcementMode::DryRun` that would otherwise
+    /// have been rejected.
+    pub fn dry_run_rejections(&self) -> u64 {
+        self.mode.dry_run_rejections()
+    }
+
+    /// Trigger the cooldown for `key` at its current rate, without having to pass one. See
+    /// `DynamicMapping::get_rate` for how the rate is chosen.
+    ///
+    /// # Arguments
+    /// * `key` - The key to trigger.
+    ///
+    /// # Panics
+    /// Panics if `key` has no rate, which can't happen if the mapping has a default rate or a
+    /// policy.
+    pub fn trigger_key<Q>(&self, key: &Q) -> Option<Duration>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let (capacity, period) = match self.resolve_rate(key) {
+            Some(rate) => rate,
+            None => panic!("the key has no rate, and the mapping has no default rate"),
+        };
+        self.trigger(key, capacity, period)
+    }
+
+    /// Like `DynamicMapping::get_rate`, but only calls the policy if `key` has no limiter.
+    fn resolve_rate<Q>(&self, key: &Q) -> Option<(u64, Duration)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        if let Some(rate) = self.rates.get(key) {
+            return Some(*rate);
+        }
+        self.mapping
+            .with_existing(key, |bucket| (bucket.capacity(), bucket.period()))
+            .or_else(|| self.fallback_rate(key))
+    }
+
+    /// Change the capacity and period of the limiter for `key`, keeping how many tokens it has
+    /// used in its current window. If `key` was given a rate with `DynamicMapping::add_key`,
+    /// that rate is changed too. Returns `false`, without creating a limiter, if `key` has
+    /// neither.
+    ///
+    /// If the current window has already lasted longer than the new period, it is reset.
+    /// Otherwise, the tokens used so far are taken out of the new capacity, leaving none if
+    /// more were used than the new capacity allows.
+    ///
+    /// Like any other change of rate, this only lasts until `key` is used with a different
+    /// capacity or period.
+    ///
+    /// # Arguments
+    /// * `key` - The key to update.
+    /// * `capacity` - The new capacity.
+    /// * `period` - The new period.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::DynamicMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = DynamicMapping::new(Duration::from_secs(60));
+    /// let period = Duration::from_secs(10);
+    /// mapping.trigger(&1, 5, period);
+    ///
+    /// assert!(mapping.update_rate(&1, 3, period));
+    /// assert_eq!(mapping.get_rate(&1).map(|rate| rate.rate()), Some((3, period)));
+    /// assert_eq!(mapping.tokens(&1, 3, period), 2);
+    ///
+    /// assert!(!mapping.update_rate(&2, 3, period));
+    /// assert_eq!(mapping.get_rate(&2), None);
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `period` is invalid for `L`.
+    pub fn update_rate<Q>(&self, key: &Q, capacity: u64, period: Duration) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if let Err(err) = L::validate(capacity, period) {
+            panic!("{err}");
+        }
+
+        let registered = match self.rates.get_mut(key) {
+            Some(mut rate) => {
+                *rate = (capacity, period);
+                true
+            }
+            None => false,
+        };
+
+        let updated = self.rescale(key, capacity, period);
+        registered || updated
+    }
+
+    /// Change the rate of the limiter for `key` if it has one, keeping the tokens it has used.
+    /// See `DynamicMapping::update_rate`.
+    fn rescale<Q>(&self, key: &Q, capacity: u64, period: Duration) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = Some(self.clock.now());
+        self.mapping
+            .with_existing(key, |bucket| {
+                // change the period first, so that a window that has already ended is reset
+                // rather than carried over.
+                bucket.set_rate(bucket.capacity(), period);
+                let used = bucket.capacity() - bucket.tokens(now);
+
+                bucket.set_rate(capacity, period);
+                let excess = bucket
+                    .tokens(now)
+                    .saturating_sub(capacity.saturating_sub(used));
+                if excess > 0 {
+                    // can't fail, the limiter has at least `excess` tokens.
+                    let _ = bucket.trigger_n(excess, now);
+                }
+            })
+            .is_some()
+    }
+
+    /// Whether a trigger made in `mode` should be rejected, given whether the limiter
+    /// `rejected` it, counting it in the mapping's stats.
+    fn rejects(&self, mode: EnforcementMode, rejected: bool) -> bool {
+        self.mode.rejects(mode, rejected, self.mapping.counters())
+    }
+
+    /// Run `f` on the limiter for `key`, with the current time from the mapping's clock.
+    fn with_bucket<Q, T>(
+        &self,
+        key: &Q,
+        capacity: u64,
+        period: Duration,
+        f: impl FnOnce(&mut L, Option<Instant>) -> T,
+    ) -> T
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        debug_assert!(period <= self.cycle_period());
+        let now = self.clock.now();
+        let capacity = match &self.warmup {
+            Some(warmup) => warmup.capacity(capacity, now),
+            None => capacity,
+        };
+        let mut bucket = self.mapping.get_bucket(key, capacity, period, now);
+        f(&mut bucket, Some(now))
+    }
+
+    pub fn tokens<Q>(&self, key: &Q, capacity: u64, period: Duration) -> u64
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.with_bucket(key, capacity, period, |bucket, now| bucket.tokens(now))
+    }
+
+    pub fn next_reset<Q>(&self, key: &Q, capacity: u64, period: Duration) -> Duration
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.with_bucket(key, capacity, period, |bucket, now| bucket.next_reset(now))
+    }
+
+    pub fn retry_after<Q>(&self, key: &Q, capacity: u64, period: Duration) -> Option<Duration>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        if self.mode.get() != EnforcementMode::Enforce {
+            return None;
+        }
+        self.with_bucket(key, capacity, period, |bucket, now| bucket.retry_after(now))
+    }
+
+    /// How long until `key` can be triggered `n` times at once, or `None` if it never can.
+    /// Nothing is consumed, and no limiter is stored for `key`. See
+    /// `floodgate::FixedMapping::wait_for`.
+    pub fn wait_for<Q>(&self, key: &Q, capacity: u64, period: Duration, n: u64) -> Option<Duration>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.mode.get() != EnforcementMode::Enforce {
+            return Some(Duration::ZERO);
+        }
+        let now = self.clock.now();
+        let wait = self.mapping.with_existing(key, |bucket| {
+            if bucket.capacity() != capacity || bucket.period() != period {
+                bucket.set_rate(capacity, period);
+            }
+            bucket.wait_for(n, Some(now))
+        });
+        // ask the limiter `key` would be given, so that what it carries over counts.
+        wait.unwrap_or_else(|| {
+            let capacity = match &self.warmup {
+                Some(warmup) => warmup.capacity(capacity, now),
+                None => capacity,
+            };
+            self.mapping
+                .new_limiter(key, capacity, period, now)
+                .wait_for(n, Some(now))
+        })
+    }
+
+    pub fn can_trigger<Q>(&self, key: &Q, capacity: u64, period: Duration) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        if self.mode.get() != EnforcementMode::Enforce {
+            return true;
+        }
+        self.with_bucket(key, capacity, period, |bucket, now| bucket.can_trigger(now))
+    }
+
+    pub fn trigger<Q>(&self, key: &Q, capacity: u64, period: Duration) -> Option<Duration>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let mode = self.mode.get();
+        if mode == EnforcementMode::Bypass {
+            return None;
+        }
+        let retry_after =
+            self.with_bucket(key, capacity, period, |bucket, now| bucket.trigger(now));
+        match self.rejects(mode, retry_after.is_some()) {
+            true => retry_after,
+            false => None,
+        }
+    }
+
+    /// Trigger the cooldown for `key`, returning `Err(RateLimited)` with the key if it is on
+    /// cooldown. See `floodgate::JumpingWindow::try_trigger`.
+    pub fn try_trigger<Q>(
+        &self,
+        key: &Q,
+        capacity: u64,
+        period: Duration,
+    ) -> Result<(), RateLimited<K>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        match self.trigger(key, capacity, period) {
+            Some(retry_after) => Err(RateLimited {
+                retry_after,
+                key: key.to_owned(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Trigger the cooldown for `key`, returning the resulting state of its limiter. See
+    /// `floodgate::JumpingWindow::trigger_info`.
+    ///
+    /// Like `floodgate::FixedMapping::trigger_info`, triggers that are only let through because
+    /// of `EnforcementMode::DryRun` are reported as not allowed.
+    pub fn trigger_info<Q>(&self, key: &Q, capacity: u64, period: Duration) -> RateLimitInfo
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let mode = self.mode.get();
+        if mode == EnforcementMode::Bypass {
+            return RateLimitInfo {
+                allowed: true,
+                limit: capacity,
+                remaining: capacity,
+                retry_after: None,
+                reset_after: Duration::ZERO,
+            };
+        }
+        let info = self.with_bucket(key, capacity, period, |bucket, now| {
+            bucket.trigger_info(now)
+        });
+        self.rejects(mode, !info.allowed);
+        info
+    }
+
+    /// Like `trigger`, but returns the time at which `key` can be triggered again instead of
+    /// how long that is from now. See `floodgate::JumpingWindow::retry_at`.
+    pub fn trigger_at<Q>(&self, key: &Q, capacity: u64, period: Duration) -> Option<Instant>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let mode = self.mode.get();
+        if mode == EnforcementMode::Bypass {
+            return None;
+        }
+        let retry_at = self.with_bucket(key, capacity, period, |bucket, now| {
+            // soft rejections leave tokens, so the deadline comes from the trigger itself.
+            let now = now.unwrap_or_else(|| self.clock.now());
+            bucket
+                .trigger(Some(now))
+                .map(|retry_after| now + retry_after)
+        });
+        match self.rejects(mode, retry_at.is_some()) {
+            true => retry_at,
+            false => None,
+        }
+    }
+
+    /// Trigger the cooldown for `key`, consuming `cost` tokens at once. See
+    /// `floodgate::JumpingWindow::trigger_n`.
+    pub fn trigger_n<Q>(
+        &self,
+        key: &Q,
+        capacity: u64,
+        period: Duration,
+        cost: u64,
+    ) -> Result<(), Duration>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let mode = self.mode.get();
+        if mode == EnforcementMode::Bypass {
+            return Ok(());
         }
+        let result = self.with_bucket(key, capacity, period, |bucket, now| {
+            bucket.trigger_n(cost, now)
+        });
+        match self.rejects(mode, result.is_err()) {
+            true => result,
+            false => Ok(()),
+        }
+    }
+
+    /// The state of `key`'s limiter, without consuming a token or creating a limiter. See
+    /// `floodgate::FixedMapping::status`.
+    ///
+    /// # Arguments
+    /// * `key` - The key to check.
+    /// * `now` - Optionally specify the current time, instead of reading the mapping's clock.
+    pub fn status<Q>(&self, key: &Q, now: Option<Instant>) -> Option<RateLimitInfo>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = now.unwrap_or_else(|| self.clock.now());
+        self.mapping.status(key, now)
+    }
+
+    /// The state of each of `keys`, all computed from the same instant. See
+    /// `floodgate::FixedMapping::status`.
+    ///
+    /// # Arguments
+    /// * `keys` - The keys to check.
+    /// * `now` - Optionally specify the current time, instead of reading the mapping's clock.
+    pub fn status_many(&self, keys: &[K], now: Option<Instant>) -> Vec<Option<RateLimitInfo>> {
+        let now = now.unwrap_or_else(|| self.clock.now());
+        keys.iter()
+            .map(|key| self.mapping.status(key, now))
+            .collect()
     }
 
-    fn get_bucket(&self, key: &K, capacity: u64, period: Duration) -> RefMut<K, JumpingWindow> {
-        debug_assert!(period <= self.cycle_period);
-        self.mapping.get_bucket(key, capacity, period)
+    /// The keys that are on cooldown, with the state of their limiters. See
+    /// `floodgate::FixedMapping::iter`.
+    pub fn iter(&self) -> impl Iterator<Item = (K, RateLimitInfo)> {
+        self.mapping.cooldowns(self.clock.now()).into_iter()
     }
 
-    pub fn tokens(&self, key: &K, capacity: u64, period: Duration) -> u64 {
-        self.get_bucket(key, capacity, period).tokens(None)
+    /// How much of their capacity the stored limiters are using, each relative to its own
+    /// rate. See `floodgate::FixedMapping::utilization`.
+    pub fn utilization(&self) -> UtilizationStats {
+        self.mapping.utilization(self.clock.now())
     }
 
-    pub fn next_reset(&self, key: &K, capacity: u64, period: Duration) -> Duration {
-        self.get_bucket(key, capacity, period).next_reset(None)
+    /// Reset the cooldown for `key`, keeping its rate. Resetting a key the mapping isn't
+    /// storing does nothing. See `floodgate::FixedMapping::reset`.
+    pub fn reset<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.clock.now();
+        self.mapping
+            .with_existing(key, |bucket| bucket.reset(Some(now)));
+        #[cfg(feature = "tokio")]
+        self.waiters.notify_reset(key);
     }
 
-    pub fn retry_after(&self, key: &K, capacity: u64, period: Duration) -> Option<Duration> {
-        self.get_bucket(key, capacity, period).retry_after(None)
+    /// Reset the cooldown of every key the mapping is storing.
+    pub fn reset_all(&self) {
+        let now = self.clock.now();
+        self.mapping
+            .for_each_mut(|_, bucket| bucket.reset(Some(now)));
+        #[cfg(feature = "tokio")]
+        self.waiters.notify_reset_all();
     }
 
-    pub fn can_trigger(&self, key: &K, capacity: u64, period: Duration) -> bool {
-        self.get_bucket(key, capacity, period).can_trigger(None)
+    /// Drop the limiter for `key`, along with its rate, including one given with
+    /// `DynamicMapping::add_key`. Returns whether the mapping was storing a limiter for `key`.
+    ///
+    /// The next trigger for `key` starts a fresh window at the capacity and period it is
+    /// given, just like the first trigger for a new key.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::DynamicMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = DynamicMapping::new(Duration::from_secs(60));
+    /// let period = Duration::from_secs(10);
+    /// mapping.trigger(&1, 1, period);
+    ///
+    /// assert!(mapping.remove(&1));
+    /// assert_eq!(mapping.tokens(&1, 3, period), 3);
+    /// ```
+    pub fn remove<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.rates.remove(key);
+        self.mapping.remove_template(key);
+        let removed = self.mapping.remove(key);
+        #[cfg(feature = "tokio")]
+        self.waiters.notify_reset(key);
+        removed
     }
 
-    pub fn trigger(&self, key: &K, capacity: u64, period: Duration) -> Option<Duration> {
-        self.get_bucket(key, capacity, period).trigger(None)
+    /// A number identifying the current window of `key`, to read before triggering it for
+    /// `DynamicMapping::refund`. See `floodgate::JumpingWindow::epoch`.
+    pub fn epoch<Q>(&self, key: &Q, capacity: u64, period: Duration) -> u64
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.with_bucket(key, capacity, period, |bucket, now| bucket.epoch(now))
     }
 
-    pub fn reset(&self, key: &K, capacity: u64, period: Duration) {
-        self.get_bucket(key, capacity, period).reset(None)
+    /// Give back `n` tokens taken from the window of `key` that `epoch` identifies, or nothing
+    /// if it has ended. See `floodgate::JumpingWindow::refund`.
+    pub fn refund<Q>(&self, key: &Q, capacity: u64, period: Duration, n: u64, epoch: u64)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        if self.mode.get() == EnforcementMode::Bypass {
+            return;
+        }
+        self.with_bucket(key, capacity, period, |bucket, now| {
+            bucket.refund(n, epoch, now)
+        })
     }
 
     /// Cycles the mapping. Returns `true` if it cycled, or `false` if not.
     pub fn cycle(&self) -> bool {
-        self.mapping.cycle(None)
+        self.mapping.cycle(self.clock.now())
+    }
+
+    /// Drop the limiters of keys that aren't on cooldown, returning how many were dropped. See
+    /// `floodgate::FixedMapping::cleanup`.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    pub fn cleanup(&self, now: Option<Instant>) -> usize {
+        self.mapping
+            .cleanup(now.unwrap_or_else(|| self.clock.now()))
     }
 
-    /// Start the background cycler. Failing to do this will result in a memory leak.
+    /// Start the background cycler, returning a handle to stop it with. Failing to do this will
+    /// result in a memory leak.
     ///
     /// If, for some reason, you don't want to use the default cycler, you must manually call
     /// the `.cycle` method on the mapping periodically.
     ///
+    /// The cycler only holds a weak reference to the mapping, so it exits once the mapping is
+    /// dropped. See `floodgate::CleanupHandle`.
+    ///
+    /// Not available on `wasm32-unknown-unknown`, which has no threads. There, the mapping
+    /// cycles itself whenever it is used and its cycle period has passed.
+    ///
     /// # Arguments
     /// * `mapping` - The DynamicMapping, wrapped in an Arc.
-    pub fn start(mapping: Arc<Self>) {
-        thread::spawn(move || loop {
-            sleep(mapping.cycle_period);
-            mapping.mapping.cycle(None);
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn start(mapping: Arc<Self>) -> CleanupHandle
+    where
+        L: Send + Sync + 'static,
+        C: Send + Sync + 'static,
+        S: Send + Sync + 'static,
+    {
+        let first = mapping.cycle_period();
+        let mapping = Arc::downgrade(&mapping);
+        CleanupHandle::spawn(first, move || {
+            let mapping = mapping.upgrade()?;
+            mapping.cycle();
+            Some(mapping.cycle_period())
+        })
+    }
+}
+
+/// Serialized as the cycle period and the limiter of each key. The cycler isn't saved, so it
+/// has to be started again after deserializing.
+#[cfg(feature = "serde")]
+impl<K, L, C, H> serde::Serialize for DynamicMapping<K, L, C, H>
+where
+    H: BuildHasher + Clone,
+    K: Eq + Hash + Clone + Send + Sync + 'static + serde::Serialize,
+    L: serde::Serialize,
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("DynamicMapping", 4)?;
+        let cycle_period = Duration::from_nanos(self.cycle_period.load(Ordering::Relaxed));
+        state.serialize_field("cycle_period", &cycle_period)?;
+        state.serialize_field("default_rate", &self.default_rate)?;
+        let rates: Vec<_> = self
+            .rates
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().0, entry.value().1))
+            .collect();
+        state.serialize_field("rates", &rates)?;
+        state.serialize_field("limiters", &self.mapping)?;
+        state.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, K, L> serde::Deserialize<'de> for DynamicMapping<K, L>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + serde::Deserialize<'de>,
+    L: RateLimiter + serde::Deserialize<'de>,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        #[serde(rename = "DynamicMapping")]
+        struct State<K, L> {
+            cycle_period: Duration,
+            #[serde(default)]
+            default_rate: Option<(u64, Duration)>,
+            #[serde(default = "Vec::new")]
+            rates: Vec<(K, u64, Duration)>,
+            limiters: Vec<(K, L)>,
+        }
+
+        let state = State::<K, L>::deserialize(deserializer)?;
+        if state.cycle_period.is_zero() {
+            return Err(serde::de::Error::custom(InvalidWindow::ZeroPeriod));
+        }
+
+        let mut mapping = Self::with_limiter(state.cycle_period);
+        if let Some((capacity, period)) = state.default_rate {
+            L::validate(capacity, period).map_err(serde::de::Error::custom)?;
+            mapping.default_rate = Some((capacity, period));
+        }
+        for (key, capacity, period) in state.rates {
+            L::validate(capacity, period).map_err(serde::de::Error::custom)?;
+            mapping.rates.insert(key, (capacity, period));
+        }
+        for (key, limiter) in state.limiters {
+            mapping.mapping.insert(key, limiter, mapping.clock.now());
+        }
+        Ok(mapping)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{DynamicMapping, KeyRate};
+    use crate::{AtomicJumpingWindow, InvalidWindow, ManualClock};
+
+    #[test]
+    fn update_rate_below_the_used_tokens() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(60);
+        let mapping = DynamicMapping::with_clock(period, clock.clone());
+        for _ in 0..4 {
+            assert_eq!(mapping.trigger(&1, 5, period), None);
+        }
+
+        assert!(mapping.update_rate(&1, 2, period));
+        assert_eq!(mapping.tokens(&1, 2, period), 0);
+        assert!(mapping.trigger(&1, 2, period).is_some());
+
+        clock.advance(period);
+        assert_eq!(mapping.tokens(&1, 2, period), 2);
+    }
+
+    #[test]
+    fn update_rate_resets_windows_longer_than_the_new_period() {
+        let clock = ManualClock::new();
+        let mapping = DynamicMapping::with_clock(Duration::from_secs(60), clock.clone());
+        mapping.trigger(&1, 2, Duration::from_secs(60));
+
+        clock.advance(Duration::from_secs(20));
+        assert!(mapping.update_rate(&1, 4, Duration::from_secs(30)));
+        assert_eq!(mapping.tokens(&1, 4, Duration::from_secs(30)), 3);
+
+        clock.advance(Duration::from_secs(20));
+        assert!(mapping.update_rate(&1, 4, Duration::from_secs(10)));
+        assert_eq!(mapping.tokens(&1, 4, Duration::from_secs(10)), 4);
+    }
+
+    #[test]
+    fn added_rates_outlive_their_limiters() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(60);
+        let mapping =
+            DynamicMapping::with_clock(period, clock.clone()).with_default_rate(1, period);
+        mapping.add_key(1, 2, period);
+        mapping.trigger_key(&1);
+
+        clock.advance(period);
+        assert_eq!(mapping.cleanup(None), 1);
+        assert!(mapping.trigger_key(&1).is_none());
+        assert!(mapping.trigger_key(&1).is_none());
+        assert!(mapping.trigger_key(&1).is_some());
+
+        assert!(mapping.remove(&1));
+        assert_eq!(mapping.get_rate(&1), Some(KeyRate::Default(1, period)));
+    }
+
+    #[test]
+    fn policy_is_called_once_per_limiter() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(60);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mapping = DynamicMapping::with_clock(period, clock.clone()).with_policy({
+            let calls = calls.clone();
+            move |_: &u64| {
+                calls.fetch_add(1, Ordering::Relaxed);
+                (3, period)
+            }
         });
+
+        for _ in 0..3 {
+            assert!(mapping.trigger_key(&1).is_none());
+            assert_eq!(mapping.get_rate(&1), Some(KeyRate::Default(3, period)));
+        }
+        assert!(mapping.trigger_key(&1).is_some());
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+        clock.advance(period);
+        mapping.cleanup(None);
+        mapping.trigger_key(&1);
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn added_algorithms_outlive_their_limiters() {
+        use crate::{clock, Algorithm, Limiter};
+
+        let period = Duration::from_secs(10);
+        let mapping = DynamicMapping::<u64, Limiter>::with_limiter(Duration::from_secs(60));
+        mapping.add_limiter(1, Algorithm::TokenBucket, 2, period);
+        mapping.trigger(&2, 2, period);
+        assert_eq!(mapping.algorithm(&1), None);
+        assert_eq!(mapping.algorithm(&2), Some(Algorithm::Jumping));
+
+        for _ in 0..2 {
+            mapping.trigger_key(&1);
+            mapping.trigger_key(&2);
+        }
+        // a token bucket gives its tokens back one at a time.
+        assert!(mapping.trigger_key(&1).unwrap() <= period / 2);
+        assert!(mapping.trigger_key(&2).unwrap() > period / 2);
+
+        assert_eq!(mapping.cleanup(Some(clock::now() + period)), 2);
+        mapping.trigger_key(&1);
+        assert_eq!(mapping.algorithm(&1), Some(Algorithm::TokenBucket));
+
+        mapping.add_limiter(1, Algorithm::Sliding, 2, period);
+        assert_eq!(mapping.algorithm(&1), None);
+        mapping.trigger_key(&1);
+        assert_eq!(mapping.algorithm(&1), Some(Algorithm::Sliding));
+        assert!(mapping.remove(&1));
+        mapping.trigger(&1, 2, period);
+        assert_eq!(mapping.algorithm(&1), Some(Algorithm::Jumping));
+    }
+
+    #[test]
+    fn rates_are_validated_for_the_limiter() {
+        let period = Duration::from_micros(1500);
+        let mapping =
+            DynamicMapping::<u64, AtomicJumpingWindow>::with_limiter(Duration::from_secs(1));
+        assert!(matches!(
+            mapping.try_add_key(1, 1, period),
+            Err(InvalidWindow::PeriodResolution { .. })
+        ));
+        assert!(matches!(
+            mapping.try_with_default_rate(1, period).err(),
+            Some(InvalidWindow::PeriodResolution { .. })
+        ));
+
+        // the same rate is fine for limiters that keep time in nanoseconds.
+        let mapping = DynamicMapping::<u64>::new(Duration::from_secs(1));
+        assert!(mapping.try_add_key(1, 1, period).is_ok());
+        assert!(mapping.try_with_default_rate(1, period).is_ok());
     }
 }
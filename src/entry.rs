@@ -0,0 +1,69 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hash},
+    ops::{Deref, DerefMut},
+};
+
+use dashmap::mapref::one::MappedRefMut;
+
+use crate::{clock::Instant, mapping::Slot};
+
+/// Exclusive access to the limiter of one key of a `floodgate::FixedMapping`, for combining
+/// several operations on it atomically. Derefs to the limiter, such as a
+/// `floodgate::JumpingWindow`.
+///
+/// The guard holds a lock on the part of the mapping where the key is stored, which other keys
+/// share. Calling any method of the mapping while holding it may deadlock, so drop it first.
+/// For the same reason, the guard can't be sent to another thread, and shouldn't be kept
+/// across `.await` points.
+///
+/// The mapping's mode, lists and hooks don't apply to the limiter's own methods.
+///
+/// Created by `floodgate::FixedMapping::entry`.
+pub struct EntryGuard<'a, K: Eq + Hash, L, S: BuildHasher + Clone = RandomState> {
+    limiter: MappedRefMut<'a, K, Slot<L>, L, S>,
+    now: Instant,
+}
+
+impl<'a, K, L, S> EntryGuard<'a, K, L, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Clone,
+{
+    pub(crate) fn new(limiter: MappedRefMut<'a, K, Slot<L>, L, S>, now: Instant) -> Self {
+        Self { limiter, now }
+    }
+
+    /// The key of the limiter.
+    pub fn key(&self) -> &K {
+        self.limiter.key()
+    }
+
+    /// The time of the mapping's clock when the guard was created. Pass it to the limiter's
+    /// methods, so that they agree with the mapping's clock.
+    pub fn now(&self) -> Option<Instant> {
+        Some(self.now)
+    }
+}
+
+impl<K, L, S> Deref for EntryGuard<'_, K, L, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Clone,
+{
+    type Target = L;
+
+    fn deref(&self) -> &L {
+        &self.limiter
+    }
+}
+
+impl<K, L, S> DerefMut for EntryGuard<'_, K, L, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Clone,
+{
+    fn deref_mut(&mut self) -> &mut L {
+        &mut self.limiter
+    }
+}
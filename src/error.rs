@@ -0,0 +1,311 @@
+use core::fmt;
+#[cfg(feature = "std")]
+use std::{error::Error, time::Duration};
+
+/// An error returned when a window is configured with invalid parameters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidWindow {
+    /// The capacity was zero, so the window could never be triggered.
+    ZeroCapacity,
+    /// The period was zero, which is shorter than the resolution of `Instant`.
+    ZeroPeriod,
+    /// The window was given more initial tokens than its capacity.
+    TooManyTokens { tokens: u64, capacity: u64 },
+    /// The capacity was larger than the limiter can represent.
+    CapacityTooLarge { capacity: u64, max: u64 },
+    /// A `floodgate::MultiWindow` was created without any windows.
+    NoWindows,
+    /// A `floodgate::AdaptiveWindow` was given a floor above its ceiling.
+    FloorAboveCeiling { floor: u64, ceiling: u64 },
+    /// The period wasn't a whole number of the limiter's resolution, such as a whole number
+    /// of milliseconds for a `floodgate::AtomicJumpingWindow`.
+    PeriodResolution {
+        period: core::time::Duration,
+        resolution: core::time::Duration,
+    },
+}
+
+impl fmt::Display for InvalidWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ZeroCapacity => write!(f, "capacity must be greater than zero"),
+            Self::ZeroPeriod => write!(f, "period must be greater than zero"),
+            Self::TooManyTokens { tokens, capacity } => write!(
+                f,
+                "initial tokens ({tokens}) must not exceed the capacity ({capacity})"
+            ),
+            Self::CapacityTooLarge { capacity, max } => {
+                write!(f, "capacity ({capacity}) must not exceed {max}")
+            }
+            Self::NoWindows => write!(f, "at least one window is required"),
+            Self::FloorAboveCeiling { floor, ceiling } => {
+                write!(f, "floor ({floor}) must not exceed the ceiling ({ceiling})")
+            }
+            Self::PeriodResolution { period, resolution } => write!(
+                f,
+                "period ({period:?}) must be a whole multiple of {resolution:?}"
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for InvalidWindow {}
+
+/// Check that `capacity` and `period` describe a usable limiter.
+#[cfg(feature = "std")]
+pub(crate) fn validate(capacity: u64, period: Duration) -> Result<(), InvalidWindow> {
+    if capacity == 0 {
+        Err(InvalidWindow::ZeroCapacity)
+    } else if period.is_zero() {
+        Err(InvalidWindow::ZeroPeriod)
+    } else {
+        Ok(())
+    }
+}
+
+/// Check that `period` is a whole number of `resolution`, for limiters that can't keep time
+/// any finer.
+#[cfg(feature = "std")]
+pub(crate) fn validate_resolution(
+    period: Duration,
+    resolution: Duration,
+) -> Result<(), InvalidWindow> {
+    if !period.as_nanos().is_multiple_of(resolution.as_nanos()) {
+        Err(InvalidWindow::PeriodResolution { period, resolution })
+    } else {
+        Ok(())
+    }
+}
+
+/// An error returned when parsing a `floodgate::Rate` from a string like `5/10s` fails.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseRateError {
+    /// There was no `/` between the capacity and the period.
+    MissingSlash,
+    /// The capacity wasn't a whole number.
+    InvalidCapacity(String),
+    /// The period was empty, or a part of it didn't start with a whole number.
+    InvalidPeriod(String),
+    /// A part of the period had no unit, like the `10` in `5/10`.
+    MissingUnit(String),
+    /// A part of the period had a unit that isn't supported.
+    UnknownUnit(String),
+    /// The period was too long to represent.
+    PeriodTooLong,
+    /// The capacity or period was zero.
+    Invalid(InvalidWindow),
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for ParseRateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingSlash => write!(f, "expected a rate like `5/10s`, with a `/`"),
+            Self::InvalidCapacity(capacity) => {
+                write!(f, "capacity `{capacity}` is not a whole number")
+            }
+            Self::InvalidPeriod(period) => write!(f, "period `{period}` is not a duration"),
+            Self::MissingUnit(part) => write!(f, "`{part}` is missing a unit, such as `s`"),
+            Self::UnknownUnit(unit) => write!(
+                f,
+                "unknown unit `{unit}`, expected one of ns, us, ms, s, m, min, h or d"
+            ),
+            Self::PeriodTooLong => write!(f, "period is too long"),
+            Self::Invalid(err) => err.fmt(f),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for ParseRateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Invalid(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// An error returned when a `floodgate::Policy` can't be turned into a limiter. The messages
+/// name the field of the policy that is wrong.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidPolicy {
+    /// The policy had no rates.
+    NoRates,
+    /// One of the rates couldn't be parsed or was invalid.
+    InvalidRate { index: usize, error: ParseRateError },
+    /// `max_keys` was zero.
+    ZeroMaxKeys,
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for InvalidPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoRates => write!(f, "`rates` must contain at least one rate"),
+            Self::InvalidRate { index, error } => write!(f, "`rates[{index}]`: {error}"),
+            Self::ZeroMaxKeys => write!(f, "`max_keys` must be greater than zero"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for InvalidPolicy {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::InvalidRate { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// An error returned by `try_trigger` when a trigger is ratelimited, such as by
+/// `floodgate::JumpingWindow::try_trigger`. For mappings, `K` is the type of the key that was
+/// ratelimited, and for single limiters it is `()`.
+///
+/// The error is `Send + Sync + 'static` whenever the key is, so it can be boxed into any error
+/// type. Use `RateLimited::without_key` to drop the key.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimited<K = ()> {
+    /// How long until the key can be triggered again.
+    pub retry_after: Duration,
+    /// The key that was ratelimited.
+    pub key: K,
+}
+
+#[cfg(feature = "std")]
+impl<K> RateLimited<K> {
+    /// The same error, without the key.
+    pub fn without_key(self) -> RateLimited {
+        RateLimited {
+            retry_after: self.retry_after,
+            key: (),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K> fmt::Display for RateLimited<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rate limited, retry in {:.1?}", self.retry_after)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: fmt::Debug> Error for RateLimited<K> {}
+
+/// The error returned early by a function guarded with `#[floodgate::cooldown]` while it is on
+/// cooldown.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CooldownError {
+    /// How long until the function can be called again.
+    pub retry_after: Duration,
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for CooldownError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "on cooldown, retry in {:.1?}", self.retry_after)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for CooldownError {}
+
+/// An error returned by `floodgate::FixedMapping::reserve`.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReserveError {
+    /// The key already has as many tokens booked as `floodgate::FixedMapping::max_queue`
+    /// allows.
+    QueueFull,
+    /// The key is blocked, for the given time.
+    Blocked(Duration),
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for ReserveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::QueueFull => write!(f, "too many tokens are booked already"),
+            Self::Blocked(retry_after) => write!(f, "blocked, retry in {retry_after:.1?}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for ReserveError {}
+
+/// An error returned by `floodgate::FixedMapping::trigger_all` when none of its keys were
+/// triggered.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TriggerAllError<K> {
+    /// The key was ratelimited or blocked, for the given time.
+    Limited { key: K, retry_after: Duration },
+    /// The batch had more distinct keys than `floodgate::FixedMapping::max_keys` allows, so it
+    /// can never be triggered as a whole.
+    TooManyKeys { keys: usize, max_keys: usize },
+    /// Concurrent insertions kept evicting the batch's keys; it can be retried after the given
+    /// backoff.
+    Contended { retry_after: Duration },
+}
+
+#[cfg(feature = "std")]
+impl<K> TriggerAllError<K> {
+    /// How long until the batch may be retried, or `None` if it never can be.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::Limited { retry_after, .. } | Self::Contended { retry_after } => {
+                Some(*retry_after)
+            }
+            Self::TooManyKeys { .. } => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: fmt::Debug> fmt::Display for TriggerAllError<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Limited { key, retry_after } => {
+                write!(f, "{key:?} is ratelimited, retry in {retry_after:.1?}")
+            }
+            Self::TooManyKeys { keys, max_keys } => {
+                write!(
+                    f,
+                    "{keys} keys can't fit in a mapping of at most {max_keys}"
+                )
+            }
+            Self::Contended { retry_after } => {
+                write!(
+                    f,
+                    "contended by concurrent insertions, retry in {retry_after:.1?}"
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: fmt::Debug> Error for TriggerAllError<K> {}
+
+/// An error returned when waiting for a trigger would take longer than the given timeout.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+#[cfg(feature = "std")]
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the wait would exceed the timeout")
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for Elapsed {}
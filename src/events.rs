@@ -0,0 +1,186 @@
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use futures_core::Stream;
+use tokio::sync::broadcast::{self, error::RecvError, Receiver, Sender};
+
+/// How many events are buffered for each subscriber before the oldest ones are dropped.
+const CAPACITY: usize = 1024;
+
+/// Something that happened to a key of a `floodgate::FixedMapping`, yielded by
+/// `floodgate::FixedMapping::events`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RateLimitEvent<K> {
+    /// The key was rejected for the first time. See `floodgate::FixedMapping::on_exhausted`.
+    Exhausted {
+        /// The key that was rejected.
+        key: K,
+        /// How long until the key can be triggered again.
+        retry_after: Duration,
+    },
+    /// An exhausted key can be triggered again. See `floodgate::FixedMapping::on_reset`.
+    Reset {
+        /// The key that was reset.
+        key: K,
+    },
+    /// The key's limiter was dropped by cycling or cleaning up the mapping, or to stay under
+    /// `floodgate::FixedMapping::max_keys`.
+    Evicted {
+        /// The key whose limiter was dropped.
+        key: K,
+    },
+}
+
+/// The sending half of a mapping's events. Clones send to the same subscribers.
+#[derive(Clone)]
+pub(crate) struct EventSender<K> {
+    sender: Sender<RateLimitEvent<K>>,
+    /// Kept separately from the channel, so that checking for subscribers doesn't lock it.
+    subscribers: Arc<AtomicUsize>,
+}
+
+impl<K: Clone + Send + 'static> EventSender<K> {
+    pub(crate) fn new() -> Self {
+        Self {
+            sender: broadcast::channel(CAPACITY).0,
+            subscribers: Arc::default(),
+        }
+    }
+
+    /// Whether anything is listening for events.
+    pub(crate) fn is_active(&self) -> bool {
+        self.subscribers.load(Ordering::Relaxed) > 0
+    }
+
+    /// Send the event made by `event`, if anything is listening.
+    pub(crate) fn send(&self, event: impl FnOnce() -> RateLimitEvent<K>) {
+        if self.is_active() {
+            // fails only if the last subscriber was just dropped.
+            let _ = self.sender.send(event());
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> Events<K> {
+        self.subscribers.fetch_add(1, Ordering::Relaxed);
+        Events {
+            recv: Box::pin(recv(self.sender.subscribe())),
+            subscribers: self.subscribers.clone(),
+        }
+    }
+}
+
+type Recv<K> = Pin<Box<dyn Future<Output = RecvResult<K>> + Send>>;
+type RecvResult<K> = (
+    Result<RateLimitEvent<K>, RecvError>,
+    Receiver<RateLimitEvent<K>>,
+);
+
+async fn recv<K: Clone>(mut receiver: Receiver<RateLimitEvent<K>>) -> RecvResult<K> {
+    let result = receiver.recv().await;
+    (result, receiver)
+}
+
+/// A `Stream` of the events of a `floodgate::FixedMapping`, returned by
+/// `floodgate::FixedMapping::events`.
+///
+/// Each subscriber buffers up to 1024 events. A subscriber that falls further behind than that
+/// misses the oldest events, rather than slowing down the mapping. The stream ends once the
+/// mapping is dropped.
+pub struct Events<K> {
+    recv: Recv<K>,
+    subscribers: Arc<AtomicUsize>,
+}
+
+impl<K: Clone + Send + 'static> Stream for Events<K> {
+    type Item = RateLimitEvent<K>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let (result, receiver) = ready!(self.recv.as_mut().poll(cx));
+            self.recv = Box::pin(recv(receiver));
+            match result {
+                Ok(event) => return Poll::Ready(Some(event)),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+impl<K> fmt::Debug for Events<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Events").finish_non_exhaustive()
+    }
+}
+
+impl<K> Drop for Events<K> {
+    fn drop(&mut self) {
+        self.subscribers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::StreamExt;
+
+    use super::RateLimitEvent;
+    use crate::{FixedMapping, ManualClock};
+
+    #[tokio::test]
+    async fn subscribers_see_every_transition() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(60);
+        let mapping = FixedMapping::with_clock(1, period, clock.clone());
+        let mut first = mapping.events();
+        let second = mapping.events();
+
+        mapping.trigger(&1);
+        mapping.trigger(&1);
+        mapping.trigger(&1);
+        clock.advance(period);
+        mapping.cleanup(None);
+        drop(mapping);
+
+        let expected = [
+            RateLimitEvent::Exhausted {
+                key: 1,
+                retry_after: period,
+            },
+            RateLimitEvent::Evicted { key: 1 },
+            RateLimitEvent::Reset { key: 1 },
+        ];
+        assert_eq!(first.by_ref().collect::<Vec<_>>().await, expected);
+        assert_eq!(second.collect::<Vec<_>>().await, expected);
+    }
+
+    #[tokio::test]
+    async fn lagging_subscribers_miss_the_oldest_events() {
+        let mapping = FixedMapping::new(1, Duration::from_secs(60));
+        let events = mapping.events();
+        for key in 1..=2000 {
+            mapping.trigger(&key);
+            mapping.trigger(&key);
+        }
+        drop(mapping);
+
+        let keys: Vec<_> = events
+            .map(|event| match event {
+                RateLimitEvent::Exhausted { key, .. } => key,
+                _ => unreachable!(),
+            })
+            .collect()
+            .await;
+        assert_eq!(keys, (977..=2000).collect::<Vec<_>>());
+    }
+}
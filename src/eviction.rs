@@ -0,0 +1,51 @@
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::clock::Instant;
+
+/// The keys of a mapping bounded with `floodgate::FixedMapping::max_keys`, in the order they
+/// are to be considered for eviction.
+///
+/// Keys are queued when their limiter is created. The key at the front is evicted, unless it
+/// was used since it was queued, in which case it is queued again: a second chance, which
+/// keeps the order close to least recently used while costing a constant amount per insertion
+/// on average. Like in `Sweep`, each key is queued with the id of its slot, so that an entry
+/// left behind by a removed key doesn't apply to a new slot for the same key.
+pub(crate) struct EvictionQueue<K> {
+    max_keys: usize,
+    queue: Mutex<VecDeque<Candidate<K>>>,
+}
+
+/// A key that may be evicted if it hasn't been used since `used`.
+pub(crate) struct Candidate<K> {
+    pub(crate) key: K,
+    pub(crate) id: u64,
+    pub(crate) used: Instant,
+}
+
+impl<K> EvictionQueue<K> {
+    pub(crate) fn new(max_keys: usize) -> Self {
+        Self {
+            max_keys,
+            queue: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// The most keys the mapping may store a limiter for.
+    pub(crate) fn max_keys(&self) -> usize {
+        self.max_keys
+    }
+
+    /// Lock the queue. Insertions into the mapping hold the lock from making room until the
+    /// new limiter is stored, so that concurrent insertions can't both take the last place.
+    ///
+    /// The queue is only ever changed by whole pushes and pops, so a poisoned lock still holds
+    /// a valid queue.
+    pub(crate) fn lock(&self) -> MutexGuard<'_, VecDeque<Candidate<K>>> {
+        self.queue
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
@@ -1,87 +1,3987 @@
+#[cfg(feature = "tracing")]
+use std::fmt;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::sync::Arc;
 use std::{
-    hash::Hash,
-    sync::Arc,
-    thread::{self, sleep},
+    borrow::Borrow,
+    collections::{
+        hash_map::{self, RandomState},
+        HashMap,
+    },
+    hash::{BuildHasher, Hash},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
     time::Duration,
 };
 
-use dashmap::mapref::one::RefMut;
+use dashmap::DashMap;
 
-use crate::{mapping::Mapping, JumpingWindow};
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use crate::cleanup::CleanupHandle;
+#[cfg(feature = "stream")]
+use crate::events::{Events, RateLimitEvent};
+#[cfg(feature = "tokio")]
+use crate::wait_queue::WaitQueues;
+use crate::{
+    clock::{self, Instant, SystemTime},
+    error::validate,
+    hooks::Hooks,
+    mapping::{nanos, BatchError, Charge, Mapping},
+    mode::ModeCell,
+    warmup::SharedWarmup,
+    AdaptiveWindow, Clock, EnforcementMode, EntryGuard, InvalidPolicy, InvalidWindow, Jitter,
+    JumpingWindow, KeyedReservation, MappingSnapshot, MappingStats, MergeStrategy, MonotonicClock,
+    MultiWindow, Penalty, Policy, Priority, Rate, RateLimitInfo, RateLimited, RateLimiter,
+    Reservation, ReserveError, Shedding, SnapshotEntry, TriggerAllError, TriggerGuard,
+    TriggerRecord, UtilizationStats, Warmup,
+};
 
-/// A key-based mapping of `floodgate::JumpingWindow`'s.
+/// A key-based mapping of `floodgate::JumpingWindow`'s, or of any other `floodgate::RateLimiter`.
+///
+/// The time is read from a `floodgate::Clock`, which is also used to decide when the mapping
+/// cycles. See `FixedMapping::with_clock`.
+///
+/// Like `std::collections::HashMap`, methods taking a key accept any borrowed form of it, so
+/// the key is only cloned when a new limiter is created for it.
+///
+/// ```
+/// use floodgate::FixedMapping;
+/// use std::time::Duration;
+///
+/// let mapping = FixedMapping::<String>::new(1, Duration::from_secs(10));
+/// assert_eq!(mapping.trigger("general"), None);
+/// assert!(mapping.trigger("general").is_some());
+/// assert!(mapping.contains_key("general"));
+/// ```
 ///
 /// For some method documentation, please see `floodgate::JumpingWindow`.
-pub struct FixedMapping<K: Eq + Hash + Clone + Send + Sync + 'static> {
-    mapping: Mapping<K>,
-    capacity: u64,
-    period: Duration,
+pub struct FixedMapping<
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    L = JumpingWindow,
+    C = MonotonicClock,
+    S = RandomState,
+> {
+    mapping: Mapping<K, L, S>,
+    #[cfg(feature = "tokio")]
+    pub(crate) waiters: WaitQueues<K>,
+    capacity: AtomicU64,
+    period: AtomicU64,
+    cycle_period: AtomicU64,
+    overrides: DashMap<K, (u64, Duration), S>,
+    multipliers: DashMap<K, f64, S>,
+    /// How many keys have an override or a multiplier, so that triggers can skip looking them
+    /// up while there are none.
+    keyed_rates: AtomicUsize,
+    lists: DashMap<K, Listing, S>,
+    mode: ModeCell,
+    name: Option<String>,
+    hooks: Hooks<K, S>,
+    warmup: Option<SharedWarmup>,
+    /// The most tokens a key can have booked by `reserve`.
+    max_queue: Option<u64>,
+    clock: C,
+}
+
+/// Whether a key is exempt from its cooldown, or blocked.
+#[derive(Clone, Copy)]
+enum Listing {
+    Exempt,
+    /// Blocked until the given time, or indefinitely.
+    Blocked(Option<Instant>),
 }
 
+/// The retry-after reported for keys that are blocked indefinitely.
+const INDEFINITE_BLOCK: Duration = Duration::from_secs(u32::MAX as u64);
+
+/// The retry-after reported for batches given up on because of concurrent insertions.
+const CONTENDED_BACKOFF: Duration = Duration::from_millis(1);
+
 impl<K: Eq + Hash + Clone + Send + Sync + 'static> FixedMapping<K> {
     /// Create a new FixedMapping.
     ///
     /// # Arguments
     /// * `capacity` - The capacity of the `JumpingWindow`s.
     /// * `period` - The duration of the `JumpingWindow`s.
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `period` is zero. See `FixedMapping::try_new`.
     pub fn new(capacity: u64, period: Duration) -> Self {
-        Self {
+        Self::with_limiter(capacity, period)
+    }
+
+    /// Create a new FixedMapping, returning an error if `capacity` or `period` is zero.
+    ///
+    /// # Arguments
+    /// * `capacity` - The capacity of the `JumpingWindow`s.
+    /// * `period` - The duration of the `JumpingWindow`s.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{FixedMapping, InvalidWindow};
+    /// use std::time::Duration;
+    ///
+    /// let result = FixedMapping::<u64>::try_new(0, Duration::from_secs(10));
+    /// assert_eq!(result.err(), Some(InvalidWindow::ZeroCapacity));
+    /// ```
+    pub fn try_new(capacity: u64, period: Duration) -> Result<Self, InvalidWindow> {
+        validate(capacity, period)?;
+        Ok(Self::with_limiter(capacity, period))
+    }
+
+    /// Create a new FixedMapping whose `JumpingWindow`s allow `rate`.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{FixedMapping, Rate};
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::<u64>::from_rate(Rate::per_minute(5));
+    /// assert_eq!(mapping.capacity(), 5);
+    /// assert_eq!(mapping.period(), Duration::from_secs(60));
+    /// ```
+    pub fn from_rate(rate: Rate) -> Self {
+        Self::with_limiter(rate.capacity(), rate.period())
+    }
+
+    /// Create a new FixedMapping with a name, to tell it apart from other mappings when
+    /// reporting on it.
+    ///
+    /// With the `metrics` feature, the mapping reports to the `metrics` facade, labeled with
+    /// `mapping = name`:
+    /// * `floodgate_accepted_total` and `floodgate_rejected_total` - Counters of the triggers
+    ///   that were allowed and rejected, updated as they happen.
+    /// * `floodgate_evicted_total` - A counter of the limiters dropped by cycling or cleaning
+    ///   up the mapping.
+    /// * `floodgate_keys` - A gauge of how many keys have a limiter, updated when the mapping
+    ///   is cycled or cleaned up.
+    ///
+    /// The metrics are registered when the mapping is created, so the recorder has to be
+    /// installed before then. Reporting also keeps the mapping's stats, as if
+    /// `FixedMapping::with_stats` had been called.
+    ///
+    /// # Arguments
+    /// * `name` - The name of the mapping.
+    /// * `capacity` - The capacity of the `JumpingWindow`s.
+    /// * `period` - The duration of the `JumpingWindow`s.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::<u64>::named("commands", 1, Duration::from_secs(10));
+    /// assert_eq!(mapping.name(), Some("commands"));
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `period` is zero.
+    pub fn named(name: impl Into<String>, capacity: u64, period: Duration) -> Self {
+        let mut mapping = Self::new(capacity, period);
+        let name = name.into();
+        #[cfg(feature = "metrics")]
+        mapping.mapping.enable_metrics(&name);
+        mapping.name = Some(name);
+        mapping
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync + 'static, C: Clock> FixedMapping<K, JumpingWindow, C> {
+    /// Create a new FixedMapping that reads the time from `clock`.
+    ///
+    /// # Arguments
+    /// * `capacity` - The capacity of the `JumpingWindow`s.
+    /// * `period` - The duration of the `JumpingWindow`s.
+    /// * `clock` - The clock to read the time from.
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `period` is zero.
+    pub fn with_clock(capacity: u64, period: Duration, clock: C) -> Self {
+        if let Err(err) = validate(capacity, period) {
+            panic!("{err}");
+        }
+
+        let now = clock.now();
+        let mapping = Mapping::new(period, now);
+        Self::from_parts(capacity, period, mapping, clock, RandomState::new())
+    }
+}
+
+impl<K, S> FixedMapping<K, JumpingWindow, MonotonicClock, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    S: BuildHasher + Clone,
+{
+    /// Create a new FixedMapping that hashes keys with `hasher`, like
+    /// `std::collections::HashMap::with_hasher`.
+    ///
+    /// # Arguments
+    /// * `capacity` - The capacity of the `JumpingWindow`s.
+    /// * `period` - The duration of the `JumpingWindow`s.
+    /// * `hasher` - The hasher to hash keys with.
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `period` is zero.
+    pub fn with_hasher(capacity: u64, period: Duration, hasher: S) -> Self {
+        Self::with_capacity_and_hasher(capacity, period, 0, hasher)
+    }
+
+    /// Create a new FixedMapping that hashes keys with `hasher`, and has room for `map_capacity`
+    /// keys before reallocating, like `std::collections::HashMap::with_capacity_and_hasher`.
+    ///
+    /// # Arguments
+    /// * `capacity` - The capacity of the `JumpingWindow`s.
+    /// * `period` - The duration of the `JumpingWindow`s.
+    /// * `map_capacity` - How many keys to allocate room for.
+    /// * `hasher` - The hasher to hash keys with.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::{hash::BuildHasherDefault, collections::hash_map::DefaultHasher, time::Duration};
+    ///
+    /// let hasher = BuildHasherDefault::<DefaultHasher>::default();
+    /// let mapping = FixedMapping::with_capacity_and_hasher(1, Duration::from_secs(10), 1024, hasher);
+    ///
+    /// assert_eq!(mapping.trigger(&1u64), None);
+    /// assert!(mapping.trigger(&1).is_some());
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `period` is zero.
+    pub fn with_capacity_and_hasher(
+        capacity: u64,
+        period: Duration,
+        map_capacity: usize,
+        hasher: S,
+    ) -> Self {
+        if let Err(err) = validate(capacity, period) {
+            panic!("{err}");
+        }
+
+        let mapping =
+            Mapping::with_capacity_and_hasher(period, clock::now(), map_capacity, hasher.clone());
+        Self::from_parts(capacity, period, mapping, MonotonicClock, hasher)
+    }
+}
+
+impl<K, C, S> FixedMapping<K, JumpingWindow, C, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    /// Extend the window of a key whenever one of its triggers is rejected. See
+    /// `floodgate::JumpingWindow::punitive`.
+    ///
+    /// The penalty is applied to every key separately, so only the keys that keep hammering
+    /// their limit are held back for longer.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{FixedMapping, ManualClock, Penalty};
+    /// use std::time::Duration;
+    ///
+    /// let clock = ManualClock::new();
+    /// let period = Duration::from_secs(10);
+    /// let mapping = FixedMapping::with_clock(1, period, clock.clone()).punitive(Penalty::RestartWindow);
+    /// mapping.trigger(&1);
+    /// mapping.trigger(&2);
+    ///
+    /// clock.advance(Duration::from_secs(4));
+    /// // the rejected trigger restarted the window of `1`.
+    /// assert_eq!(mapping.trigger(&1), Some(period));
+    /// assert_eq!(mapping.retry_after(&2), Some(Duration::from_secs(6)));
+    /// ```
+    pub fn punitive(mut self, penalty: Penalty) -> Self {
+        self.mapping
+            .add_configure(move |window: &mut JumpingWindow| window.set_penalty(Some(penalty)));
+        self
+    }
+
+    /// Carry the unused tokens of a key over into its next window, so that up to `burst`
+    /// triggers can be made at once after it has been quiet. See
+    /// `floodgate::JumpingWindow::carry_over`.
+    ///
+    /// Note that a key whose window is full may be cleaned up, which drops whatever it had
+    /// banked.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{FixedMapping, ManualClock};
+    /// use std::time::Duration;
+    ///
+    /// let clock = ManualClock::new();
+    /// let period = Duration::from_secs(10);
+    /// let mapping = FixedMapping::with_clock(2, period, clock.clone()).carry_over(4);
+    /// mapping.trigger(&1);
+    ///
+    /// clock.advance(period);
+    /// assert_eq!(mapping.tokens(&1), 3);
+    /// ```
+    pub fn carry_over(mut self, burst: u64) -> Self {
+        self.mapping
+            .add_configure(move |window: &mut JumpingWindow| window.set_carry_over(Some(burst)));
+        self
+    }
+
+    /// Randomize the length of each key's windows. See `floodgate::JumpingWindow::jittered`.
+    ///
+    /// Every key draws its own numbers, even when `jitter` is seeded, so keys that were first
+    /// triggered together drift apart. With a seed, the same keys created in the same order
+    /// always get the same windows.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{FixedMapping, Jitter, ManualClock};
+    /// use std::time::Duration;
+    ///
+    /// let clock = ManualClock::new();
+    /// let jitter = Jitter::new(Duration::from_secs(3)).seed(1);
+    /// let mapping = FixedMapping::with_clock(1, Duration::from_secs(10), clock).jittered(jitter);
+    /// mapping.trigger(&1);
+    /// mapping.trigger(&2);
+    ///
+    /// assert_ne!(mapping.retry_after(&1), mapping.retry_after(&2));
+    /// ```
+    pub fn jittered(mut self, jitter: Jitter) -> Self {
+        let windows = AtomicU64::new(0);
+        self.mapping
+            .add_configure(move |window: &mut JumpingWindow| {
+                let n = windows.fetch_add(1, Ordering::Relaxed);
+                window.set_jitter(Some(jitter.fork(n)));
+            });
+        self
+    }
+
+    /// Reject triggers of a key at random once its window is nearly used up. See
+    /// `floodgate::JumpingWindow::load_shedding`.
+    ///
+    /// Every key sheds its own load and draws its own numbers, so a busy key doesn't make the
+    /// others shed.
+    pub fn load_shedding(mut self, shedding: Shedding) -> Self {
+        let windows = AtomicU64::new(0);
+        self.mapping
+            .add_configure(move |window: &mut JumpingWindow| {
+                let n = windows.fetch_add(1, Ordering::Relaxed);
+                window.set_shedding(Some(shedding.fork(n)));
+            });
+        self
+    }
+
+    /// Keep `floor` of the capacity of every key's windows for high priority triggers. See
+    /// `floodgate::JumpingWindow::prioritized`.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{FixedMapping, Priority};
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::new(5, Duration::from_secs(10)).prioritized(0.2);
+    ///
+    /// // a backfill uses up what low priority triggers are allowed...
+    /// while mapping.trigger_with_priority(&1, Priority::Low).is_none() {}
+    /// assert_eq!(mapping.tokens_for(&1, Priority::Low), 0);
+    /// // ...but a webhook still gets through.
+    /// assert_eq!(mapping.trigger_with_priority(&1, Priority::High), None);
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `floor` isn't between `0.0` and `1.0`.
+    pub fn prioritized(mut self, floor: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&floor),
+            "floor must be between 0 and 1"
+        );
+        self.mapping
+            .add_configure(move |window: &mut JumpingWindow| {
+                window.set_priority_floor(Some(floor))
+            });
+        self
+    }
+
+    /// Trigger the cooldown for `key` with the given priority. See
+    /// `floodgate::JumpingWindow::trigger_with_priority`.
+    pub fn trigger_with_priority<Q>(&self, key: &Q, priority: Priority) -> Option<Duration>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let mode = self.mode.get();
+        if mode == EnforcementMode::Bypass {
+            return None;
+        }
+        let retry_after = match self.listing(key) {
+            Some(listing) => listing.err(),
+            None => self.with_bucket(key, |bucket, now| {
+                bucket.trigger_with_priority(priority, now)
+            }),
+        };
+        match self.rejects(key, mode, retry_after) {
+            true => retry_after,
+            false => None,
+        }
+    }
+
+    /// How many triggers of `key` with the given priority are left. See
+    /// `floodgate::JumpingWindow::tokens_for`.
+    pub fn tokens_for<Q>(&self, key: &Q, priority: Priority) -> u64
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.with_bucket(key, |bucket, now| bucket.tokens_for(priority, now))
+    }
+
+    /// How long until `key` can be triggered with the given priority, or `None` if it can be
+    /// now. See `floodgate::JumpingWindow::retry_after_for`.
+    pub fn retry_after_for<Q>(&self, key: &Q, priority: Priority) -> Option<Duration>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        if self.mode.get() != EnforcementMode::Enforce {
+            return None;
+        }
+        if let Some(listing) = self.listing(key) {
+            return listing.err();
+        }
+        self.with_bucket(key, |bucket, now| bucket.retry_after_for(priority, now))
+    }
+
+    /// Record the last `n` allowed and rejected triggers of every key. See
+    /// `floodgate::JumpingWindow::with_history`.
+    ///
+    /// Each key keeps its own history, and loses it when its limiter is cleaned up. To only
+    /// record the keys being looked into, use `FixedMapping::set_history` instead.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::new(1, Duration::from_secs(10)).with_history(10);
+    /// mapping.trigger(&1);
+    /// mapping.trigger(&1);
+    ///
+    /// let history = mapping.history(&1);
+    /// assert_eq!(history.len(), 2);
+    /// assert!(!history[1].allowed);
+    /// ```
+    pub fn with_history(mut self, n: usize) -> Self {
+        self.mapping
+            .add_configure(move |window: &mut JumpingWindow| window.set_history(n));
+        self
+    }
+
+    /// Change how many triggers of `key` are recorded, creating its limiter if needed. Zero
+    /// stops recording them. See `floodgate::JumpingWindow::set_history`.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::new(1, Duration::from_secs(10));
+    /// mapping.set_history("suspicious-user", 10);
+    /// mapping.trigger("suspicious-user");
+    /// mapping.trigger("someone-else");
+    ///
+    /// assert_eq!(mapping.history("suspicious-user").len(), 1);
+    /// assert!(mapping.history("someone-else").is_empty());
+    /// ```
+    pub fn set_history<Q>(&self, key: &Q, n: usize)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.with_bucket(key, |bucket, _| bucket.set_history(n));
+    }
+
+    /// The recorded triggers of `key`, oldest first. Empty if its history isn't recorded or the
+    /// mapping isn't storing a limiter for it. See `floodgate::JumpingWindow::history`.
+    pub fn history<Q>(&self, key: &Q) -> Vec<TriggerRecord>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.mapping
+            .with_existing(key, |bucket| bucket.history().to_vec())
+            .unwrap_or_default()
+    }
+
+    /// Reserve a token of `key`, returning when it may be used instead of rejecting it:
+    /// callers are spread across the key's coming windows. See
+    /// `floodgate::JumpingWindow::reserve`.
+    ///
+    /// The token is released when the reservation is dropped, unless
+    /// `KeyedReservation::commit` is called, so commit it once the slot is used. Exempt keys
+    /// get a reservation for now that holds no token.
+    ///
+    /// # Errors
+    /// Returns `ReserveError::QueueFull` if the key already has as many tokens booked as
+    /// `FixedMapping::max_queue` allows, and `ReserveError::Blocked` if the key is blocked.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{FixedMapping, ReserveError};
+    /// use std::time::Duration;
+    ///
+    /// let period = Duration::from_secs(10);
+    /// let mapping = FixedMapping::new(1, period).max_queue(1);
+    ///
+    /// let now = mapping.reserve("example.com").unwrap();
+    /// let later = mapping.reserve("example.com").unwrap();
+    /// assert_eq!(later.at() - now.at(), period);
+    /// assert_eq!(mapping.reserve("example.com").err(), Some(ReserveError::QueueFull));
+    ///
+    /// // dropping a reservation releases its token.
+    /// drop(later);
+    /// let later = mapping.reserve("example.com").unwrap();
+    /// now.commit();
+    /// later.commit();
+    /// ```
+    pub fn reserve<Q>(&self, key: &Q) -> Result<KeyedReservation<'_, K, C, S>, ReserveError>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let bypass = self.mode.get() == EnforcementMode::Bypass;
+        match self.listing(key) {
+            Some(Err(retry_after)) if !bypass => return Err(ReserveError::Blocked(retry_after)),
+            None if !bypass => {}
+            _ => {
+                let now = self.clock.now();
+                return Ok(KeyedReservation::new(self, key.to_owned(), now, None));
+            }
+        }
+
+        let reservation = self.with_bucket(key, |window, now| {
+            let full =
+                window.tokens(now) == 0 && self.max_queue.is_some_and(|max| window.booked() >= max);
+            (!full).then(|| window.reserve(now))
+        });
+        let reservation = reservation.ok_or(ReserveError::QueueFull)?;
+        let at = reservation.at;
+        Ok(KeyedReservation::new(
+            self,
+            key.to_owned(),
+            at,
+            Some(reservation),
+        ))
+    }
+
+    /// Let each key have at most `depth` tokens of its coming windows booked by
+    /// `FixedMapping::reserve`, so that callers aren't scheduled too far ahead. Unlimited by
+    /// default.
+    ///
+    /// # Arguments
+    /// * `depth` - How many tokens can be booked per key.
+    pub fn max_queue(mut self, depth: u64) -> Self {
+        self.max_queue = Some(depth);
+        self
+    }
+
+    /// Release a token reserved for `key`, if it still has a limiter.
+    pub(crate) fn cancel_reservation<Q>(&self, key: &Q, reservation: Reservation)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.clock.now();
+        self.mapping
+            .with_existing(key, |window| reservation.cancel(window, Some(now)));
+    }
+
+    /// How many triggers of `key` have been rejected since the last one that was allowed. See
+    /// `floodgate::JumpingWindow::rejections`.
+    pub fn rejections<Q>(&self, key: &Q) -> u64
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.with_bucket(key, |bucket, _| bucket.rejections())
+    }
+
+    /// Trigger the cooldown for `key`, returning how many triggers were rejected before this
+    /// one if it is allowed. See `floodgate::JumpingWindow::trigger_counted`.
+    pub fn trigger_counted<Q>(&self, key: &Q) -> Result<u64, Duration>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let mode = self.mode.get();
+        if mode == EnforcementMode::Bypass {
+            return Ok(0);
+        }
+        let result = match self.listing(key) {
+            Some(listing) => listing.map(|()| 0),
+            None => self.with_bucket(key, |bucket, now| bucket.trigger_counted(now)),
+        };
+        match self.rejects(key, mode, result.err()) {
+            true => result,
+            false => result.or(Ok(0)),
+        }
+    }
+
+    /// Pause the cooldown of every key, including the keys created until
+    /// `FixedMapping::resume_all` is called. See `floodgate::JumpingWindow::pause`. Pausing a
+    /// paused mapping does nothing.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{FixedMapping, ManualClock};
+    /// use std::time::Duration;
+    ///
+    /// let clock = ManualClock::new();
+    /// let mapping = FixedMapping::with_clock(1, Duration::from_secs(10), clock.clone());
+    /// mapping.trigger(&1);
+    ///
+    /// mapping.pause_all();
+    /// mapping.trigger(&2);
+    /// clock.advance(Duration::from_secs(60));
+    /// assert_eq!(mapping.next_reset(&1), Duration::from_secs(10));
+    ///
+    /// mapping.resume_all();
+    /// assert_eq!(mapping.next_reset(&1), Duration::from_secs(10));
+    /// assert_eq!(mapping.next_reset(&2), Duration::from_secs(10));
+    /// ```
+    pub fn pause_all(&self) {
+        let now = self.clock.now();
+        self.mapping
+            .pause(|window, now| window.pause(Some(now)), now);
+    }
+
+    /// Resume the cooldown of every key paused by `FixedMapping::pause_all`. Resuming a mapping
+    /// that isn't paused does nothing. The `KeyedReservation::at` of reservations made before
+    /// the pause isn't moved along with their tokens. See `floodgate::JumpingWindow::resume`.
+    pub fn resume_all(&self) {
+        let now = self.clock.now();
+        self.mapping
+            .resume(|window, now| window.resume(Some(now)), now);
+    }
+}
+
+impl<K, C, S> FixedMapping<K, AdaptiveWindow, C, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    /// Report that a call for `key` went through. See
+    /// `floodgate::AdaptiveWindow::record_success`.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{AdaptiveWindow, FixedMapping};
+    /// use std::time::Duration;
+    ///
+    /// let template = AdaptiveWindow::new(1, 10, Duration::from_secs(1));
+    /// let hosts = FixedMapping::<String, _>::with_template(template);
+    ///
+    /// hosts.record_retry_after("flaky.example", Duration::from_secs(30));
+    /// assert!(hosts.trigger("flaky.example").is_some());
+    /// assert_eq!(hosts.trigger("stable.example"), None);
+    ///
+    /// hosts.record_failure("stable.example");
+    /// assert_eq!(hosts.trigger_info("stable.example").limit, 5);
+    /// ```
+    pub fn record_success<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.with_bucket(key, |bucket, _| bucket.record_success());
+    }
+
+    /// Report that a call for `key` failed or was throttled. See
+    /// `floodgate::AdaptiveWindow::record_failure`.
+    pub fn record_failure<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.with_bucket(key, |bucket, _| bucket.record_failure());
+    }
+
+    /// Report that the upstream for `key` asked to retry after `retry_after`. See
+    /// `floodgate::AdaptiveWindow::record_retry_after`.
+    pub fn record_retry_after<Q>(&self, key: &Q, retry_after: Duration)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.with_bucket(key, |bucket, now| {
+            bucket.record_retry_after(retry_after, now)
+        });
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync + 'static, L: RateLimiter> FixedMapping<K, L> {
+    /// Create a new FixedMapping using `L` as the limiter for each key.
+    ///
+    /// # Arguments
+    /// * `capacity` - The capacity of the limiters.
+    /// * `period` - The period of the limiters.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{FixedMapping, TokenBucket};
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::<u64, TokenBucket>::with_limiter(1, Duration::from_secs(1));
+    /// assert_eq!(mapping.trigger(&1), None);
+    /// assert!(mapping.trigger(&1).is_some());
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `period` is invalid for `L`. See
+    /// `floodgate::RateLimiter::validate`.
+    pub fn with_limiter(capacity: u64, period: Duration) -> Self {
+        if let Err(err) = L::validate(capacity, period) {
+            panic!("{err}");
+        }
+
+        let mapping = Mapping::new(period, clock::now());
+        Self::from_parts(
+            capacity,
+            period,
+            mapping,
+            MonotonicClock,
+            RandomState::new(),
+        )
+    }
+
+    /// Create a new FixedMapping where each key starts from a fresh copy of `template`. This
+    /// is useful for limiters that can't be described by a single capacity and period, such as
+    /// `floodgate::MultiWindow`.
+    ///
+    /// # Arguments
+    /// * `template` - The limiter to copy for each key.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{FixedMapping, MultiWindow};
+    /// use std::time::Duration;
+    ///
+    /// let policy = MultiWindow::new([
+    ///     (1, Duration::from_secs(10)),
+    ///     (5, Duration::from_secs(600)),
+    /// ]);
+    /// let mapping = FixedMapping::<u64, _>::with_template(policy);
+    ///
+    /// assert_eq!(mapping.trigger(&1), None);
+    /// assert!(mapping.trigger(&1).is_some());
+    /// assert_eq!(mapping.trigger(&2), None);
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if the template's capacity or period is invalid for `L`.
+    pub fn with_template(template: L) -> Self
+    where
+        L: Clone + Send + Sync + 'static,
+    {
+        let capacity = template.capacity();
+        let period = template.period();
+        if let Err(err) = L::validate(capacity, period) {
+            panic!("{err}");
+        }
+
+        let mapping = Mapping::new(period, clock::now()).with_factory(move || template.clone());
+        Self::from_parts(
             capacity,
             period,
-            mapping: Mapping::new(period),
+            mapping,
+            MonotonicClock,
+            RandomState::new(),
+        )
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync + 'static> FixedMapping<K, MultiWindow> {
+    /// Create a new FixedMapping where each key is limited as described by `policy`.
+    ///
+    /// The period of the mapping is that of the longest rate. See `floodgate::Policy`.
+    ///
+    /// # Arguments
+    /// * `policy` - The rates and options to apply to each key.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{FixedMapping, InvalidPolicy, Policy, Rate};
+    /// use std::time::Duration;
+    ///
+    /// let policy = Policy {
+    ///     carry_over: Some(4),
+    ///     ..Policy::new([Rate::per_second(2)])
+    /// };
+    /// let mapping = FixedMapping::<u64, _>::from_policy(&policy).unwrap();
+    /// assert_eq!(mapping.period(), Duration::from_secs(1));
+    ///
+    /// let result = FixedMapping::<u64, _>::from_policy(&Policy::new([]));
+    /// assert_eq!(result.err(), Some(InvalidPolicy::NoRates));
+    /// ```
+    pub fn from_policy(policy: &Policy) -> Result<Self, InvalidPolicy> {
+        policy.validate()?;
+
+        let mut template = MultiWindow::new(policy.rates.iter().map(|&rate| rate.into()));
+        for window in template.windows_mut() {
+            window.set_penalty(policy.penalty);
+            window.set_carry_over(policy.carry_over);
+        }
+
+        let mut mapping = Self::with_template(template);
+        if let Some(amount) = policy.jitter {
+            let jitter = Jitter::new(amount);
+            let windows = AtomicU64::new(0);
+            mapping
+                .mapping
+                .add_configure(move |limiter: &mut MultiWindow| {
+                    for window in limiter.windows_mut() {
+                        let n = windows.fetch_add(1, Ordering::Relaxed);
+                        window.set_jitter(Some(jitter.fork(n)));
+                    }
+                });
+        }
+        if let Some(max_keys) = policy.max_keys {
+            mapping = mapping.max_keys(max_keys);
         }
+        if let Some(periods) = policy.idle_periods(mapping.period()) {
+            mapping.set_idle_periods(periods);
+        }
+        Ok(mapping)
     }
+}
 
-    fn get_bucket(&self, key: &K) -> RefMut<K, JumpingWindow> {
-        self.mapping.get_bucket(key, self.capacity, self.period)
+impl<K, L, C, S> FixedMapping<K, L, C, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    L: RateLimiter,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    fn from_parts(
+        capacity: u64,
+        period: Duration,
+        mapping: Mapping<K, L, S>,
+        clock: C,
+        hasher: S,
+    ) -> Self {
+        let hooks = Hooks::with_hasher(hasher.clone());
+        #[cfg(feature = "stream")]
+        let mut mapping = mapping;
+        #[cfg(feature = "stream")]
+        mapping.set_on_evict({
+            let events = hooks.events.clone();
+            move |key: &K| events.send(|| RateLimitEvent::Evicted { key: key.clone() })
+        });
+
+        Self {
+            capacity: AtomicU64::new(capacity),
+            period: AtomicU64::new(nanos(period)),
+            cycle_period: AtomicU64::new(0),
+            overrides: DashMap::with_hasher(hasher.clone()),
+            multipliers: DashMap::with_hasher(hasher.clone()),
+            keyed_rates: AtomicUsize::new(0),
+            lists: DashMap::with_hasher(hasher),
+            hooks,
+            mode: ModeCell::default(),
+            name: None,
+            warmup: None,
+            max_queue: None,
+            #[cfg(feature = "tokio")]
+            waiters: WaitQueues::new(),
+            mapping,
+            clock,
+        }
     }
 
-    pub fn tokens(&self, key: &K) -> u64 {
-        self.get_bucket(key).tokens(None)
+    /// The clock the mapping reads the time from.
+    pub fn clock(&self) -> &C {
+        &self.clock
     }
 
-    pub fn next_reset(&self, key: &K) -> Duration {
-        self.get_bucket(key).next_reset(None)
+    /// The name the mapping was created with. See `FixedMapping::named`.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
     }
 
-    pub fn retry_after(&self, key: &K) -> Option<Duration> {
-        self.get_bucket(key).retry_after(None)
+    /// The capacity of each key's limiter.
+    pub fn capacity(&self) -> u64 {
+        self.capacity.load(Ordering::Relaxed)
     }
 
-    pub fn can_trigger(&self, key: &K) -> bool {
-        self.get_bucket(key).can_trigger(None)
+    /// The period of each key's limiter.
+    pub fn period(&self) -> Duration {
+        Duration::from_nanos(self.period.load(Ordering::Relaxed))
     }
 
-    pub fn trigger(&self, key: &K) -> Option<Duration> {
-        self.get_bucket(key).trigger(None)
+    /// How often the mapping is cycled. Unless set with `FixedMapping::set_cycle_period`, this
+    /// is the mapping's period.
+    pub fn cycle_period(&self) -> Duration {
+        Duration::from_nanos(self.cycle_period.load(Ordering::Relaxed)).max(self.period())
     }
 
-    pub fn reset(&self, key: &K) {
-        self.get_bucket(key).reset(None)
+    /// Change how often the mapping is cycled. A running cycler picks up the new cycle period
+    /// after its current wait.
+    ///
+    /// # Arguments
+    /// * `cycle_period` - How often to cycle the mapping. Must be greater than the mapping's
+    ///   period.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::<u64>::new(1, Duration::from_secs(10));
+    /// assert_eq!(mapping.cycle_period(), Duration::from_secs(10));
+    ///
+    /// mapping.set_cycle_period(Duration::from_secs(60));
+    /// assert_eq!(mapping.cycle_period(), Duration::from_secs(60));
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `cycle_period` is shorter than the mapping's period.
+    pub fn set_cycle_period(&self, cycle_period: Duration) {
+        assert!(cycle_period >= self.period());
+        self.cycle_period
+            .store(nanos(cycle_period), Ordering::Relaxed);
+        self.mapping.set_cycle_period(cycle_period);
     }
 
-    /// Cycles the mapping. Returns `true` if it cycled, or `false` if not.
-    pub fn cycle(&self) -> bool {
-        self.mapping.cycle(None)
+    /// How many periods a key has to go unused before its limiter is dropped. See
+    /// `FixedMapping::set_idle_periods`.
+    pub fn idle_periods(&self) -> u32 {
+        self.mapping.idle_periods()
     }
 
-    /// Start the background cycler.
+    /// Keep each key's limiter until it has gone unused for `periods` times the mapping's
+    /// period, instead of dropping it as soon as its window expires. Limiters that are still
+    /// on cooldown are never dropped. Defaults to 0.
     ///
-    /// If, for some reason, you don't want to use the default cycler, you must manually call
-    /// the `.cycle` method on the mapping periodically.
+    /// This applies to both cycling and `FixedMapping::cleanup`. Keeping idle limiters around
+    /// avoids recreating them for keys that come back often, at the cost of memory.
     ///
     /// # Arguments
-    /// * `mapping` - The FixedMapping, wrapped in an Arc.
-    /// * `cycle_period` - How often to cycle the mapping. If specified, must be greater than the
-    /// mapping's period.
-    pub fn start(mapping: Arc<Self>, cycle_period: Option<Duration>) {
-        let period = cycle_period.unwrap_or(mapping.period);
-        assert!(period >= mapping.period);
-        thread::spawn(move || loop {
-            sleep(period);
-            if !mapping.mapping.cycle(None) {
-                eprintln!("Cycler attempted to call the mapping too soon.");
-            }
-        });
+    /// * `periods` - The idle time to keep limiters for, in multiples of the period.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let mapping = FixedMapping::new(1, Duration::from_secs(10));
+    /// mapping.set_idle_periods(10);
+    /// mapping.trigger(&1);
+    ///
+    /// let later = Instant::now() + Duration::from_secs(20);
+    /// assert_eq!(mapping.cleanup(Some(later)), 0);
+    ///
+    /// let later = Instant::now() + Duration::from_secs(100);
+    /// assert_eq!(mapping.cleanup(Some(later)), 1);
+    /// ```
+    pub fn set_idle_periods(&self, periods: u32) {
+        self.mapping.set_idle_periods(periods);
+    }
+
+    /// Bound the number of keys the mapping stores a limiter for.
+    ///
+    /// When a new key would go over the bound, the least recently used limiters are dropped
+    /// to make room, even if they are still on cooldown, so a dropped key starts over with a
+    /// full limiter. This is checked every time a limiter is created, not only when the mapping
+    /// is cycled, and the bound holds even while new keys are inserted concurrently.
+    ///
+    /// Limiters are considered in the order they were created, and one that was used since it
+    /// was last considered is given a second chance, so eviction is close to least recently
+    /// used order without having to scan the mapping. Creating limiters in a bounded mapping is
+    /// serialized, but triggering existing ones isn't.
+    ///
+    /// # Arguments
+    /// * `max_keys` - The most keys to store a limiter for.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::new(1, Duration::from_secs(10)).max_keys(2);
+    /// mapping.trigger(&1);
+    /// mapping.trigger(&2);
+    /// mapping.trigger(&3);
+    ///
+    /// assert_eq!(mapping.len(), 2);
+    /// assert!(!mapping.contains_key(&1));
+    /// assert_eq!(mapping.forced_evictions(), 1);
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `max_keys` is zero.
+    pub fn max_keys(mut self, max_keys: usize) -> Self {
+        assert!(max_keys > 0, "max_keys must be greater than zero");
+        self.mapping.set_max_keys(max_keys);
+        self
+    }
+
+    /// Clean up incrementally when cycling, instead of walking every key at once, for mappings
+    /// with so many keys that a full walk holds up triggers.
+    ///
+    /// The mapping then queues each new key with the earliest time it could be dropped, and
+    /// each cycle only visits the keys whose time has passed. Keys are taken from the queue
+    /// `batch_size` at a time, and each is checked under its own lock, so no lock is held for
+    /// long. The keys that are on cooldown are queued again for when they could next be
+    /// dropped. Which keys get dropped doesn't change, only when: a key may survive until the
+    /// keys queued before it could be dropped too. The queue holds a clone of every key.
+    ///
+    /// `FixedMapping::cleanup` and `FixedMapping::drain_expired` still walk every key.
+    ///
+    /// # Arguments
+    /// * `batch_size` - How many keys to take from the queue at a time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::<u64>::new(1, Duration::from_millis(10)).incremental_cleanup(256);
+    /// mapping.trigger(&1);
+    ///
+    /// std::thread::sleep(Duration::from_millis(20));
+    /// assert!(mapping.cycle());
+    /// assert!(mapping.is_empty());
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `batch_size` is zero.
+    pub fn incremental_cleanup(mut self, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "batch_size must be greater than zero");
+        self.mapping.set_sweep(batch_size);
+        self
+    }
+
+    /// How many limiters have been dropped to stay under the bound set with
+    /// `FixedMapping::max_keys`. Limiters dropped by cycling or cleaning up aren't counted.
+    pub fn forced_evictions(&self) -> u64 {
+        self.mapping.forced_evictions()
+    }
+
+    /// Keep counters of the mapping's triggers, new keys and evictions, read with
+    /// `FixedMapping::stats`. Counting adds a single atomic increment to each trigger.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::new(1, Duration::from_secs(10)).with_stats();
+    /// mapping.trigger(&1);
+    /// mapping.trigger(&1);
+    /// mapping.trigger(&2);
+    ///
+    /// let stats = mapping.stats();
+    /// assert_eq!((stats.accepted, stats.rejected, stats.keys_created), (2, 1, 2));
+    ///
+    /// mapping.reset_stats();
+    /// assert_eq!(mapping.stats(), Default::default());
+    /// ```
+    pub fn with_stats(mut self) -> Self {
+        self.mapping.enable_stats();
+        self
+    }
+
+    /// Ramp every key's capacity up from a fraction of the configured one, starting now. See
+    /// `floodgate::Warmup`.
+    ///
+    /// The warm-up is shared by the whole mapping rather than kept per key, so keys that are
+    /// first triggered late into it start with the capacity in force then. Overridden
+    /// capacities are ramped up too.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{FixedMapping, ManualClock, Warmup};
+    /// use std::time::Duration;
+    ///
+    /// let clock = ManualClock::new();
+    /// let warmup = Warmup::new(Duration::from_secs(300), 0.1);
+    /// let mapping = FixedMapping::with_clock(100, Duration::from_secs(10), clock.clone())
+    ///     .slow_start(warmup);
+    ///
+    /// assert_eq!(mapping.trigger_info(&1).limit, 10);
+    /// clock.advance(Duration::from_secs(150));
+    /// assert_eq!(mapping.effective_capacity(), 55);
+    ///
+    /// // a dependency restarted, so ease off again.
+    /// mapping.begin_warmup();
+    /// assert_eq!(mapping.tokens(&1), 10);
+    /// ```
+    pub fn slow_start(mut self, warmup: Warmup) -> Self {
+        self.warmup = Some(SharedWarmup::new(warmup, self.clock.now()));
+        self
+    }
+
+    /// Start the warm-up set with `FixedMapping::slow_start` again from now. Keys that already
+    /// have a limiter drop back to the start of the ramp, clamping the tokens they have left.
+    /// Does nothing without a warm-up.
+    pub fn begin_warmup(&self) {
+        if let Some(warmup) = &self.warmup {
+            warmup.begin(self.clock.now());
+        }
+    }
+
+    /// The capacity in force for keys without an override, which is below `capacity` while
+    /// warming up.
+    pub fn effective_capacity(&self) -> u64 {
+        self.warmed(self.capacity(), self.clock.now())
+    }
+
+    /// `capacity`, reduced by the warm-up at `now`.
+    fn warmed(&self, capacity: u64, now: Instant) -> u64 {
+        match &self.warmup {
+            Some(warmup) => warmup.capacity(capacity, now),
+            None => capacity,
+        }
+    }
+
+    /// The mapping's counters. See `floodgate::MappingStats`.
+    pub fn stats(&self) -> MappingStats {
+        let dry_run_rejected = self.mode.dry_run_rejections();
+        match self.mapping.counters() {
+            Some(counters) => counters.snapshot(dry_run_rejected),
+            None => MappingStats {
+                dry_run_rejected,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Set every counter of `FixedMapping::stats` back to zero.
+    pub fn reset_stats(&self) {
+        if let Some(counters) = self.mapping.counters() {
+            counters.reset();
+        }
+        self.mode.reset_dry_run_rejections();
+    }
+
+    /// How many keys the mapping is storing a limiter for.
+    ///
+    /// This counts every stored limiter, including those whose window has expired but which
+    /// haven't been cycled out yet, so it can be higher than the number of keys on cooldown.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::new(1, Duration::from_secs(10));
+    /// assert!(mapping.is_empty());
+    ///
+    /// mapping.trigger(&1);
+    /// mapping.trigger(&2);
+    /// assert_eq!(mapping.len(), 2);
+    /// ```
+    pub fn len(&self) -> usize {
+        self.mapping.len()
+    }
+
+    /// Whether the mapping isn't storing any limiters. See `FixedMapping::len`.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the mapping is storing a limiter for `key`. Unlike `trigger` and the other
+    /// methods taking a key, this never creates a limiter.
+    ///
+    /// # Arguments
+    /// * `key` - The key to look for.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::new(1, Duration::from_secs(10));
+    /// assert!(!mapping.contains_key(&1));
+    ///
+    /// mapping.trigger(&1);
+    /// assert!(mapping.contains_key(&1));
+    /// assert_eq!(mapping.len(), 1);
+    /// ```
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.mapping.contains_key(key)
+    }
+
+    /// Change the capacity and period used for every key. Existing limiters keep their state
+    /// and adopt the new rate the next time they are used; see `floodgate::RateLimiter::set_rate`.
+    ///
+    /// If the cycle period was set explicitly, it should still be greater than the new period.
+    ///
+    /// # Arguments
+    /// * `capacity` - The new capacity.
+    /// * `period` - The new period.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::new(5, Duration::from_secs(10));
+    /// mapping.trigger(&1);
+    ///
+    /// mapping.reconfigure(2, Duration::from_secs(10));
+    /// assert_eq!(mapping.tokens(&1), 2);
+    /// assert_eq!(mapping.tokens(&2), 2);
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `period` is invalid for `L`. See
+    /// `floodgate::RateLimiter::validate`.
+    pub fn reconfigure(&self, capacity: u64, period: Duration) {
+        if let Err(err) = L::validate(capacity, period) {
+            panic!("{err}");
+        }
+        self.capacity.store(capacity, Ordering::Relaxed);
+        self.period.store(nanos(period), Ordering::Relaxed);
+        self.mapping.set_cycle_period(self.cycle_period());
+    }
+
+    /// Give `key` its own capacity and period, instead of the mapping's. Other keys keep using
+    /// the mapping's, including after `FixedMapping::reconfigure`.
+    ///
+    /// If `key` already has a limiter, it keeps its state and adopts the new rate right away;
+    /// see `floodgate::RateLimiter::set_rate`.
+    ///
+    /// # Arguments
+    /// * `key` - The key to override.
+    /// * `capacity` - The capacity for `key`.
+    /// * `period` - The period for `key`.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::new(5, Duration::from_secs(60));
+    /// mapping.trigger(&1);
+    ///
+    /// mapping.set_override(1, 20, Duration::from_secs(60));
+    /// assert_eq!(mapping.tokens(&1), 19);
+    /// assert_eq!(mapping.tokens(&2), 5);
+    ///
+    /// assert!(mapping.clear_override(&1));
+    /// assert_eq!(mapping.tokens(&1), 5);
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `period` is invalid for `L`. See
+    /// `floodgate::RateLimiter::validate`.
+    pub fn set_override(&self, key: K, capacity: u64, period: Duration) {
+        if let Err(err) = L::validate(capacity, period) {
+            panic!("{err}");
+        }
+
+        let now = self.clock.now();
+        self.mapping.with_existing(&key, |bucket| {
+            // bring the window up to date first, so it isn't rescaled from a stale count.
+            bucket.tokens(Some(now));
+            bucket.set_rate(capacity, period);
+        });
+        if self.overrides.insert(key, (capacity, period)).is_none() {
+            self.keyed_rates.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Make `key` use the mapping's capacity and period again. Returns whether it had an
+    /// override. Like `FixedMapping::set_override`, an existing limiter keeps its state.
+    ///
+    /// # Arguments
+    /// * `key` - The key to stop overriding.
+    pub fn clear_override<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let cleared = self.overrides.remove(key).is_some();
+        if cleared {
+            self.keyed_rates.fetch_sub(1, Ordering::Relaxed);
+        }
+        cleared
+    }
+
+    /// Every key with an override, with its capacity and period. See
+    /// `FixedMapping::set_override`.
+    pub fn overrides(&self) -> impl Iterator<Item = (K, u64, Duration)> {
+        // collected first, so that the mapping can be used while iterating.
+        let overrides: Vec<_> = self
+            .overrides
+            .iter()
+            .map(|entry| {
+                let (capacity, period) = *entry.value();
+                (entry.key().clone(), capacity, period)
+            })
+            .collect();
+        overrides.into_iter()
+    }
+
+    /// Scale the mapping's capacity for `key` by `multiplier`, such as `2.0` for a premium
+    /// tier. The scaled capacity is rounded, and is at least 1. Unlike the limiter of a key,
+    /// the multiplier is kept when the key is evicted, and it follows the mapping's capacity
+    /// through `FixedMapping::reconfigure`. An override set with `FixedMapping::set_override`
+    /// takes precedence over it.
+    ///
+    /// If `key` already has a limiter, it keeps its state and adopts the new capacity right
+    /// away, rather than at its next reset; see `floodgate::RateLimiter::set_rate`.
+    ///
+    /// # Arguments
+    /// * `key` - The key to scale.
+    /// * `multiplier` - How much to scale the capacity by.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::new(10, Duration::from_secs(60));
+    /// mapping.set_multiplier(1, 2.5);
+    /// mapping.set_multiplier(2, 0.01);
+    ///
+    /// assert_eq!(mapping.trigger_info(&1).limit, 25);
+    /// assert_eq!(mapping.trigger_info(&2).limit, 1);
+    /// assert_eq!(mapping.multiplier(&1), Some(2.5));
+    ///
+    /// assert!(mapping.clear_multiplier(&1));
+    /// assert_eq!(mapping.status(&1, None).unwrap().limit, 10);
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `multiplier` isn't positive and finite.
+    pub fn set_multiplier(&self, key: K, multiplier: f64) {
+        assert!(
+            multiplier.is_finite() && multiplier > 0.0,
+            "multiplier must be positive and finite, got {multiplier}"
+        );
+
+        if self.multipliers.insert(key.clone(), multiplier).is_none() {
+            self.keyed_rates.fetch_add(1, Ordering::Relaxed);
+        }
+        self.adopt_rate(&key);
+    }
+
+    /// Make `key` use the mapping's capacity unscaled again. Returns whether it had a
+    /// multiplier. Like `FixedMapping::set_multiplier`, an existing limiter keeps its state.
+    ///
+    /// # Arguments
+    /// * `key` - The key to stop scaling.
+    pub fn clear_multiplier<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let cleared = self.multipliers.remove(key).is_some();
+        if cleared {
+            self.keyed_rates.fetch_sub(1, Ordering::Relaxed);
+            self.adopt_rate(key);
+        }
+        cleared
+    }
+
+    /// The multiplier of `key`, if it has one. See `FixedMapping::set_multiplier`.
+    pub fn multiplier<Q>(&self, key: &Q) -> Option<f64>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.multipliers.get(key).map(|multiplier| *multiplier)
+    }
+
+    /// Call `hook` when a key is first rejected, with how long until it can be triggered again.
+    /// The key is then exhausted, and `hook` isn't called for it again until it has been
+    /// reset. See `FixedMapping::on_reset`.
+    ///
+    /// Hooks are called after the mapping has released its locks, so they can use the mapping.
+    /// A panic in a hook is caught, and doesn't reach the code that triggered the key.
+    ///
+    /// # Arguments
+    /// * `hook` - Called with the key and its retry-after.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::{
+    ///     sync::atomic::{AtomicU64, Ordering},
+    ///     time::Duration,
+    /// };
+    ///
+    /// static WARNED: AtomicU64 = AtomicU64::new(0);
+    ///
+    /// let mapping = FixedMapping::new(1, Duration::from_secs(10)).on_exhausted(|user, _| {
+    ///     // send the user a "slow down" message.
+    ///     WARNED.fetch_add(1, Ordering::Relaxed);
+    /// });
+    ///
+    /// for _ in 0..5 {
+    ///     mapping.trigger(&1);
+    /// }
+    /// assert_eq!(WARNED.load(Ordering::Relaxed), 1);
+    /// ```
+    pub fn on_exhausted(mut self, hook: impl Fn(&K, Duration) + Send + Sync + 'static) -> Self {
+        self.hooks.set_on_exhausted(hook);
+        self
+    }
+
+    /// Subscribe to the mapping's events: keys being exhausted, reset, or evicted. See
+    /// `floodgate::RateLimitEvent` and `floodgate::Events`.
+    ///
+    /// Events are only produced while there is at least one subscriber, and a subscriber only
+    /// receives the events produced after it subscribed.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{FixedMapping, RateLimitEvent};
+    /// use futures::StreamExt;
+    /// use std::time::Duration;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let mapping = FixedMapping::new(1, Duration::from_secs(10));
+    /// let mut events = mapping.events();
+    ///
+    /// mapping.trigger(&1);
+    /// mapping.trigger(&1);
+    /// mapping.trigger(&1);
+    ///
+    /// let event = events.next().await.unwrap();
+    /// assert!(matches!(event, RateLimitEvent::Exhausted { key: 1, .. }));
+    /// # }
+    /// ```
+    #[cfg(feature = "stream")]
+    pub fn events(&self) -> Events<K> {
+        self.hooks.events.subscribe()
+    }
+
+    /// Call `hook` when an exhausted key can be triggered again. This is noticed when the key
+    /// is next allowed, or when the mapping is cycled or cleaned up, whichever comes first.
+    ///
+    /// See `FixedMapping::on_exhausted` for how hooks are called.
+    ///
+    /// # Arguments
+    /// * `hook` - Called with the key.
+    pub fn on_reset(mut self, hook: impl Fn(&K) + Send + Sync + 'static) -> Self {
+        self.hooks.set_on_reset(hook);
+        self
+    }
+
+    /// Emit a `tracing` event at the debug level when a key is exhausted, with the key and its
+    /// retry-after. Events are emitted when `FixedMapping::on_exhausted` would be called, and
+    /// cost next to nothing while they are filtered out.
+    ///
+    /// Keys are recorded with their `Debug` impl, which mappings don't otherwise require, so
+    /// their events have to be turned on with this. Cleanups are traced either way.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// // emits a "key exhausted" event, with the key 1 and a retry-after of 10s.
+    /// let mapping = FixedMapping::new(1, Duration::from_secs(10)).traced();
+    /// mapping.trigger(&1);
+    /// mapping.trigger(&1);
+    /// ```
+    #[cfg(feature = "tracing")]
+    pub fn traced(mut self) -> Self
+    where
+        K: fmt::Debug,
+    {
+        self.hooks.set_traced(<K as fmt::Debug>::fmt, None);
+        self
+    }
+
+    /// Emit a `tracing` event at the warn level when a key is rejected more than `rejections`
+    /// times within one window, counted from when it is exhausted until it is reset. Each
+    /// key is warned about at most once per window. This also turns on the events of
+    /// `FixedMapping::traced`.
+    ///
+    /// # Arguments
+    /// * `rejections` - How many rejections of a key per window are expected.
+    #[cfg(feature = "tracing")]
+    pub fn warn_after_rejections(mut self, rejections: u64) -> Self
+    where
+        K: fmt::Debug,
+    {
+        self.hooks
+            .set_traced(<K as fmt::Debug>::fmt, Some(rejections));
+        self
+    }
+
+    /// How the mapping treats triggers. See `floodgate::EnforcementMode`.
+    pub fn mode(&self) -> EnforcementMode {
+        self.mode.get()
+    }
+
+    /// Switch how the mapping treats triggers, for example to stop enforcing it during an
+    /// incident, or to try out a new limit before enforcing it. See
+    /// `floodgate::EnforcementMode`.
+    ///
+    /// # Arguments
+    /// * `mode` - The new mode.
+    pub fn set_mode(&self, mode: EnforcementMode) {
+        self.mode.set(mode);
+    }
+
+    /// How many triggers were let through in `EnforcementMode::DryRun` that would otherwise
+    /// have been rejected.
+    pub fn dry_run_rejections(&self) -> u64 {
+        self.mode.dry_run_rejections()
+    }
+
+    /// Let `key` be triggered without limit, without using or creating its limiter. Exempting a
+    /// blocked key unblocks it.
+    ///
+    /// Exempt keys aren't affected by cycling or cleaning up the mapping, and aren't
+    /// serialized.
+    ///
+    /// # Arguments
+    /// * `key` - The key to exempt.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::new(1, Duration::from_secs(10));
+    /// mapping.exempt("owner".to_string());
+    ///
+    /// assert_eq!(mapping.trigger("owner"), None);
+    /// assert_eq!(mapping.trigger("owner"), None);
+    /// assert!(!mapping.contains_key("owner"));
+    ///
+    /// assert!(mapping.unexempt("owner"));
+    /// assert_eq!(mapping.trigger("owner"), None);
+    /// assert!(mapping.trigger("owner").is_some());
+    /// ```
+    pub fn exempt(&self, key: K) {
+        self.lists.insert(key, Listing::Exempt);
+    }
+
+    /// Stop exempting `key`. Returns whether it was exempt.
+    ///
+    /// # Arguments
+    /// * `key` - The key to stop exempting.
+    pub fn unexempt<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.lists
+            .remove_if(key, |_, listing| matches!(listing, Listing::Exempt))
+            .is_some()
+    }
+
+    /// Whether `key` is exempt. See `FixedMapping::exempt`.
+    pub fn is_exempt<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        matches!(self.lists.get(key).map(|l| *l), Some(Listing::Exempt))
+    }
+
+    /// Every exempt key.
+    pub fn exempted(&self) -> impl Iterator<Item = K> {
+        let exempted: Vec<_> = self
+            .lists
+            .iter()
+            .filter(|entry| matches!(entry.value(), Listing::Exempt))
+            .map(|entry| entry.key().clone())
+            .collect();
+        exempted.into_iter()
+    }
+
+    /// Reject every trigger of `key`, whatever its tokens, until `duration` has passed or
+    /// indefinitely. Blocking an exempt key stops exempting it.
+    ///
+    /// Triggering a blocked key returns the time left on the block, or about 136 years for
+    /// indefinite blocks. Like exempt keys, blocked keys aren't affected by cycling or
+    /// cleaning up the mapping, and aren't serialized.
+    ///
+    /// # Arguments
+    /// * `key` - The key to block.
+    /// * `duration` - How long to block `key` for, or `None` to block it until
+    ///   `FixedMapping::unblock` is called. Durations too long to represent block `key`
+    ///   indefinitely.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::new(5, Duration::from_secs(10));
+    /// mapping.block(1, Some(Duration::from_secs(3600)));
+    ///
+    /// assert!(mapping.trigger(&1).unwrap() > Duration::from_secs(3500));
+    /// assert!(mapping.is_blocked(&1));
+    ///
+    /// assert!(mapping.unblock(&1));
+    /// assert_eq!(mapping.trigger(&1), None);
+    /// ```
+    pub fn block(&self, key: K, duration: Option<Duration>) {
+        let until = duration.and_then(|duration| self.clock.now().checked_add(duration));
+        self.lists.insert(key, Listing::Blocked(until));
+    }
+
+    /// Lift the block on `key`. Returns whether it was blocked.
+    ///
+    /// # Arguments
+    /// * `key` - The key to unblock.
+    pub fn unblock<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.clock.now();
+        self.lists
+            .remove_if(key, |_, listing| match listing {
+                Listing::Blocked(None) => true,
+                Listing::Blocked(Some(until)) => *until > now,
+                Listing::Exempt => false,
+            })
+            .is_some()
+    }
+
+    /// Whether `key` is blocked. See `FixedMapping::block`.
+    pub fn is_blocked<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        matches!(self.listing(key), Some(Err(_)))
+    }
+
+    /// Every blocked key, with the time left on its block, or `None` if it is blocked
+    /// indefinitely.
+    pub fn blocked(&self) -> impl Iterator<Item = (K, Option<Duration>)> {
+        let now = self.clock.now();
+        let blocked: Vec<_> = self
+            .lists
+            .iter()
+            .filter_map(|entry| match *entry.value() {
+                Listing::Blocked(None) => Some((entry.key().clone(), None)),
+                Listing::Blocked(Some(until)) if until > now => {
+                    Some((entry.key().clone(), Some(until - now)))
+                }
+                _ => None,
+            })
+            .collect();
+        blocked.into_iter()
+    }
+
+    /// Make `key` sit out for `duration` longer than its current cooldown, or than the block
+    /// it already has. A key that isn't on cooldown is exhausted for `duration`.
+    ///
+    /// The penalty is a block, so it is reported by `FixedMapping::blocked`, can be lifted
+    /// with `FixedMapping::unblock`, and outlives both the key's window and cleaning up the
+    /// mapping. Penalizing an exempt key stops exempting it, and penalizing a key that is
+    /// blocked indefinitely does nothing.
+    ///
+    /// # Arguments
+    /// * `key` - The key to penalize.
+    /// * `duration` - How much longer `key` has to wait.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{FixedMapping, ManualClock};
+    /// use std::time::Duration;
+    ///
+    /// let clock = ManualClock::new();
+    /// let mapping = FixedMapping::with_clock(1, Duration::from_secs(10), clock.clone());
+    /// mapping.trigger(&1);
+    ///
+    /// mapping.penalize(&1, Duration::from_secs(60));
+    /// assert_eq!(mapping.retry_after(&1), Some(Duration::from_secs(70)));
+    ///
+    /// clock.advance(Duration::from_secs(70));
+    /// assert_eq!(mapping.trigger(&1), None);
+    /// ```
+    pub fn penalize<Q>(&self, key: &Q, duration: Duration)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let now = self.clock.now();
+        let cooldown = self
+            .mapping
+            .with_existing(key, |bucket| bucket.retry_after(Some(now)))
+            .flatten()
+            .unwrap_or_default();
+        let mut until = now.checked_add(cooldown);
+
+        // a block that ends now is the same as no block.
+        let mut listing = self
+            .lists
+            .entry(key.to_owned())
+            .or_insert(Listing::Blocked(Some(now)));
+        match *listing {
+            Listing::Blocked(None) => return,
+            Listing::Blocked(Some(blocked)) => until = until.map(|until| until.max(blocked)),
+            Listing::Exempt => {}
+        }
+        *listing = Listing::Blocked(until.and_then(|until| until.checked_add(duration)));
+    }
+
+    /// Give `key` back `tokens` triggers, without going over its capacity. Unlike
+    /// `FixedMapping::refund`, the tokens go to the key's current window whichever window they
+    /// were taken from, and this works whatever the mapping's mode. Penalties and blocks aren't
+    /// lifted; see `FixedMapping::unblock`.
+    ///
+    /// # Arguments
+    /// * `key` - The key to reward.
+    /// * `tokens` - How many triggers to give back.
+    pub fn reward<Q>(&self, key: &Q, tokens: u64)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.clock.now();
+        self.mapping.with_existing(key, |bucket| {
+            let epoch = bucket.epoch(Some(now));
+            bucket.refund(tokens, epoch, Some(now));
+        });
+        #[cfg(feature = "tokio")]
+        self.waiters.notify_reset(key);
+    }
+
+    /// `Ok` if `key` is exempt, `Err` with the time left if it is blocked, or `None` if it is
+    /// neither. Expired blocks are dropped.
+    fn listing<Q>(&self, key: &Q) -> Option<Result<(), Duration>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let listing = *self.lists.get(key)?;
+        match listing {
+            Listing::Exempt => Some(Ok(())),
+            Listing::Blocked(None) => Some(Err(INDEFINITE_BLOCK)),
+            Listing::Blocked(Some(until)) => {
+                let now = self.clock.now();
+                if until > now {
+                    return Some(Err(until - now));
+                }
+                self.lists.remove_if(
+                    key,
+                    |_, listing| matches!(listing, Listing::Blocked(Some(until)) if *until <= now),
+                );
+                None
+            }
+        }
+    }
+
+    /// Whether a trigger of `key` made in `mode` should be rejected, given the retry-after if
+    /// the limiter rejected it. The trigger is counted in the mapping's stats, and passed on to
+    /// its hooks.
+    fn rejects<Q>(&self, key: &Q, mode: EnforcementMode, retry_after: Option<Duration>) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let rejects = self
+            .mode
+            .rejects(mode, retry_after.is_some(), self.mapping.counters());
+        match retry_after {
+            Some(retry_after) if rejects => self.hooks.rejected(key, retry_after),
+            None => self.hooks.allowed(key),
+            Some(_) => {}
+        }
+        rejects
+    }
+
+    /// The capacity and period of `key`, taking its override into account.
+    fn rate<Q>(&self, key: &Q) -> (u64, Duration)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.keyed_rates.load(Ordering::Relaxed) == 0 {
+            return (self.capacity(), self.period());
+        }
+        if let Some(rate) = self.overrides.get(key) {
+            return *rate;
+        }
+        let capacity = match self.multipliers.get(key) {
+            Some(multiplier) => scaled(self.capacity(), *multiplier),
+            None => self.capacity(),
+        };
+        (capacity, self.period())
+    }
+
+    /// Move the existing limiter of `key`, if any, to the rate `key` should have now.
+    fn adopt_rate<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.clock.now();
+        let (capacity, period) = self.rate(key);
+        let capacity = self.warmed(capacity, now);
+        self.mapping.with_existing(key, |bucket| {
+            // bring the window up to date first, so it isn't rescaled from a stale count.
+            bucket.tokens(Some(now));
+            bucket.set_rate(capacity, period);
+        });
+    }
+
+    /// Run `f` on the limiter for `key`, with the current time from the mapping's clock.
+    fn with_bucket<Q, T>(&self, key: &Q, f: impl FnOnce(&mut L, Option<Instant>) -> T) -> T
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let mut entry = self.entry(key);
+        let now = entry.now();
+        f(&mut entry, now)
+    }
+
+    /// Trigger the limiter of `key`. Limiters that can be triggered through a shared
+    /// reference, such as `floodgate::AtomicJumpingWindow`, are, so that triggers of the same
+    /// key don't block each other.
+    fn trigger_bucket<Q>(&self, key: &Q) -> Option<Duration>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let now = self.clock.now();
+        let (capacity, period) = self.rate(key);
+        let capacity = self.warmed(capacity, now);
+        if let Some(retry_after) = self.mapping.trigger_shared(key, capacity, period, now) {
+            return retry_after;
+        }
+        self.mapping
+            .get_bucket(key, capacity, period, now)
+            .trigger(Some(now))
+    }
+
+    pub fn tokens<Q>(&self, key: &Q) -> u64
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.with_bucket(key, |bucket, now| bucket.tokens(now))
+    }
+
+    pub fn next_reset<Q>(&self, key: &Q) -> Duration
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.with_bucket(key, |bucket, now| bucket.next_reset(now))
+    }
+
+    pub fn retry_after<Q>(&self, key: &Q) -> Option<Duration>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        if self.mode.get() != EnforcementMode::Enforce {
+            return None;
+        }
+        if let Some(listing) = self.listing(key) {
+            return listing.err();
+        }
+        self.with_bucket(key, |bucket, now| bucket.retry_after(now))
+    }
+
+    /// How long until `key` can be triggered `n` times at once, or `None` if it never can.
+    /// This is a pure query: nothing is consumed, and no limiter is stored for `key`. Keys
+    /// without one are answered by the limiter they would be given, so the burst set with
+    /// `FixedMapping::carry_over` counts for them too. See `floodgate::JumpingWindow::wait_for`.
+    ///
+    /// Exempt keys never have to wait, and blocked keys wait at least until the block ends.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::new(5, Duration::from_secs(10));
+    /// assert_eq!(mapping.wait_for(&1, 5), Some(Duration::ZERO));
+    /// assert_eq!(mapping.wait_for(&1, 6), None);
+    ///
+    /// mapping.trigger_n(&1, 3).unwrap();
+    /// assert!(mapping.wait_for(&1, 3).unwrap() > Duration::ZERO);
+    /// ```
+    pub fn wait_for<Q>(&self, key: &Q, n: u64) -> Option<Duration>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.mode.get() != EnforcementMode::Enforce {
+            return Some(Duration::ZERO);
+        }
+        let listing = self.listing(key);
+        if let Some(Ok(())) = listing {
+            return Some(Duration::ZERO);
+        }
+        let now = self.clock.now();
+        let wait = match self
+            .mapping
+            .with_existing(key, |bucket| bucket.wait_for(n, Some(now)))
+        {
+            Some(wait) => wait,
+            // ask the limiter `key` would be given, so that what it carries over counts.
+            None => {
+                let (capacity, period) = self.rate(key);
+                let capacity = self.warmed(capacity, now);
+                self.mapping
+                    .new_limiter(key, capacity, period, now)
+                    .wait_for(n, Some(now))
+            }
+        }?;
+        match listing {
+            Some(Err(blocked)) => Some(wait.max(blocked)),
+            _ => Some(wait),
+        }
+    }
+
+    pub fn can_trigger<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        if self.mode.get() != EnforcementMode::Enforce {
+            return true;
+        }
+        if let Some(listing) = self.listing(key) {
+            return listing.is_ok();
+        }
+        self.with_bucket(key, |bucket, now| bucket.can_trigger(now))
+    }
+
+    pub fn trigger<Q>(&self, key: &Q) -> Option<Duration>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let mode = self.mode.get();
+        if mode == EnforcementMode::Bypass {
+            return None;
+        }
+        let retry_after = match self.listing(key) {
+            Some(listing) => listing.err(),
+            None => self.trigger_bucket(key),
+        };
+        match self.rejects(key, mode, retry_after) {
+            true => retry_after,
+            false => None,
+        }
+    }
+
+    /// Trigger the cooldown for `key`, returning `Err(RateLimited)` with the key if it is on
+    /// cooldown. See `floodgate::JumpingWindow::try_trigger`.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::<String>::new(1, Duration::from_secs(10));
+    ///
+    /// assert_eq!(mapping.try_trigger("alice"), Ok(()));
+    /// let err = mapping.try_trigger("alice").unwrap_err();
+    /// assert_eq!(err.key, "alice");
+    /// ```
+    pub fn try_trigger<Q>(&self, key: &Q) -> Result<(), RateLimited<K>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        match self.trigger(key) {
+            Some(retry_after) => Err(RateLimited {
+                retry_after,
+                key: key.to_owned(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Trigger the cooldown for `key`, returning the resulting state of its limiter. See
+    /// `floodgate::JumpingWindow::trigger_info`.
+    ///
+    /// In `EnforcementMode::DryRun`, the state is reported as it would be when enforcing, so
+    /// `allowed` is `false` for triggers that are only let through because of the mode.
+    pub fn trigger_info<Q>(&self, key: &Q) -> RateLimitInfo
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let mode = self.mode.get();
+        let limit = self.warmed(self.rate(key).0, self.clock.now());
+        let unlimited = RateLimitInfo {
+            allowed: true,
+            limit,
+            remaining: limit,
+            retry_after: None,
+            reset_after: Duration::ZERO,
+        };
+        if mode == EnforcementMode::Bypass {
+            return unlimited;
+        }
+
+        let info = match self.listing(key) {
+            Some(Ok(())) => unlimited,
+            Some(Err(retry_after)) => RateLimitInfo {
+                allowed: false,
+                limit,
+                remaining: 0,
+                retry_after: Some(retry_after),
+                reset_after: retry_after,
+            },
+            None => self.with_bucket(key, |bucket, now| bucket.trigger_info(now)),
+        };
+        let retry_after = match info.allowed {
+            true => None,
+            false => Some(info.retry_after.unwrap_or_default()),
+        };
+        self.rejects(key, mode, retry_after);
+        info
+    }
+
+    /// Like `trigger`, but returns the time at which `key` can be triggered again instead of
+    /// how long that is from now. See `floodgate::JumpingWindow::retry_at`.
+    pub fn trigger_at<Q>(&self, key: &Q) -> Option<Instant>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let mode = self.mode.get();
+        if mode == EnforcementMode::Bypass {
+            return None;
+        }
+        let retry_at = match self.listing(key) {
+            Some(listing) => listing
+                .err()
+                .map(|retry_after| self.clock.now() + retry_after),
+            None => self.with_bucket(key, |bucket, now| {
+                // soft rejections leave tokens, so the deadline comes from the trigger itself.
+                let now = now.unwrap_or_else(|| self.clock.now());
+                bucket
+                    .trigger(Some(now))
+                    .map(|retry_after| now + retry_after)
+            }),
+        };
+        let now = self.clock.now();
+        let retry_after = retry_at.map(|at| at.saturating_duration_since(now));
+        match self.rejects(key, mode, retry_after) {
+            true => retry_at,
+            false => None,
+        }
+    }
+
+    /// Trigger the cooldown for `key`, returning a guard that refunds the token when dropped
+    /// unless `TriggerGuard::commit` is called. If the key can't be triggered, the retry-after
+    /// is returned instead.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::new(1, Duration::from_secs(10));
+    ///
+    /// fn run(mapping: &FixedMapping<u64>, fail: bool) -> Result<(), Duration> {
+    ///     let guard = mapping.trigger_guard(&1)?;
+    ///     if fail {
+    ///         // returning early drops the guard, refunding the token.
+    ///         return Ok(());
+    ///     }
+    ///     guard.commit();
+    ///     Ok(())
+    /// }
+    ///
+    /// run(&mapping, true).unwrap();
+    /// assert_eq!(mapping.tokens(&1), 1);
+    /// run(&mapping, false).unwrap();
+    /// assert_eq!(mapping.tokens(&1), 0);
+    /// assert!(run(&mapping, false).is_err());
+    /// ```
+    pub fn trigger_guard<Q>(&self, key: &Q) -> Result<TriggerGuard<'_, K, L, C, S>, Duration>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        // read before triggering, so that a window ending in between isn't refunded.
+        let epoch = self.epoch(key);
+        match self.trigger(key) {
+            Some(retry_after) => Err(retry_after),
+            None => Ok(TriggerGuard::new(self, key.to_owned(), epoch)),
+        }
+    }
+
+    /// Lock the limiter for `key`, creating it with the mapping's rate for the key if needed,
+    /// to run several operations on it atomically. See `floodgate::EntryGuard`.
+    ///
+    /// The mapping mustn't be used while the guard is held, even for other keys, which may
+    /// deadlock.
+    ///
+    /// # Arguments
+    /// * `key` - The key whose limiter to lock.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::new(3, Duration::from_secs(10));
+    ///
+    /// {
+    ///     let mut entry = mapping.entry(&1);
+    ///     let now = entry.now();
+    ///     // take two tokens only if a third one would be left.
+    ///     if entry.tokens(now) > 2 {
+    ///         entry.trigger(now);
+    ///         entry.trigger(now);
+    ///     }
+    /// }
+    /// assert_eq!(mapping.tokens(&1), 1);
+    /// ```
+    pub fn entry<Q>(&self, key: &Q) -> EntryGuard<'_, K, L, S>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let now = self.clock.now();
+        let (capacity, period) = self.rate(key);
+        let capacity = self.warmed(capacity, now);
+        EntryGuard::new(self.mapping.get_bucket(key, capacity, period, now), now)
+    }
+
+    /// Trigger the cooldown for `key`, consuming `cost` tokens at once. See
+    /// `floodgate::JumpingWindow::trigger_n`.
+    pub fn trigger_n<Q>(&self, key: &Q, cost: u64) -> Result<(), Duration>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let mode = self.mode.get();
+        if mode == EnforcementMode::Bypass {
+            return Ok(());
+        }
+        let result = match self.listing(key) {
+            Some(listing) => listing,
+            None => self.with_bucket(key, |bucket, now| bucket.trigger_n(cost, now)),
+        };
+        match self.rejects(key, mode, result.err()) {
+            true => result,
+            false => Ok(()),
+        }
+    }
+
+    /// Trigger the cooldown of every key in `keys`, or of none of them. A key that appears more
+    /// than once consumes one token per appearance.
+    ///
+    /// The keys are locked together while they are checked and charged, so a concurrent
+    /// trigger of any of them sees either every key charged or none. If one of them can't be
+    /// triggered, `floodgate::TriggerAllError::Limited` is returned with its key and how long
+    /// until it can, and only its rejection is counted in the stats and hooks.
+    ///
+    /// A mapping bounded with `FixedMapping::max_keys` can't hold more distinct keys than its
+    /// bound at once, so a batch of more is rejected with `TriggerAllError::TooManyKeys`
+    /// without touching any limiter. If concurrent insertions keep evicting the batch's keys
+    /// before they can be locked, the batch is given up on with `TriggerAllError::Contended`
+    /// and a short backoff. Neither is counted in the stats or hooks, whatever the enforcement
+    /// mode.
+    ///
+    /// # Arguments
+    /// * `keys` - The keys to trigger.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{FixedMapping, TriggerAllError};
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::new(2, Duration::from_secs(10));
+    /// mapping.trigger(&"global");
+    /// mapping.trigger(&"global");
+    ///
+    /// let err = mapping.trigger_all(&["user", "guild", "global"]).unwrap_err();
+    /// assert!(matches!(err, TriggerAllError::Limited { key: "global", .. }));
+    /// assert_eq!(mapping.tokens(&"user"), 2);
+    /// assert_eq!(mapping.tokens(&"guild"), 2);
+    ///
+    /// assert_eq!(mapping.trigger_all(&["user", "user"]), Ok(()));
+    /// assert_eq!(mapping.tokens(&"user"), 0);
+    /// ```
+    pub fn trigger_all(&self, keys: &[K]) -> Result<(), TriggerAllError<K>> {
+        let mode = self.mode.get();
+        if mode == EnforcementMode::Bypass {
+            return Ok(());
+        }
+
+        // each key once, in order of first appearance, with a token per appearance.
+        let mut costs: Vec<(&K, u64)> = Vec::with_capacity(keys.len());
+        let mut indices: HashMap<&K, usize> = HashMap::with_capacity(keys.len());
+        for key in keys {
+            match indices.entry(key) {
+                hash_map::Entry::Occupied(index) => costs[*index.get()].1 += 1,
+                hash_map::Entry::Vacant(index) => {
+                    index.insert(costs.len());
+                    costs.push((key, 1));
+                }
+            }
+        }
+
+        let now = self.clock.now();
+        let mut charges = Vec::with_capacity(costs.len());
+        for (key, cost) in costs {
+            match self.listing(key) {
+                Some(Ok(())) => continue,
+                Some(Err(retry_after)) => match self.rejects(key, mode, Some(retry_after)) {
+                    true => {
+                        return Err(TriggerAllError::Limited {
+                            key: key.clone(),
+                            retry_after,
+                        })
+                    }
+                    false => continue,
+                },
+                None => {}
+            }
+            let (capacity, period) = self.rate(key);
+            charges.push(Charge {
+                key,
+                cost,
+                capacity: self.warmed(capacity, now),
+                period,
+            });
+        }
+
+        match self.mapping.trigger_all(&charges, now) {
+            Ok(()) => {
+                for charge in &charges {
+                    self.rejects(charge.key, mode, None);
+                }
+                Ok(())
+            }
+            Err(BatchError::Limited(index, retry_after)) => {
+                let key = charges[index].key;
+                match self.rejects(key, mode, Some(retry_after)) {
+                    true => Err(TriggerAllError::Limited {
+                        key: key.clone(),
+                        retry_after,
+                    }),
+                    false => Ok(()),
+                }
+            }
+            Err(BatchError::TooManyKeys(max_keys)) => Err(TriggerAllError::TooManyKeys {
+                keys: charges.len(),
+                max_keys,
+            }),
+            Err(BatchError::Contended) => Err(TriggerAllError::Contended {
+                retry_after: CONTENDED_BACKOFF,
+            }),
+        }
+    }
+
+    /// The keys that are on cooldown, with the state of their limiters. Keys with a full
+    /// limiter, including those whose window has expired, are skipped. `allowed` is whether
+    /// the key could be triggered right now.
+    ///
+    /// Every entry is computed from the same instant, and they are all collected before the
+    /// iterator is returned. The mapping can be used freely while iterating, but changes made
+    /// in the meantime aren't reflected.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::new(2, Duration::from_secs(10));
+    /// mapping.trigger(&1);
+    /// mapping.trigger(&2);
+    /// mapping.trigger(&2);
+    /// mapping.reset(&1);
+    ///
+    /// let cooldowns: Vec<_> = mapping.iter().collect();
+    /// assert_eq!(cooldowns.len(), 1);
+    ///
+    /// let (key, info) = cooldowns[0];
+    /// assert_eq!(key, 2);
+    /// assert!(!info.allowed);
+    /// assert_eq!(info.remaining, 0);
+    /// assert_eq!(info.retry_after, Some(info.reset_after));
+    /// ```
+    pub fn iter(&self) -> impl Iterator<Item = (K, RateLimitInfo)> {
+        self.mapping.cooldowns(self.clock.now()).into_iter()
+    }
+
+    /// How much of their capacity the stored limiters are using, all computed from the same
+    /// instant. Limiters whose window has expired count as unused, and are reset.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::new(4, Duration::from_secs(10));
+    /// mapping.trigger(&1);
+    /// mapping.trigger_n(&2, 3).unwrap();
+    ///
+    /// let utilization = mapping.utilization();
+    /// assert_eq!(utilization.keys, 2);
+    /// assert_eq!(utilization.mean, 0.5);
+    /// assert_eq!(utilization.max, 0.75);
+    /// ```
+    pub fn utilization(&self) -> UtilizationStats {
+        self.mapping.utilization(self.clock.now())
+    }
+
+    /// The state of `key`'s limiter, without consuming a token or creating a limiter. `None`
+    /// if the mapping isn't storing a limiter for `key`, which is also the case for exempt
+    /// keys. Blocked keys are reported as not allowed until the block ends.
+    ///
+    /// Here `allowed` is whether `key` could be triggered now. The state is reported as it is,
+    /// whatever the mapping's `floodgate::EnforcementMode`.
+    ///
+    /// # Arguments
+    /// * `key` - The key to check.
+    /// * `now` - Optionally specify the current time, instead of reading the mapping's clock.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::new(1, Duration::from_secs(10));
+    /// assert_eq!(mapping.status(&1, None), None);
+    ///
+    /// mapping.trigger(&1);
+    /// let status = mapping.status(&1, None).unwrap();
+    /// assert!(!status.allowed);
+    /// assert!(status.retry_after.is_some());
+    /// assert_eq!(mapping.status(&1, None).unwrap().remaining, 0);
+    /// ```
+    pub fn status<Q>(&self, key: &Q, now: Option<Instant>) -> Option<RateLimitInfo>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = now.unwrap_or_else(|| self.clock.now());
+        match self.listing(key) {
+            Some(Ok(())) => None,
+            Some(Err(retry_after)) => Some(RateLimitInfo {
+                allowed: false,
+                limit: self.warmed(self.rate(key).0, now),
+                remaining: 0,
+                retry_after: Some(retry_after),
+                reset_after: retry_after,
+            }),
+            None => self.mapping.status(key, now),
+        }
+    }
+
+    /// The state of each of `keys`, all computed from the same instant. See
+    /// `FixedMapping::status`.
+    ///
+    /// # Arguments
+    /// * `keys` - The keys to check.
+    /// * `now` - Optionally specify the current time, instead of reading the mapping's clock.
+    pub fn status_many(&self, keys: &[K], now: Option<Instant>) -> Vec<Option<RateLimitInfo>> {
+        let now = now.unwrap_or_else(|| self.clock.now());
+        keys.iter().map(|key| self.status(key, Some(now))).collect()
+    }
+
+    /// Take a snapshot of every key that is on cooldown, to hand over to another mapping with
+    /// `FixedMapping::import`, possibly in another process.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{FixedMapping, MergeStrategy};
+    /// use std::time::Duration;
+    ///
+    /// let old = FixedMapping::new(2, Duration::from_secs(10));
+    /// old.trigger(&1);
+    /// old.trigger(&2);
+    /// old.trigger(&2);
+    ///
+    /// let new = FixedMapping::new(2, Duration::from_secs(10));
+    /// assert_eq!(new.import(old.export(), MergeStrategy::Overwrite), 2);
+    /// assert_eq!(new.tokens(&1), 1);
+    /// assert!(new.trigger(&2).is_some());
+    /// ```
+    pub fn export(&self) -> MappingSnapshot<K> {
+        let now = self.clock.now();
+        let mut entries = Vec::new();
+        self.mapping.for_each_mut(|key, bucket| {
+            entries.extend(snapshot_entry(key, bucket, now));
+        });
+        MappingSnapshot {
+            taken_at: SystemTime::now(),
+            entries,
+        }
+    }
+
+    /// Remove every key that matches `predicate`, returning a snapshot of those on cooldown,
+    /// such as to move a guild's keys to the mapping of another shard with
+    /// `FixedMapping::import`. See `FixedMapping::export` and `FixedMapping::remove_matching`.
+    ///
+    /// # Arguments
+    /// * `predicate` - Whether to extract a key.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{FixedMapping, MergeStrategy};
+    /// use std::time::Duration;
+    ///
+    /// let here = FixedMapping::new(2, Duration::from_secs(10));
+    /// here.trigger(&("guild-1", 1));
+    /// here.trigger(&("guild-2", 1));
+    ///
+    /// let there = FixedMapping::new(2, Duration::from_secs(10));
+    /// let moved = here.extract_matching(|(guild, _)| *guild == "guild-1");
+    /// assert_eq!(there.import(moved, MergeStrategy::KeepMoreRestrictive), 1);
+    ///
+    /// assert_eq!(there.tokens(&("guild-1", 1)), 1);
+    /// assert!(!here.contains_key(&("guild-1", 1)));
+    /// assert!(here.contains_key(&("guild-2", 1)));
+    /// ```
+    pub fn extract_matching(&self, mut predicate: impl FnMut(&K) -> bool) -> MappingSnapshot<K> {
+        let now = self.clock.now();
+        let mut entries = Vec::new();
+        self.mapping.remove_matching(|key, bucket| {
+            if !predicate(key) {
+                return false;
+            }
+            entries.extend(snapshot_entry(key, bucket, now));
+            #[cfg(feature = "tokio")]
+            self.waiters.notify_reset(key);
+            true
+        });
+        MappingSnapshot {
+            taken_at: SystemTime::now(),
+            entries,
+        }
+    }
+
+    /// Restore the keys of a snapshot taken by `FixedMapping::export`, returning how many
+    /// keys took their state from it.
+    ///
+    /// The time since the snapshot was taken counts towards each key's window, and keys whose
+    /// window has ended since are skipped. Each key keeps its own rate in this mapping, so a
+    /// key with more tokens than its capacity is capped. The restored tokens are treated as
+    /// having been consumed at the start of the window.
+    ///
+    /// # Arguments
+    /// * `snapshot` - The snapshot to restore.
+    /// * `strategy` - What to do with keys that already have a limiter in this mapping.
+    pub fn import(&self, snapshot: MappingSnapshot<K>, strategy: MergeStrategy) -> usize {
+        let transit = SystemTime::now()
+            .duration_since(snapshot.taken_at)
+            .unwrap_or_default();
+        let mut imported = 0;
+        for entry in snapshot.entries {
+            let elapsed = entry.elapsed.saturating_add(transit);
+            if elapsed >= self.rate(&entry.key).1 {
+                continue;
+            }
+            let restored = self.with_bucket(&entry.key, |bucket, now| {
+                let tokens = entry.tokens.min(bucket.capacity());
+                // a full limiter, including one that was only just created, has no state worth
+                // keeping.
+                let existing = (
+                    bucket.tokens(now),
+                    bucket.period().saturating_sub(bucket.next_reset(now)),
+                );
+                if existing.0 < bucket.capacity() && !strategy.replaces((tokens, elapsed), existing)
+                {
+                    return false;
+                }
+
+                let start = now.and_then(|now| now.checked_sub(elapsed)).or(now);
+                bucket.reset(start);
+                let _ = bucket.trigger_n(bucket.capacity() - tokens, start);
+                true
+            });
+            if restored {
+                imported += 1;
+                #[cfg(feature = "tokio")]
+                self.waiters.notify_reset(&entry.key);
+            }
+        }
+        imported
+    }
+
+    /// Reset the cooldown for `key`, refilling its tokens. Unlike `trigger`, this doesn't
+    /// create a limiter, so resetting a key the mapping isn't storing does nothing.
+    ///
+    /// # Arguments
+    /// * `key` - The key to reset.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::new(1, Duration::from_secs(10));
+    /// mapping.trigger(&1);
+    /// assert!(mapping.trigger(&1).is_some());
+    ///
+    /// mapping.reset(&1);
+    /// assert_eq!(mapping.trigger(&1), None);
+    ///
+    /// mapping.reset(&2);
+    /// assert!(!mapping.contains_key(&2));
+    /// ```
+    pub fn reset<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let now = self.clock.now();
+        self.mapping
+            .with_existing(key, |bucket| bucket.reset(Some(now)));
+        #[cfg(feature = "tokio")]
+        self.waiters.notify_reset(key);
+    }
+
+    /// Reset the cooldown of every key the mapping is storing. See `FixedMapping::reset`.
+    pub fn reset_all(&self) {
+        let now = self.clock.now();
+        self.mapping
+            .for_each_mut(|_, bucket| bucket.reset(Some(now)));
+        #[cfg(feature = "tokio")]
+        self.waiters.notify_reset_all();
+    }
+
+    /// Reset the cooldown of every stored key that matches `predicate`, such as every key of
+    /// one command when keying by `(command, user)`. Returns how many keys were reset. See
+    /// `FixedMapping::reset`.
+    ///
+    /// Keys are visited in place, without collecting or cloning them first. `predicate` is
+    /// called while a part of the mapping is locked, so it must not use the mapping.
+    ///
+    /// # Arguments
+    /// * `predicate` - Whether to reset a key.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::new(1, Duration::from_secs(10));
+    /// mapping.trigger(&("ping", 1));
+    /// mapping.trigger(&("ping", 2));
+    /// mapping.trigger(&("ban", 1));
+    ///
+    /// assert_eq!(mapping.reset_matching(|(command, _)| *command == "ping"), 2);
+    /// assert_eq!(mapping.trigger(&("ping", 1)), None);
+    /// assert!(mapping.trigger(&("ban", 1)).is_some());
+    /// ```
+    pub fn reset_matching(&self, mut predicate: impl FnMut(&K) -> bool) -> usize {
+        let now = self.clock.now();
+        let mut reset = 0;
+        self.mapping.for_each_mut(|key, bucket| {
+            if predicate(key) {
+                bucket.reset(Some(now));
+                reset += 1;
+                #[cfg(feature = "tokio")]
+                self.waiters.notify_reset(key);
+            }
+        });
+        reset
+    }
+
+    /// Drop the limiter of every stored key that matches `predicate`, returning how many
+    /// were dropped. Like `FixedMapping::remove`, overrides, multipliers and lists are kept.
+    /// See `FixedMapping::reset_matching`.
+    ///
+    /// # Arguments
+    /// * `predicate` - Whether to remove a key.
+    pub fn remove_matching(&self, mut predicate: impl FnMut(&K) -> bool) -> usize {
+        self.mapping.remove_matching(|key, _| {
+            let remove = predicate(key);
+            #[cfg(feature = "tokio")]
+            if remove {
+                self.waiters.notify_reset(key);
+            }
+            remove
+        })
+    }
+
+    /// Drop the limiter for `key`, so that its next trigger starts a fresh window. Returns
+    /// whether the mapping was storing a limiter for `key`.
+    ///
+    /// # Arguments
+    /// * `key` - The key to remove.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// let mapping = FixedMapping::new(1, Duration::from_secs(10));
+    /// mapping.trigger(&1);
+    ///
+    /// assert!(mapping.remove(&1));
+    /// assert!(!mapping.remove(&1));
+    /// assert_eq!(mapping.trigger(&1), None);
+    /// ```
+    pub fn remove<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let removed = self.mapping.remove(key);
+        #[cfg(feature = "tokio")]
+        self.waiters.notify_reset(key);
+        removed
+    }
+
+    /// A number identifying the current window of `key`, to read before triggering it for
+    /// `FixedMapping::refund`. Creates the key's limiter if it doesn't have one yet, as
+    /// triggering it would. See `floodgate::JumpingWindow::epoch`.
+    pub fn epoch<Q>(&self, key: &Q) -> u64
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.with_bucket(key, |bucket, now| bucket.epoch(now))
+    }
+
+    /// Give back `n` tokens taken from the window of `key` that `epoch` identifies, or nothing
+    /// if it has ended. See `floodgate::JumpingWindow::refund`.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{FixedMapping, ManualClock};
+    /// use std::time::Duration;
+    ///
+    /// let clock = ManualClock::new();
+    /// let mapping = FixedMapping::with_clock(2, Duration::from_secs(10), clock.clone());
+    ///
+    /// let epoch = mapping.epoch(&1);
+    /// mapping.trigger(&1);
+    /// // the action failed, so the user shouldn't be charged for it.
+    /// mapping.refund(&1, 1, epoch);
+    /// assert_eq!(mapping.tokens(&1), 2);
+    ///
+    /// let epoch = mapping.epoch(&1);
+    /// mapping.trigger(&1);
+    /// clock.advance(Duration::from_secs(11));
+    /// mapping.trigger(&1);
+    ///
+    /// // the token came from the window that ended, so it isn't given back.
+    /// mapping.refund(&1, 1, epoch);
+    /// assert_eq!(mapping.tokens(&1), 1);
+    /// ```
+    pub fn refund<Q>(&self, key: &Q, n: u64, epoch: u64)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.mode.get() == EnforcementMode::Bypass {
+            return;
+        }
+        if let Some(Ok(())) = self.listing(key) {
+            return;
+        }
+        let now = self.clock.now();
+        self.mapping
+            .with_existing(key, |bucket| bucket.refund(n, epoch, Some(now)));
+    }
+
+    /// Cycles the mapping. Returns `true` if it cycled, or `false` if not.
+    pub fn cycle(&self) -> bool {
+        let now = self.clock.now();
+        let cycled = self.mapping.cycle(now);
+        self.run_reset_hooks(now);
+        cycled
+    }
+
+    /// Cycle the mapping from a cycler, warning if it was cycled too soon.
+    pub(crate) fn cycle_or_warn(&self) {
+        if !self.cycle() {
+            eprintln!("Cycler attempted to call the mapping too soon.");
+        }
+    }
+
+    /// Call the `FixedMapping::on_reset` hook for every exhausted key that can be triggered
+    /// again at `now`, or whose limiter was dropped.
+    fn run_reset_hooks(&self, now: Instant) {
+        self.hooks.reset_where(|key| {
+            !self.is_blocked(key)
+                && self
+                    .mapping
+                    .with_existing(key, |bucket| bucket.can_trigger(Some(now)))
+                    .unwrap_or(true)
+        });
+    }
+
+    /// Drop the limiters of keys that aren't on cooldown, returning how many were dropped.
+    ///
+    /// A limiter is dropped once its tokens are back at capacity, which for the default
+    /// `floodgate::JumpingWindow` means its window has expired. Keys in the middle of a
+    /// cooldown are never dropped, so this can be called at any time, for example from a
+    /// maintenance loop instead of starting the background cycler.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let mapping = FixedMapping::new(1, Duration::from_secs(10));
+    /// mapping.trigger(&1);
+    /// mapping.tokens(&2);
+    ///
+    /// assert_eq!(mapping.cleanup(None), 1);
+    /// assert!(mapping.contains_key(&1));
+    ///
+    /// let later = Instant::now() + Duration::from_secs(10);
+    /// assert_eq!(mapping.cleanup(Some(later)), 1);
+    /// assert!(mapping.is_empty());
+    /// ```
+    pub fn cleanup(&self, now: Option<Instant>) -> usize {
+        let now = now.unwrap_or_else(|| self.clock.now());
+        let evicted = self.mapping.cleanup(now);
+        self.run_reset_hooks(now);
+        evicted
+    }
+
+    /// Like `FixedMapping::cleanup`, but returns the keys whose limiters were dropped, for
+    /// cleaning up state kept alongside them elsewhere. Keys on cooldown are never drained.
+    ///
+    /// The keys are collected while the mapping is locked a part at a time, and returned once
+    /// every lock is released, so they can be processed while the mapping is used. Limiters
+    /// dropped by the background cycler or `FixedMapping::cleanup` are reported as
+    /// `floodgate::RateLimitEvent::Evicted` instead, with the `stream` feature.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let mapping = FixedMapping::new(1, Duration::from_secs(10));
+    /// mapping.trigger(&1);
+    /// mapping.tokens(&2);
+    ///
+    /// assert_eq!(mapping.drain_expired(None), vec![2]);
+    ///
+    /// let later = Instant::now() + Duration::from_secs(10);
+    /// assert_eq!(mapping.drain_expired(Some(later)), vec![1]);
+    /// assert!(mapping.is_empty());
+    /// ```
+    pub fn drain_expired(&self, now: Option<Instant>) -> Vec<K> {
+        let now = now.unwrap_or_else(|| self.clock.now());
+        let keys = self.mapping.drain_expired(now);
+        self.run_reset_hooks(now);
+        keys
+    }
+
+    /// Start the background cycler, returning a handle to stop it with.
+    ///
+    /// If, for some reason, you don't want to use the default cycler, you must manually call
+    /// the `.cycle` method on the mapping periodically.
+    ///
+    /// The cycler only holds a weak reference to the mapping, so it exits once the mapping is
+    /// dropped. See `floodgate::CleanupHandle`.
+    ///
+    /// Not available on `wasm32-unknown-unknown`, which has no threads. There, the mapping
+    /// cycles itself whenever it is used and its cycle period has passed.
+    ///
+    /// # Arguments
+    /// * `mapping` - The FixedMapping, wrapped in an Arc.
+    /// * `cycle_period` - How often to cycle the mapping. If specified, must be greater than the
+    ///   mapping's period. See `FixedMapping::set_cycle_period`.
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn start(mapping: Arc<Self>, cycle_period: Option<Duration>) -> CleanupHandle
+    where
+        L: Send + Sync + 'static,
+        C: Send + Sync + 'static,
+        S: Send + Sync + 'static,
+    {
+        if let Some(cycle_period) = cycle_period {
+            mapping.set_cycle_period(cycle_period);
+        }
+
+        let first = mapping.cycle_period();
+        let mapping = Arc::downgrade(&mapping);
+        CleanupHandle::spawn(first, move || {
+            let mapping = mapping.upgrade()?;
+            mapping.cycle_or_warn();
+            Some(mapping.cycle_period())
+        })
+    }
+
+    /// Start a thread that calls `FixedMapping::cleanup` every `interval`, returning a handle
+    /// to stop it with. Unlike `FixedMapping::start`, the interval can be shorter than the
+    /// mapping's period, for dropping limiters soon after they are full again.
+    ///
+    /// Neither needs an async runtime, so the mapping can be used without the `tokio`
+    /// feature. The thread only holds a weak reference to the mapping, like the cycler.
+    ///
+    /// Not available on `wasm32-unknown-unknown`, which has no threads.
+    ///
+    /// # Arguments
+    /// * `mapping` - The FixedMapping, wrapped in an Arc.
+    /// * `interval` - How often to clean the mapping up.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::{sync::Arc, thread, time::Duration};
+    ///
+    /// let mapping = Arc::new(FixedMapping::new(1, Duration::from_millis(10)));
+    /// let cleaner = FixedMapping::start_thread(mapping.clone(), Duration::from_millis(5));
+    ///
+    /// mapping.trigger(&1);
+    /// thread::sleep(Duration::from_millis(50));
+    /// assert!(mapping.is_empty());
+    /// cleaner.stop();
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `interval` is zero.
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn start_thread(mapping: Arc<Self>, interval: Duration) -> CleanupHandle
+    where
+        L: Send + Sync + 'static,
+        C: Send + Sync + 'static,
+        S: Send + Sync + 'static,
+    {
+        assert!(!interval.is_zero(), "the cleanup interval must be positive");
+
+        let mapping = Arc::downgrade(&mapping);
+        CleanupHandle::spawn(interval, move || {
+            mapping.upgrade()?.cleanup(None);
+            Some(interval)
+        })
+    }
+}
+
+/// The state of `bucket` at `now` for a snapshot, if it is on cooldown.
+fn snapshot_entry<K: Clone, L: RateLimiter>(
+    key: &K,
+    bucket: &mut L,
+    now: Instant,
+) -> Option<SnapshotEntry<K>> {
+    let tokens = bucket.tokens(Some(now));
+    if tokens >= bucket.capacity() {
+        return None;
+    }
+    let elapsed = bucket.period().saturating_sub(bucket.next_reset(Some(now)));
+    Some(SnapshotEntry {
+        key: key.clone(),
+        tokens,
+        elapsed,
+    })
+}
+
+/// `capacity` scaled by `multiplier`, rounded to the nearest token and at least 1.
+fn scaled(capacity: u64, multiplier: f64) -> u64 {
+    ((capacity as f64 * multiplier).round() as u64).max(1)
+}
+
+/// Serialized as the capacity, the period, the overrides, the multipliers, and the limiter of
+/// each key. The
+/// cycler isn't saved, so it has to be started again after deserializing.
+#[cfg(feature = "serde")]
+impl<K, L, C, H> serde::Serialize for FixedMapping<K, L, C, H>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + serde::Serialize,
+    L: RateLimiter + serde::Serialize,
+    C: Clock,
+    H: BuildHasher + Clone,
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("FixedMapping", 5)?;
+        state.serialize_field("capacity", &self.capacity())?;
+        state.serialize_field("period", &self.period())?;
+        let overrides: Vec<_> = self.overrides().collect();
+        state.serialize_field("overrides", &overrides)?;
+        let multipliers: Vec<_> = self
+            .multipliers
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect();
+        state.serialize_field("multipliers", &multipliers)?;
+        state.serialize_field("limiters", &self.mapping)?;
+        state.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, K, L> serde::Deserialize<'de> for FixedMapping<K, L>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + serde::Deserialize<'de>,
+    L: RateLimiter + serde::Deserialize<'de>,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        #[serde(rename = "FixedMapping")]
+        struct State<K, L> {
+            capacity: u64,
+            period: Duration,
+            #[serde(default = "Vec::new")]
+            overrides: Vec<(K, u64, Duration)>,
+            #[serde(default = "Vec::new")]
+            multipliers: Vec<(K, f64)>,
+            limiters: Vec<(K, L)>,
+        }
+
+        let state = State::<K, L>::deserialize(deserializer)?;
+        L::validate(state.capacity, state.period).map_err(serde::de::Error::custom)?;
+
+        let mapping = Self::with_limiter(state.capacity, state.period);
+        for (key, capacity, period) in state.overrides {
+            L::validate(capacity, period).map_err(serde::de::Error::custom)?;
+            if mapping.overrides.insert(key, (capacity, period)).is_none() {
+                mapping.keyed_rates.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        for (key, multiplier) in state.multipliers {
+            if !(multiplier.is_finite() && multiplier > 0.0) {
+                let message = format!("multiplier must be positive and finite, got {multiplier}");
+                return Err(serde::de::Error::custom(message));
+            }
+            if mapping.multipliers.insert(key, multiplier).is_none() {
+                mapping.keyed_rates.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        for (key, limiter) in state.limiters {
+            mapping.mapping.insert(key, limiter, mapping.clock.now());
+        }
+        Ok(mapping)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex, Weak,
+        },
+        time::Duration,
+    };
+
+    use super::FixedMapping;
+    use crate::{
+        clock::SystemTime, Clock, EnforcementMode, ManualClock, MappingSnapshot, MergeStrategy,
+        Shedding, SnapshotEntry, TriggerAllError,
+    };
+
+    #[test]
+    #[should_panic(expected = "period must be greater than zero")]
+    fn reconfiguring_to_a_zero_period_panics() {
+        let mapping = FixedMapping::<u64>::new(1, Duration::from_secs(10));
+        mapping.reconfigure(1, Duration::ZERO);
+    }
+
+    #[test]
+    fn trigger_guards_dropped_after_a_reset_refund_nothing() {
+        let clock = ManualClock::new();
+        let mapping = FixedMapping::with_clock(2, Duration::from_secs(10), clock.clone());
+
+        let guard = mapping.trigger_guard(&1).unwrap();
+        clock.advance(Duration::from_secs(11));
+        assert_eq!(mapping.trigger(&1), None);
+        // the guard's token came from the window that ended.
+        drop(guard);
+        assert_eq!(mapping.tokens(&1), 1);
+
+        // a guard dropped in the same window still refunds its token.
+        drop(mapping.trigger_guard(&1).unwrap());
+        assert_eq!(mapping.tokens(&1), 1);
+    }
+
+    #[test]
+    fn blocking_for_longer_than_time_goes_blocks_indefinitely() {
+        let mapping = FixedMapping::new(1, Duration::from_secs(10));
+        mapping.block(1, Some(Duration::MAX));
+
+        assert!(mapping.is_blocked(&1));
+        assert_eq!(mapping.blocked().collect::<Vec<_>>(), [(1, None)]);
+    }
+
+    #[test]
+    fn trigger_at_reports_shed_triggers_as_rejected() {
+        let clock = ManualClock::new();
+        let mapping = FixedMapping::with_clock(10, Duration::from_secs(10), clock.clone())
+            .load_shedding(Shedding::new(0.0).rng(|_| 0));
+
+        assert_eq!(mapping.trigger_at(&1), None);
+        for _ in 0..9 {
+            let retry_at = mapping.trigger_at(&1).unwrap();
+            assert!(retry_at > clock.now());
+        }
+        assert_eq!(mapping.tokens(&1), 9);
+    }
+
+    #[test]
+    fn manual_clock_drives_cycling() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(60);
+        let mapping = FixedMapping::with_clock(1, period, clock.clone());
+
+        mapping.trigger(&1);
+        assert!(!mapping.cycle());
+
+        clock.advance(period);
+        assert!(mapping.cycle());
+        assert_eq!(mapping.len(), 1);
+
+        clock.advance(period);
+        assert!(mapping.cycle());
+        assert_eq!(mapping.len(), 0);
+    }
+
+    #[test]
+    fn blocks_expire_and_lists_survive_cleanup() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(60);
+        let mapping = FixedMapping::with_clock(1, period, clock.clone());
+        mapping.exempt(1);
+        mapping.block(2, Some(period));
+        mapping.block(3, None);
+
+        assert_eq!(mapping.trigger_n(&1, 5), Ok(()));
+        assert_eq!(mapping.trigger(&2), Some(period));
+        assert!(mapping.trigger(&3).unwrap() > period * 1000);
+        assert!(!mapping.can_trigger(&3));
+        assert_eq!(mapping.len(), 0);
+
+        clock.advance(period / 2);
+        assert_eq!(mapping.retry_after(&2), Some(period / 2));
+        assert_eq!(mapping.blocked().count(), 2);
+        assert_eq!(mapping.cleanup(None), 0);
+        assert!(mapping.is_exempt(&1) && mapping.is_blocked(&3));
+
+        clock.advance(period / 2);
+        assert!(!mapping.is_blocked(&2));
+        assert_eq!(mapping.trigger(&2), None);
+        assert_eq!(mapping.blocked().collect::<Vec<_>>(), vec![(3, None)]);
+        assert_eq!(mapping.exempted().collect::<Vec<_>>(), vec![1]);
+
+        mapping.block(1, None);
+        assert!(!mapping.is_exempt(&1));
+        assert!(!mapping.unexempt(&1));
+        assert!(mapping.unblock(&1));
+        assert!(mapping.can_trigger(&1));
+    }
+
+    #[test]
+    fn multipliers_outlive_cleanup_and_follow_reconfigure() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(60);
+        let mapping = FixedMapping::with_clock(4, period, clock.clone());
+        mapping.trigger(&1);
+        mapping.set_multiplier(1, 0.5);
+        assert_eq!(mapping.status(&1, None).unwrap().limit, 2);
+        // the 3 tokens left are capped at the new capacity.
+        assert_eq!(mapping.tokens(&1), 2);
+
+        clock.advance(period);
+        assert_eq!(mapping.cleanup(None), 1);
+        assert_eq!(mapping.trigger_n(&1, 2), Ok(()));
+        assert_eq!(mapping.trigger_info(&1).limit, 2);
+        assert_eq!(mapping.trigger(&1), Some(period));
+
+        mapping.reconfigure(10, period);
+        assert_eq!(mapping.trigger_info(&1).limit, 5);
+        mapping.set_override(1, 3, period);
+        assert_eq!(mapping.trigger_info(&1).limit, 3);
+        assert!(mapping.clear_override(&1));
+        assert_eq!(mapping.trigger_info(&1).limit, 5);
+    }
+
+    #[test]
+    fn matching_keys_are_reset_and_removed_together() {
+        let period = Duration::from_secs(60);
+        let mapping = FixedMapping::new(1, period);
+        for key in [("ping", 1), ("ping", 2), ("ban", 1)] {
+            mapping.trigger(&key);
+        }
+        assert_eq!(mapping.reset_matching(|_| false), 0);
+        assert_eq!(mapping.remove_matching(|(_, user)| *user == 1), 2);
+        assert_eq!(mapping.len(), 1);
+        assert!(mapping.trigger(&("ping", 2)).is_some());
+        assert_eq!(mapping.trigger(&("ban", 1)), None);
+    }
+
+    #[test]
+    fn entries_start_with_the_rate_of_their_key() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(60);
+        let mapping = FixedMapping::with_clock(2, period, clock.clone());
+        mapping.set_override(1, 5, period);
+
+        let mut entry = mapping.entry(&1);
+        let now = entry.now();
+        assert_eq!(*entry.key(), 1);
+        assert_eq!(entry.capacity(), 5);
+        assert_eq!(entry.trigger_n(5, now), Ok(()));
+        drop(entry);
+        assert!(mapping.trigger(&1).is_some());
+
+        clock.advance(period);
+        let mut entry = mapping.entry(&2);
+        assert_eq!(entry.tokens(None), 2);
+        drop(entry);
+        assert_eq!(mapping.len(), 2);
+    }
+
+    #[test]
+    fn penalties_outlive_the_window_and_cleanup() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(60);
+        let mapping = FixedMapping::with_clock(2, period, clock.clone());
+        mapping.trigger_n(&1, 2).unwrap();
+        mapping.penalize(&1, period);
+        mapping.penalize(&2, period);
+        mapping.penalize(&2, period);
+        assert_eq!(mapping.retry_after(&1), Some(period * 2));
+        assert_eq!(mapping.retry_after(&2), Some(period * 2));
+        assert!(!mapping.contains_key(&2));
+
+        clock.advance(period);
+        assert_eq!(mapping.cleanup(None), 1);
+        assert_eq!(mapping.trigger(&1), Some(period));
+        mapping.reward(&2, 1);
+        assert!(!mapping.contains_key(&2));
+
+        clock.advance(period);
+        assert_eq!(mapping.trigger_n(&1, 2), Ok(()));
+        mapping.reward(&1, 5);
+        assert_eq!(mapping.tokens(&1), 2);
+        assert_eq!(mapping.blocked().count(), 0);
+    }
+
+    #[test]
+    fn stats_count_concurrent_triggers_and_evictions() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(60);
+        let mapping = FixedMapping::with_clock(100, period, clock.clone()).with_stats();
+
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for key in 0..1_000 {
+                        mapping.trigger(&(key % 2));
+                    }
+                });
+            }
+        });
+
+        mapping.set_mode(EnforcementMode::DryRun);
+        mapping.trigger(&0);
+        clock.advance(period);
+        assert_eq!(mapping.cleanup(None), 2);
+
+        let stats = mapping.stats();
+        assert_eq!(stats.accepted, 200);
+        assert_eq!(stats.rejected, 7_800);
+        assert_eq!(stats.dry_run_rejected, 1);
+        assert_eq!(stats.keys_created, 2);
+        assert_eq!(stats.evicted, 2);
+    }
+
+    #[test]
+    fn incremental_cleanup_only_visits_due_keys() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(60);
+        let mapping = FixedMapping::with_clock(2, period, clock.clone()).incremental_cleanup(3);
+        for key in 0..10 {
+            mapping.trigger(&key);
+        }
+        clock.advance(period);
+        assert!(mapping.cycle());
+        assert!(mapping.is_empty());
+
+        mapping.trigger(&1);
+        mapping.trigger(&2);
+        mapping.remove(&2);
+        clock.advance(period / 2);
+        mapping.trigger(&2);
+        mapping.trigger(&3);
+        clock.advance(period / 2);
+        assert!(mapping.cycle());
+        // 2 was queued again when it was recreated, so its first entry doesn't drop it.
+        assert!(!mapping.contains_key(&1));
+        assert_eq!(mapping.len(), 2);
+
+        clock.advance(period);
+        assert!(mapping.cycle());
+        assert!(mapping.is_empty());
+    }
+
+    #[test]
+    fn drained_keys_are_never_on_cooldown() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(60);
+        let mapping = FixedMapping::with_clock(2, period, clock.clone()).with_stats();
+        for key in 0..10 {
+            mapping.trigger_n(&key, key % 3).unwrap();
+        }
+
+        let mut drained = mapping.drain_expired(None);
+        drained.sort_unstable();
+        assert_eq!(drained, [0, 3, 6, 9]);
+        assert_eq!(mapping.len(), 6);
+
+        clock.advance(period);
+        assert_eq!(mapping.drain_expired(None).len(), 6);
+        assert!(mapping.drain_expired(None).is_empty());
+        assert_eq!(mapping.stats().evicted, 10);
+    }
+
+    #[test]
+    fn hooks_run_once_per_window_outside_the_locks() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(60);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mapping = Arc::new_cyclic(|mapping: &Weak<FixedMapping<u64, _, ManualClock>>| {
+            let exhausted = (events.clone(), mapping.clone());
+            let reset = events.clone();
+            FixedMapping::with_clock(1, period, clock.clone())
+                .on_exhausted(move |key, retry_after| {
+                    let (events, mapping) = &exhausted;
+                    // using the mapping from a hook mustn't deadlock.
+                    let tokens = mapping.upgrade().unwrap().tokens(key);
+                    events
+                        .lock()
+                        .unwrap()
+                        .push(format!("exhausted {key} {retry_after:?} {tokens}"));
+                    if *key == 2 {
+                        panic!("hooks can panic");
+                    }
+                })
+                .on_reset(move |key| reset.lock().unwrap().push(format!("reset {key}")))
+        });
+
+        for _ in 0..3 {
+            mapping.trigger(&1);
+            mapping.trigger(&2);
+        }
+        clock.advance(period / 2);
+        mapping.cycle();
+        clock.advance(period / 2);
+        mapping.trigger(&1);
+        mapping.cycle();
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "exhausted 1 60s 0",
+                "exhausted 2 60s 0",
+                "reset 1",
+                "reset 2"
+            ]
+        );
+    }
+
+    #[test]
+    fn idle_periods_keep_limiters_across_cycles() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(60);
+        let mapping = FixedMapping::with_clock(1, period, clock.clone());
+        mapping.set_idle_periods(5);
+        mapping.set_cycle_period(period * 2);
+
+        mapping.trigger(&1);
+        clock.advance(period);
+        assert!(!mapping.cycle());
+
+        // the limiter has been full since the first period, but it is kept until it has been
+        // idle for 5.
+        clock.advance(period);
+        for _ in 0..3 {
+            assert!(mapping.cycle());
+            assert_eq!(mapping.len(), 1);
+            clock.advance(period * 2);
+        }
+
+        assert!(mapping.cycle());
+        assert_eq!(mapping.len(), 0);
+    }
+
+    #[test]
+    fn max_keys_evicts_the_least_recently_used() {
+        let clock = ManualClock::new();
+        let mapping =
+            FixedMapping::with_clock(1, Duration::from_secs(60), clock.clone()).max_keys(2);
+
+        mapping.trigger(&1);
+        clock.advance(Duration::from_secs(1));
+        mapping.trigger(&2);
+        clock.advance(Duration::from_secs(1));
+        mapping.tokens(&1);
+        clock.advance(Duration::from_secs(1));
+
+        // 2 is still on cooldown, but it was used less recently than 1.
+        assert_eq!(mapping.trigger(&3), None);
+        assert!(mapping.contains_key(&1));
+        assert!(!mapping.contains_key(&2));
+        assert_eq!(mapping.trigger(&2), None);
+        assert_eq!(mapping.len(), 2);
+        assert_eq!(mapping.forced_evictions(), 2);
+    }
+
+    #[test]
+    fn max_keys_holds_under_concurrent_insertions() {
+        let mapping = FixedMapping::new(1, Duration::from_secs(60)).max_keys(16);
+
+        std::thread::scope(|scope| {
+            for thread in 0..8 {
+                let mapping = &mapping;
+                scope.spawn(move || {
+                    for key in 0..1_000 {
+                        mapping.trigger(&(thread * 1_000 + key));
+                        assert!(mapping.len() <= 16);
+                    }
+                });
+            }
+        });
+        assert_eq!(mapping.len(), 16);
+        assert_eq!(mapping.forced_evictions(), 8_000 - 16);
+    }
+
+    #[test]
+    fn max_keys_skips_keys_removed_since_they_were_created() {
+        let clock = ManualClock::new();
+        let mapping =
+            FixedMapping::with_clock(1, Duration::from_secs(60), clock.clone()).max_keys(2);
+        for key in 0..100 {
+            mapping.trigger(&key);
+            assert!(mapping.remove(&key));
+        }
+
+        mapping.trigger(&1);
+        clock.advance(Duration::from_secs(1));
+        mapping.trigger(&2);
+        assert_eq!(mapping.trigger(&3), None);
+        assert!(!mapping.contains_key(&1));
+        assert!(mapping.contains_key(&2));
+        assert_eq!(mapping.forced_evictions(), 1);
+    }
+
+    #[test]
+    fn trigger_all_counts_duplicates_against_the_capacity() {
+        let mapping = FixedMapping::new(2, Duration::from_secs(60));
+
+        let err = mapping.trigger_all(&[1, 2, 1, 1]).unwrap_err();
+        assert!(matches!(err, TriggerAllError::Limited { key: 1, .. }));
+        assert_eq!(mapping.tokens(&1), 2);
+        assert_eq!(mapping.tokens(&2), 2);
+
+        assert_eq!(mapping.trigger_all(&[2, 1, 1]), Ok(()));
+        assert_eq!(mapping.trigger_all(&[2]), Ok(()));
+        assert!(mapping.trigger_all(&[2]).is_err());
+    }
+
+    #[test]
+    fn trigger_all_never_takes_tokens_from_concurrent_triggers() {
+        let mapping = FixedMapping::new(1_000, Duration::from_secs(60));
+        assert_eq!(mapping.trigger_n(&1, 1_000), Ok(()));
+        let done = AtomicBool::new(false);
+
+        std::thread::scope(|scope| {
+            for keys in [[0, 1], [1, 0]] {
+                let (mapping, done) = (&mapping, &done);
+                scope.spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        assert!(mapping.trigger_all(&keys).is_err());
+                    }
+                });
+            }
+            for _ in 0..1_000 {
+                assert_eq!(mapping.trigger(&0), None);
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+        assert_eq!(mapping.tokens(&0), 0);
+    }
+
+    #[test]
+    fn trigger_all_rejects_more_keys_than_max_keys() {
+        let mapping = FixedMapping::new(5, Duration::from_secs(10)).max_keys(2);
+        mapping.trigger(&4);
+
+        assert_eq!(
+            mapping.trigger_all(&[1, 2, 3]),
+            Err(TriggerAllError::TooManyKeys {
+                keys: 3,
+                max_keys: 2
+            })
+        );
+        assert!(mapping.contains_key(&4));
+
+        // a batch that fits doesn't evict its own keys to make room for each other.
+        assert_eq!(mapping.trigger_all(&[1, 2, 1]), Ok(()));
+        assert_eq!(mapping.tokens(&1), 3);
+        assert_eq!(mapping.tokens(&2), 4);
+        assert!(!mapping.contains_key(&4));
+    }
+
+    #[test]
+    fn trigger_all_only_counts_the_rejected_key() {
+        let mapping = FixedMapping::new(1, Duration::from_secs(60)).with_stats();
+        mapping.trigger(&3);
+
+        assert!(matches!(
+            mapping.trigger_all(&[1, 2, 3]),
+            Err(TriggerAllError::Limited { key: 3, .. })
+        ));
+        let stats = mapping.stats();
+        assert_eq!((stats.accepted, stats.rejected), (1, 1));
+
+        assert_eq!(mapping.trigger_all(&[1, 2]), Ok(()));
+        let stats = mapping.stats();
+        assert_eq!((stats.accepted, stats.rejected), (3, 1));
+    }
+
+    #[test]
+    fn wait_for_counts_the_carried_over_burst() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(10);
+        let mapping = FixedMapping::with_clock(2, period, clock.clone()).carry_over(4);
+        mapping.trigger(&1);
+        clock.advance(period * 2);
+
+        assert_eq!(mapping.tokens(&1), 4);
+        assert_eq!(mapping.wait_for(&1, 3), Some(Duration::ZERO));
+        assert_eq!(mapping.trigger_n(&1, 3), Ok(()));
+
+        // a new key starts with the capacity, and banks the rest over the next window.
+        assert_eq!(mapping.wait_for(&2, 3), Some(period));
+        assert!(!mapping.contains_key(&2));
+        assert_eq!(mapping.wait_for(&2, 5), None);
+    }
+
+    #[test]
+    fn len_counts_expired_entries() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(60);
+        let mapping = FixedMapping::with_clock(1, period, clock.clone());
+
+        mapping.trigger(&1);
+        clock.advance(period * 2);
+        assert_eq!(mapping.tokens(&2), 1);
+
+        // the window for 1 has expired, but it is only dropped by cycling.
+        assert_eq!(mapping.len(), 2);
+        assert!(mapping.contains_key(&1));
+        assert!(!mapping.contains_key(&3));
+        assert_eq!(mapping.len(), 2);
+    }
+
+    #[test]
+    fn reset_all_refills_every_key() {
+        let mapping = FixedMapping::new(1, Duration::from_secs(60));
+        mapping.trigger(&1);
+        mapping.trigger(&2);
+
+        mapping.reset_all();
+        assert_eq!(mapping.tokens(&1), 1);
+        assert_eq!(mapping.tokens(&2), 1);
+        assert_eq!(mapping.len(), 2);
+    }
+
+    #[test]
+    fn iter_allows_using_the_mapping() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(60);
+        let mapping = FixedMapping::with_clock(1, period, clock.clone());
+        mapping.trigger(&1);
+        mapping.trigger(&2);
+        clock.advance(period / 2);
+        mapping.trigger(&3);
+
+        for (key, info) in mapping.iter() {
+            mapping.reset(&key);
+            mapping.trigger(&(key + 10));
+            assert_eq!(info.remaining, 0);
+        }
+        assert_eq!(mapping.iter().count(), 3);
+
+        clock.advance(period);
+        assert_eq!(mapping.iter().count(), 0);
+    }
+
+    #[test]
+    fn status_is_read_only() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(60);
+        let mapping = FixedMapping::with_clock(2, period, clock.clone());
+        mapping.trigger(&1);
+        mapping.exempt(2);
+        mapping.block(3, Some(period));
+
+        let statuses = mapping.status_many(&[1, 2, 3, 4], None);
+        assert_eq!(statuses[0].unwrap().remaining, 1);
+        assert_eq!(statuses[1], None);
+        assert_eq!(statuses[2].unwrap().retry_after, Some(period));
+        assert_eq!(statuses[3], None);
+        assert_eq!(mapping.len(), 1);
+        assert_eq!(mapping.tokens(&1), 1);
+
+        let later = clock.now() + period;
+        let status = mapping.status(&1, Some(later)).unwrap();
+        assert!(status.allowed);
+        assert_eq!((status.remaining, status.reset_after), (2, period));
+    }
+
+    #[test]
+    fn import_merges_with_existing_keys() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(60);
+        let mapping = FixedMapping::with_clock(3, period, clock.clone());
+        mapping.trigger(&1);
+        mapping.trigger_n(&2, 2).unwrap();
+        clock.advance(period / 2);
+        mapping.trigger(&3);
+
+        let entry = |key, tokens, elapsed| SnapshotEntry {
+            key,
+            tokens,
+            elapsed,
+        };
+        let snapshot = MappingSnapshot {
+            taken_at: SystemTime::now(),
+            entries: vec![
+                entry(1, 0, period / 4),
+                entry(2, 2, period / 4),
+                entry(3, 1, period / 4),
+                entry(4, 0, period),
+            ],
+        };
+
+        let restrictive = mapping.import(snapshot.clone(), MergeStrategy::KeepMoreRestrictive);
+        assert_eq!(restrictive, 2);
+        assert_eq!((mapping.tokens(&1), mapping.tokens(&2)), (0, 1));
+        assert_eq!(mapping.tokens(&3), 1);
+        let retry_after = mapping.retry_after(&1).unwrap();
+        assert!(retry_after <= period * 3 / 4 && retry_after > period / 2);
+        assert!(!mapping.contains_key(&4));
+
+        assert_eq!(mapping.import(snapshot, MergeStrategy::KeepNewer), 1);
+        assert_eq!((mapping.tokens(&2), mapping.tokens(&3)), (2, 1));
+
+        let exported = mapping.export();
+        assert_eq!(exported.entries.len(), 3);
+        let copy = FixedMapping::with_clock(3, period, clock);
+        assert_eq!(copy.import(exported, MergeStrategy::Overwrite), 3);
+        assert_eq!(copy.tokens(&1), 0);
+        assert_eq!(copy.tokens(&3), 1);
+    }
+
+    #[test]
+    fn extracted_keys_move_between_mappings() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(60);
+        let from = FixedMapping::with_clock(2, period, clock.clone());
+        let to = FixedMapping::with_clock(2, period, clock);
+        for key in [(1, 1), (1, 2), (2, 1)] {
+            from.trigger(&key);
+        }
+        from.tokens(&(1, 3));
+        to.trigger_n(&(1, 2), 2).unwrap();
+
+        let snapshot = from.extract_matching(|(guild, _)| *guild == 1);
+        assert_eq!(snapshot.entries.len(), 2);
+        assert_eq!(from.len(), 1);
+
+        assert_eq!(to.import(snapshot, MergeStrategy::KeepExisting), 1);
+        assert_eq!(to.tokens(&(1, 1)), 1);
+        assert_eq!(to.tokens(&(1, 2)), 0);
+        assert!(!to.contains_key(&(1, 3)));
+    }
+
+    #[test]
+    fn cleanup_keeps_limiters_that_are_not_idle() {
+        use crate::{clock::Instant, RateLimiter};
+
+        /// Allows everything, but remembers whether it was triggered in its period.
+        struct Seen {
+            period: Duration,
+            triggered: Option<Instant>,
+        }
+
+        impl RateLimiter for Seen {
+            fn new(_capacity: u64, period: Duration) -> Self {
+                Self {
+                    period,
+                    triggered: None,
+                }
+            }
+
+            fn capacity(&self) -> u64 {
+                1
+            }
+
+            fn period(&self) -> Duration {
+                self.period
+            }
+
+            fn set_rate(&mut self, _capacity: u64, period: Duration) {
+                self.period = period;
+            }
+
+            fn tokens(&mut self, _now: Option<Instant>) -> u64 {
+                1
+            }
+
+            fn next_reset(&mut self, _now: Option<Instant>) -> Duration {
+                Duration::ZERO
+            }
+
+            fn retry_after(&mut self, _now: Option<Instant>) -> Option<Duration> {
+                None
+            }
+
+            fn trigger(&mut self, now: Option<Instant>) -> Option<Duration> {
+                self.triggered = now;
+                None
+            }
+
+            fn trigger_n(&mut self, _cost: u64, now: Option<Instant>) -> Result<(), Duration> {
+                self.triggered = now;
+                Ok(())
+            }
+
+            fn reset(&mut self, _now: Option<Instant>) {
+                self.triggered = None;
+            }
+
+            fn refund(&mut self, _n: u64, _epoch: u64, _now: Option<Instant>) {}
+
+            fn is_idle(&mut self, now: Option<Instant>) -> bool {
+                match (self.triggered, now) {
+                    (Some(triggered), Some(now)) => now >= triggered + self.period,
+                    _ => true,
+                }
+            }
+        }
+
+        let period = Duration::from_secs(60);
+        let mapping = FixedMapping::<u64, Seen>::with_limiter(1, period);
+        mapping.trigger(&1);
+        mapping.tokens(&2);
+
+        // both are full, but only the untriggered one is idle.
+        assert_eq!(mapping.cleanup(None), 1);
+        assert!(mapping.contains_key(&1));
+        assert!(!mapping.contains_key(&2));
+    }
+
+    #[test]
+    fn concurrent_triggers_of_a_hot_key_never_exceed_capacity() {
+        use std::{
+            sync::atomic::{AtomicU64, Ordering},
+            thread,
+        };
+
+        use crate::AtomicJumpingWindow;
+
+        const THREADS: usize = 8;
+        const CAPACITY: u64 = 1_000;
+
+        let mapping = FixedMapping::<u64, AtomicJumpingWindow>::with_limiter(
+            CAPACITY,
+            Duration::from_secs(3600),
+        );
+        mapping.trigger(&0);
+        let accepted = AtomicU64::new(1);
+
+        thread::scope(|scope| {
+            for _ in 0..THREADS {
+                scope.spawn(|| {
+                    for _ in 0..CAPACITY {
+                        if mapping.trigger(&0).is_none() {
+                            accepted.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+
+        assert_eq!(accepted.load(Ordering::Relaxed), CAPACITY);
+        assert_eq!(mapping.tokens(&0), 0);
+        assert_eq!(mapping.len(), 1);
+    }
+
+    #[test]
+    fn exclusive_limiters_only_opt_their_own_key_out_of_shared_triggers() {
+        use std::sync::atomic::AtomicU64;
+
+        use crate::{clock::Instant, AtomicJumpingWindow, RateLimiter};
+
+        static SHARED: AtomicU64 = AtomicU64::new(0);
+
+        /// An atomic window that needs exclusive access when its capacity is odd.
+        struct Mixed(AtomicJumpingWindow);
+
+        impl RateLimiter for Mixed {
+            fn new(capacity: u64, period: Duration) -> Self {
+                Self(AtomicJumpingWindow::new(capacity, period))
+            }
+
+            fn capacity(&self) -> u64 {
+                RateLimiter::capacity(&self.0)
+            }
+
+            fn period(&self) -> Duration {
+                RateLimiter::period(&self.0)
+            }
+
+            fn set_rate(&mut self, capacity: u64, period: Duration) {
+                RateLimiter::set_rate(&mut self.0, capacity, period)
+            }
+
+            fn tokens(&mut self, now: Option<Instant>) -> u64 {
+                RateLimiter::tokens(&mut self.0, now)
+            }
+
+            fn next_reset(&mut self, now: Option<Instant>) -> Duration {
+                RateLimiter::next_reset(&mut self.0, now)
+            }
+
+            fn retry_after(&mut self, now: Option<Instant>) -> Option<Duration> {
+                RateLimiter::retry_after(&mut self.0, now)
+            }
+
+            fn trigger(&mut self, now: Option<Instant>) -> Option<Duration> {
+                RateLimiter::trigger(&mut self.0, now)
+            }
+
+            fn trigger_n(&mut self, cost: u64, now: Option<Instant>) -> Result<(), Duration> {
+                RateLimiter::trigger_n(&mut self.0, cost, now)
+            }
+
+            fn trigger_shared(&self, now: Option<Instant>) -> Option<Option<Duration>> {
+                if RateLimiter::capacity(&self.0) % 2 == 1 {
+                    return None;
+                }
+                SHARED.fetch_add(1, Ordering::Relaxed);
+                self.0.trigger_shared(now)
+            }
+
+            fn shares_triggers() -> bool {
+                true
+            }
+
+            fn reset(&mut self, now: Option<Instant>) {
+                RateLimiter::reset(&mut self.0, now)
+            }
+
+            fn refund(&mut self, n: u64, epoch: u64, now: Option<Instant>) {
+                RateLimiter::refund(&mut self.0, n, epoch, now)
+            }
+        }
+
+        let period = Duration::from_secs(60);
+        let mapping = FixedMapping::<u64, Mixed>::with_limiter(4, period);
+        mapping.set_override(1, 3, period);
+        for key in [1, 2] {
+            mapping.trigger(&key);
+        }
+
+        for _ in 0..2 {
+            assert_eq!(mapping.trigger(&1), None);
+            assert_eq!(mapping.trigger(&2), None);
+        }
+        assert_eq!(SHARED.load(Ordering::Relaxed), 2);
+        assert!(mapping.trigger(&1).is_some());
+        assert_eq!(mapping.tokens(&2), 1);
+    }
+
+    #[cfg(feature = "tokio-time")]
+    #[tokio::test(start_paused = true)]
+    async fn paused_time_drives_windows_and_cycling() {
+        use std::sync::Arc;
+
+        let period = Duration::from_secs(3600);
+        let mapping = Arc::new(FixedMapping::new(1, period));
+
+        assert_eq!(mapping.trigger(&1), None);
+        assert_eq!(mapping.trigger(&1), Some(period));
+        tokio::time::advance(period).await;
+        assert_eq!(mapping.trigger(&1), None);
+
+        let cycler = FixedMapping::start_task(mapping.clone(), None);
+        tokio::time::sleep(period * 2 + Duration::from_secs(1)).await;
+        assert_eq!(mapping.len(), 0);
+        cycler.stop().await;
+    }
+
+    /// Collects the events it is sent, as their level, message and fields.
+    #[cfg(feature = "tracing")]
+    struct Collect(Arc<Mutex<Vec<String>>>);
+
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for Collect {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            tracing::span::Id::from_u64(1)
+        }
+
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, event: &tracing::Event<'_>) {
+            struct Fields(String);
+
+            impl tracing::field::Visit for Fields {
+                fn record_debug(
+                    &mut self,
+                    field: &tracing::field::Field,
+                    value: &dyn std::fmt::Debug,
+                ) {
+                    match field.name() {
+                        "message" => self.0 += &format!(" {value:?}"),
+                        // varies from run to run.
+                        "elapsed" => self.0 += " elapsed",
+                        name => self.0 += &format!(" {name}={value:?}"),
+                    }
+                }
+            }
+
+            let mut fields = Fields(event.metadata().level().to_string());
+            event.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0);
+        }
+
+        fn enter(&self, _: &tracing::span::Id) {}
+
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    #[test]
+    #[cfg(feature = "tracing")]
+    fn traced_mappings_emit_events_for_exhausted_keys_and_cleanups() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(60);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mapping = FixedMapping::with_clock(1, period, clock.clone()).warn_after_rejections(2);
+
+        tracing::subscriber::with_default(Collect(events.clone()), || {
+            for _ in 0..5 {
+                mapping.trigger(&1);
+            }
+            clock.advance(period);
+            mapping.trigger(&1);
+            mapping.trigger(&1);
+            mapping.cleanup(None);
+        });
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "DEBUG key exhausted key=1 retry_after=60s",
+                "WARN key rejected repeatedly key=1 rejections=3",
+                "DEBUG key exhausted key=1 retry_after=60s",
+                "DEBUG cleaned up limiters scanned=1 evicted=0 elapsed",
+            ]
+        );
+    }
+
+    /// Records the value of every metric, by its name and labels.
+    #[cfg(feature = "metrics")]
+    #[derive(Default)]
+    struct Record(Mutex<std::collections::HashMap<String, Arc<std::sync::atomic::AtomicU64>>>);
+
+    #[cfg(feature = "metrics")]
+    impl Record {
+        fn metric(&self, key: &metrics::Key) -> Arc<std::sync::atomic::AtomicU64> {
+            let mut name = key.name().to_owned();
+            for label in key.labels() {
+                name += &format!(" {}={}", label.key(), label.value());
+            }
+            self.0.lock().unwrap().entry(name).or_default().clone()
+        }
+
+        fn get(&self, name: &str) -> u64 {
+            self.0.lock().unwrap()[name].load(Ordering::Relaxed)
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    impl metrics::Recorder for Record {
+        fn describe_counter(
+            &self,
+            _: metrics::KeyName,
+            _: Option<metrics::Unit>,
+            _: metrics::SharedString,
+        ) {
+        }
+
+        fn describe_gauge(
+            &self,
+            _: metrics::KeyName,
+            _: Option<metrics::Unit>,
+            _: metrics::SharedString,
+        ) {
+        }
+
+        fn describe_histogram(
+            &self,
+            _: metrics::KeyName,
+            _: Option<metrics::Unit>,
+            _: metrics::SharedString,
+        ) {
+        }
+
+        fn register_counter(
+            &self,
+            key: &metrics::Key,
+            _: &metrics::Metadata<'_>,
+        ) -> metrics::Counter {
+            metrics::Counter::from_arc(self.metric(key))
+        }
+
+        fn register_gauge(&self, key: &metrics::Key, _: &metrics::Metadata<'_>) -> metrics::Gauge {
+            metrics::Gauge::from_arc(self.metric(key))
+        }
+
+        fn register_histogram(
+            &self,
+            _: &metrics::Key,
+            _: &metrics::Metadata<'_>,
+        ) -> metrics::Histogram {
+            metrics::Histogram::noop()
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "metrics")]
+    fn named_mappings_report_metrics_labeled_with_their_name() {
+        let record = Record::default();
+        // the metrics are registered with the recorder once, when the mapping is created.
+        let mapping = metrics::with_local_recorder(&record, || {
+            FixedMapping::named("commands", 1, Duration::from_secs(10))
+        });
+        let keys = || f64::from_bits(record.get("floodgate_keys mapping=commands"));
+
+        mapping.trigger(&1);
+        mapping.trigger(&1);
+        mapping.trigger(&2);
+        assert_eq!(record.get("floodgate_accepted_total mapping=commands"), 2);
+        assert_eq!(record.get("floodgate_rejected_total mapping=commands"), 1);
+
+        mapping.cleanup(None);
+        assert_eq!(keys(), 2.0);
+        mapping.cleanup(Some(mapping.clock().now() + Duration::from_secs(10)));
+        assert_eq!(record.get("floodgate_evicted_total mapping=commands"), 2);
+        assert_eq!(keys(), 0.0);
     }
 }
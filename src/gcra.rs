@@ -0,0 +1,425 @@
+use std::time::Duration;
+
+use crate::clock::{self, Instant};
+use crate::RateLimiter;
+
+/// A ratelimit implementation using the generic cell rate algorithm.
+///
+/// Triggers are paced evenly at one per `period / capacity`, while still allowing a burst of up
+/// to `capacity` triggers after the limiter has been idle. The only state kept is a single
+/// timestamp, the theoretical arrival time (TAT) of the next trigger.
+#[derive(Debug)]
+pub struct Gcra {
+    pub(crate) capacity: u64,
+    pub(crate) period: Duration,
+
+    emission_interval: Duration,
+    tat: Instant,
+}
+
+impl Gcra {
+    /// Create a new Gcra.
+    ///
+    /// # Arguments
+    /// * `capacity` - How many triggers can occur per period, which is also the largest burst
+    ///   allowed.
+    /// * `period` - The period over which `capacity` triggers are allowed.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::Gcra;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = Gcra::new(2, Duration::from_secs(10));
+    /// cooldown.reset(Some(now));
+    ///
+    /// // the full burst is available straight away...
+    /// assert_eq!(cooldown.trigger(Some(now)), None);
+    /// assert_eq!(cooldown.trigger(Some(now)), None);
+    ///
+    /// // ...after which triggers are paced at one every 5 seconds.
+    /// assert_eq!(cooldown.trigger(Some(now)), Some(Duration::from_secs(5)));
+    /// assert_eq!(cooldown.trigger(Some(now + Duration::from_secs(5))), None);
+    /// ```
+    pub fn new(capacity: u64, period: Duration) -> Self {
+        Self {
+            capacity,
+            period,
+            emission_interval: emission_interval(capacity, period),
+            tat: clock::now(),
+        }
+    }
+
+    /// The theoretical arrival time: the point in time at which the limiter will have fully
+    /// recovered, assuming no more triggers happen. If it is in the past, the limiter is idle.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::Gcra;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = Gcra::new(2, Duration::from_secs(10));
+    /// cooldown.reset(Some(now));
+    ///
+    /// cooldown.trigger(Some(now));
+    /// assert_eq!(cooldown.theoretical_arrival_time(), now + Duration::from_secs(5));
+    /// ```
+    pub fn theoretical_arrival_time(&self) -> Instant {
+        self.tat
+    }
+
+    /// How many triggers can currently happen back-to-back.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::Gcra;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = Gcra::new(2, Duration::from_secs(10));
+    /// cooldown.reset(Some(now));
+    ///
+    /// assert_eq!(cooldown.tokens(Some(now)), 2);
+    /// cooldown.trigger(Some(now));
+    /// assert_eq!(cooldown.tokens(Some(now)), 1);
+    /// ```
+    pub fn tokens(&mut self, now: Option<Instant>) -> u64 {
+        let now = now.unwrap_or_else(clock::now);
+
+        if self.emission_interval.is_zero() {
+            return self.capacity;
+        }
+
+        let free = self.period.saturating_sub(self.ahead(now));
+        let tokens = free.as_nanos() / self.emission_interval.as_nanos();
+        tokens.min(self.capacity as u128) as u64
+    }
+
+    /// Return the time until the limiter has fully recovered.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::Gcra;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = Gcra::new(2, Duration::from_secs(10));
+    /// cooldown.reset(Some(now));
+    ///
+    /// cooldown.trigger(Some(now));
+    /// cooldown.trigger(Some(now));
+    /// assert_eq!(cooldown.next_reset(Some(now)), Duration::from_secs(10));
+    /// ```
+    pub fn next_reset(&mut self, now: Option<Instant>) -> Duration {
+        let now = now.unwrap_or_else(clock::now);
+        self.ahead(now)
+    }
+
+    /// Return the time until the next trigger is allowed, or `None` if it is allowed now.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::Gcra;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = Gcra::new(1, Duration::from_secs(10));
+    /// cooldown.reset(Some(now));
+    ///
+    /// assert_eq!(cooldown.retry_after(Some(now)), None);
+    /// cooldown.trigger(Some(now));
+    /// assert_eq!(cooldown.retry_after(Some(now)), Some(Duration::from_secs(10)));
+    /// ```
+    pub fn retry_after(&mut self, now: Option<Instant>) -> Option<Duration> {
+        let now = now.unwrap_or_else(clock::now);
+        self.check(1, now).err()
+    }
+
+    /// How long until `n` triggers can be made at once. Returns `None` if `n` exceeds the
+    /// burst capacity, so that they never can.
+    ///
+    /// # Arguments
+    /// * `n` - How many triggers to wait for.
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::Gcra;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = Gcra::new(4, Duration::from_secs(8));
+    /// cooldown.reset(Some(now));
+    /// cooldown.trigger_n(4, Some(now)).unwrap();
+    ///
+    /// assert_eq!(cooldown.wait_for(3, Some(now)), Some(Duration::from_secs(6)));
+    /// assert_eq!(cooldown.wait_for(5, Some(now)), None);
+    /// ```
+    pub fn wait_for(&self, n: u64, now: Option<Instant>) -> Option<Duration> {
+        if n > self.capacity {
+            return None;
+        }
+
+        let now = now.unwrap_or_else(clock::now);
+        Some(self.check(n, now).err().unwrap_or_default())
+    }
+
+    /// Returns whether or not a trigger is currently allowed.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::Gcra;
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = Gcra::new(1, Duration::from_secs(10));
+    ///
+    /// assert!(cooldown.can_trigger(None));
+    /// cooldown.trigger(None);
+    /// assert!(!cooldown.can_trigger(None));
+    /// ```
+    pub fn can_trigger(&mut self, now: Option<Instant>) -> bool {
+        self.retry_after(now).is_none()
+    }
+
+    /// Trigger the cooldown.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::Gcra;
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = Gcra::new(1, Duration::from_secs(10));
+    ///
+    /// assert_eq!(cooldown.trigger(None), None);
+    /// assert!(matches!(cooldown.trigger(None), Some(_)));
+    /// ```
+    pub fn trigger(&mut self, now: Option<Instant>) -> Option<Duration> {
+        self.trigger_n(1, now).err()
+    }
+
+    /// Trigger the cooldown with a cost of `cost` triggers.
+    ///
+    /// Either the whole cost is accepted, or nothing is. If `cost` is greater than the
+    /// capacity, the trigger can never succeed, so `Duration::MAX` is returned immediately.
+    ///
+    /// # Arguments
+    /// * `cost` - How many triggers this counts as.
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::Gcra;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = Gcra::new(4, Duration::from_secs(4));
+    /// cooldown.reset(Some(now));
+    ///
+    /// assert_eq!(cooldown.trigger_n(3, Some(now)), Ok(()));
+    /// assert_eq!(cooldown.trigger_n(3, Some(now)), Err(Duration::from_secs(2)));
+    /// assert_eq!(cooldown.trigger_n(5, Some(now)), Err(Duration::MAX));
+    /// ```
+    pub fn trigger_n(&mut self, cost: u64, now: Option<Instant>) -> Result<(), Duration> {
+        if cost > self.capacity {
+            return Err(Duration::MAX);
+        }
+
+        let now = now.unwrap_or_else(clock::now);
+        self.tat = now + self.check(cost, now)?;
+        Ok(())
+    }
+
+    /// Reset the cooldown, so that the full burst is available again.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::Gcra;
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = Gcra::new(1, Duration::from_secs(10));
+    /// cooldown.trigger(None);
+    ///
+    /// assert!(!cooldown.can_trigger(None));
+    /// cooldown.reset(None);
+    /// assert!(cooldown.can_trigger(None));
+    /// ```
+    pub fn reset(&mut self, now: Option<Instant>) {
+        self.tat = now.unwrap_or_else(clock::now);
+    }
+
+    /// Give back `n` triggers, moving the TAT back. The TAT never moves before `now`, so an
+    /// idle limiter doesn't gain extra credit.
+    ///
+    /// # Arguments
+    /// * `n` - How many triggers to give back.
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::Gcra;
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = Gcra::new(1, Duration::from_secs(10));
+    ///
+    /// assert_eq!(cooldown.trigger(None), None);
+    /// cooldown.refund(1, None);
+    /// assert_eq!(cooldown.trigger(None), None);
+    /// ```
+    pub fn refund(&mut self, n: u64, now: Option<Instant>) {
+        let now = now.unwrap_or_else(clock::now);
+        let refunded = self.emission_interval.as_nanos() * n as u128;
+        let ahead = self.ahead(now).as_nanos().saturating_sub(refunded);
+        self.tat = now + nanos(ahead);
+    }
+
+    /// How far the TAT is ahead of `now`. An idle limiter never builds up credit beyond a
+    /// full burst, since a TAT in the past counts the same as one at `now`.
+    fn ahead(&self, now: Instant) -> Duration {
+        self.tat.saturating_duration_since(now)
+    }
+
+    /// Check whether `cost` triggers are allowed at `now`. On success, returns how far ahead
+    /// of `now` the new TAT would be; otherwise, returns the time until they are allowed.
+    fn check(&self, cost: u64, now: Instant) -> Result<Duration, Duration> {
+        let increment = self.emission_interval.as_nanos() * cost as u128;
+        let ahead = self.ahead(now).as_nanos() + increment;
+        let period = self.period.as_nanos();
+
+        if ahead > period {
+            Err(nanos(ahead - period))
+        } else {
+            Ok(nanos(ahead))
+        }
+    }
+}
+
+fn emission_interval(capacity: u64, period: Duration) -> Duration {
+    match capacity {
+        0 => period,
+        _ => nanos(period.as_nanos() / capacity as u128),
+    }
+}
+
+fn nanos(nanos: u128) -> Duration {
+    Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
+}
+
+impl RateLimiter for Gcra {
+    fn new(capacity: u64, period: Duration) -> Self {
+        Self::new(capacity, period)
+    }
+
+    fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    fn period(&self) -> Duration {
+        self.period
+    }
+
+    fn set_rate(&mut self, capacity: u64, period: Duration) {
+        self.capacity = capacity;
+        self.period = period;
+        self.emission_interval = emission_interval(capacity, period);
+    }
+
+    fn tokens(&mut self, now: Option<Instant>) -> u64 {
+        self.tokens(now)
+    }
+
+    fn next_reset(&mut self, now: Option<Instant>) -> Duration {
+        self.next_reset(now)
+    }
+
+    fn retry_after(&mut self, now: Option<Instant>) -> Option<Duration> {
+        self.retry_after(now)
+    }
+
+    fn wait_for(&mut self, n: u64, now: Option<Instant>) -> Option<Duration> {
+        Gcra::wait_for(self, n, now)
+    }
+
+    fn can_trigger(&mut self, now: Option<Instant>) -> bool {
+        self.can_trigger(now)
+    }
+
+    fn trigger(&mut self, now: Option<Instant>) -> Option<Duration> {
+        self.trigger(now)
+    }
+
+    fn trigger_n(&mut self, cost: u64, now: Option<Instant>) -> Result<(), Duration> {
+        self.trigger_n(cost, now)
+    }
+
+    fn reset(&mut self, now: Option<Instant>) {
+        self.reset(now)
+    }
+
+    fn refund(&mut self, n: u64, _epoch: u64, now: Option<Instant>) {
+        self.refund(n, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::Gcra;
+
+    #[test]
+    fn spread_triggers_are_accepted() {
+        let now = Instant::now();
+        let mut gcra = Gcra::new(5, Duration::from_secs(5));
+        gcra.reset(Some(now));
+
+        for i in 0..20 {
+            assert_eq!(gcra.trigger(Some(now + Duration::from_secs(i))), None);
+        }
+    }
+
+    #[test]
+    fn back_to_back_burst_is_limited() {
+        let now = Instant::now();
+        let mut gcra = Gcra::new(5, Duration::from_secs(5));
+        gcra.reset(Some(now));
+
+        for _ in 0..5 {
+            assert_eq!(gcra.trigger(Some(now)), None);
+        }
+        assert_eq!(gcra.trigger(Some(now)), Some(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn idle_does_not_accumulate_credit() {
+        let now = Instant::now();
+        let mut gcra = Gcra::new(5, Duration::from_secs(5));
+        gcra.reset(Some(now));
+
+        let later = now + Duration::from_secs(3600);
+        assert_eq!(gcra.tokens(Some(later)), 5);
+        for _ in 0..5 {
+            assert_eq!(gcra.trigger(Some(later)), None);
+        }
+        assert!(gcra.trigger(Some(later)).is_some());
+    }
+}
@@ -0,0 +1,179 @@
+use std::time::Duration;
+
+use http::{header::RETRY_AFTER, HeaderMap, HeaderName, HeaderValue, Response, StatusCode};
+
+use crate::{RateLimitInfo, RateLimited};
+
+/// The `X-RateLimit-Limit` header.
+pub const X_RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("x-ratelimit-limit");
+/// The `X-RateLimit-Remaining` header.
+pub const X_RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("x-ratelimit-remaining");
+/// The `X-RateLimit-Reset` header.
+pub const X_RATELIMIT_RESET: HeaderName = HeaderName::from_static("x-ratelimit-reset");
+
+impl RateLimitInfo {
+    /// Convert the info into the standard ratelimit headers: `X-RateLimit-Limit`,
+    /// `X-RateLimit-Remaining`, `X-RateLimit-Reset` (in seconds), and `Retry-After` if the
+    /// trigger wasn't allowed.
+    ///
+    /// Durations are rounded up to whole seconds, so a client that waits that long is
+    /// guaranteed to be able to trigger again.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::RateLimitInfo;
+    /// use std::time::Duration;
+    ///
+    /// let info = RateLimitInfo {
+    ///     allowed: false,
+    ///     limit: 5,
+    ///     remaining: 0,
+    ///     retry_after: Some(Duration::from_millis(300)),
+    ///     reset_after: Duration::from_millis(300),
+    /// };
+    ///
+    /// let headers = info.to_headers();
+    /// assert_eq!(headers["x-ratelimit-limit"], "5");
+    /// assert_eq!(headers["x-ratelimit-remaining"], "0");
+    /// assert_eq!(headers["x-ratelimit-reset"], "1");
+    /// assert_eq!(headers["retry-after"], "1");
+    /// ```
+    pub fn to_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(X_RATELIMIT_LIMIT, HeaderValue::from(self.limit));
+        headers.insert(X_RATELIMIT_REMAINING, HeaderValue::from(self.remaining));
+        headers.insert(
+            X_RATELIMIT_RESET,
+            HeaderValue::from(ceil_secs(self.reset_after)),
+        );
+        if let Some(retry_after) = self.retry_after {
+            headers.insert(RETRY_AFTER, HeaderValue::from(ceil_secs(retry_after)));
+        }
+        headers
+    }
+
+    /// Parse the headers created by `to_headers`, for example from a response sent by a
+    /// ratelimited server. A missing `Retry-After` means the request was allowed.
+    ///
+    /// Many servers only send `Retry-After` when they reject a request. If it is present, the
+    /// `X-RateLimit-*` headers may be missing, and the info is partial: nothing is left, the
+    /// limiter resets once the retry delay is over, and `limit` is zero since it isn't known.
+    /// Returns `None` if any header is invalid, or if an `X-RateLimit-*` header is missing
+    /// without a `Retry-After`.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::RateLimitInfo;
+    /// use http::HeaderMap;
+    /// use std::time::Duration;
+    ///
+    /// let mut headers = HeaderMap::new();
+    /// headers.insert("x-ratelimit-limit", "5".parse().unwrap());
+    /// headers.insert("x-ratelimit-remaining", "0".parse().unwrap());
+    /// headers.insert("x-ratelimit-reset", "3".parse().unwrap());
+    /// headers.insert("retry-after", "3".parse().unwrap());
+    ///
+    /// let info = RateLimitInfo::from_headers(&headers).unwrap();
+    /// assert!(!info.allowed);
+    /// assert_eq!(info.retry_after, Some(Duration::from_secs(3)));
+    ///
+    /// let mut headers = HeaderMap::new();
+    /// headers.insert("retry-after", "2".parse().unwrap());
+    ///
+    /// let info = RateLimitInfo::from_headers(&headers).unwrap();
+    /// assert!(!info.allowed);
+    /// assert_eq!(info.limit, 0);
+    /// assert_eq!(info.remaining, 0);
+    /// assert_eq!(info.reset_after, Duration::from_secs(2));
+    /// ```
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let retry_after = match headers.get(RETRY_AFTER) {
+            Some(value) => Some(Duration::from_secs(parse(value)?)),
+            None => None,
+        };
+        // without a `Retry-After`, every `X-RateLimit-*` header is required.
+        let header = |name| match (headers.get(name), retry_after) {
+            (Some(value), _) => parse(value).map(Some),
+            (None, Some(_)) => Some(None),
+            (None, None) => None,
+        };
+
+        let limit = header(X_RATELIMIT_LIMIT)?;
+        let remaining = header(X_RATELIMIT_REMAINING)?;
+        let reset_after = header(X_RATELIMIT_RESET)?.map(Duration::from_secs);
+        Some(Self {
+            allowed: retry_after.is_none(),
+            limit: limit.unwrap_or(0),
+            remaining: remaining.unwrap_or(0),
+            retry_after,
+            reset_after: reset_after.or(retry_after).unwrap_or_default(),
+        })
+    }
+}
+
+/// A `429 Too Many Requests` response with a `Retry-After` header, so that handlers can
+/// return a `floodgate::RateLimited` with `?`.
+///
+/// # Examples
+/// ```
+/// use floodgate::JumpingWindow;
+/// use http::{Response, StatusCode};
+/// use std::time::Duration;
+///
+/// fn handle(cooldown: &mut JumpingWindow) -> Result<Response<String>, Response<String>> {
+///     cooldown.try_trigger(None)?;
+///     Ok(Response::new("hello".into()))
+/// }
+///
+/// let mut cooldown = JumpingWindow::new(1, Duration::from_millis(1500));
+/// assert!(handle(&mut cooldown).is_ok());
+///
+/// let response = handle(&mut cooldown).unwrap_err();
+/// assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+/// assert_eq!(response.headers()["retry-after"], "2");
+/// ```
+impl<K, B: From<&'static str>> From<RateLimited<K>> for Response<B> {
+    fn from(err: RateLimited<K>) -> Self {
+        let mut response = Response::new(B::from("Too Many Requests"));
+        *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(ceil_secs(err.retry_after)));
+        response
+    }
+}
+
+/// How long a ratelimited response asks to wait before retrying, from its `Retry-After`
+/// header, for clients backing off a server's limits. Only the delay in seconds is understood;
+/// `None` is returned if the header is missing, or is an HTTP date.
+///
+/// # Examples
+/// ```
+/// use floodgate::{headers::retry_after, FixedMapping, ManualClock};
+/// use http::HeaderMap;
+/// use std::time::Duration;
+///
+/// let mut headers = HeaderMap::new();
+/// headers.insert("retry-after", "2".parse().unwrap());
+///
+/// // back off the host that sent the response.
+/// let hosts = FixedMapping::with_clock(10, Duration::from_secs(1), ManualClock::new());
+/// if let Some(wait) = retry_after(&headers) {
+///     hosts.penalize("api.example.com", wait);
+/// }
+/// assert_eq!(hosts.retry_after("api.example.com"), Some(Duration::from_secs(2)));
+/// ```
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    parse(headers.get(RETRY_AFTER)?).map(Duration::from_secs)
+}
+
+fn ceil_secs(duration: Duration) -> u64 {
+    duration
+        .as_nanos()
+        .div_ceil(1_000_000_000)
+        .min(u64::MAX as u128) as u64
+}
+
+fn parse(value: &HeaderValue) -> Option<u64> {
+    value.to_str().ok()?.trim().parse().ok()
+}
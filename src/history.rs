@@ -0,0 +1,148 @@
+use crate::clock::Instant;
+#[cfg(feature = "serde")]
+use crate::clock::SystemTime;
+
+/// A trigger recorded by a window's history. See `floodgate::JumpingWindow::with_history`.
+///
+/// With the `serde` feature, records also carry the wall-clock time of the trigger and can be
+/// serialized, so that they still mean something in logs once the program has restarted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TriggerRecord {
+    /// When the trigger happened.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub at: Instant,
+    /// When the trigger happened, according to the system clock.
+    #[cfg(feature = "serde")]
+    pub time: SystemTime,
+    /// How many tokens the trigger asked for.
+    pub cost: u64,
+    /// Whether the trigger was allowed.
+    pub allowed: bool,
+}
+
+impl TriggerRecord {
+    /// A record of a trigger at `at`, read from a clock that currently reads `current`.
+    pub(crate) fn new(at: Instant, current: Instant, cost: u64, allowed: bool) -> Self {
+        #[cfg(not(feature = "serde"))]
+        let _ = current;
+        Self {
+            at,
+            #[cfg(feature = "serde")]
+            time: wall_time(at, current),
+            cost,
+            allowed,
+        }
+    }
+}
+
+/// The system time of `at`, given that it is `current` now.
+#[cfg(feature = "serde")]
+fn wall_time(at: Instant, current: Instant) -> SystemTime {
+    let now = SystemTime::now();
+    match current.checked_duration_since(at) {
+        Some(ago) => now.checked_sub(ago).unwrap_or(now),
+        None => now + at.duration_since(current),
+    }
+}
+
+/// The last allowed and rejected triggers of a window, oldest first.
+#[derive(Debug, Clone)]
+pub(crate) struct TriggerHistory {
+    records: Vec<TriggerRecord>,
+    /// How many allowed and how many rejected triggers to keep.
+    len: usize,
+}
+
+impl TriggerHistory {
+    pub(crate) fn new(len: usize) -> Self {
+        Self {
+            records: Vec::new(),
+            len,
+        }
+    }
+
+    pub(crate) fn records(&self) -> &[TriggerRecord] {
+        &self.records
+    }
+
+    pub(crate) fn limit(&self) -> usize {
+        self.len
+    }
+
+    /// Record a trigger, forgetting the oldest one of the same kind if there are too many.
+    pub(crate) fn push(&mut self, record: TriggerRecord) {
+        self.trim(record.allowed, self.len.saturating_sub(1));
+        self.records.push(record);
+    }
+
+    /// Keep up to `len` allowed and `len` rejected triggers.
+    pub(crate) fn set_len(&mut self, len: usize) {
+        self.len = len;
+        self.trim(true, len);
+        self.trim(false, len);
+    }
+
+    /// Forget the oldest triggers that were `allowed` or not, until `len` of them are left.
+    fn trim(&mut self, allowed: bool, len: usize) {
+        let count = self.records.iter().filter(|r| r.allowed == allowed).count();
+        let mut excess = count.saturating_sub(len);
+        self.records.retain(|record| {
+            let forget = excess > 0 && record.allowed == allowed;
+            if forget {
+                excess -= 1;
+            }
+            !forget
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    #[cfg(feature = "serde")]
+    use std::time::Instant;
+
+    use crate::{JumpingWindow, ManualClock};
+
+    #[test]
+    fn allowed_and_rejected_triggers_are_kept_separately() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(10);
+        let mut window = JumpingWindow::with_clock(2, period, clock.clone()).with_history(2);
+
+        window.trigger(None);
+        window.trigger(None);
+        clock.advance(Duration::from_secs(1));
+        for _ in 0..5 {
+            window.trigger(None);
+        }
+        clock.advance(period);
+        window.trigger_n(2, None).unwrap();
+
+        let history = window.history();
+        let allowed: Vec<_> = history.iter().map(|record| record.allowed).collect();
+        assert_eq!(allowed, [true, false, false, true]);
+        assert_eq!(history[3].cost, 2);
+        assert_eq!(history[3].at - history[2].at, period);
+
+        window.set_history(1);
+        assert_eq!(window.history().len(), 2);
+        window.set_history(0);
+        assert!(window.history().is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn records_are_anchored_to_the_system_clock() {
+        let now = Instant::now();
+        let mut window = JumpingWindow::new(1, Duration::from_secs(10)).with_history(2);
+        window.trigger(Some(now));
+        window.trigger(Some(now + Duration::from_secs(3600)));
+
+        let history = window.history();
+        let elapsed = history[1].time.duration_since(history[0].time).unwrap();
+        assert!(elapsed >= Duration::from_secs(3599));
+        assert!(serde_json::to_value(history[1]).unwrap()["time"].is_object());
+    }
+}
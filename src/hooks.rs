@@ -0,0 +1,221 @@
+#[cfg(feature = "tracing")]
+use std::fmt;
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+    panic::{catch_unwind, AssertUnwindSafe},
+    time::Duration,
+};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+
+#[cfg(feature = "stream")]
+use crate::events::{EventSender, RateLimitEvent};
+
+/// The callbacks registered with `floodgate::FixedMapping::on_exhausted` and
+/// `floodgate::FixedMapping::on_reset`, the subscribers of `floodgate::FixedMapping::events`,
+/// and how keys are traced, along with the keys that are exhausted and how many times they were
+/// rejected since.
+pub(crate) struct Hooks<K, S> {
+    #[allow(clippy::type_complexity)]
+    on_exhausted: Option<Box<dyn Fn(&K, Duration) + Send + Sync>>,
+    #[allow(clippy::type_complexity)]
+    on_reset: Option<Box<dyn Fn(&K) + Send + Sync>>,
+    exhausted: DashMap<K, u64, S>,
+    #[cfg(feature = "stream")]
+    pub(crate) events: EventSender<K>,
+    #[cfg(feature = "tracing")]
+    trace: Option<Trace<K>>,
+}
+
+/// How the keys of a mapping are traced, set with `floodgate::FixedMapping::traced` and
+/// `floodgate::FixedMapping::warn_after_rejections`.
+#[cfg(feature = "tracing")]
+struct Trace<K> {
+    /// The key's `Debug` impl, which mappings don't otherwise require.
+    debug: fn(&K, &mut fmt::Formatter<'_>) -> fmt::Result,
+    warn_after: Option<u64>,
+}
+
+/// A key, formatted with the `Debug` impl it was traced with.
+#[cfg(feature = "tracing")]
+struct TracedKey<'a, K>(&'a K, fn(&K, &mut fmt::Formatter<'_>) -> fmt::Result);
+
+#[cfg(feature = "tracing")]
+impl<K> fmt::Debug for TracedKey<'_, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (self.1)(self.0, f)
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + 'static, S: BuildHasher + Clone> Hooks<K, S> {
+    pub(crate) fn with_hasher(hasher: S) -> Self {
+        Self {
+            on_exhausted: None,
+            on_reset: None,
+            exhausted: DashMap::with_hasher(hasher),
+            #[cfg(feature = "stream")]
+            events: EventSender::new(),
+            #[cfg(feature = "tracing")]
+            trace: None,
+        }
+    }
+
+    pub(crate) fn set_on_exhausted(&mut self, hook: impl Fn(&K, Duration) + Send + Sync + 'static) {
+        self.on_exhausted = Some(Box::new(hook));
+    }
+
+    pub(crate) fn set_on_reset(&mut self, hook: impl Fn(&K) + Send + Sync + 'static) {
+        self.on_reset = Some(Box::new(hook));
+    }
+
+    /// Trace the mapping's keys with `debug`, warning about keys rejected more than
+    /// `warn_after` times while exhausted if it is set.
+    #[cfg(feature = "tracing")]
+    pub(crate) fn set_traced(
+        &mut self,
+        debug: fn(&K, &mut fmt::Formatter<'_>) -> fmt::Result,
+        warn_after: Option<u64>,
+    ) {
+        let trace = self.trace.get_or_insert(Trace {
+            debug,
+            warn_after: None,
+        });
+        trace.warn_after = warn_after.or(trace.warn_after);
+    }
+
+    /// Whether the mapping's keys are traced, and a subscriber is interested in the events.
+    /// This is checked against the filters' cached interest, so it is cheap when they are
+    /// filtered out.
+    #[cfg(feature = "tracing")]
+    fn is_traced(&self) -> bool {
+        match &self.trace {
+            Some(trace) => {
+                tracing::enabled!(tracing::Level::DEBUG)
+                    || (trace.warn_after.is_some() && tracing::enabled!(tracing::Level::WARN))
+            }
+            None => false,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        #[cfg(feature = "stream")]
+        if self.events.is_active() {
+            return false;
+        }
+        #[cfg(feature = "tracing")]
+        if self.is_traced() {
+            return false;
+        }
+        self.on_exhausted.is_none() && self.on_reset.is_none()
+    }
+
+    /// Record that a trigger of `key` was rejected, calling `on_exhausted` if it is the first
+    /// rejection since the key was last allowed.
+    pub(crate) fn rejected<Q>(&self, key: &Q, retry_after: Duration)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        if self.is_empty() {
+            return;
+        }
+        if self.exhausted.contains_key(key) {
+            #[cfg(feature = "tracing")]
+            self.rejected_again(key);
+            return;
+        }
+        let key = match self.exhausted.entry(key.to_owned()) {
+            Entry::Vacant(entry) => entry.insert(1).key().clone(),
+            Entry::Occupied(_) => return,
+        };
+        if let Some(hook) = &self.on_exhausted {
+            call(|| hook(&key, retry_after));
+        }
+        #[cfg(feature = "tracing")]
+        if let Some(trace) = &self.trace {
+            let key = TracedKey(&key, trace.debug);
+            tracing::debug!(?key, ?retry_after, "key exhausted");
+        }
+        #[cfg(feature = "stream")]
+        self.events
+            .send(|| RateLimitEvent::Exhausted { key, retry_after });
+    }
+
+    /// Count another rejection of the exhausted `key`, warning once it has been rejected more
+    /// than the threshold set with `floodgate::FixedMapping::warn_after_rejections`.
+    #[cfg(feature = "tracing")]
+    fn rejected_again<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let Some(Trace {
+            debug,
+            warn_after: Some(warn_after),
+        }) = &self.trace
+        else {
+            return;
+        };
+        let Some(mut rejections) = self.exhausted.get_mut(key) else {
+            return;
+        };
+        *rejections += 1;
+        let rejections_now = *rejections;
+        if rejections_now != warn_after.saturating_add(1) {
+            return;
+        }
+        // warned about outside of the lock, like hooks are called.
+        let key = rejections.key().clone();
+        drop(rejections);
+        let key = TracedKey(&key, *debug);
+        tracing::warn!(?key, rejections = rejections_now, "key rejected repeatedly");
+    }
+
+    /// Record that a trigger of `key` was allowed, calling `on_reset` if it was exhausted.
+    pub(crate) fn allowed<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.is_empty() {
+            return;
+        }
+        if let Some((key, _)) = self.exhausted.remove(key) {
+            self.reset(&key);
+        }
+    }
+
+    /// Call `on_reset` for every exhausted key for which `is_reset` returns `true`.
+    pub(crate) fn reset_where(&self, mut is_reset: impl FnMut(&K) -> bool) {
+        if self.exhausted.is_empty() {
+            return;
+        }
+        // collect the keys first, so that `is_reset` and the hook run without holding a lock.
+        let keys: Vec<K> = self
+            .exhausted
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect();
+        for key in keys {
+            if is_reset(&key) && self.exhausted.remove(&key).is_some() {
+                self.reset(&key);
+            }
+        }
+    }
+
+    fn reset(&self, key: &K) {
+        if let Some(hook) = &self.on_reset {
+            call(|| hook(key));
+        }
+        #[cfg(feature = "stream")]
+        self.events
+            .send(|| RateLimitEvent::Reset { key: key.clone() });
+    }
+}
+
+/// Call a hook, without letting a panic in it unwind into the mapping. The panic is still
+/// reported by the panic hook.
+fn call(hook: impl FnOnce()) {
+    let _ = catch_unwind(AssertUnwindSafe(hook));
+}
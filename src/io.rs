@@ -0,0 +1,363 @@
+//! Readers and writers limited to a number of bytes per period, where every byte transferred
+//! consumes one token.
+//!
+//! The wrappers implement `std::io::Read` and `std::io::Write`, sleeping the current thread
+//! while the limiter is exhausted. With the `tokio` feature, they also implement
+//! `tokio::io::AsyncRead` and `tokio::io::AsyncWrite`, sleeping the task instead.
+//!
+//! To share one cap between several connections, give each wrapper a clone of the same
+//! `floodgate::SharedJumpingWindow`.
+
+use std::{
+    io::{self, Read, Write},
+    thread::sleep,
+    time::Duration,
+};
+
+#[cfg(feature = "tokio")]
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+#[cfg(feature = "tokio")]
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::Sleep,
+};
+
+use crate::{RateLimiter, SharedJumpingWindow};
+
+/// A reader that consumes a token from its limiter for every byte read.
+///
+/// Each read is shortened to the tokens that are left, and only the bytes actually read are
+/// consumed.
+///
+/// # Examples
+/// ```
+/// use floodgate::{io::ThrottledReader, SharedJumpingWindow};
+/// use std::{io::Read, time::Duration};
+///
+/// let limiter = SharedJumpingWindow::new(4, Duration::from_secs(10));
+/// let mut reader = ThrottledReader::new(&b"hello world"[..], limiter.clone());
+///
+/// let mut buf = [0; 16];
+/// assert_eq!(reader.read(&mut buf).unwrap(), 4);
+/// assert_eq!(&buf[..4], b"hell");
+/// assert_eq!(limiter.tokens(None), 0);
+/// ```
+#[derive(Debug)]
+pub struct ThrottledReader<R, L = SharedJumpingWindow> {
+    inner: R,
+    limiter: L,
+    #[cfg(feature = "tokio")]
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+/// A writer that consumes a token from its limiter for every byte written.
+///
+/// Each write is shortened to the tokens that are left, and only the bytes actually written
+/// are consumed.
+///
+/// # Examples
+/// ```
+/// use floodgate::{io::ThrottledWriter, SharedJumpingWindow};
+/// use std::{io::Write, time::Duration};
+///
+/// let limiter = SharedJumpingWindow::new(4, Duration::from_secs(10));
+/// let mut writer = ThrottledWriter::new(Vec::new(), limiter.clone());
+///
+/// assert_eq!(writer.write(b"hello world").unwrap(), 4);
+/// assert_eq!(writer.get_ref(), b"hell");
+/// assert_eq!(limiter.tokens(None), 0);
+/// ```
+#[derive(Debug)]
+pub struct ThrottledWriter<W, L = SharedJumpingWindow> {
+    inner: W,
+    limiter: L,
+    #[cfg(feature = "tokio")]
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+macro_rules! impl_wrapper {
+    ($name:ident, $inner:ident) => {
+        impl<$inner, L> $name<$inner, L> {
+            /// Wrap `inner`, consuming a token from `limiter` for every byte.
+            ///
+            /// # Arguments
+            /// * `inner` - The reader or writer to wrap.
+            /// * `limiter` - The limiter to consume tokens from.
+            pub fn new(inner: $inner, limiter: L) -> Self {
+                Self {
+                    inner,
+                    limiter,
+                    #[cfg(feature = "tokio")]
+                    sleep: None,
+                }
+            }
+
+            /// The limiter throttling the wrapper.
+            pub fn limiter(&self) -> &L {
+                &self.limiter
+            }
+
+            /// A reference to the wrapped reader or writer.
+            pub fn get_ref(&self) -> &$inner {
+                &self.inner
+            }
+
+            /// A mutable reference to the wrapped reader or writer. Bytes transferred through
+            /// it directly don't consume any tokens.
+            pub fn get_mut(&mut self) -> &mut $inner {
+                &mut self.inner
+            }
+
+            /// Consume the wrapper, returning the wrapped reader or writer.
+            pub fn into_inner(self) -> $inner {
+                self.inner
+            }
+        }
+    };
+}
+
+impl_wrapper!(ThrottledReader, R);
+impl_wrapper!(ThrottledWriter, W);
+
+/// Tokens taken from a limiter for a transfer, and the window they were taken from.
+#[derive(Clone, Copy)]
+struct Reserved {
+    tokens: usize,
+    epoch: u64,
+}
+
+/// Trigger `limiter` for as many of `wanted` bytes as it has tokens for, returning how many
+/// were reserved, or how long to wait if there are no tokens left.
+fn reserve<L: RateLimiter>(limiter: &mut L, wanted: usize) -> Result<Reserved, Duration> {
+    loop {
+        let tokens = limiter.tokens(None).min(wanted as u64);
+        if tokens == 0 {
+            return Err(limiter.retry_after(None).unwrap_or_default());
+        }
+        // read before triggering, so that a window ending in between isn't refunded.
+        let epoch = limiter.epoch(None);
+        // a shared limiter may have lost tokens since they were counted, so count again.
+        if limiter.trigger_n(tokens, None).is_ok() {
+            return Ok(Reserved {
+                tokens: tokens as usize,
+                epoch,
+            });
+        }
+    }
+}
+
+/// Like `reserve`, but sleeps the current thread until there are tokens.
+fn reserve_blocking<L: RateLimiter>(limiter: &mut L, wanted: usize) -> Reserved {
+    loop {
+        match reserve(limiter, wanted) {
+            Ok(reserved) => return reserved,
+            Err(retry_after) => sleep(retry_after),
+        }
+    }
+}
+
+/// Like `reserve`, but registers a timer with the current task if there are no tokens.
+#[cfg(feature = "tokio")]
+fn poll_reserve<L: RateLimiter>(
+    limiter: &mut L,
+    sleep: &mut Option<Pin<Box<Sleep>>>,
+    wanted: usize,
+    cx: &mut Context<'_>,
+) -> Poll<Reserved> {
+    loop {
+        if let Some(timer) = sleep {
+            ready!(timer.as_mut().poll(cx));
+            *sleep = None;
+        }
+
+        match reserve(limiter, wanted) {
+            Ok(reserved) => return Poll::Ready(reserved),
+            Err(retry_after) => *sleep = Some(Box::pin(tokio::time::sleep(retry_after))),
+        }
+    }
+}
+
+/// Give back the tokens that were reserved but not used, unless their window has ended.
+fn release<L: RateLimiter>(limiter: &mut L, reserved: Reserved, used: usize) {
+    if reserved.tokens > used {
+        limiter.refund((reserved.tokens - used) as u64, reserved.epoch, None);
+    }
+}
+
+impl<R: Read, L: RateLimiter> Read for ThrottledReader<R, L> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return self.inner.read(buf);
+        }
+
+        let reserved = reserve_blocking(&mut self.limiter, buf.len());
+        let result = self.inner.read(&mut buf[..reserved.tokens]);
+        release(&mut self.limiter, reserved, *result.as_ref().unwrap_or(&0));
+        result
+    }
+}
+
+impl<W: Write, L: RateLimiter> Write for ThrottledWriter<W, L> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return self.inner.write(buf);
+        }
+
+        let reserved = reserve_blocking(&mut self.limiter, buf.len());
+        let result = self.inner.write(&buf[..reserved.tokens]);
+        release(&mut self.limiter, reserved, *result.as_ref().unwrap_or(&0));
+        result
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<R: AsyncRead + Unpin, L: RateLimiter + Unpin> AsyncRead for ThrottledReader<R, L> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if buf.remaining() == 0 {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        }
+
+        let reserved = ready!(poll_reserve(
+            &mut this.limiter,
+            &mut this.sleep,
+            buf.remaining(),
+            cx
+        ));
+
+        let mut limited = buf.take(reserved.tokens);
+        let ptr = limited.filled().as_ptr();
+        let result = Pin::new(&mut this.inner).poll_read(cx, &mut limited);
+        assert_eq!(
+            ptr,
+            limited.filled().as_ptr(),
+            "the reader swapped the buffer"
+        );
+        let read = limited.filled().len();
+
+        // SAFETY: the inner reader filled, and so initialized, `read` bytes at the start of
+        // the unfilled part of `buf`.
+        unsafe { buf.assume_init(read) };
+        buf.advance(read);
+
+        release(&mut this.limiter, reserved, read);
+        result
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<W: AsyncWrite + Unpin, L: RateLimiter + Unpin> AsyncWrite for ThrottledWriter<W, L> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        }
+
+        let reserved = ready!(poll_reserve(
+            &mut this.limiter,
+            &mut this.sleep,
+            buf.len(),
+            cx
+        ));
+
+        let result = Pin::new(&mut this.inner).poll_write(cx, &buf[..reserved.tokens]);
+        let written = match &result {
+            Poll::Ready(Ok(written)) => *written,
+            _ => 0,
+        };
+        release(&mut this.limiter, reserved, written);
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        time::{Duration, Instant},
+    };
+
+    use super::{ThrottledReader, ThrottledWriter};
+    use crate::SharedJumpingWindow;
+
+    #[test]
+    fn reads_wait_for_tokens() {
+        let start = Instant::now();
+        let limiter = SharedJumpingWindow::new(4, Duration::from_millis(20));
+        let mut reader = ThrottledReader::new(&b"0123456789"[..], limiter);
+
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, b"0123456789");
+        assert!(start.elapsed() >= Duration::from_millis(40));
+    }
+
+    #[test]
+    fn short_writes_refund_the_rest() {
+        let limiter = SharedJumpingWindow::new(10, Duration::from_secs(10));
+        let mut buf = [0; 3];
+        let mut writer = ThrottledWriter::new(&mut buf[..], limiter.clone());
+
+        assert_eq!(writer.write(b"hello").unwrap(), 3);
+        assert_eq!(limiter.tokens(None), 7);
+    }
+
+    #[test]
+    fn connections_share_a_limiter() {
+        let limiter = SharedJumpingWindow::new(6, Duration::from_secs(10));
+        let mut first = ThrottledWriter::new(Vec::new(), limiter.clone());
+        let mut second = ThrottledWriter::new(Vec::new(), limiter.clone());
+
+        assert_eq!(first.write(b"hello").unwrap(), 5);
+        assert_eq!(second.write(b"hello").unwrap(), 1);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn async_reads_and_writes_are_limited() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let start = Instant::now();
+        let limiter = SharedJumpingWindow::new(4, Duration::from_millis(20));
+
+        let mut reader = ThrottledReader::new(&b"0123456789"[..], limiter.clone());
+        let mut read = Vec::new();
+        AsyncReadExt::read_to_end(&mut reader, &mut read)
+            .await
+            .unwrap();
+        assert_eq!(read, b"0123456789");
+
+        let mut writer = ThrottledWriter::new(Vec::new(), limiter);
+        AsyncWriteExt::write_all(&mut writer, b"abcd")
+            .await
+            .unwrap();
+        assert_eq!(writer.get_ref(), b"abcd");
+        // 14 bytes at 4 per window.
+        assert!(start.elapsed() >= Duration::from_millis(60));
+    }
+}
@@ -0,0 +1,159 @@
+//! Adapters for throttling an `Iterator` with a cooldown, for blocking code.
+
+use std::{
+    iter::{Fuse, FusedIterator},
+    thread::sleep,
+};
+
+use crate::RateLimiter;
+
+/// The cost function used by `RateLimitIteratorExt::ratelimit`, where every item costs one
+/// token.
+pub type UnitCost<T> = fn(&T) -> u64;
+
+/// An extension trait for throttling any `Iterator` with a cooldown.
+pub trait RateLimitIteratorExt: Iterator + Sized {
+    /// Trigger `limiter` before yielding each item, sleeping the current thread until the next
+    /// reset if there are no triggers left.
+    ///
+    /// # Arguments
+    /// * `limiter` - The limiter to trigger for each item.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{iter::RateLimitIteratorExt, JumpingWindow};
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let start = Instant::now();
+    /// let window = JumpingWindow::new(2, Duration::from_millis(20));
+    ///
+    /// let items: Vec<_> = (0..5).ratelimit(window).collect();
+    /// assert_eq!(items, [0, 1, 2, 3, 4]);
+    /// // the last item had to wait for the third window.
+    /// assert!(start.elapsed() >= Duration::from_millis(40));
+    /// ```
+    fn ratelimit<L: RateLimiter>(self, limiter: L) -> RateLimited<Self, L, UnitCost<Self::Item>> {
+        self.ratelimit_with_cost(limiter, |_| 1)
+    }
+
+    /// Like `ratelimit`, but each item consumes `cost(&item)` tokens. A cost greater than the
+    /// limiter's capacity is treated as the capacity, so that every item is eventually yielded.
+    ///
+    /// # Arguments
+    /// * `limiter` - The limiter to trigger for each item.
+    /// * `cost` - A function returning how many tokens an item consumes.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{iter::RateLimitIteratorExt, JumpingWindow};
+    /// use std::time::Duration;
+    ///
+    /// let window = JumpingWindow::new(10, Duration::from_secs(10));
+    /// let mut batches = [vec![0; 4], vec![0; 4], vec![0; 4]]
+    ///     .into_iter()
+    ///     .ratelimit_with_cost(window, |batch| batch.len() as u64);
+    ///
+    /// batches.next();
+    /// batches.next();
+    /// assert_eq!(batches.limiter().peek_tokens(None), 2);
+    /// ```
+    fn ratelimit_with_cost<L, F>(self, limiter: L, cost: F) -> RateLimited<Self, L, F>
+    where
+        L: RateLimiter,
+        F: FnMut(&Self::Item) -> u64,
+    {
+        RateLimited {
+            iter: self.fuse(),
+            limiter,
+            cost,
+        }
+    }
+}
+
+impl<I: Iterator> RateLimitIteratorExt for I {}
+
+/// An iterator throttled by a limiter, created by `RateLimitIteratorExt::ratelimit` or
+/// `RateLimitIteratorExt::ratelimit_with_cost`.
+#[derive(Debug, Clone)]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct RateLimited<I, L, F> {
+    iter: Fuse<I>,
+    limiter: L,
+    cost: F,
+}
+
+impl<I, L, F> RateLimited<I, L, F> {
+    /// The limiter throttling the iterator.
+    pub fn limiter(&self) -> &L {
+        &self.limiter
+    }
+}
+
+impl<I, L, F> Iterator for RateLimited<I, L, F>
+where
+    I: Iterator,
+    L: RateLimiter,
+    F: FnMut(&I::Item) -> u64,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.iter.next()?;
+        let cost = (self.cost)(&item).min(self.limiter.capacity());
+
+        while let Err(retry_after) = self.limiter.trigger_n(cost, None) {
+            sleep(retry_after);
+        }
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<I, L, F> FusedIterator for RateLimited<I, L, F>
+where
+    I: Iterator,
+    L: RateLimiter,
+    F: FnMut(&I::Item) -> u64,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::Cell,
+        time::{Duration, Instant},
+    };
+
+    use super::RateLimitIteratorExt;
+    use crate::JumpingWindow;
+
+    #[test]
+    fn items_are_pulled_lazily() {
+        let pulled = Cell::new(0);
+        let window = JumpingWindow::new(1, Duration::from_millis(20));
+        let mut limited = (0..3)
+            .inspect(|_| pulled.set(pulled.get() + 1))
+            .ratelimit(window);
+
+        assert_eq!(pulled.get(), 0);
+        assert_eq!(limited.next(), Some(0));
+        assert_eq!(pulled.get(), 1);
+    }
+
+    #[test]
+    fn oversized_items_take_a_whole_window() {
+        let start = Instant::now();
+        let window = JumpingWindow::new(2, Duration::from_millis(20));
+        let mut limited = (0..3).ratelimit_with_cost(window, |_| 5);
+
+        assert_eq!(limited.next(), Some(0));
+        assert_eq!(limited.next(), Some(1));
+        assert_eq!(limited.next(), Some(2));
+        assert!(start.elapsed() >= Duration::from_millis(40));
+        assert_eq!(limited.next(), None);
+        assert_eq!(limited.next(), None);
+    }
+}
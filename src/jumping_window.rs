@@ -1,13 +1,116 @@
-use std::time::{Duration, Instant};
+use std::{collections::VecDeque, fmt, iter, time::Duration};
+
+#[cfg(feature = "serde")]
+use crate::{clock::SystemTime, TickDuration};
+use crate::{
+    clock::{self, Instant},
+    error::validate,
+    history::TriggerHistory,
+    Clock, InvalidWindow, Jitter, JumpingWindowCore, MonotonicClock, Penalty, Priority, Rate,
+    RateLimitInfo, RateLimited, RateLimiter, Shedding, TriggerRecord, Warmup,
+};
 
 /// A simple ratelimit implementation.
-#[derive(Debug)]
-pub struct JumpingWindow {
-    pub(crate) capacity: u64,
-    pub(crate) period: Duration,
+///
+/// Every method optionally takes the current time as `now`. If `now` is earlier than the start
+/// of the current window (for example, when replaying events slightly out of order), it is
+/// treated as the start of the window: the trigger counts against the current window, and the
+/// next reset is a full period away.
+///
+/// When `now` isn't given, the time is read from the window's `floodgate::Clock`. See
+/// `JumpingWindow::with_clock`.
+#[derive(Debug, Clone)]
+pub struct JumpingWindow<C = MonotonicClock> {
+    core: JumpingWindowCore<Instant>,
+    rejected: u64,
+    warmup: Option<Warmup>,
+    warming: Option<Warming>,
+    /// Boxed, since most windows don't record their recent utilization.
+    recent: Option<Box<Utilizations>>,
+    triggers: Option<TriggerHistory>,
+    /// The fraction of the capacity kept for high priority triggers.
+    floor: Option<f64>,
+    shedding: Option<Shedding>,
+    /// When the window was paused, if it is. Time stands still at it until resumed.
+    paused_at: Option<Instant>,
+    /// How long the window has been paused in total, to shift reservations made before a pause.
+    paused_for: Duration,
+    clock: C,
+}
 
-    last_reset: Instant,
-    tokens: u64,
+/// The final utilization of the most recent windows, oldest first.
+#[derive(Debug, Clone)]
+struct Utilizations {
+    samples: VecDeque<f64>,
+    /// The most samples to keep.
+    len: usize,
+}
+
+impl Utilizations {
+    fn new(len: usize) -> Self {
+        Self {
+            samples: VecDeque::new(),
+            len,
+        }
+    }
+
+    fn push(&mut self, sample: f64) {
+        if self.samples.len() == self.len {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+}
+
+/// A token reserved with `floodgate::JumpingWindow::reserve`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Reservation {
+    /// When the token may be used. This isn't updated when the window is paused and resumed
+    /// afterwards, which moves the reserved token later by how long it was paused, so `at` is
+    /// then too early. `Reservation::cancel` accounts for it.
+    pub at: Instant,
+    /// Whether the token was booked out of a coming window.
+    booked: bool,
+    /// How long the window had been paused in total when the token was reserved.
+    paused_for: Duration,
+}
+
+impl Reservation {
+    /// Whether the token was booked out of a coming window, rather than taken from the
+    /// current one.
+    pub fn is_booked(&self) -> bool {
+        self.booked
+    }
+
+    /// Give the token back to `window`, which it was reserved from. Tokens of a window that
+    /// has already ended are gone, so cancelling them does nothing.
+    ///
+    /// # Arguments
+    /// * `window` - The window the token was reserved from.
+    /// * `now` - Optionally specify the current time.
+    pub fn cancel<C: Clock>(self, window: &mut JumpingWindow<C>, now: Option<Instant>) {
+        let now = window.now(now);
+        window.advance(now);
+        // the windows were moved by every pause since the token was reserved.
+        let at = self.at + window.paused_for.saturating_sub(self.paused_for);
+        if self.booked {
+            window.core.unbook(at, now);
+            return;
+        }
+        // the token was taken from what was the current window, which may have ended since.
+        window.core.tokens(now);
+        if at >= window.window_start() {
+            window.core.give_back(1, now);
+        }
+    }
+}
+
+/// A warm-up in progress. The core holds the effective capacity in the meantime.
+#[derive(Debug, Clone, Copy)]
+struct Warming {
+    since: Instant,
+    /// The configured capacity, which is reached once the warm-up is over.
+    capacity: u64,
 }
 
 impl JumpingWindow {
@@ -32,92 +135,330 @@ impl JumpingWindow {
     /// // is, how long before there will be more triggers available.
     /// assert!(matches!(cooldown.trigger(None), Some(_)));
     /// ```
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `period` is zero. See `JumpingWindow::try_new`.
     pub fn new(capacity: u64, period: Duration) -> Self {
-        Self {
-            capacity,
-            period,
-            last_reset: Instant::now(),
-            tokens: capacity,
-        }
+        Self::try_new(capacity, period).unwrap_or_else(|err| panic!("{err}"))
     }
 
-    /// How many triggers (tokens) are left in the current window.
+    /// Create a new JumpingWindow, returning an error if `capacity` or `period` is zero.
     ///
     /// # Arguments
-    /// * `now` - Optionally specify the current time.
+    /// * `capacity` - How many triggers can occur per window.
+    /// * `period` - How long the window is.
     ///
     /// # Examples
     /// ```
-    /// use floodgate::JumpingWindow;
+    /// use floodgate::{InvalidWindow, JumpingWindow};
     /// use std::time::Duration;
     ///
-    /// let mut cooldown = JumpingWindow::new(1, Duration::from_secs(10));
+    /// assert!(JumpingWindow::try_new(1, Duration::from_secs(10)).is_ok());
+    /// assert_eq!(
+    ///     JumpingWindow::try_new(0, Duration::from_secs(10)).unwrap_err(),
+    ///     InvalidWindow::ZeroCapacity
+    /// );
+    /// assert_eq!(
+    ///     JumpingWindow::try_new(1, Duration::ZERO).unwrap_err(),
+    ///     InvalidWindow::ZeroPeriod
+    /// );
+    /// ```
+    pub fn try_new(capacity: u64, period: Duration) -> Result<Self, InvalidWindow> {
+        Self::try_with_clock(capacity, period, MonotonicClock)
+    }
+
+    /// Create a new JumpingWindow whose windows are aligned to the time it was created.
     ///
-    /// assert_eq!(cooldown.tokens(None), 1);
-    /// cooldown.trigger(None);
-    /// assert_eq!(cooldown.tokens(None), 0);
+    /// Normally, when a window has expired, the next one starts whenever the window is next
+    /// used. In aligned mode, windows instead always start at an integer number of periods after
+    /// the original anchor, so they don't drift over time and windows created together stay in
+    /// sync. To choose the anchor, use `JumpingWindowBuilder::aligned` with
+    /// `JumpingWindowBuilder::last_reset`.
+    ///
+    /// # Arguments
+    /// * `capacity` - How many triggers can occur per window.
+    /// * `period` - How long the window is.
+    ///
+    /// # Examples
     /// ```
-    pub fn tokens(&mut self, now: Option<Instant>) -> u64 {
-        let now = now.unwrap_or_else(Instant::now);
+    /// use floodgate::JumpingWindow;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let anchor = Instant::now();
+    /// let mut cooldown = JumpingWindow::builder(1, Duration::from_secs(10))
+    ///     .aligned()
+    ///     .last_reset(anchor)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// // the window used 25 seconds after the anchor started 20 seconds after it.
+    /// let now = anchor + Duration::from_secs(25);
+    /// cooldown.trigger(Some(now));
+    /// assert_eq!(cooldown.window_start(), anchor + Duration::from_secs(20));
+    /// assert_eq!(cooldown.next_reset(Some(now)), Duration::from_secs(5));
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `period` is zero.
+    pub fn new_aligned(capacity: u64, period: Duration) -> Self {
+        let mut window = Self::new(capacity, period);
+        window.core.aligned = true;
+        window
+    }
 
-        if now.duration_since(self.last_reset) > self.period {
-            self.reset(Some(now));
-        }
+    /// Create a `JumpingWindowBuilder`, for windows that shouldn't start out full.
+    ///
+    /// # Arguments
+    /// * `capacity` - How many triggers can occur per window.
+    /// * `period` - How long the window is.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// // recreate a window that was created 4 seconds ago, and has been triggered once.
+    /// let now = Instant::now();
+    /// let mut cooldown = JumpingWindow::builder(2, Duration::from_secs(10))
+    ///     .initial_tokens(1)
+    ///     .last_reset(now - Duration::from_secs(4))
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(cooldown.tokens(Some(now)), 1);
+    /// assert_eq!(cooldown.next_reset(Some(now)), Duration::from_secs(6));
+    /// ```
+    pub fn builder(capacity: u64, period: Duration) -> JumpingWindowBuilder {
+        JumpingWindowBuilder::new(capacity, period)
+    }
+
+    /// Create a new JumpingWindow that allows `rate`.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{JumpingWindow, Rate};
+    /// use std::time::Duration;
+    ///
+    /// let cooldown = JumpingWindow::from_rate(Rate::new(5, Duration::from_secs(10)));
+    /// assert_eq!(cooldown.capacity(), 5);
+    /// ```
+    pub fn from_rate(rate: Rate) -> Self {
+        Self::new(rate.capacity(), rate.period())
+    }
 
-        self.tokens
+    /// Create a new JumpingWindow that allows `capacity` triggers per second.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is zero.
+    pub fn per_second(capacity: u64) -> Self {
+        Self::from_rate(Rate::per_second(capacity))
     }
 
-    /// Return the time until the next reset.
+    /// Create a new JumpingWindow that allows `capacity` triggers per minute.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is zero.
+    pub fn per_minute(capacity: u64) -> Self {
+        Self::from_rate(Rate::per_minute(capacity))
+    }
+
+    /// Create a new JumpingWindow that allows `capacity` triggers per hour.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is zero.
+    pub fn per_hour(capacity: u64) -> Self {
+        Self::from_rate(Rate::per_hour(capacity))
+    }
+}
+
+impl<C: Clock> JumpingWindow<C> {
+    /// Create a new JumpingWindow that reads the time from `clock`.
     ///
     /// # Arguments
-    /// * `now` - Optionally specify the current time.
+    /// * `capacity` - How many triggers can occur per window.
+    /// * `period` - How long the window is.
+    /// * `clock` - The clock to read the time from when `now` isn't given.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{JumpingWindow, ManualClock};
+    /// use std::time::Duration;
+    ///
+    /// let clock = ManualClock::new();
+    /// let mut cooldown = JumpingWindow::with_clock(1, Duration::from_secs(10), clock.clone());
+    ///
+    /// assert_eq!(cooldown.trigger(None), None);
+    /// clock.advance(Duration::from_secs(4));
+    /// assert_eq!(cooldown.trigger(None), Some(Duration::from_secs(6)));
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `period` is zero. See `JumpingWindow::try_with_clock`.
+    pub fn with_clock(capacity: u64, period: Duration, clock: C) -> Self {
+        Self::try_with_clock(capacity, period, clock).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Create a new JumpingWindow that reads the time from `clock`, returning an error if
+    /// `capacity` or `period` is zero.
+    ///
+    /// # Arguments
+    /// * `capacity` - How many triggers can occur per window.
+    /// * `period` - How long the window is.
+    /// * `clock` - The clock to read the time from when `now` isn't given.
+    pub fn try_with_clock(
+        capacity: u64,
+        period: Duration,
+        clock: C,
+    ) -> Result<Self, InvalidWindow> {
+        validate(capacity, period)?;
+
+        Ok(Self {
+            core: JumpingWindowCore::new(capacity, period, clock.now()),
+            rejected: 0,
+            warmup: None,
+            warming: None,
+            recent: None,
+            triggers: None,
+            floor: None,
+            shedding: None,
+            paused_at: None,
+            paused_for: Duration::ZERO,
+            clock,
+        })
+    }
+
+    /// The clock the window reads the time from.
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    /// How many triggers can occur per window.
     ///
     /// # Examples
     /// ```
     /// use floodgate::JumpingWindow;
     /// use std::time::Duration;
     ///
-    /// let mut cooldown = JumpingWindow::new(1, Duration::from_secs(10));
-    /// let next_reset = cooldown.next_reset(None);
-    /// assert!(next_reset > Duration::from_secs(9));
-    /// assert!(next_reset < Duration::from_secs(11));
+    /// let cooldown = JumpingWindow::new(2, Duration::from_secs(10));
+    /// assert_eq!(cooldown.capacity(), 2);
     /// ```
-    pub fn next_reset(&mut self, now: Option<Instant>) -> Duration {
-        let now = now.unwrap_or_else(Instant::now);
-        let since = now.duration_since(self.last_reset);
+    pub fn capacity(&self) -> u64 {
+        match self.warming {
+            Some(warming) => warming.capacity,
+            None => self.core.capacity(),
+        }
+    }
 
-        if since > self.period {
-            Duration::from_secs(0)
-        } else {
-            self.period - since
+    /// How long the window is.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::Duration;
+    ///
+    /// let cooldown = JumpingWindow::new(2, Duration::from_secs(10));
+    /// assert_eq!(cooldown.period(), Duration::from_secs(10));
+    /// ```
+    pub fn period(&self) -> Duration {
+        self.core.period()
+    }
+
+    /// The capacity and period together. See `JumpingWindow::capacity`.
+    pub fn rate(&self) -> Rate {
+        Rate::new(self.capacity(), self.period())
+    }
+
+    /// When the current window started, i.e. the time of the last reset. Note that the window
+    /// is reset lazily, so this may be more than `period` ago.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = JumpingWindow::new(2, Duration::from_secs(10));
+    /// cooldown.reset(Some(now));
+    ///
+    /// assert_eq!(cooldown.window_start(), now);
+    /// ```
+    pub fn window_start(&self) -> Instant {
+        self.core.window_start()
+    }
+
+    /// Change the capacity of the window, keeping its current state. When shrinking, the
+    /// remaining tokens are clamped to the new capacity; when growing, the difference is added
+    /// to them.
+    ///
+    /// # Arguments
+    /// * `capacity` - The new capacity.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = JumpingWindow::new(5, Duration::from_secs(10));
+    /// cooldown.reset(Some(now));
+    /// cooldown.trigger(Some(now));
+    ///
+    /// // 4 tokens are left, which is more than the new capacity.
+    /// cooldown.set_capacity(2);
+    /// assert_eq!(cooldown.tokens(Some(now)), 2);
+    ///
+    /// cooldown.set_capacity(4);
+    /// assert_eq!(cooldown.tokens(Some(now)), 4);
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `capacity` is zero.
+    pub fn set_capacity(&mut self, capacity: u64) {
+        if let Err(err) = validate(capacity, self.period()) {
+            panic!("{err}");
+        }
+        match &mut self.warming {
+            Some(warming) => {
+                warming.capacity = capacity;
+                self.advance(self.now(None));
+            }
+            None => self.core.set_capacity(capacity),
         }
     }
 
-    /// Similar to `next_reset`, except that it returns `None` if you still have triggers.
+    /// Change the period of the window, keeping its current state. If the current window is
+    /// already older than the new period, it is reset the next time it is used.
     ///
     /// # Arguments
-    /// * `now` - Optionally specify the current time.
+    /// * `period` - The new period.
     ///
     /// # Examples
     /// ```
     /// use floodgate::JumpingWindow;
-    /// use std::time::Duration;
+    /// use std::time::{Duration, Instant};
     ///
+    /// let now = Instant::now();
     /// let mut cooldown = JumpingWindow::new(1, Duration::from_secs(10));
+    /// cooldown.reset(Some(now));
+    /// cooldown.trigger(Some(now));
     ///
-    /// assert_eq!(cooldown.retry_after(None), None);
-    /// cooldown.trigger(None);
-    /// assert!(matches!(cooldown.retry_after(None), Some(_)));
+    /// let later = now + Duration::from_secs(6);
+    /// assert_eq!(cooldown.tokens(Some(later)), 0);
+    ///
+    /// cooldown.set_period(Duration::from_secs(5));
+    /// assert_eq!(cooldown.tokens(Some(later)), 1);
     /// ```
-    pub fn retry_after(&mut self, now: Option<Instant>) -> Option<Duration> {
-        if self.tokens(now) == 0 {
-            Some(self.next_reset(now))
-        } else {
-            None
+    ///
+    /// # Panics
+    /// Panics if `period` is zero.
+    pub fn set_period(&mut self, period: Duration) {
+        if let Err(err) = validate(self.capacity(), period) {
+            panic!("{err}");
         }
+        self.core.set_period(period);
     }
 
-    /// Returns whether or not there are still available triggers.
+    /// How many triggers (tokens) are left in the current window.
     ///
     /// # Arguments
     /// * `now` - Optionally specify the current time.
@@ -129,59 +470,2187 @@ impl JumpingWindow {
     ///
     /// let mut cooldown = JumpingWindow::new(1, Duration::from_secs(10));
     ///
-    /// assert_eq!(cooldown.can_trigger(None), true);
+    /// assert_eq!(cooldown.tokens(None), 1);
     /// cooldown.trigger(None);
-    /// assert_eq!(cooldown.can_trigger(None), false);
+    /// assert_eq!(cooldown.tokens(None), 0);
     /// ```
-    pub fn can_trigger(&mut self, now: Option<Instant>) -> bool {
-        self.tokens(now) != 0
+    pub fn tokens(&mut self, now: Option<Instant>) -> u64 {
+        let now = self.now(now);
+        self.advance(now);
+        self.core.tokens(now)
     }
 
-    /// Trigger the cooldown.
+    /// Whether this window is aligned to its anchor. See `JumpingWindow::new_aligned`.
+    pub fn is_aligned(&self) -> bool {
+        self.core.is_aligned()
+    }
+
+    /// Punish triggers made while the window is exhausted, by extending the window with
+    /// `penalty` every time one is rejected. `retry_after` and `next_reset` report the
+    /// extended window, so a client that waits that long before triggering again still
+    /// succeeds. A `trigger_n` rejected only because fewer tokens are left than it costs isn't
+    /// punished.
     ///
     /// # Arguments
-    /// * `now` - Optionally specify the current time.
+    /// * `penalty` - How the window is extended.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{JumpingWindow, Penalty};
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let penalty = Penalty::Extend(Duration::from_secs(5), Duration::from_secs(30));
+    /// let mut cooldown = JumpingWindow::builder(1, Duration::from_secs(10))
+    ///     .last_reset(now)
+    ///     .build()
+    ///     .unwrap()
+    ///     .punitive(penalty);
+    ///
+    /// cooldown.trigger(Some(now));
+    /// assert_eq!(cooldown.trigger(Some(now)), Some(Duration::from_secs(15)));
+    /// assert_eq!(cooldown.trigger(Some(now)), Some(Duration::from_secs(20)));
+    /// assert_eq!(cooldown.retry_after(Some(now)), Some(Duration::from_secs(20)));
+    /// ```
+    pub fn punitive(mut self, penalty: Penalty) -> Self {
+        self.core.set_penalty(Some(penalty));
+        self
+    }
+
+    /// Set or clear the penalty. See `JumpingWindow::punitive`. An extension the current
+    /// window already has is kept until it ends.
+    pub fn set_penalty(&mut self, penalty: Option<Penalty>) {
+        self.core.set_penalty(penalty);
+    }
+
+    /// The penalty set with `JumpingWindow::punitive`, if any.
+    pub fn penalty(&self) -> Option<Penalty> {
+        self.core.penalty()
+    }
+
+    /// Carry tokens left unused at the end of a window over into the next one, so that up to
+    /// `burst` triggers can be made at once after a quiet spell. Each window still adds only
+    /// `capacity` tokens, so the long-run rate is unchanged. This approximates a token bucket
+    /// that refills once per period, while keeping the window's reset semantics.
+    ///
+    /// `trigger_n`, `wait_for` and `refund` count against `burst` rather than the capacity.
+    /// A `burst` below the capacity is treated as the capacity.
+    ///
+    /// # Arguments
+    /// * `burst` - The most tokens that can be banked.
     ///
     /// # Examples
     /// ```
     /// use floodgate::JumpingWindow;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = JumpingWindow::builder(2, Duration::from_secs(10))
+    ///     .last_reset(now)
+    ///     .build()
+    ///     .unwrap()
+    ///     .carry_over(5);
+    ///
+    /// cooldown.trigger(Some(now));
+    /// // the unused token is carried over, and so are both tokens of the quiet window after.
+    /// assert_eq!(cooldown.tokens(Some(now + Duration::from_secs(25))), 5);
+    /// ```
+    pub fn carry_over(mut self, burst: u64) -> Self {
+        self.core.set_carry_over(Some(burst));
+        self
+    }
+
+    /// Set or clear the carry-over burst. See `JumpingWindow::carry_over`. Clearing it clamps
+    /// any banked tokens to the capacity.
+    pub fn set_carry_over(&mut self, burst: Option<u64>) {
+        self.core.set_carry_over(burst);
+    }
+
+    /// Randomize the length of each window by up to `jitter`'s amount either way, so that
+    /// windows which started at the same time, such as those of every client after a deploy,
+    /// reset at different times. The current window is jittered straight away. `retry_after`
+    /// and `next_reset` report the jittered length, so waiting for them is still enough.
+    ///
+    /// Aligned windows are never jittered, and the jitter isn't kept when the window is
+    /// serialized.
+    ///
+    /// # Arguments
+    /// * `jitter` - How much to randomize the windows by, and how.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{Jitter, JumpingWindow};
     /// use std::time::Duration;
     ///
-    /// let mut cooldown = JumpingWindow::new(1, Duration::from_secs(10));
+    /// let period = Duration::from_secs(10);
+    /// let jitter = Jitter::new(Duration::from_secs(1));
+    /// let mut cooldown = JumpingWindow::new(1, period).jittered(jitter);
     ///
-    /// assert_eq!(cooldown.trigger(None), None);
-    /// assert!(matches!(cooldown.trigger(None), Some(_)));
+    /// assert!(cooldown.next_reset(None) <= period + Duration::from_secs(1));
     /// ```
-    pub fn trigger(&mut self, now: Option<Instant>) -> Option<Duration> {
-        let tokens = self.tokens(now);
+    pub fn jittered(mut self, jitter: Jitter) -> Self {
+        self.core.set_jitter(Some(jitter));
+        self
+    }
 
-        if tokens == 0 {
-            Some(self.next_reset(now))
-        } else {
-            self.tokens -= 1;
-            None
+    /// Set or clear the jitter, redrawing the length of the current window. See
+    /// `JumpingWindow::jittered`.
+    pub fn set_jitter(&mut self, jitter: Option<Jitter>) {
+        self.core.set_jitter(jitter);
+    }
+
+    /// The jitter set with `JumpingWindow::jittered`, if any.
+    pub fn jitter(&self) -> Option<Jitter> {
+        self.core.jitter()
+    }
+
+    /// Keep `floor` of the capacity of each window for high priority triggers: low priority
+    /// ones made with `JumpingWindow::trigger_with_priority` are rejected once only that much
+    /// is left. The reserved tokens are the fraction of the capacity, rounded to the nearest
+    /// token. Low priority triggers held back only by the floor aren't punished by
+    /// `JumpingWindow::punitive`, so they never delay the reset for high priority ones.
+    ///
+    /// # Arguments
+    /// * `floor` - The fraction of the capacity to reserve, from `0.0` to `1.0`.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{JumpingWindow, Priority};
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = JumpingWindow::new(5, Duration::from_secs(10)).prioritized(0.2);
+    ///
+    /// for _ in 0..4 {
+    ///     assert_eq!(cooldown.trigger_with_priority(Priority::Low, None), None);
+    /// }
+    /// // the last token is kept for high priority triggers.
+    /// assert!(cooldown.trigger_with_priority(Priority::Low, None).is_some());
+    /// assert_eq!(cooldown.trigger_with_priority(Priority::High, None), None);
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `floor` isn't between `0.0` and `1.0`.
+    pub fn prioritized(mut self, floor: f64) -> Self {
+        self.set_priority_floor(Some(floor));
+        self
+    }
+
+    /// Change the fraction of the capacity kept for high priority triggers, or stop keeping
+    /// any with `None`. See `JumpingWindow::prioritized`.
+    ///
+    /// # Panics
+    /// Panics if `floor` isn't between `0.0` and `1.0`.
+    pub fn set_priority_floor(&mut self, floor: Option<f64>) {
+        if let Some(floor) = floor {
+            assert!(
+                (0.0..=1.0).contains(&floor),
+                "floor must be between 0 and 1"
+            );
         }
+        self.floor = floor;
+    }
+
+    /// The fraction of the capacity kept for high priority triggers, if any.
+    pub fn priority_floor(&self) -> Option<f64> {
+        self.floor
+    }
+
+    /// Start rejecting triggers at random once the window is nearly used up, instead of only
+    /// once it is exhausted. See `floodgate::Shedding`.
+    ///
+    /// Triggers rejected this way are soft rejections: their retry-after is how long a token
+    /// lasts on average, rather than the time until the next reset, and
+    /// `RateLimitInfo::is_soft_rejection` tells them apart.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{JumpingWindow, Shedding};
+    /// use std::time::Duration;
+    ///
+    /// // reject every trigger past the threshold, to show what a soft rejection looks like.
+    /// let shedding = Shedding::new(0.5).rng(|_| 0);
+    /// let mut cooldown = JumpingWindow::new(4, Duration::from_secs(10)).load_shedding(shedding);
+    ///
+    /// cooldown.trigger_n(2, None).unwrap();
+    /// assert_eq!(cooldown.trigger(None), None);
+    /// let info = cooldown.trigger_info(None);
+    /// assert!(info.is_soft_rejection());
+    /// assert_eq!(info.retry_after, Some(Duration::from_millis(2500)));
+    /// ```
+    pub fn load_shedding(mut self, shedding: Shedding) -> Self {
+        self.shedding = Some(shedding);
+        self
     }
 
-    /// Reset the cooldown.
+    /// Change how triggers are shed, or stop shedding them with `None`. See
+    /// `JumpingWindow::load_shedding`.
+    pub fn set_shedding(&mut self, shedding: Option<Shedding>) {
+        self.shedding = shedding;
+    }
+
+    /// How triggers are shed, if they are.
+    pub fn shedding(&self) -> Option<Shedding> {
+        self.shedding
+    }
+
+    /// Start with a fraction of the capacity, and ramp up to all of it, for example so that a
+    /// service that just restarted isn't hit with full load while its caches are cold. The
+    /// warm-up starts now; use `JumpingWindow::begin_warmup` to start it again later.
+    ///
+    /// While warming up, `capacity` is the configured capacity, and `effective_capacity` the
+    /// one currently in force. Growing the capacity adds the difference to the current window,
+    /// as with `JumpingWindow::set_capacity`. The warm-up isn't kept when the window is
+    /// serialized.
     ///
     /// # Arguments
-    /// * `now` - Optionally specify the current time.
+    /// * `warmup` - How long the ramp takes, and where it starts.
     ///
     /// # Examples
     /// ```
-    /// use floodgate::JumpingWindow;
+    /// use floodgate::{JumpingWindow, ManualClock, Warmup};
     /// use std::time::Duration;
     ///
-    /// let mut cooldown = JumpingWindow::new(1, Duration::from_secs(10));
-    /// cooldown.trigger(None);
+    /// let clock = ManualClock::new();
+    /// let warmup = Warmup::new(Duration::from_secs(60), 0.5);
+    /// let mut cooldown =
+    ///     JumpingWindow::with_clock(10, Duration::from_secs(10), clock.clone()).slow_start(warmup);
     ///
-    /// assert!(!cooldown.can_trigger(None));
-    /// cooldown.reset(None);
-    /// assert!(cooldown.can_trigger(None));
+    /// assert_eq!(cooldown.tokens(None), 5);
+    /// assert_eq!(cooldown.capacity(), 10);
+    ///
+    /// clock.advance(Duration::from_secs(60));
+    /// assert_eq!(cooldown.tokens(None), 10);
     /// ```
-    pub fn reset(&mut self, now: Option<Instant>) {
-        self.tokens = self.capacity;
-        self.last_reset = now.unwrap_or_else(Instant::now);
+    pub fn slow_start(mut self, warmup: Warmup) -> Self {
+        self.warmup = Some(warmup);
+        self.begin_warmup(None);
+        self
+    }
+
+    /// Start the warm-up set with `JumpingWindow::slow_start` again from `now`, for example after
+    /// a dependency restarted. The effective capacity drops back to the start of the ramp,
+    /// clamping the tokens left. Does nothing without a warm-up.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    pub fn begin_warmup(&mut self, now: Option<Instant>) {
+        if self.warmup.is_none() {
+            return;
+        }
+        let now = self.now(now);
+        self.warming = Some(Warming {
+            since: now,
+            capacity: self.capacity(),
+        });
+        self.advance(now);
+    }
+
+    /// The warm-up set with `JumpingWindow::slow_start`, if any.
+    pub fn warmup(&self) -> Option<Warmup> {
+        self.warmup
+    }
+
+    /// The capacity in force at `now`, which is below `capacity` while warming up.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    pub fn effective_capacity(&self, now: Option<Instant>) -> u64 {
+        match (self.warmup, self.warming) {
+            (Some(warmup), Some(warming)) => {
+                let now = self.now(now);
+                let elapsed = now.saturating_duration_since(warming.since);
+                warmup.capacity(warming.capacity, elapsed)
+            }
+            _ => self.core.capacity(),
+        }
+    }
+
+    /// The most triggers that can be made at once: the burst set with
+    /// `JumpingWindow::carry_over`, or else the capacity.
+    pub fn burst_capacity(&self) -> u64 {
+        self.core.burst_capacity()
+    }
+
+    /// Like `tokens`, except that it doesn't mutate the window. If the window has expired, the
+    /// returned value is what `tokens` would return after resetting it.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = JumpingWindow::new(2, Duration::from_secs(10));
+    /// cooldown.trigger(Some(now));
+    ///
+    /// assert_eq!(cooldown.peek_tokens(Some(now)), 1);
+    ///
+    /// let later = now + Duration::from_secs(11);
+    /// assert_eq!(cooldown.peek_tokens(Some(later)), 2);
+    /// assert_eq!(cooldown.peek_tokens(Some(later)), cooldown.tokens(Some(later)));
+    /// ```
+    pub fn peek_tokens(&self, now: Option<Instant>) -> u64 {
+        let now = self.now(now);
+        let capacity = self.effective_capacity(Some(now));
+        if capacity == self.core.capacity() {
+            return self.core.peek_tokens(now);
+        }
+        // move a copy along the warm-up, like `tokens` does to the window itself.
+        let mut core = self.core.clone();
+        core.set_capacity(capacity);
+        core.peek_tokens(now)
+    }
+
+    /// Return the time until the next reset.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = JumpingWindow::new(1, Duration::from_secs(10));
+    /// let next_reset = cooldown.next_reset(None);
+    /// assert!(next_reset > Duration::from_secs(9));
+    /// assert!(next_reset < Duration::from_secs(11));
+    /// ```
+    pub fn next_reset(&mut self, now: Option<Instant>) -> Duration {
+        self.peek_next_reset(now)
+    }
+
+    /// Like `next_reset`, except that it only requires a shared reference.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::Duration;
+    ///
+    /// let cooldown = JumpingWindow::new(1, Duration::from_secs(10));
+    /// assert!(cooldown.peek_next_reset(None) <= Duration::from_secs(10));
+    /// ```
+    pub fn peek_next_reset(&self, now: Option<Instant>) -> Duration {
+        let now = self.now(now);
+        self.core.next_reset(now)
+    }
+
+    /// Return the time at which the next reset happens.
+    ///
+    /// This is computed from the start of the current window, so repeated calls return the same
+    /// value. If the window has already expired but hasn't been reset yet, the returned time is
+    /// in the past (except for aligned windows, where it's the end of the window containing
+    /// `now`).
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = JumpingWindow::new(1, Duration::from_secs(10));
+    /// cooldown.reset(Some(now));
+    ///
+    /// let later = now + Duration::from_secs(3);
+    /// assert_eq!(cooldown.next_reset_at(Some(now)), now + Duration::from_secs(10));
+    /// assert_eq!(cooldown.next_reset_at(Some(later)), now + Duration::from_secs(10));
+    /// ```
+    pub fn next_reset_at(&self, now: Option<Instant>) -> Instant {
+        let now = self.now(now);
+        self.core.next_reset_at(now)
+    }
+
+    /// Like `next_reset_at`, except that it returns `None` if you still have triggers. Windows
+    /// whose tokens were all booked by `JumpingWindow::reserve` are waited past.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = JumpingWindow::new(1, Duration::from_secs(10));
+    /// cooldown.reset(Some(now));
+    ///
+    /// assert_eq!(cooldown.retry_at(Some(now)), None);
+    /// cooldown.trigger(Some(now));
+    /// assert_eq!(cooldown.retry_at(Some(now)), Some(now + Duration::from_secs(10)));
+    /// ```
+    pub fn retry_at(&mut self, now: Option<Instant>) -> Option<Instant> {
+        let now = self.now(now);
+
+        if self.tokens(Some(now)) == 0 {
+            let wait = self.core.wait_for(1, now).unwrap_or(Duration::MAX);
+            Some(now.checked_add(wait).unwrap_or(now))
+        } else {
+            None
+        }
+    }
+
+    /// Similar to `next_reset`, except that it returns `None` if you still have triggers.
+    /// Windows whose tokens were all booked by `JumpingWindow::reserve` are waited past, so
+    /// that a trigger after waiting this long succeeds.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = JumpingWindow::new(1, Duration::from_secs(10));
+    ///
+    /// assert_eq!(cooldown.retry_after(None), None);
+    /// cooldown.trigger(None);
+    /// assert!(matches!(cooldown.retry_after(None), Some(_)));
+    /// ```
+    pub fn retry_after(&mut self, now: Option<Instant>) -> Option<Duration> {
+        let now = self.now(now);
+
+        if self.tokens(Some(now)) == 0 {
+            Some(self.core.wait_for(1, now).unwrap_or(Duration::MAX))
+        } else {
+            None
+        }
+    }
+
+    /// Like `retry_after`, except that it doesn't mutate the window.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = JumpingWindow::new(1, Duration::from_secs(10));
+    ///
+    /// assert_eq!(cooldown.peek_retry_after(Some(now)), None);
+    /// cooldown.trigger(Some(now));
+    /// assert_eq!(
+    ///     cooldown.peek_retry_after(Some(now)),
+    ///     Some(Duration::from_secs(10))
+    /// );
+    /// ```
+    pub fn peek_retry_after(&self, now: Option<Instant>) -> Option<Duration> {
+        let now = self.now(now);
+
+        if self.peek_tokens(Some(now)) == 0 {
+            Some(self.core.wait_for(1, now).unwrap_or(Duration::MAX))
+        } else {
+            None
+        }
+    }
+
+    /// How long until `n` triggers can be made at once, which is either now or at the next
+    /// reset, or later if `n` tokens are still being carried over or the coming windows were
+    /// booked by `JumpingWindow::reserve`. Returns `None` if `n` exceeds the burst capacity, so
+    /// that they never can. Nothing is consumed, and the window isn't mutated.
+    ///
+    /// # Arguments
+    /// * `n` - How many triggers to wait for.
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = JumpingWindow::new(5, Duration::from_secs(10));
+    /// cooldown.trigger_n(3, Some(now)).unwrap();
+    ///
+    /// assert_eq!(cooldown.wait_for(2, Some(now)), Some(Duration::ZERO));
+    /// assert_eq!(cooldown.wait_for(5, Some(now)), Some(Duration::from_secs(10)));
+    /// assert_eq!(cooldown.wait_for(6, Some(now)), None);
+    /// ```
+    pub fn wait_for(&self, n: u64, now: Option<Instant>) -> Option<Duration> {
+        let now = self.now(now);
+        self.core.wait_for(n, now)
+    }
+
+    /// Returns whether or not there are still available triggers.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = JumpingWindow::new(1, Duration::from_secs(10));
+    ///
+    /// assert_eq!(cooldown.can_trigger(None), true);
+    /// cooldown.trigger(None);
+    /// assert_eq!(cooldown.can_trigger(None), false);
+    /// ```
+    pub fn can_trigger(&mut self, now: Option<Instant>) -> bool {
+        self.tokens(now) != 0
+    }
+
+    /// Like `can_trigger`, except that it doesn't mutate the window.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = JumpingWindow::new(1, Duration::from_secs(10));
+    ///
+    /// assert!(cooldown.peek_can_trigger(None));
+    /// cooldown.trigger(None);
+    /// assert!(!cooldown.peek_can_trigger(None));
+    /// ```
+    pub fn peek_can_trigger(&self, now: Option<Instant>) -> bool {
+        self.peek_tokens(now) != 0
+    }
+
+    /// Trigger the cooldown.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = JumpingWindow::new(1, Duration::from_secs(10));
+    ///
+    /// assert_eq!(cooldown.trigger(None), None);
+    /// assert!(matches!(cooldown.trigger(None), Some(_)));
+    /// ```
+    pub fn trigger(&mut self, now: Option<Instant>) -> Option<Duration> {
+        let now = self.now(now);
+        self.advance(now);
+        let retry_after = self.shed(now).or_else(|| self.core.trigger(now));
+        self.count(retry_after.is_none(), 1, now);
+        retry_after
+    }
+
+    /// Trigger the cooldown, returning `Err(RateLimited)` if there are no triggers left. This
+    /// is `JumpingWindow::trigger` for functions that return a `Result`.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{JumpingWindow, RateLimited};
+    /// use std::time::Duration;
+    ///
+    /// fn send(cooldown: &mut JumpingWindow) -> Result<(), Box<dyn std::error::Error>> {
+    ///     cooldown.try_trigger(None)?;
+    ///     Ok(())
+    /// }
+    ///
+    /// let mut cooldown = JumpingWindow::new(1, Duration::from_secs(10));
+    ///
+    /// assert!(send(&mut cooldown).is_ok());
+    /// let err = send(&mut cooldown).unwrap_err();
+    /// assert!(err.to_string().starts_with("rate limited, retry in"));
+    /// ```
+    pub fn try_trigger(&mut self, now: Option<Instant>) -> Result<(), RateLimited> {
+        match self.trigger(now) {
+            Some(retry_after) => Err(RateLimited {
+                retry_after,
+                key: (),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Trigger the cooldown, consuming `cost` tokens at once.
+    ///
+    /// Either all `cost` tokens are consumed, or none are. If there aren't enough tokens left,
+    /// the time until the next reset is returned. If `cost` is greater than the capacity, the
+    /// trigger can never succeed, so `Duration::MAX` is returned immediately.
+    ///
+    /// # Arguments
+    /// * `cost` - How many tokens to consume.
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = JumpingWindow::new(5, Duration::from_secs(10));
+    ///
+    /// assert_eq!(cooldown.trigger_n(3, None), Ok(()));
+    /// assert_eq!(cooldown.tokens(None), 2);
+    ///
+    /// // not enough tokens, so nothing is consumed.
+    /// assert!(cooldown.trigger_n(3, None).is_err());
+    /// assert_eq!(cooldown.tokens(None), 2);
+    ///
+    /// // a cost larger than the capacity can never succeed.
+    /// assert_eq!(cooldown.trigger_n(6, None), Err(Duration::MAX));
+    /// ```
+    pub fn trigger_n(&mut self, cost: u64, now: Option<Instant>) -> Result<(), Duration> {
+        let now = self.now(now);
+        self.advance(now);
+        let result = match self.shed(now) {
+            Some(retry_after) => Err(retry_after),
+            None => self.core.trigger_n(cost, now),
+        };
+        self.count(result.is_ok(), cost, now);
+        result
+    }
+
+    /// Trigger the cooldown with the given priority. High priority triggers are the same as
+    /// `JumpingWindow::trigger`, while low priority ones can't use the tokens reserved with
+    /// `JumpingWindow::prioritized`.
+    ///
+    /// # Arguments
+    /// * `priority` - How important the trigger is.
+    /// * `now` - Optionally specify the current time.
+    pub fn trigger_with_priority(
+        &mut self,
+        priority: Priority,
+        now: Option<Instant>,
+    ) -> Option<Duration> {
+        if priority == Priority::High {
+            return self.trigger(now);
+        }
+
+        let now = self.now(now);
+        self.advance(now);
+        let result = match self.shed(now) {
+            Some(retry_after) => Err(retry_after),
+            None => self.core.trigger_reserving(1, self.floor_tokens(), now),
+        };
+        self.count(result.is_ok(), 1, now);
+        result.err()
+    }
+
+    /// How many triggers of the given priority are left in the current window.
+    ///
+    /// # Arguments
+    /// * `priority` - The priority of the triggers.
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{JumpingWindow, Priority};
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = JumpingWindow::new(10, Duration::from_secs(10)).prioritized(0.3);
+    ///
+    /// assert_eq!(cooldown.tokens_for(Priority::High, None), 10);
+    /// assert_eq!(cooldown.tokens_for(Priority::Low, None), 7);
+    /// ```
+    pub fn tokens_for(&mut self, priority: Priority, now: Option<Instant>) -> u64 {
+        let tokens = self.tokens(now);
+        match priority {
+            Priority::High => tokens,
+            Priority::Low => tokens.saturating_sub(self.floor_tokens()),
+        }
+    }
+
+    /// How long until a trigger of the given priority would be allowed, or `None` if it would
+    /// be allowed now. If low priority triggers can never be allowed, because the whole
+    /// capacity is reserved, `Duration::MAX` is returned.
+    ///
+    /// # Arguments
+    /// * `priority` - The priority of the trigger.
+    /// * `now` - Optionally specify the current time.
+    pub fn retry_after_for(
+        &mut self,
+        priority: Priority,
+        now: Option<Instant>,
+    ) -> Option<Duration> {
+        let needed = match priority {
+            Priority::High => 1,
+            Priority::Low => self.floor_tokens().saturating_add(1),
+        };
+        let now = self.now(now);
+        self.advance(now);
+        match self.core.wait_for(needed, now) {
+            Some(Duration::ZERO) => None,
+            Some(wait) => Some(wait),
+            None => Some(Duration::MAX),
+        }
+    }
+
+    /// How many triggers have been rejected since the last one that was allowed.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = JumpingWindow::new(1, Duration::from_secs(10));
+    ///
+    /// cooldown.trigger(None);
+    /// cooldown.trigger(None);
+    /// cooldown.trigger(None);
+    /// assert_eq!(cooldown.rejections(), 2);
+    /// ```
+    pub fn rejections(&self) -> u64 {
+        self.rejected
+    }
+
+    /// How much of the current window's capacity has been used, from `0.0` to `1.0`. Tokens
+    /// carried over from earlier windows count as unused, so it never goes above `1.0`.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = JumpingWindow::new(4, Duration::from_secs(10));
+    ///
+    /// cooldown.trigger(None);
+    /// assert_eq!(cooldown.utilization(None), 0.25);
+    /// ```
+    pub fn utilization(&self, now: Option<Instant>) -> f64 {
+        let now = self.now(now);
+        // the core only catches up with the warm-up when it is used, so read it as of `now`.
+        Self::used(
+            self.peek_tokens(Some(now)),
+            self.effective_capacity(Some(now)),
+        )
+    }
+
+    /// The average utilization the most recent windows ended with, or `None` if no window has
+    /// ended yet. Windows that passed without the limiter being used count as unused.
+    ///
+    /// The windows are only recorded once enabled with `JumpingWindow::recent_windows`, and
+    /// this is always `None` until then.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = JumpingWindow::new(2, Duration::from_secs(10)).recent_windows(10);
+    /// cooldown.reset(Some(now));
+    ///
+    /// cooldown.trigger(Some(now));
+    /// cooldown.trigger(Some(now));
+    /// assert_eq!(cooldown.recent_utilization(Some(now)), None);
+    ///
+    /// // the first window was fully used, and the second one not at all.
+    /// let later = now + Duration::from_secs(20);
+    /// assert_eq!(cooldown.recent_utilization(Some(later)), Some(0.5));
+    /// ```
+    pub fn recent_utilization(&self, now: Option<Instant>) -> Option<f64> {
+        let recent = self.recent.as_ref()?;
+        let now = self.now(now);
+        let (last, ended) = self.ended(now);
+
+        let len = recent.samples.len() + ended;
+        let skip = len.saturating_sub(recent.len);
+        if len == skip {
+            return None;
+        }
+        let sum: f64 = recent
+            .samples
+            .iter()
+            .copied()
+            .chain(samples(last, ended))
+            .skip(skip)
+            .sum();
+        Some(sum / (len - skip) as f64)
+    }
+
+    /// Record the utilization of the last `windows` windows, for
+    /// `JumpingWindow::recent_utilization` to average over. Zero stops recording them and drops
+    /// the record. Off by default, so that windows that don't need it stay small.
+    ///
+    /// # Arguments
+    /// * `windows` - How many windows to average over.
+    pub fn recent_windows(mut self, windows: usize) -> Self {
+        self.set_recent_windows(windows);
+        self
+    }
+
+    /// Change how many windows `JumpingWindow::recent_utilization` averages over, forgetting
+    /// the oldest ones if there are fewer. See `JumpingWindow::recent_windows`.
+    pub fn set_recent_windows(&mut self, windows: usize) {
+        match (&mut self.recent, windows) {
+            (_, 0) => self.recent = None,
+            (Some(recent), windows) => {
+                let excess = recent.samples.len().saturating_sub(windows);
+                recent.samples.drain(..excess);
+                recent.len = windows;
+            }
+            (None, windows) => self.recent = Some(Box::new(Utilizations::new(windows))),
+        }
+    }
+
+    /// Record the last `n` allowed triggers and the last `n` rejected ones, to find out why a
+    /// trigger was rejected. See `JumpingWindow::history`.
+    ///
+    /// # Arguments
+    /// * `n` - How many triggers of each kind to keep.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = JumpingWindow::new(2, Duration::from_secs(10)).with_history(5);
+    /// cooldown.trigger(None);
+    /// cooldown.trigger_n(2, None).unwrap_err();
+    ///
+    /// let history = cooldown.history();
+    /// assert_eq!(history.len(), 2);
+    /// assert!(history[0].allowed);
+    /// assert_eq!(history[1].cost, 2);
+    /// assert!(!history[1].allowed);
+    /// ```
+    pub fn with_history(mut self, n: usize) -> Self {
+        self.set_history(n);
+        self
+    }
+
+    /// Change how many triggers of each kind are recorded, forgetting the oldest ones if there
+    /// are fewer. Zero stops recording them and drops the history. See
+    /// `JumpingWindow::with_history`.
+    pub fn set_history(&mut self, n: usize) {
+        match (&mut self.triggers, n) {
+            (_, 0) => self.triggers = None,
+            (Some(triggers), n) => triggers.set_len(n),
+            (None, n) => self.triggers = Some(TriggerHistory::new(n)),
+        }
+    }
+
+    /// How many triggers of each kind are recorded, or zero if the history is off.
+    pub fn history_len(&self) -> usize {
+        self.triggers.as_ref().map_or(0, TriggerHistory::limit)
+    }
+
+    /// The recorded triggers, oldest first. Empty unless enabled with
+    /// `JumpingWindow::with_history`.
+    pub fn history(&self) -> &[TriggerRecord] {
+        match &self.triggers {
+            Some(triggers) => triggers.records(),
+            None => &[],
+        }
+    }
+
+    /// Like `trigger`, but if the trigger is allowed, returns how many triggers were rejected
+    /// before it. This is useful for reporting what was suppressed while the cooldown was
+    /// active.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = JumpingWindow::new(1, Duration::from_secs(10));
+    /// cooldown.reset(Some(now));
+    ///
+    /// assert_eq!(cooldown.trigger_counted(Some(now)), Ok(0));
+    /// assert!(cooldown.trigger_counted(Some(now)).is_err());
+    /// assert!(cooldown.trigger_counted(Some(now)).is_err());
+    ///
+    /// let later = now + Duration::from_secs(10);
+    /// assert_eq!(cooldown.trigger_counted(Some(later)), Ok(2));
+    /// assert_eq!(cooldown.rejections(), 0);
+    /// ```
+    pub fn trigger_counted(&mut self, now: Option<Instant>) -> Result<u64, Duration> {
+        let rejected = self.rejected;
+        match self.trigger(now) {
+            None => Ok(rejected),
+            Some(retry_after) => Err(retry_after),
+        }
+    }
+
+    /// Trigger the cooldown, returning the resulting state of the window. Everything is
+    /// computed from the same `now`, so the fields are always consistent with each other.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{JumpingWindow, RateLimitInfo};
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = JumpingWindow::new(1, Duration::from_secs(10));
+    /// cooldown.reset(Some(now));
+    ///
+    /// let info = cooldown.trigger_info(Some(now));
+    /// assert!(info.allowed);
+    /// assert_eq!(info.remaining, 0);
+    ///
+    /// let later = now + Duration::from_secs(4);
+    /// assert_eq!(
+    ///     cooldown.trigger_info(Some(later)),
+    ///     RateLimitInfo {
+    ///         allowed: false,
+    ///         limit: 1,
+    ///         remaining: 0,
+    ///         retry_after: Some(Duration::from_secs(6)),
+    ///         reset_after: Duration::from_secs(6),
+    ///     }
+    /// );
+    /// ```
+    pub fn trigger_info(&mut self, now: Option<Instant>) -> RateLimitInfo {
+        let now = self.now(now);
+        let retry_after = self.trigger(Some(now));
+
+        RateLimitInfo {
+            allowed: retry_after.is_none(),
+            limit: self.core.capacity(),
+            remaining: self.tokens(Some(now)),
+            retry_after,
+            reset_after: self.next_reset(Some(now)),
+        }
+    }
+
+    /// Reset the cooldown. For aligned windows, the new window starts at the most recent
+    /// multiple of the period since the anchor rather than at `now`.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = JumpingWindow::new(1, Duration::from_secs(10));
+    /// cooldown.trigger(None);
+    ///
+    /// assert!(!cooldown.can_trigger(None));
+    /// cooldown.reset(None);
+    /// assert!(cooldown.can_trigger(None));
+    /// ```
+    pub fn reset(&mut self, now: Option<Instant>) {
+        let now = self.now(now);
+        self.advance(now);
+        self.core.reset(now);
+    }
+
+    /// Give back `n` tokens taken from the window `epoch` identifies, for example when a
+    /// triggered action ended up not happening. Read the epoch with `JumpingWindow::epoch`
+    /// before taking the tokens. The tokens never exceed the capacity, and if that window has
+    /// ended since, nothing is given back, so that the current window isn't given tokens it
+    /// never handed out.
+    ///
+    /// # Arguments
+    /// * `n` - How many tokens to give back.
+    /// * `epoch` - The window the tokens were taken from.
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = JumpingWindow::new(1, Duration::from_secs(10));
+    /// cooldown.reset(Some(now));
+    ///
+    /// let epoch = cooldown.epoch(Some(now));
+    /// assert_eq!(cooldown.trigger(Some(now)), None);
+    /// // the action failed, so the user shouldn't be charged for it.
+    /// cooldown.refund(1, epoch, Some(now));
+    /// assert_eq!(cooldown.trigger(Some(now)), None);
+    ///
+    /// // refunding never goes above the capacity.
+    /// cooldown.refund(5, epoch, Some(now));
+    /// assert_eq!(cooldown.tokens(Some(now)), 1);
+    ///
+    /// // once the window has been reset, the token is gone.
+    /// let later = now + Duration::from_secs(11);
+    /// assert_eq!(cooldown.trigger(Some(later)), None);
+    /// cooldown.refund(1, epoch, Some(later));
+    /// assert_eq!(cooldown.tokens(Some(later)), 0);
+    /// ```
+    pub fn refund(&mut self, n: u64, epoch: u64, now: Option<Instant>) {
+        let now = self.now(now);
+        self.advance(now);
+        self.core.refund(n, epoch, now);
+    }
+
+    /// A number identifying the window that is current at `now`, which changes whenever the
+    /// window is reset. Read it when taking tokens to give them back with
+    /// `JumpingWindow::refund`.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    pub fn epoch(&self, now: Option<Instant>) -> u64 {
+        self.core.epoch(self.now(now))
+    }
+
+    /// Pause the window at `now`, for example while the service it protects is down. Until it
+    /// is resumed, time stands still for the window: every method, `trigger` included, acts as
+    /// if it were called at the instant it was paused. Pausing a paused window does nothing.
+    ///
+    /// A pause isn't kept when the window is serialized.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = JumpingWindow::new(1, Duration::from_secs(10));
+    /// cooldown.reset(Some(now));
+    /// cooldown.trigger(Some(now));
+    ///
+    /// cooldown.pause(Some(now + Duration::from_secs(4)));
+    /// let later = now + Duration::from_secs(60);
+    /// assert_eq!(cooldown.next_reset(Some(later)), Duration::from_secs(6));
+    ///
+    /// // the window picks up where it was paused.
+    /// cooldown.resume(Some(later));
+    /// assert_eq!(cooldown.next_reset(Some(later)), Duration::from_secs(6));
+    /// ```
+    pub fn pause(&mut self, now: Option<Instant>) {
+        if self.paused_at.is_none() {
+            self.paused_at = Some(self.now(now));
+        }
+    }
+
+    /// Resume the window paused with `JumpingWindow::pause` at `now`, moving the start of the
+    /// current window and of the warm-up forward by how long it was paused. Resuming a window
+    /// that isn't paused does nothing.
+    ///
+    /// Booked tokens are moved along with the windows they were booked from, but the `at` of
+    /// reservations made before the pause isn't: they become usable later than it says, by how
+    /// long the window was paused. They can still be cancelled with `Reservation::cancel`.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    pub fn resume(&mut self, now: Option<Instant>) {
+        let Some(paused_at) = self.paused_at.take() else {
+            return;
+        };
+        let paused = self.now(now).saturating_duration_since(paused_at);
+        self.core.last_reset += paused;
+        self.paused_for += paused;
+        if let Some(warming) = &mut self.warming {
+            warming.since += paused;
+        }
+    }
+
+    /// Whether the window is paused. See `JumpingWindow::pause`.
+    pub fn is_paused(&self) -> bool {
+        self.paused_at.is_some()
+    }
+
+    /// Reserve a token, returning when it may be used: now if the current window has one left,
+    /// or else the start of the first coming window that does. Each reservation books a token
+    /// of that window, so later ones are pushed into the windows after it.
+    ///
+    /// Booked tokens don't change `tokens` for the current window, but they are taken out of
+    /// their window once it starts. A reservation holds its token until it is cancelled with
+    /// `Reservation::cancel`; dropping it doesn't give the token back.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let period = Duration::from_secs(10);
+    /// let mut cooldown = JumpingWindow::new(1, period);
+    /// cooldown.reset(Some(now));
+    ///
+    /// assert_eq!(cooldown.reserve(Some(now)).at, now);
+    /// // the next two go to the following windows.
+    /// assert_eq!(cooldown.reserve(Some(now)).at, now + period);
+    /// let last = cooldown.reserve(Some(now));
+    /// assert_eq!(last.at, now + period * 2);
+    /// assert_eq!(cooldown.tokens(Some(now)), 0);
+    ///
+    /// // the second window's token was booked.
+    /// assert_eq!(cooldown.tokens(Some(now + period)), 0);
+    /// last.cancel(&mut cooldown, Some(now + period));
+    /// assert_eq!(cooldown.tokens(Some(now + period * 2)), 1);
+    /// ```
+    pub fn reserve(&mut self, now: Option<Instant>) -> Reservation {
+        let now = self.now(now);
+        self.advance(now);
+        match self.core.reserve(now) {
+            Ok(()) => Reservation {
+                at: now,
+                booked: false,
+                paused_for: self.paused_for,
+            },
+            Err(at) => Reservation {
+                at,
+                booked: true,
+                paused_for: self.paused_for,
+            },
+        }
+    }
+
+    /// How many tokens of the coming windows have been booked by `JumpingWindow::reserve`.
+    pub fn booked(&self) -> u64 {
+        self.core.booked()
+    }
+
+    /// The time to use for `now`: read from the clock if it isn't given, and held at the
+    /// instant the window was paused while it is.
+    fn now(&self, now: Option<Instant>) -> Instant {
+        let now = now.unwrap_or_else(|| self.clock.now());
+        self.paused_at.map_or(now, |paused_at| now.min(paused_at))
+    }
+
+    /// Catch the window up with `now`, before it is used.
+    fn advance(&mut self, now: Instant) {
+        self.record(now);
+        self.warm(now);
+    }
+
+    /// Record the windows that ended by `now`, before the core resets the current one.
+    fn record(&mut self, now: Instant) {
+        if self.recent.is_none() {
+            return;
+        }
+        let (last, ended) = self.ended(now);
+        if let Some(recent) = &mut self.recent {
+            for sample in samples(last, ended) {
+                recent.push(sample);
+            }
+        }
+    }
+
+    /// The final utilization of the current window if it has expired at `now`, and how many
+    /// windows have ended since it was last used, up to the `recent_windows`. All but the first
+    /// of them went unused.
+    fn ended(&self, now: Instant) -> (f64, usize) {
+        if !self.core.is_expired(now) {
+            return (0.0, 0);
+        }
+        let idle = now
+            .saturating_duration_since(self.core.last_reset)
+            .saturating_sub(self.core.length());
+        let ended = (idle.as_nanos() / self.core.period.as_nanos()).saturating_add(1);
+        let windows = self.recent.as_ref().map_or(0, |recent| recent.len);
+        let ended = ended.min(windows as u128) as usize;
+        (Self::used(self.core.tokens, self.core.capacity), ended)
+    }
+
+    /// The soft rejection of a trigger at `now`, if the load shedding turns it away. Exhausted
+    /// windows are left to reject triggers as usual.
+    fn shed(&mut self, now: Instant) -> Option<Duration> {
+        self.shedding?;
+        let tokens = self.core.tokens(now);
+        if tokens == 0 {
+            return None;
+        }
+        let used = Self::used(tokens, self.core.capacity);
+        if !self.shedding.as_mut()?.sheds(used) {
+            return None;
+        }
+
+        let spacing = self.core.period.as_secs_f64() / self.core.capacity.max(1) as f64;
+        Some(Duration::from_secs_f64(spacing).min(self.core.next_reset(now)))
+    }
+
+    /// How many tokens of the current window are kept for high priority triggers.
+    fn floor_tokens(&self) -> u64 {
+        let Some(floor) = self.floor else {
+            return 0;
+        };
+        let capacity = self.core.capacity;
+        ((capacity as f64 * floor).round() as u64).min(capacity)
+    }
+
+    /// The share of `capacity` that was used when `tokens` are left.
+    fn used(tokens: u64, capacity: u64) -> f64 {
+        let capacity = capacity.max(1);
+        capacity.saturating_sub(tokens) as f64 / capacity as f64
+    }
+
+    /// Move the effective capacity along the warm-up, ending it once it is over.
+    fn warm(&mut self, now: Instant) {
+        let (Some(warmup), Some(warming)) = (self.warmup, self.warming) else {
+            return;
+        };
+        let elapsed = now.saturating_duration_since(warming.since);
+        let capacity = warmup.capacity(warming.capacity, elapsed);
+        if capacity != self.core.capacity() {
+            self.core.set_capacity(capacity);
+        }
+        if warmup.is_over(elapsed) {
+            self.warming = None;
+        }
+    }
+
+    /// Update the rejection count and the history after a trigger of `cost` at `now`.
+    fn count(&mut self, allowed: bool, cost: u64, now: Instant) {
+        self.rejected = match allowed {
+            true => 0,
+            false => self.rejected.saturating_add(1),
+        };
+        if let Some(triggers) = &mut self.triggers {
+            triggers.push(TriggerRecord::new(now, self.clock.now(), cost, allowed));
+        }
+    }
+}
+
+/// `ended` windows, the first of which ended with a utilization of `last`.
+fn samples(last: f64, ended: usize) -> impl Iterator<Item = f64> {
+    iter::once(last).chain(iter::repeat(0.0)).take(ended)
+}
+
+/// A builder for `floodgate::JumpingWindow`, created by `JumpingWindow::builder`.
+#[derive(Debug, Clone)]
+pub struct JumpingWindowBuilder {
+    capacity: u64,
+    period: Duration,
+    initial_tokens: Option<u64>,
+    last_reset: Option<Instant>,
+    aligned: bool,
+    penalty: Option<Penalty>,
+    carry_over: Option<u64>,
+    jitter: Option<Jitter>,
+    warmup: Option<Warmup>,
+    recent_windows: usize,
+}
+
+impl JumpingWindowBuilder {
+    fn new(capacity: u64, period: Duration) -> Self {
+        Self {
+            capacity,
+            period,
+            initial_tokens: None,
+            last_reset: None,
+            aligned: false,
+            penalty: None,
+            carry_over: None,
+            jitter: None,
+            warmup: None,
+            recent_windows: 0,
+        }
+    }
+
+    /// How many tokens the first window starts with. Defaults to the capacity.
+    pub fn initial_tokens(mut self, tokens: u64) -> Self {
+        self.initial_tokens = Some(tokens);
+        self
+    }
+
+    /// Start with no tokens, so that a full period has to pass before the first trigger.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::JumpingWindow;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = JumpingWindow::builder(1, Duration::from_secs(10))
+    ///     .start_exhausted()
+    ///     .last_reset(now)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(cooldown.trigger(Some(now)), Some(Duration::from_secs(10)));
+    /// ```
+    pub fn start_exhausted(self) -> Self {
+        self.initial_tokens(0)
+    }
+
+    /// When the first window started. Defaults to the time `build` is called.
+    pub fn last_reset(mut self, last_reset: Instant) -> Self {
+        self.last_reset = Some(last_reset);
+        self
+    }
+
+    /// Align windows to the start of the first window. See `JumpingWindow::new_aligned`.
+    pub fn aligned(mut self) -> Self {
+        self.aligned = true;
+        self
+    }
+
+    /// Extend the window whenever a trigger is rejected. See `JumpingWindow::punitive`.
+    pub fn punitive(mut self, penalty: Penalty) -> Self {
+        self.penalty = Some(penalty);
+        self
+    }
+
+    /// Carry unused tokens over into the next window. See `JumpingWindow::carry_over`.
+    pub fn carry_over(mut self, burst: u64) -> Self {
+        self.carry_over = Some(burst);
+        self
+    }
+
+    /// Randomize the length of every window, starting with the first. See
+    /// `JumpingWindow::jittered`.
+    pub fn jittered(mut self, jitter: Jitter) -> Self {
+        self.jitter = Some(jitter);
+        self
+    }
+
+    /// Ramp the capacity up from the start of the first window. See
+    /// `JumpingWindow::slow_start`.
+    pub fn slow_start(mut self, warmup: Warmup) -> Self {
+        self.warmup = Some(warmup);
+        self
+    }
+
+    /// Record the utilization of the last `windows` windows. See
+    /// `JumpingWindow::recent_windows`.
+    pub fn recent_windows(mut self, windows: usize) -> Self {
+        self.recent_windows = windows;
+        self
+    }
+
+    /// Build the window.
+    ///
+    /// # Errors
+    /// Returns `InvalidWindow::ZeroCapacity` or `InvalidWindow::ZeroPeriod` if the capacity or
+    /// period is zero, and `InvalidWindow::TooManyTokens` if the initial tokens exceed the
+    /// capacity.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{InvalidWindow, JumpingWindow};
+    /// use std::time::Duration;
+    ///
+    /// let result = JumpingWindow::builder(1, Duration::from_secs(10))
+    ///     .initial_tokens(2)
+    ///     .build();
+    ///
+    /// assert_eq!(
+    ///     result.unwrap_err(),
+    ///     InvalidWindow::TooManyTokens { tokens: 2, capacity: 1 }
+    /// );
+    /// ```
+    pub fn build(self) -> Result<JumpingWindow, InvalidWindow> {
+        validate(self.capacity, self.period)?;
+
+        let tokens = self.initial_tokens.unwrap_or(self.capacity);
+        if tokens > self.capacity {
+            return Err(InvalidWindow::TooManyTokens {
+                tokens,
+                capacity: self.capacity,
+            });
+        }
+
+        let mut window = JumpingWindow {
+            core: JumpingWindowCore {
+                capacity: self.capacity,
+                period: self.period,
+                last_reset: self.last_reset.unwrap_or_else(clock::now),
+                tokens,
+                aligned: self.aligned,
+                penalty: self.penalty,
+                extension: Duration::ZERO,
+                carry_over: self.carry_over,
+                jitter: None,
+                skew: (Duration::ZERO, Duration::ZERO),
+                bookings: VecDeque::new(),
+                epoch: 0,
+            },
+            rejected: 0,
+            warmup: None,
+            warming: None,
+            recent: None,
+            triggers: None,
+            floor: None,
+            shedding: None,
+            paused_at: None,
+            paused_for: Duration::ZERO,
+            clock: MonotonicClock,
+        };
+        window.set_jitter(self.jitter);
+        window.set_recent_windows(self.recent_windows);
+        if let Some(warmup) = self.warmup {
+            let since = window.window_start();
+            window = window.slow_start(warmup);
+            window.begin_warmup(Some(since));
+        }
+        Ok(window)
+    }
+}
+
+/// Displays the rate and the window's state, like `5/10s (3 remaining, resets in 4.2s)`,
+/// without changing the window. Once the window has expired, only the remaining triggers are
+/// shown, since the next window doesn't start until the limiter is used again.
+impl<C: Clock> fmt::Display for JumpingWindow<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let now = self.now(None);
+        write!(
+            f,
+            "{} ({} remaining",
+            self.rate(),
+            self.peek_tokens(Some(now))
+        )?;
+        match self.peek_next_reset(Some(now)) {
+            Duration::ZERO => write!(f, ")"),
+            next_reset => write!(f, ", resets in {next_reset:.1?})"),
+        }
+    }
+}
+
+impl<C: Clock + Default> RateLimiter for JumpingWindow<C> {
+    fn new(capacity: u64, period: Duration) -> Self {
+        Self::with_clock(capacity, period, C::default())
+    }
+
+    fn capacity(&self) -> u64 {
+        self.capacity()
+    }
+
+    fn period(&self) -> Duration {
+        self.period()
+    }
+
+    fn set_rate(&mut self, capacity: u64, period: Duration) {
+        self.set_capacity(capacity);
+        self.set_period(period);
+    }
+
+    fn tokens(&mut self, now: Option<Instant>) -> u64 {
+        self.tokens(now)
+    }
+
+    fn next_reset(&mut self, now: Option<Instant>) -> Duration {
+        self.next_reset(now)
+    }
+
+    fn retry_after(&mut self, now: Option<Instant>) -> Option<Duration> {
+        self.retry_after(now)
+    }
+
+    fn retry_at(&mut self, now: Option<Instant>) -> Option<Instant> {
+        self.retry_at(now)
+    }
+
+    fn wait_for(&mut self, n: u64, now: Option<Instant>) -> Option<Duration> {
+        JumpingWindow::wait_for(self, n, now)
+    }
+
+    fn can_trigger(&mut self, now: Option<Instant>) -> bool {
+        self.can_trigger(now)
+    }
+
+    fn trigger(&mut self, now: Option<Instant>) -> Option<Duration> {
+        self.trigger(now)
+    }
+
+    fn trigger_n(&mut self, cost: u64, now: Option<Instant>) -> Result<(), Duration> {
+        self.trigger_n(cost, now)
+    }
+
+    fn trigger_info(&mut self, now: Option<Instant>) -> RateLimitInfo {
+        self.trigger_info(now)
+    }
+
+    fn reset(&mut self, now: Option<Instant>) {
+        self.reset(now)
+    }
+
+    fn refund(&mut self, n: u64, epoch: u64, now: Option<Instant>) {
+        self.refund(n, epoch, now)
+    }
+
+    fn epoch(&mut self, now: Option<Instant>) -> u64 {
+        JumpingWindow::epoch(self, now)
+    }
+
+    fn utilization(&mut self, now: Option<Instant>) -> f64 {
+        JumpingWindow::utilization(self, now)
+    }
+}
+
+/// The serialized form of a `JumpingWindow`. `Instant`s can't be serialized, so the window
+/// start is stored as how far into the window it was saved, and when.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct WindowState {
+    capacity: u64,
+    period: Duration,
+    tokens: u64,
+    elapsed: Duration,
+    saved_at: SystemTime,
+    aligned: bool,
+    #[serde(default)]
+    rejected: u64,
+    #[serde(default)]
+    penalty: Option<Penalty>,
+    #[serde(default)]
+    extension: Duration,
+    #[serde(default)]
+    carry_over: Option<u64>,
+    /// Whether the window was paused, in which case `elapsed` is as of the pause, and the
+    /// time until it is loaded again doesn't count.
+    #[serde(default)]
+    paused: bool,
+}
+
+#[cfg(feature = "serde")]
+impl WindowState {
+    fn restore(self) -> Result<JumpingWindow, InvalidWindow> {
+        validate(self.capacity, self.period)?;
+
+        let now = clock::now();
+        // if the clock went backwards since the window was saved, assume no time has passed.
+        let since_saved = match self.paused {
+            true => Duration::ZERO,
+            false => SystemTime::now()
+                .duration_since(self.saved_at)
+                .unwrap_or_default(),
+        };
+        let mut elapsed = self.elapsed.saturating_add(since_saved);
+        let burst = self
+            .carry_over
+            .map_or(self.capacity, |burst| burst.max(self.capacity));
+        let mut tokens = self.tokens.min(burst);
+        let mut extension = self.extension;
+
+        let length = self.period.saturating_add(extension);
+        if elapsed >= length {
+            tokens = match self.carry_over {
+                // one window's worth for the window that ended, and one for each after it.
+                Some(_) => {
+                    let windows = (elapsed - length).as_nanos() / self.period.as_nanos() + 1;
+                    let added = u64::try_from(windows).unwrap_or(u64::MAX);
+                    tokens
+                        .saturating_add(self.capacity.saturating_mul(added))
+                        .min(burst)
+                }
+                None => self.capacity,
+            };
+            extension = Duration::ZERO;
+            elapsed = match self.aligned {
+                true => TickDuration::rem(elapsed, self.period),
+                false => Duration::ZERO,
+            };
+        }
+
+        Ok(JumpingWindow {
+            core: JumpingWindowCore {
+                capacity: self.capacity,
+                period: self.period,
+                last_reset: now.checked_sub(elapsed).unwrap_or(now),
+                tokens,
+                aligned: self.aligned,
+                penalty: self.penalty,
+                extension,
+                carry_over: self.carry_over,
+                jitter: None,
+                skew: (Duration::ZERO, Duration::ZERO),
+                bookings: VecDeque::new(),
+                epoch: 0,
+            },
+            rejected: self.rejected,
+            warmup: None,
+            warming: None,
+            recent: None,
+            triggers: None,
+            floor: None,
+            shedding: None,
+            paused_at: self.paused.then_some(now),
+            paused_for: Duration::ZERO,
+            clock: MonotonicClock,
+        })
+    }
+}
+
+/// Windows are saved relative to the system clock, and the time that passed until they are
+/// loaded again counts towards the window. Paused windows are saved as of when they were
+/// paused, and are loaded still paused, so no time counts until they are resumed.
+#[cfg(feature = "serde")]
+impl serde::Serialize for JumpingWindow {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        WindowState {
+            capacity: self.core.capacity,
+            period: self.core.period,
+            tokens: self.core.tokens,
+            elapsed: self
+                .now(None)
+                .saturating_duration_since(self.core.last_reset),
+            saved_at: SystemTime::now(),
+            aligned: self.core.aligned,
+            rejected: self.rejected,
+            penalty: self.core.penalty,
+            extension: self.core.extension,
+            carry_over: self.core.carry_over,
+            paused: self.is_paused(),
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for JumpingWindow {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        WindowState::deserialize(deserializer)?
+            .restore()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::JumpingWindow;
+    use crate::{Clock, Jitter, ManualClock, Penalty, Priority, Warmup};
+
+    fn exhausted(start: Instant) -> JumpingWindow {
+        let mut window = JumpingWindow::new(1, Duration::from_secs(10));
+        window.reset(Some(start));
+        window.trigger(Some(start));
+        window
+    }
+
+    #[test]
+    fn window_is_active_until_period() {
+        let start = Instant::now();
+        let now = start + Duration::from_secs(10) - Duration::from_nanos(1);
+        let mut window = exhausted(start);
+
+        assert_eq!(window.peek_tokens(Some(now)), 0);
+        assert_eq!(window.next_reset(Some(now)), Duration::from_nanos(1));
+        assert_eq!(window.trigger(Some(now)), Some(Duration::from_nanos(1)));
+    }
+
+    #[test]
+    fn window_resets_at_exactly_period() {
+        let start = Instant::now();
+        let now = start + Duration::from_secs(10);
+        let mut window = exhausted(start);
+
+        assert_eq!(window.peek_next_reset(Some(now)), Duration::ZERO);
+        assert_eq!(window.peek_tokens(Some(now)), 1);
+        assert_eq!(window.trigger(Some(now)), None);
+        assert_eq!(window.window_start(), now);
+    }
+
+    #[test]
+    fn window_resets_after_period() {
+        let start = Instant::now();
+        let now = start + Duration::from_secs(10) + Duration::from_nanos(1);
+        let mut window = exhausted(start);
+
+        assert_eq!(window.peek_next_reset(Some(now)), Duration::ZERO);
+        assert_eq!(window.trigger(Some(now)), None);
+    }
+
+    #[test]
+    fn waiting_for_next_reset_is_enough() {
+        let start = Instant::now();
+        let mut window = exhausted(start);
+
+        let now = start + Duration::from_secs(3);
+        let retry_after = window.trigger(Some(now)).unwrap();
+        assert_eq!(window.trigger(Some(now + retry_after)), None);
+    }
+
+    #[test]
+    fn aligned_windows_do_not_drift() {
+        let anchor = Instant::now();
+        let period = Duration::from_secs(10);
+        let mut aligned = JumpingWindow::builder(1, period)
+            .aligned()
+            .last_reset(anchor)
+            .build()
+            .unwrap();
+        let mut unaligned = JumpingWindow::builder(1, period)
+            .last_reset(anchor)
+            .build()
+            .unwrap();
+
+        // use both windows a little later after each expiry.
+        for i in 1..=5 {
+            let now = anchor + period * i + Duration::from_millis(1500) * i;
+            assert_eq!(aligned.trigger(Some(now)), None);
+            assert_eq!(unaligned.trigger(Some(now)), None);
+        }
+
+        assert_eq!(aligned.window_start(), anchor + period * 5);
+        assert_eq!(
+            unaligned.window_start(),
+            anchor + period * 5 + Duration::from_millis(7500)
+        );
+    }
+
+    #[test]
+    fn aligned_next_reset_spans_multiple_periods() {
+        let anchor = Instant::now();
+        let period = Duration::from_secs(10);
+        let mut window = JumpingWindow::builder(1, period)
+            .aligned()
+            .last_reset(anchor)
+            .build()
+            .unwrap();
+        window.trigger(Some(anchor));
+
+        let now = anchor + Duration::from_secs(43);
+        assert_eq!(window.peek_next_reset(Some(now)), Duration::from_secs(7));
+        assert_eq!(window.retry_after(Some(now)), None);
+        assert_eq!(window.trigger(Some(now)), None);
+        assert_eq!(window.retry_after(Some(now)), Some(Duration::from_secs(7)));
+        assert_eq!(window.window_start(), anchor + Duration::from_secs(40));
+    }
+
+    #[test]
+    fn now_before_window_start_is_clamped() {
+        let start = Instant::now() + Duration::from_secs(60);
+        let period = Duration::from_secs(10);
+        let mut window = JumpingWindow::new(3, period);
+        window.reset(Some(start));
+
+        for secs in [0, 1, 5, 30] {
+            let now = start - Duration::from_secs(secs);
+            assert_eq!(window.next_reset(Some(now)), period);
+            assert_eq!(window.peek_next_reset(Some(now)), period);
+        }
+
+        assert_eq!(window.trigger(Some(start - Duration::from_secs(1))), None);
+        assert_eq!(window.trigger(Some(start - Duration::from_secs(2))), None);
+        assert_eq!(window.trigger(Some(start - Duration::from_secs(3))), None);
+        assert_eq!(window.window_start(), start);
+
+        let now = start - Duration::from_secs(4);
+        assert_eq!(window.tokens(Some(now)), 0);
+        assert_eq!(window.retry_after(Some(now)), Some(period));
+        assert_eq!(window.trigger(Some(now)), Some(period));
+    }
+
+    #[test]
+    fn penalties_only_hold_back_spammers() {
+        let start = Instant::now();
+        let secs = Duration::from_secs;
+        let penalty = Penalty::Extend(secs(5), secs(25));
+
+        // waiting for the reported retry-after is always enough.
+        let mut patient = exhausted(start).punitive(penalty);
+        let retry_after = patient.trigger(Some(start)).unwrap();
+        assert_eq!(retry_after, secs(15));
+        assert_eq!(patient.trigger(Some(start + retry_after)), None);
+        assert_eq!(
+            patient.retry_after(Some(start + retry_after)),
+            Some(secs(10))
+        );
+
+        let mut spammer = exhausted(start).punitive(penalty);
+        let waits: Vec<_> = (0..5)
+            .filter_map(|_| spammer.trigger(Some(start + secs(2))))
+            .collect();
+        assert_eq!(waits, [secs(13), secs(18), secs(23), secs(25), secs(25)]);
+        assert_eq!(spammer.next_reset(Some(start + secs(2))), secs(25));
+        assert_eq!(spammer.trigger(Some(start + secs(27))), None);
+
+        let mut restarted = exhausted(start).punitive(Penalty::RestartWindow);
+        assert_eq!(restarted.trigger(Some(start + secs(8))), Some(secs(10)));
+        assert_eq!(restarted.trigger(Some(start + secs(17))), Some(secs(10)));
+        assert_eq!(restarted.trigger(Some(start + secs(27))), None);
+    }
+
+    #[test]
+    fn oversized_batches_are_not_penalized() {
+        let start = Instant::now();
+        let secs = Duration::from_secs;
+        let mut window = JumpingWindow::builder(3, secs(10))
+            .last_reset(start)
+            .build()
+            .unwrap()
+            .punitive(Penalty::Extend(secs(5), secs(25)));
+
+        assert_eq!(window.trigger(Some(start)), None);
+        for _ in 0..3 {
+            assert_eq!(window.trigger_n(3, Some(start + secs(2))), Err(secs(8)));
+        }
+        assert_eq!(window.next_reset(Some(start + secs(2))), secs(8));
+        assert_eq!(window.trigger_n(2, Some(start + secs(2))), Ok(()));
+    }
+
+    #[test]
+    fn carry_over_banks_tokens_up_to_the_burst() {
+        let start = Instant::now();
+        let secs = Duration::from_secs;
+        let mut window = JumpingWindow::builder(2, secs(10))
+            .last_reset(start)
+            .build()
+            .unwrap()
+            .carry_over(5);
+
+        assert_eq!(window.burst_capacity(), 5);
+        assert_eq!(window.trigger_n(3, Some(start)), Err(secs(10)));
+        window.trigger(Some(start));
+        assert_eq!(window.peek_tokens(Some(start + secs(10))), 3);
+        assert_eq!(window.wait_for(5, Some(start)), Some(secs(20)));
+        assert_eq!(window.wait_for(6, Some(start)), None);
+
+        // a long quiet spell banks no more than the burst.
+        assert_eq!(window.tokens(Some(start + secs(100))), 5);
+        assert_eq!(window.trigger_n(5, Some(start + secs(100))), Ok(()));
+        assert_eq!(window.tokens(Some(start + secs(110))), 2);
+
+        window.set_carry_over(None);
+        let epoch = window.epoch(Some(start + secs(110)));
+        window.refund(3, epoch, Some(start + secs(110)));
+        assert_eq!(window.tokens(Some(start + secs(110))), 2);
+    }
+
+    #[test]
+    fn jitter_redraws_every_window_and_never_empties_it() {
+        let start = Instant::now();
+        let secs = Duration::from_secs;
+        let jittered = |jitter| {
+            JumpingWindow::builder(1, secs(10))
+                .last_reset(start)
+                .jittered(jitter)
+                .build()
+                .unwrap()
+        };
+
+        let mut longer = jittered(Jitter::new(secs(4)).rng(|_| 1 << 63));
+        longer.trigger(Some(start));
+        assert_eq!(longer.trigger(Some(start + secs(11))), Some(secs(1)));
+
+        let mut emptied = jittered(Jitter::new(secs(20)).rng(|_| u64::MAX));
+        assert_eq!(emptied.next_reset(Some(start)), secs(10));
+
+        let mut first = jittered(Jitter::new(secs(2)).seed(3));
+        let mut second = jittered(Jitter::new(secs(2)).seed(3));
+        let mut now = start;
+        let mut lengths = Vec::new();
+        for _ in 0..10 {
+            assert_eq!(first.trigger(Some(now)), None);
+            let retry_after = first.trigger(Some(now)).unwrap();
+            second.trigger(Some(now));
+            assert_eq!(second.trigger(Some(now)), Some(retry_after));
+            assert!(retry_after >= secs(8) && retry_after <= secs(12));
+            lengths.push(retry_after);
+            now += retry_after;
+        }
+        lengths.dedup();
+        assert!(lengths.len() > 1);
+    }
+
+    #[test]
+    fn warmup_ramps_the_capacity_and_can_restart() {
+        let start = Instant::now();
+        let secs = Duration::from_secs;
+        let mut window = JumpingWindow::builder(10, secs(10))
+            .last_reset(start)
+            .slow_start(Warmup::new(secs(100), 0.2))
+            .build()
+            .unwrap();
+
+        assert_eq!(window.capacity(), 10);
+        assert_eq!(window.trigger_info(Some(start)).limit, 2);
+        assert_eq!(window.tokens(Some(start)), 1);
+
+        // growing mid-window adds the difference, which peeking sees without a trigger.
+        assert_eq!(window.effective_capacity(Some(start + secs(50))), 6);
+        assert_eq!(window.peek_tokens(Some(start + secs(50))), 6);
+        assert_eq!(window.tokens(Some(start + secs(50))), 6);
+        window.set_capacity(20);
+        assert_eq!(window.capacity(), 20);
+
+        assert_eq!(window.peek_tokens(Some(start + secs(100))), 20);
+        assert_eq!(window.tokens(Some(start + secs(100))), 20);
+        assert_eq!(window.tokens(Some(start + secs(200))), 20);
+
+        window.begin_warmup(Some(start + secs(200)));
+        assert_eq!(window.tokens(Some(start + secs(200))), 4);
+        assert_eq!(window.capacity(), 20);
+    }
+
+    #[test]
+    fn display_shows_the_state_without_changing_it() {
+        let clock = ManualClock::new();
+        let mut window = JumpingWindow::with_clock(5, Duration::from_secs(10), clock.clone());
+        window.trigger_n(2, None).unwrap();
+        let start = window.window_start();
+        clock.advance(Duration::from_millis(5800));
+
+        assert_eq!(window.to_string(), "5/10s (3 remaining, resets in 4.2s)");
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(window.to_string(), "5/10s (5 remaining)");
+        assert_eq!(window.window_start(), start);
+    }
+
+    #[test]
+    fn low_priority_rejections_leave_the_reset_alone() {
+        let clock = ManualClock::new();
+        let secs = Duration::from_secs;
+        let mut window = JumpingWindow::with_clock(5, secs(10), clock.clone())
+            .prioritized(0.4)
+            .punitive(Penalty::Extend(secs(5), secs(30)));
+
+        for _ in 0..3 {
+            assert_eq!(window.trigger_with_priority(Priority::Low, None), None);
+        }
+        for _ in 0..10 {
+            assert!(window.trigger_with_priority(Priority::Low, None).is_some());
+        }
+        assert_eq!(window.next_reset(None), secs(10));
+        assert_eq!(window.trigger_with_priority(Priority::High, None), None);
+        assert_eq!(window.trigger_with_priority(Priority::High, None), None);
+    }
+
+    #[test]
+    fn low_priority_load_never_takes_the_reserved_tokens() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(10);
+        let mut window = JumpingWindow::with_clock(10, period, clock.clone()).prioritized(0.2);
+
+        let allowed = (0..50)
+            .filter(|_| window.trigger_with_priority(Priority::Low, None).is_none())
+            .count();
+        assert_eq!(allowed, 8);
+        assert_eq!(window.tokens_for(Priority::Low, None), 0);
+        assert_eq!(window.retry_after_for(Priority::Low, None), Some(period));
+        assert_eq!(window.retry_after_for(Priority::High, None), None);
+
+        assert_eq!(window.trigger_with_priority(Priority::High, None), None);
+        assert_eq!(window.trigger_with_priority(Priority::High, None), None);
+        assert_eq!(
+            window.trigger_with_priority(Priority::High, None),
+            Some(period)
+        );
+
+        window.set_priority_floor(Some(1.0));
+        clock.advance(period);
+        assert_eq!(
+            window.retry_after_for(Priority::Low, None),
+            Some(Duration::MAX)
+        );
+    }
+
+    #[test]
+    fn utilization_follows_the_warmup_without_a_trigger() {
+        let start = Instant::now();
+        let secs = Duration::from_secs;
+        let mut window = JumpingWindow::builder(100, secs(1_000))
+            .last_reset(start)
+            .slow_start(Warmup::new(secs(100), 0.1))
+            .build()
+            .unwrap();
+        assert_eq!(window.trigger_n(10, Some(start)), Ok(()));
+        assert_eq!(window.utilization(Some(start)), 1.0);
+
+        let end = start + secs(100);
+        assert_eq!(window.peek_tokens(Some(end)), 90);
+        assert_eq!(window.utilization(Some(end)), 0.1);
+        assert_eq!(window.tokens(Some(end)), 90);
+        assert_eq!(window.utilization(Some(end)), 0.1);
+    }
+
+    #[test]
+    fn recent_utilization_is_off_by_default() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(10);
+        let mut window = JumpingWindow::with_clock(4, period, clock.clone());
+
+        window.trigger_n(4, None).unwrap();
+        clock.advance(period * 2);
+        window.trigger(None);
+        assert_eq!(window.recent_utilization(None), None);
+        assert!(window.recent.is_none());
+    }
+
+    #[test]
+    fn recent_utilization_keeps_the_last_windows() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(10);
+        let mut window = JumpingWindow::with_clock(4, period, clock.clone()).recent_windows(3);
+
+        window.trigger_n(4, None).unwrap();
+        assert_eq!(window.utilization(None), 1.0);
+        clock.advance(period);
+        assert_eq!(window.utilization(None), 0.0);
+        window.trigger_n(2, None).unwrap();
+        assert_eq!(window.recent_utilization(None), Some(1.0));
+
+        // a window at half, then 5 unused ones, of which only 2 are kept.
+        clock.advance(period * 6);
+        assert_eq!(window.recent_utilization(None), Some(0.5 / 3.0));
+        window.trigger(None);
+        clock.advance(period);
+        assert_eq!(window.recent_utilization(None), Some(0.25 / 3.0));
+
+        window.set_recent_windows(0);
+        assert_eq!(window.recent_utilization(None), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity must be greater than zero")]
+    fn zero_capacity_is_rejected_after_creation() {
+        let mut window = JumpingWindow::new(1, Duration::from_secs(10));
+        window.set_capacity(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "period must be greater than zero")]
+    fn zero_period_is_rejected_after_creation() {
+        let mut window = JumpingWindow::new(1, Duration::from_secs(10));
+        window.set_period(Duration::ZERO);
+    }
+
+    #[test]
+    fn repeated_pauses_and_resumes_keep_the_timeline() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(10);
+        let mut window = JumpingWindow::with_clock(2, period, clock.clone());
+        window.trigger(None);
+
+        clock.advance(Duration::from_secs(4));
+        window.pause(None);
+        clock.advance(Duration::from_secs(3));
+        // pausing again keeps the first pause.
+        window.pause(None);
+        clock.advance(Duration::from_secs(30));
+        assert!(window.is_paused());
+        assert_eq!(window.trigger(None), None);
+        assert_eq!(window.retry_after(None), Some(Duration::from_secs(6)));
+
+        window.resume(None);
+        window.resume(None);
+        assert!(!window.is_paused());
+        assert_eq!(window.next_reset(None), Duration::from_secs(6));
+        clock.advance(Duration::from_secs(6));
+        assert_eq!(window.tokens(None), 2);
+    }
+
+    #[test]
+    fn retries_wait_past_booked_windows() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(10);
+        let mut window = JumpingWindow::with_clock(1, period, clock.clone());
+        assert_eq!(window.trigger(None), None);
+        assert!(window.reserve(None).is_booked());
+
+        // the next window's token is booked, so a retry has to wait for the one after it.
+        assert_eq!(window.trigger(None), Some(period * 2));
+        assert_eq!(window.retry_after(None), Some(period * 2));
+        assert_eq!(window.wait_for(1, None), Some(period * 2));
+
+        clock.advance(period * 2);
+        assert_eq!(window.trigger(None), None);
+    }
+
+    #[test]
+    fn reservations_made_before_a_pause_can_be_cancelled_after_it() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(10);
+        let mut window = JumpingWindow::with_clock(1, period, clock.clone());
+        let start = clock.now();
+        window.reserve(None);
+        let booked = window.reserve(None);
+        assert_eq!(booked.at, start + period);
+
+        clock.advance(Duration::from_secs(2));
+        window.pause(None);
+        clock.advance(Duration::from_secs(30));
+        window.resume(None);
+        // the booked token moved with its window, but `at` didn't.
+        assert_eq!(window.booked(), 1);
+        assert_eq!(window.next_reset(None), Duration::from_secs(8));
+
+        booked.cancel(&mut window, None);
+        assert_eq!(window.booked(), 0);
+        clock.advance(Duration::from_secs(8));
+        assert_eq!(window.tokens(None), 1);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip_keeps_time_passing() {
+        let period = Duration::from_millis(200);
+        let mut window = JumpingWindow::new(4, period);
+        window.trigger_n(2, None).unwrap();
+
+        let saved = serde_json::to_string(&window).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        let mut restored: JumpingWindow = serde_json::from_str(&saved).unwrap();
+
+        assert_eq!(restored.capacity(), 4);
+        assert_eq!(restored.peek_tokens(None), 2);
+        let remaining = restored.next_reset(None);
+        assert!(remaining <= Duration::from_millis(150));
+        assert!(remaining > Duration::from_millis(100));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip_keeps_paused_windows_paused() {
+        let period = Duration::from_secs(10);
+        let start = Instant::now();
+        let mut window = JumpingWindow::new(1, period);
+        window.reset(Some(start));
+        window.trigger(Some(start));
+        window.pause(Some(start));
+
+        std::thread::sleep(Duration::from_millis(50));
+        let saved = serde_json::to_string(&window).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        let mut restored: JumpingWindow = serde_json::from_str(&saved).unwrap();
+
+        // neither the time before saving nor the time until loading counted.
+        assert!(restored.is_paused());
+        assert_eq!(restored.next_reset(None), period);
+        assert_eq!(restored.tokens(None), 0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_clamps_invalid_state() {
+        let future = std::time::SystemTime::now() + Duration::from_secs(3600);
+        let saved = serde_json::json!({
+            "capacity": 2,
+            "period": { "secs": 10, "nanos": 0 },
+            "tokens": 50,
+            "elapsed": { "secs": 1, "nanos": 0 },
+            "saved_at": future,
+            "aligned": false,
+        });
+
+        let mut restored: JumpingWindow = serde_json::from_value(saved).unwrap();
+        assert_eq!(restored.tokens(None), 2);
+        assert!(restored.next_reset(None) <= Duration::from_secs(9));
+
+        let saved = serde_json::json!({
+            "capacity": 0,
+            "period": { "secs": 10, "nanos": 0 },
+            "tokens": 0,
+            "elapsed": { "secs": 0, "nanos": 0 },
+            "saved_at": future,
+            "aligned": false,
+        });
+        assert!(serde_json::from_value::<JumpingWindow>(saved).is_err());
     }
 }
@@ -0,0 +1,879 @@
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::collections::VecDeque;
+
+use crate::InvalidWindow;
+
+/// A point on a monotonic timeline, used by `floodgate::JumpingWindowCore`.
+///
+/// Implemented for `u64` ticks (for example, microseconds from a hardware timer), where the
+/// period is a `u64` in the same units, and with the `std` feature for `std::time::Instant`.
+pub trait TickInstant: Copy {
+    /// The span between two instants, in the same units the period is given in.
+    type Duration: TickDuration;
+
+    /// The time from `earlier` to `self`, or zero if `earlier` is later.
+    fn saturating_duration_since(&self, earlier: Self) -> Self::Duration;
+
+    /// The instant `duration` after this one, saturating at the end of the timeline.
+    fn saturating_add(&self, duration: Self::Duration) -> Self;
+}
+
+/// The span type of a `floodgate::TickInstant`.
+pub trait TickDuration: Copy + Ord {
+    /// The empty span.
+    const ZERO: Self;
+    /// The longest representable span, returned when a trigger can never succeed.
+    const MAX: Self;
+
+    /// `self - rhs`, or zero if `rhs` is longer.
+    fn saturating_sub(self, rhs: Self) -> Self;
+
+    /// `self + rhs`, saturating at `MAX`.
+    fn saturating_add(self, rhs: Self) -> Self;
+
+    /// What is left of `self` after removing as many whole `period`s as fit. `period` is never
+    /// zero.
+    fn rem(self, period: Self) -> Self;
+
+    /// `self` times `fraction / 2^64`, used to draw a `floodgate::Jitter`. The default is zero,
+    /// so that spans which don't implement it are never jittered.
+    fn scale(self, fraction: u64) -> Self {
+        let _ = fraction;
+        Self::ZERO
+    }
+}
+
+impl TickInstant for u64 {
+    type Duration = u64;
+
+    fn saturating_duration_since(&self, earlier: Self) -> u64 {
+        u64::saturating_sub(*self, earlier)
+    }
+
+    fn saturating_add(&self, duration: u64) -> Self {
+        u64::saturating_add(*self, duration)
+    }
+}
+
+impl TickDuration for u64 {
+    const ZERO: Self = 0;
+    const MAX: Self = u64::MAX;
+
+    fn saturating_sub(self, rhs: Self) -> Self {
+        u64::saturating_sub(self, rhs)
+    }
+
+    fn saturating_add(self, rhs: Self) -> Self {
+        u64::saturating_add(self, rhs)
+    }
+
+    fn rem(self, period: Self) -> Self {
+        self % period
+    }
+
+    fn scale(self, fraction: u64) -> Self {
+        ((self as u128 * fraction as u128) >> 64) as u64
+    }
+}
+
+#[cfg(feature = "std")]
+impl TickInstant for crate::clock::Instant {
+    type Duration = Duration;
+
+    fn saturating_duration_since(&self, earlier: Self) -> Duration {
+        crate::clock::Instant::saturating_duration_since(self, earlier)
+    }
+
+    fn saturating_add(&self, duration: Duration) -> Self {
+        // `Instant` can't represent a saturated value, so clamp the span to something that fits.
+        self.checked_add(duration)
+            .unwrap_or_else(|| *self + Duration::from_secs(u32::MAX as u64))
+    }
+}
+
+impl TickDuration for Duration {
+    const ZERO: Self = Duration::ZERO;
+    const MAX: Self = Duration::MAX;
+
+    fn saturating_sub(self, rhs: Self) -> Self {
+        Duration::saturating_sub(self, rhs)
+    }
+
+    fn saturating_add(self, rhs: Self) -> Self {
+        Duration::saturating_add(self, rhs)
+    }
+
+    fn rem(self, period: Self) -> Self {
+        let nanos = self.as_nanos() % period.as_nanos();
+        Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
+    }
+
+    fn scale(self, fraction: u64) -> Self {
+        let nanos = self.as_nanos().min(u64::MAX as u128) as u64;
+        Duration::from_nanos(nanos.scale(fraction))
+    }
+}
+
+/// What happens to a `floodgate::JumpingWindowCore` when a trigger is rejected, set with
+/// `floodgate::JumpingWindow::punitive`, so that hammering an exhausted window only makes the
+/// wait longer.
+///
+/// The extended window still holds no more than the capacity, and once it ends the next
+/// window is a regular one again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Penalty<D = Duration> {
+    /// Restart the window from the rejected trigger, so that the next reset is a full period
+    /// away.
+    RestartWindow,
+    /// Push the end of the window back by the first span for every rejected trigger, until the
+    /// next reset is the second span away.
+    Extend(D, D),
+}
+
+/// Randomizes the length of each window of a `floodgate::JumpingWindowCore`, set with
+/// `floodgate::JumpingWindow::jittered`, so that windows which started at the same time drift
+/// apart instead of all resetting together.
+///
+/// Each window lasts its period plus or minus a random span of up to `amount`, but never less
+/// than nothing: a window that would be shortened by its whole period isn't shortened at all.
+///
+/// The random numbers come from a small built-in generator, seeded randomly with the `std`
+/// feature and with a fixed seed without it. Use `Jitter::seed` to make the windows
+/// reproducible, or `Jitter::rng` to draw the numbers some other way.
+///
+/// # Examples
+/// ```
+/// use floodgate::{Jitter, JumpingWindow};
+/// use std::time::{Duration, Instant};
+///
+/// let now = Instant::now();
+/// let jitter = Jitter::new(Duration::from_secs(2)).seed(7);
+/// let mut cooldown = JumpingWindow::builder(1, Duration::from_secs(10))
+///     .last_reset(now)
+///     .jittered(jitter)
+///     .build()
+///     .unwrap();
+///
+/// cooldown.trigger(Some(now));
+/// let retry_after = cooldown.trigger(Some(now)).unwrap();
+/// assert!(retry_after >= Duration::from_secs(8));
+/// assert!(retry_after <= Duration::from_secs(12));
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Jitter<D = Duration> {
+    amount: D,
+    state: u64,
+    next: fn(&mut u64) -> u64,
+}
+
+impl<D: TickDuration> Jitter<D> {
+    /// Create a new Jitter of up to `amount` either way.
+    ///
+    /// # Arguments
+    /// * `amount` - The most a window is lengthened or shortened by.
+    pub fn new(amount: D) -> Self {
+        Self {
+            amount,
+            state: random_seed(),
+            next: splitmix64,
+        }
+    }
+
+    /// Seed the generator, so that the same seed always draws the same windows.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.state = seed;
+        self
+    }
+
+    /// Draw the random numbers with `next` instead of the built-in generator. `next` is given
+    /// the generator's state, which starts out as the seed, and returns the next number.
+    /// Numbers are spread over the whole range of `u64`: the lowest bit picks whether the
+    /// window is lengthened or shortened, and the number as a fraction of `u64::MAX` how much.
+    pub fn rng(mut self, next: fn(&mut u64) -> u64) -> Self {
+        self.next = next;
+        self
+    }
+
+    pub fn amount(&self) -> D {
+        self.amount
+    }
+
+    /// A copy for the `n`th of several windows, so that they don't all draw the same numbers.
+    #[cfg(feature = "std")]
+    pub(crate) fn fork(&self, n: u64) -> Self {
+        Self {
+            state: self.state.wrapping_add(n),
+            ..*self
+        }
+    }
+
+    /// Draw how much the next window is lengthened and shortened by.
+    fn draw(&mut self) -> (D, D) {
+        let random = (self.next)(&mut self.state);
+        let span = self.amount.scale(random);
+        match random & 1 {
+            0 => (span, D::ZERO),
+            _ => (D::ZERO, span),
+        }
+    }
+}
+
+/// The SplitMix64 generator, which is tiny, fast and good enough for spreading windows out.
+pub(crate) fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn random_seed() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+
+    // every `RandomState` is keyed differently, which is all the randomness a seed needs.
+    std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish()
+}
+
+#[cfg(not(feature = "std"))]
+fn random_seed() -> u64 {
+    0
+}
+
+/// The jumping window algorithm, without a clock.
+///
+/// `floodgate::JumpingWindow` is a wrapper around a `JumpingWindowCore<Instant>`. Without the
+/// default `std` feature, this is the only limiter available, and the current time always has
+/// to be given as `now`.
+///
+/// As with `floodgate::JumpingWindow`, a `now` earlier than the start of the current window is
+/// treated as the start of the window.
+///
+/// # Examples
+/// ```
+/// use floodgate::JumpingWindowCore;
+///
+/// // 2 events per 10ms, in microsecond ticks.
+/// let mut debounce = JumpingWindowCore::new(2, 10_000, 0u64);
+///
+/// assert_eq!(debounce.trigger(100), None);
+/// assert_eq!(debounce.trigger(200), None);
+/// assert_eq!(debounce.trigger(300), Some(9_700));
+///
+/// assert_eq!(debounce.trigger(10_000), None);
+/// ```
+#[derive(Debug, Clone)]
+pub struct JumpingWindowCore<T: TickInstant> {
+    pub(crate) capacity: u64,
+    pub(crate) period: T::Duration,
+
+    pub(crate) last_reset: T,
+    pub(crate) tokens: u64,
+    pub(crate) aligned: bool,
+    pub(crate) penalty: Option<Penalty<T::Duration>>,
+    /// How far the current window has been extended by the penalty.
+    pub(crate) extension: T::Duration,
+    /// The most tokens that can be banked by carrying them over from earlier windows.
+    pub(crate) carry_over: Option<u64>,
+    pub(crate) jitter: Option<Jitter<T::Duration>>,
+    /// How much the jitter lengthened and shortened the current window.
+    pub(crate) skew: (T::Duration, T::Duration),
+    /// How many tokens of each coming window have been booked by `reserve`, starting with the
+    /// window after the current one.
+    #[cfg(feature = "std")]
+    pub(crate) bookings: VecDeque<u64>,
+    /// How many times the window has been reset, to tell which window tokens were taken from.
+    pub(crate) epoch: u64,
+}
+
+impl<T: TickInstant> JumpingWindowCore<T> {
+    /// Create a new JumpingWindowCore, whose first window starts at `now`.
+    ///
+    /// # Arguments
+    /// * `capacity` - How many triggers can occur per window.
+    /// * `period` - How long the window is.
+    /// * `now` - The current time.
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `period` is zero. See `JumpingWindowCore::try_new`.
+    pub fn new(capacity: u64, period: T::Duration, now: T) -> Self {
+        Self::try_new(capacity, period, now).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Create a new JumpingWindowCore, returning an error if `capacity` or `period` is zero.
+    ///
+    /// # Arguments
+    /// * `capacity` - How many triggers can occur per window.
+    /// * `period` - How long the window is.
+    /// * `now` - The current time.
+    pub fn try_new(capacity: u64, period: T::Duration, now: T) -> Result<Self, InvalidWindow> {
+        if capacity == 0 {
+            return Err(InvalidWindow::ZeroCapacity);
+        }
+        if period == T::Duration::ZERO {
+            return Err(InvalidWindow::ZeroPeriod);
+        }
+
+        Ok(Self {
+            capacity,
+            period,
+            last_reset: now,
+            tokens: capacity,
+            aligned: false,
+            penalty: None,
+            extension: T::Duration::ZERO,
+            carry_over: None,
+            jitter: None,
+            skew: (T::Duration::ZERO, T::Duration::ZERO),
+            #[cfg(feature = "std")]
+            bookings: VecDeque::new(),
+            epoch: 0,
+        })
+    }
+
+    /// Create a new JumpingWindowCore whose windows always start at a multiple of the period
+    /// after `now`. See `floodgate::JumpingWindow::new_aligned`.
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `period` is zero.
+    pub fn new_aligned(capacity: u64, period: T::Duration, now: T) -> Self {
+        let mut window = Self::new(capacity, period, now);
+        window.aligned = true;
+        window
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    pub fn period(&self) -> T::Duration {
+        self.period
+    }
+
+    /// When the current window started.
+    pub fn window_start(&self) -> T {
+        self.last_reset
+    }
+
+    pub fn is_aligned(&self) -> bool {
+        self.aligned
+    }
+
+    pub fn penalty(&self) -> Option<Penalty<T::Duration>> {
+        self.penalty
+    }
+
+    /// Set what happens when a trigger is rejected. See `floodgate::JumpingWindow::punitive`.
+    pub fn set_penalty(&mut self, penalty: Option<Penalty<T::Duration>>) {
+        self.penalty = penalty;
+    }
+
+    pub fn jitter(&self) -> Option<Jitter<T::Duration>> {
+        self.jitter
+    }
+
+    /// Randomize the length of every window from the current one on, or stop randomizing it
+    /// with `None`. Aligned windows are never jittered, since they have to end on a multiple of
+    /// the period. See `floodgate::JumpingWindow::jittered`.
+    pub fn set_jitter(&mut self, jitter: Option<Jitter<T::Duration>>) {
+        self.jitter = jitter;
+        self.skew = (T::Duration::ZERO, T::Duration::ZERO);
+        self.draw_skew();
+    }
+
+    /// The most tokens the window can hold, which is the capacity unless tokens are carried
+    /// over. See `floodgate::JumpingWindow::carry_over`.
+    pub fn burst_capacity(&self) -> u64 {
+        match self.carry_over {
+            Some(burst) => burst.max(self.capacity),
+            None => self.capacity,
+        }
+    }
+
+    /// Carry unused tokens over into the next window, up to `burst` tokens, or stop carrying
+    /// them over with `None`. See `floodgate::JumpingWindow::carry_over`.
+    pub fn set_carry_over(&mut self, burst: Option<u64>) {
+        self.carry_over = burst;
+        self.tokens = self.tokens.min(self.burst_capacity());
+    }
+
+    /// Change the capacity. See `floodgate::JumpingWindow::set_capacity`.
+    pub fn set_capacity(&mut self, capacity: u64) {
+        if capacity > self.capacity {
+            self.tokens = self.tokens.saturating_add(capacity - self.capacity);
+        }
+        self.capacity = capacity;
+        self.tokens = self.tokens.min(self.burst_capacity());
+    }
+
+    /// Change the period. The current window ends `period` after it started.
+    pub fn set_period(&mut self, period: T::Duration) {
+        self.period = period;
+    }
+
+    /// How many triggers are left, resetting the window if it has expired.
+    pub fn tokens(&mut self, now: T) -> u64 {
+        if self.is_expired(now) {
+            let (tokens, ended) = self.renewed(now);
+            self.reset(now);
+            self.tokens = tokens;
+            self.drop_bookings(ended + 1);
+        }
+        self.tokens
+    }
+
+    /// Like `tokens`, but without resetting an expired window.
+    pub fn peek_tokens(&self, now: T) -> u64 {
+        if self.is_expired(now) {
+            self.renewed(now).0
+        } else {
+            self.tokens
+        }
+    }
+
+    /// How many tokens of the coming windows have been booked by `reserve`.
+    #[cfg(feature = "std")]
+    pub fn booked(&self) -> u64 {
+        self.bookings
+            .iter()
+            .fold(0, |total, &booked| total.saturating_add(booked))
+    }
+
+    /// Take a token from the current window, or if there are none left, book one out of the
+    /// first coming window that still has some. Returns `Err` with the start of that window.
+    ///
+    /// Booked tokens are taken out of their window once it starts, so that they aren't given
+    /// to anyone else.
+    #[cfg(feature = "std")]
+    pub fn reserve(&mut self, now: T) -> Result<(), T> {
+        if self.tokens(now) > 0 {
+            self.tokens -= 1;
+            return Ok(());
+        }
+
+        let capacity = self.capacity;
+        let window = match self.bookings.iter().position(|&booked| booked < capacity) {
+            Some(window) => window,
+            None => {
+                self.bookings.push_back(0);
+                self.bookings.len() - 1
+            }
+        };
+        self.bookings[window] += 1;
+        let later = times(self.period, window as u64);
+        Err(self.next_reset_at(now).saturating_add(later))
+    }
+
+    /// Release a token booked by `reserve` for the window starting at `at`. If that window is
+    /// still to come, its booking is released, so that the next `reserve` can take it. If it
+    /// has started, the token is given back to it; if it has already ended, nothing happens.
+    #[cfg(feature = "std")]
+    pub fn unbook(&mut self, at: T, now: T) {
+        self.tokens(now);
+        let mut start = self.next_reset_at(now);
+        if start.saturating_duration_since(at) == T::Duration::ZERO {
+            let mut window = 0;
+            while window < self.bookings.len() && at.saturating_duration_since(start) >= self.period
+            {
+                start = start.saturating_add(self.period);
+                window += 1;
+            }
+            if let Some(booked) = self.bookings.get_mut(window) {
+                *booked = booked.saturating_sub(1);
+            }
+            while self.bookings.back() == Some(&0) {
+                self.bookings.pop_back();
+            }
+        } else if at
+            .saturating_add(self.period)
+            .saturating_duration_since(self.last_reset)
+            > T::Duration::ZERO
+        {
+            self.give_back(1, now);
+        }
+    }
+
+    /// How long until the current window ends.
+    pub fn next_reset(&self, now: T) -> T::Duration {
+        let since = self.elapsed(now);
+
+        if since < self.length() {
+            self.length().saturating_sub(since)
+        } else if self.aligned {
+            // the window has already been replaced by a later one, which started at a multiple
+            // of the period.
+            self.period.saturating_sub(since.rem(self.period))
+        } else {
+            T::Duration::ZERO
+        }
+    }
+
+    /// How long until `n` triggers can be made at once, without resetting an expired window.
+    /// `None` if `n` exceeds the burst capacity, so that they never can.
+    ///
+    /// Tokens booked by `reserve` are taken out of the coming windows, so fully booked windows
+    /// are waited past.
+    pub fn wait_for(&self, n: u64, now: T) -> Option<T::Duration> {
+        if n > self.burst_capacity() {
+            return None;
+        }
+        let mut tokens = self.peek_tokens(now);
+        if tokens >= n {
+            return Some(T::Duration::ZERO);
+        }
+
+        let (mut wait, mut window) = match self.is_expired(now) {
+            // an expired window is replaced when it is next used, or at its aligned start.
+            true => {
+                let length = match self.aligned {
+                    true => self.next_reset(now),
+                    false => self.period,
+                };
+                (length, self.renewed(now).1 + 1)
+            }
+            false => (self.next_reset(now), 0),
+        };
+        // without carrying over, the first window that isn't fully booked is enough. With it,
+        // it may take a few.
+        loop {
+            let refilled = match self.carry_over {
+                Some(_) => tokens
+                    .saturating_add(self.capacity)
+                    .min(self.burst_capacity()),
+                None => self.capacity,
+            };
+            tokens = refilled.saturating_sub(self.booked_for(window).min(self.capacity));
+            if tokens >= n {
+                return Some(wait);
+            }
+            wait = wait.saturating_add(self.period);
+            window += 1;
+        }
+    }
+
+    /// When the current window ends.
+    pub fn next_reset_at(&self, now: T) -> T {
+        if !self.aligned || self.period == T::Duration::ZERO || !self.is_expired(now) {
+            return self.last_reset.saturating_add(self.length());
+        }
+
+        let since = self.elapsed(now);
+        let start = since.saturating_sub(since.rem(self.period));
+        self.last_reset
+            .saturating_add(start.saturating_add(self.period))
+    }
+
+    /// Trigger the window, returning how long until the next reset if there are no triggers
+    /// left.
+    pub fn trigger(&mut self, now: T) -> Option<T::Duration> {
+        if self.tokens(now) == 0 {
+            self.penalize(now);
+            Some(self.wait_for(1, now).unwrap_or(T::Duration::MAX))
+        } else {
+            self.tokens -= 1;
+            None
+        }
+    }
+
+    /// Consume `cost` triggers at once. Nothing is consumed if there aren't enough left. If
+    /// `cost` exceeds the burst capacity, it can never succeed and `Err(T::Duration::MAX)` is
+    /// returned.
+    pub fn trigger_n(&mut self, cost: u64, now: T) -> Result<(), T::Duration> {
+        self.trigger_reserving(cost, 0, now)
+    }
+
+    /// Like `trigger_n`, but only succeeds if at least `floor` tokens are left afterwards, so
+    /// that they stay reserved for other triggers.
+    pub fn trigger_reserving(&mut self, cost: u64, floor: u64, now: T) -> Result<(), T::Duration> {
+        let needed = cost.saturating_add(floor);
+        if needed > self.burst_capacity() {
+            return Err(T::Duration::MAX);
+        }
+
+        let tokens = self.tokens(now);
+        if tokens < needed {
+            // only triggers made with nothing left are punished, not batches too big for what
+            // is left, nor triggers only held back by the floor, which would let low priority
+            // traffic push the reset out for everyone else.
+            let floor_only = floor > 0 && tokens >= cost;
+            if tokens == 0 && !floor_only {
+                self.penalize(now);
+            }
+            Err(self.wait_for(needed, now).unwrap_or(T::Duration::MAX))
+        } else {
+            self.tokens -= cost;
+            Ok(())
+        }
+    }
+
+    /// Reset the window. Aligned windows move forward by whole periods instead of starting at
+    /// `now`.
+    pub fn reset(&mut self, now: T) {
+        self.epoch = self.epoch.wrapping_add(1);
+        self.tokens = self.capacity;
+        self.extension = T::Duration::ZERO;
+        self.draw_skew();
+
+        if !self.aligned {
+            self.last_reset = now;
+        } else if self.period != T::Duration::ZERO {
+            let since = self.elapsed(now);
+            self.last_reset = self
+                .last_reset
+                .saturating_add(since.saturating_sub(since.rem(self.period)));
+        }
+    }
+
+    /// Give back `n` triggers taken from the window `epoch` identifies, without going over the
+    /// burst capacity. If that window has ended, nothing happens, so that tokens of an earlier
+    /// window aren't given to the current one.
+    pub fn refund(&mut self, n: u64, epoch: u64, now: T) {
+        if self.epoch(now) == epoch {
+            self.give_back(n, now);
+        }
+    }
+
+    /// A number identifying the window that is current at `now`. It changes whenever the
+    /// window is reset, so it tells `refund` whether tokens were taken from the current window.
+    pub fn epoch(&self, now: T) -> u64 {
+        self.epoch.wrapping_add(self.is_expired(now) as u64)
+    }
+
+    /// Give back `n` triggers to the current window, without going over the burst capacity,
+    /// for callers that know the tokens were taken from it.
+    pub(crate) fn give_back(&mut self, n: u64, now: T) {
+        let tokens = self.tokens(now);
+        self.tokens = tokens.saturating_add(n).min(self.burst_capacity());
+    }
+
+    /// The time since the start of the current window, treating a `now` earlier than the
+    /// start as the start itself.
+    fn elapsed(&self, now: T) -> T::Duration {
+        now.saturating_duration_since(self.last_reset)
+    }
+
+    /// The tokens of the window that replaces the current one, which has expired at `now`.
+    /// Without carrying over, that is the capacity. With it, each window that ended since
+    /// adds the capacity to what was left.
+    fn refilled(&self, now: T) -> u64 {
+        let Some(_) = self.carry_over else {
+            return self.capacity;
+        };
+        let burst = self.burst_capacity();
+        let mut tokens = self.tokens.saturating_add(self.capacity);
+        let mut since = self.elapsed(now).saturating_sub(self.length());
+        while tokens < burst && since >= self.period {
+            since = since.saturating_sub(self.period);
+            tokens = tokens.saturating_add(self.capacity);
+        }
+        tokens.min(burst)
+    }
+
+    /// The tokens of the window that replaces the current one, which has expired at `now`, and
+    /// how many of the booked windows ended in the meantime. Bookings for those are dropped,
+    /// and those of the new window are taken out of its tokens.
+    fn renewed(&self, now: T) -> (u64, usize) {
+        let tokens = self.refilled(now);
+        let mut ended = 0;
+        let mut since = self.elapsed(now).saturating_sub(self.length());
+        while ended < self.booked_windows() && since >= self.period {
+            since = since.saturating_sub(self.period);
+            ended += 1;
+        }
+
+        let taken = self.booked_for(ended).min(self.capacity);
+        (tokens.saturating_sub(taken), ended)
+    }
+
+    /// The tokens booked for the `window`th coming window, counting from the one after the
+    /// current one.
+    #[cfg(feature = "std")]
+    fn booked_for(&self, window: usize) -> u64 {
+        self.bookings.get(window).copied().unwrap_or(0)
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn booked_for(&self, _window: usize) -> u64 {
+        0
+    }
+
+    /// How many coming windows have bookings kept for them.
+    #[cfg(feature = "std")]
+    fn booked_windows(&self) -> usize {
+        self.bookings.len()
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn booked_windows(&self) -> usize {
+        0
+    }
+
+    /// Forget the bookings of the first `windows` coming windows, once they have started.
+    #[cfg(feature = "std")]
+    fn drop_bookings(&mut self, windows: usize) {
+        let windows = windows.min(self.bookings.len());
+        self.bookings.drain(..windows);
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn drop_bookings(&mut self, _windows: usize) {}
+
+    /// How long the current window lasts, including any penalty.
+    pub(crate) fn length(&self) -> T::Duration {
+        self.jittered_period().saturating_add(self.extension)
+    }
+
+    /// The period of the current window, after jitter.
+    fn jittered_period(&self) -> T::Duration {
+        let (longer, shorter) = self.skew;
+        let period = self.period.saturating_add(longer).saturating_sub(shorter);
+        if period == T::Duration::ZERO {
+            self.period
+        } else {
+            period
+        }
+    }
+
+    /// Draw the jitter of a new window.
+    fn draw_skew(&mut self) {
+        if let (Some(jitter), false) = (&mut self.jitter, self.aligned) {
+            self.skew = jitter.draw();
+        }
+    }
+
+    /// A window expires once a full period has passed, so that waiting for `next_reset` is
+    /// always enough for the window to be reset.
+    pub(crate) fn is_expired(&self, now: T) -> bool {
+        self.elapsed(now) >= self.length()
+    }
+
+    /// Extend the current window after a rejected trigger at `now`. The window is never
+    /// shortened.
+    fn penalize(&mut self, now: T) {
+        let since = self.elapsed(now);
+        let extension = match self.penalty {
+            None => return,
+            Some(Penalty::RestartWindow) => since,
+            Some(Penalty::Extend(penalty, max)) => {
+                let cap = since
+                    .saturating_add(max)
+                    .saturating_sub(self.jittered_period());
+                self.extension.saturating_add(penalty).min(cap)
+            }
+        };
+        self.extension = self.extension.max(extension);
+    }
+}
+
+/// `duration` times `n`, saturating at `MAX`.
+#[cfg(feature = "std")]
+fn times<D: TickDuration>(duration: D, mut n: u64) -> D {
+    let (mut total, mut power) = (D::ZERO, duration);
+    while n > 0 {
+        if n & 1 == 1 {
+            total = total.saturating_add(power);
+        }
+        power = power.saturating_add(power);
+        n >>= 1;
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JumpingWindowCore;
+
+    #[test]
+    fn aligned_ticks_keep_their_phase() {
+        let mut window = JumpingWindowCore::new_aligned(1, 100, 1_000u64);
+
+        assert_eq!(window.trigger(1_050), None);
+        assert_eq!(window.trigger(1_060), Some(40));
+
+        // several periods later, the window starts at a multiple of the period.
+        assert_eq!(window.trigger(1_350), None);
+        assert_eq!(window.window_start(), 1_300);
+        assert_eq!(window.next_reset_at(1_350), 1_400);
+    }
+
+    #[test]
+    fn earlier_ticks_stay_in_the_window() {
+        let mut window = JumpingWindowCore::new(2, 100, 500u64);
+
+        assert_eq!(window.trigger(400), None);
+        assert_eq!(window.next_reset(400), 100);
+        assert_eq!(window.trigger_n(2, 450), Err(100));
+        assert_eq!(window.trigger_n(3, 450), Err(u64::MAX));
+    }
+
+    #[test]
+    fn refunds_to_an_ended_window_do_nothing() {
+        let mut window = JumpingWindowCore::new(2, 100, 0u64);
+        let epoch = window.epoch(10);
+        assert_eq!(window.trigger(10), None);
+
+        // the token was taken from the first window, so the second one doesn't get it.
+        assert_eq!(window.trigger(110), None);
+        window.refund(1, epoch, 120);
+        assert_eq!(window.tokens(120), 1);
+
+        window.refund(1, window.epoch(120), 120);
+        assert_eq!(window.tokens(120), 2);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn bookings_fill_the_coming_windows() {
+        let mut window = JumpingWindowCore::new(2, 100, 0u64);
+        window.trigger_n(2, 0).unwrap();
+
+        let slots = [(); 5].map(|_| window.reserve(10));
+        assert_eq!(slots, [Err(100), Err(100), Err(200), Err(200), Err(300)]);
+        assert_eq!(window.booked(), 5);
+        assert_eq!(window.peek_tokens(120), 0);
+
+        // the second window takes its bookings, and cancelling one of them gives it back.
+        assert_eq!(window.tokens(150), 0);
+        window.unbook(100, 150);
+        assert_eq!(window.tokens(150), 1);
+        // the third window's bookings are dropped along with it.
+        window.unbook(300, 150);
+        assert_eq!(window.tokens(450), 2);
+        assert_eq!(window.booked(), 0);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn cancelled_bookings_are_booked_again() {
+        let mut window = JumpingWindowCore::new(1, 100, 0u64);
+        assert_eq!(window.trigger(0), None);
+
+        let slots = [(); 3].map(|_| window.reserve(10));
+        assert_eq!(slots, [Err(100), Err(200), Err(300)]);
+
+        // the middle booking is released, so the next one takes its window again.
+        window.unbook(200, 20);
+        assert_eq!(window.reserve(30), Err(200));
+        assert_eq!(window.reserve(30), Err(400));
+        assert_eq!(window.booked(), 4);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn waits_skip_fully_booked_windows() {
+        let mut window = JumpingWindowCore::new(1, 100, 0u64);
+        assert_eq!(window.trigger(0), None);
+        assert_eq!(window.reserve(10), Err(100));
+
+        // the second window's token is booked, so the third is the first with one free.
+        assert_eq!(window.trigger(10), Some(190));
+        assert_eq!(window.wait_for(1, 10), Some(190));
+        // once expired, the second window starts when it is used, and takes the booked token.
+        assert_eq!(window.wait_for(1, 150), Some(100));
+        assert_eq!(window.trigger(200), None);
+    }
+}
@@ -0,0 +1,343 @@
+use std::time::Duration;
+
+use crate::{
+    clock::{self, Instant, SystemTime, UNIX_EPOCH},
+    error::validate,
+    InvalidWindow, RateLimiter,
+};
+
+/// A `floodgate::JumpingWindow` that keeps time with the system clock instead of `Instant`.
+///
+/// Window boundaries are wall-clock times, so they can be shared between processes and
+/// machines. Aligned windows start at multiples of the period since the unix epoch, so every
+/// process computes the same boundaries without coordinating.
+///
+/// The system clock can go backwards, for example after an NTP adjustment. A `now` earlier
+/// than the start of the current window is treated as still being in that window.
+///
+/// For method documentation, please see `floodgate::JumpingWindow`.
+///
+/// # Examples
+/// ```
+/// use floodgate::JumpingWindowUtc;
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// let mut cooldown = JumpingWindowUtc::new_aligned(1, Duration::from_secs(60));
+///
+/// let now = UNIX_EPOCH + Duration::from_secs(1_000_000_030);
+/// cooldown.reset(Some(now));
+/// assert_eq!(cooldown.trigger(Some(now)), None);
+/// assert_eq!(cooldown.window_start(), UNIX_EPOCH + Duration::from_secs(1_000_000_020));
+/// assert_eq!(cooldown.trigger(Some(now)), Some(Duration::from_secs(50)));
+///
+/// // a clock that jumped backwards is still in the current window.
+/// let earlier = now - Duration::from_secs(3600);
+/// assert!(cooldown.trigger(Some(earlier)).is_some());
+/// ```
+#[derive(Debug, Clone)]
+pub struct JumpingWindowUtc {
+    capacity: u64,
+    period: Duration,
+
+    last_reset: SystemTime,
+    tokens: u64,
+    aligned: bool,
+    /// How many times the window has been reset, to tell which window tokens were taken from.
+    epoch: u64,
+}
+
+impl JumpingWindowUtc {
+    /// Create a new JumpingWindowUtc.
+    ///
+    /// # Arguments
+    /// * `capacity` - How many triggers can occur per window.
+    /// * `period` - How long the window is.
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `period` is zero. See `JumpingWindowUtc::try_new`.
+    pub fn new(capacity: u64, period: Duration) -> Self {
+        Self::try_new(capacity, period).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Create a new JumpingWindowUtc, returning an error if `capacity` or `period` is zero.
+    ///
+    /// # Arguments
+    /// * `capacity` - How many triggers can occur per window.
+    /// * `period` - How long the window is.
+    pub fn try_new(capacity: u64, period: Duration) -> Result<Self, InvalidWindow> {
+        validate(capacity, period)?;
+
+        Ok(Self {
+            capacity,
+            period,
+            last_reset: SystemTime::now(),
+            tokens: capacity,
+            aligned: false,
+            epoch: 0,
+        })
+    }
+
+    /// Create a new JumpingWindowUtc whose windows start at multiples of `period` since the
+    /// unix epoch.
+    ///
+    /// # Arguments
+    /// * `capacity` - How many triggers can occur per window.
+    /// * `period` - How long the window is.
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `period` is zero.
+    pub fn new_aligned(capacity: u64, period: Duration) -> Self {
+        let mut window = Self::new(capacity, period);
+        window.aligned = true;
+        window.reset(None);
+        window
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    pub fn window_start(&self) -> SystemTime {
+        self.last_reset
+    }
+
+    pub fn is_aligned(&self) -> bool {
+        self.aligned
+    }
+
+    pub fn tokens(&mut self, now: Option<SystemTime>) -> u64 {
+        let now = now.unwrap_or_else(SystemTime::now);
+
+        if self.elapsed(now) >= self.period {
+            self.reset(Some(now));
+        }
+
+        self.tokens
+    }
+
+    pub fn next_reset(&mut self, now: Option<SystemTime>) -> Duration {
+        let now = now.unwrap_or_else(SystemTime::now);
+        let since = self.elapsed(now);
+
+        if since < self.period {
+            self.period - since
+        } else if self.aligned {
+            self.period - nanos(since.as_nanos() % self.period.as_nanos())
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    pub fn retry_after(&mut self, now: Option<SystemTime>) -> Option<Duration> {
+        let now = now.unwrap_or_else(SystemTime::now);
+
+        if self.tokens(Some(now)) == 0 {
+            Some(self.next_reset(Some(now)))
+        } else {
+            None
+        }
+    }
+
+    pub fn wait_for(&mut self, n: u64, now: Option<SystemTime>) -> Option<Duration> {
+        let now = now.unwrap_or_else(SystemTime::now);
+
+        if n > self.capacity {
+            None
+        } else if self.tokens(Some(now)) >= n {
+            Some(Duration::ZERO)
+        } else {
+            Some(self.next_reset(Some(now)))
+        }
+    }
+
+    pub fn can_trigger(&mut self, now: Option<SystemTime>) -> bool {
+        self.tokens(now) != 0
+    }
+
+    pub fn trigger(&mut self, now: Option<SystemTime>) -> Option<Duration> {
+        self.trigger_n(1, now).err()
+    }
+
+    pub fn trigger_n(&mut self, cost: u64, now: Option<SystemTime>) -> Result<(), Duration> {
+        if cost > self.capacity {
+            return Err(Duration::MAX);
+        }
+
+        let now = now.unwrap_or_else(SystemTime::now);
+        if self.tokens(Some(now)) < cost {
+            Err(self.next_reset(Some(now)))
+        } else {
+            self.tokens -= cost;
+            Ok(())
+        }
+    }
+
+    /// Reset the cooldown. For aligned windows, the new window starts at the most recent
+    /// multiple of the period since the unix epoch rather than at `now`.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    pub fn reset(&mut self, now: Option<SystemTime>) {
+        let now = now.unwrap_or_else(SystemTime::now);
+        self.epoch = self.epoch.wrapping_add(1);
+        self.tokens = self.capacity;
+
+        self.last_reset = match now.duration_since(UNIX_EPOCH) {
+            Ok(since_epoch) if self.aligned => {
+                let period = self.period.as_nanos();
+                UNIX_EPOCH + nanos(since_epoch.as_nanos() / period * period)
+            }
+            // there are no multiples of the period to align to before the epoch.
+            _ => now,
+        };
+    }
+
+    pub fn refund(&mut self, n: u64, epoch: u64, now: Option<SystemTime>) {
+        let now = now.unwrap_or_else(SystemTime::now);
+        if JumpingWindowUtc::epoch(self, Some(now)) == epoch {
+            let tokens = self.tokens(Some(now));
+            self.tokens = tokens.saturating_add(n).min(self.capacity);
+        }
+    }
+
+    pub fn epoch(&self, now: Option<SystemTime>) -> u64 {
+        let now = now.unwrap_or_else(SystemTime::now);
+        let expired = self.elapsed(now) >= self.period;
+        self.epoch.wrapping_add(expired as u64)
+    }
+
+    /// The time since the start of the current window, treating a `now` earlier than the
+    /// start as the start itself.
+    fn elapsed(&self, now: SystemTime) -> Duration {
+        now.duration_since(self.last_reset).unwrap_or_default()
+    }
+}
+
+fn nanos(nanos: u128) -> Duration {
+    Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
+}
+
+/// Convert an `Instant` to the system time it corresponds to right now.
+fn wall_clock(now: Option<Instant>) -> Option<SystemTime> {
+    let now = now?;
+    let (instant, system) = (clock::now(), SystemTime::now());
+
+    Some(match instant.checked_duration_since(now) {
+        Some(ago) => system.checked_sub(ago).unwrap_or(system),
+        None => system + (now - instant),
+    })
+}
+
+/// Times passed as an `Instant` are converted to the system time they correspond to.
+impl RateLimiter for JumpingWindowUtc {
+    fn new(capacity: u64, period: Duration) -> Self {
+        Self::new(capacity, period)
+    }
+
+    fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    fn period(&self) -> Duration {
+        self.period
+    }
+
+    fn set_rate(&mut self, capacity: u64, period: Duration) {
+        if capacity > self.capacity {
+            self.tokens = self.tokens.saturating_add(capacity - self.capacity);
+        }
+        self.tokens = self.tokens.min(capacity);
+        self.capacity = capacity;
+        self.period = period;
+    }
+
+    fn tokens(&mut self, now: Option<Instant>) -> u64 {
+        self.tokens(wall_clock(now))
+    }
+
+    fn next_reset(&mut self, now: Option<Instant>) -> Duration {
+        self.next_reset(wall_clock(now))
+    }
+
+    fn retry_after(&mut self, now: Option<Instant>) -> Option<Duration> {
+        self.retry_after(wall_clock(now))
+    }
+
+    fn wait_for(&mut self, n: u64, now: Option<Instant>) -> Option<Duration> {
+        self.wait_for(n, wall_clock(now))
+    }
+
+    fn can_trigger(&mut self, now: Option<Instant>) -> bool {
+        self.can_trigger(wall_clock(now))
+    }
+
+    fn trigger(&mut self, now: Option<Instant>) -> Option<Duration> {
+        self.trigger(wall_clock(now))
+    }
+
+    fn trigger_n(&mut self, cost: u64, now: Option<Instant>) -> Result<(), Duration> {
+        self.trigger_n(cost, wall_clock(now))
+    }
+
+    fn reset(&mut self, now: Option<Instant>) {
+        self.reset(wall_clock(now))
+    }
+
+    fn refund(&mut self, n: u64, epoch: u64, now: Option<Instant>) {
+        self.refund(n, epoch, wall_clock(now))
+    }
+
+    fn epoch(&mut self, now: Option<Instant>) -> u64 {
+        JumpingWindowUtc::epoch(self, wall_clock(now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::JumpingWindowUtc;
+
+    #[test]
+    fn aligned_windows_agree_between_processes() {
+        let period = Duration::from_secs(10);
+        let mut first = JumpingWindowUtc::new_aligned(1, period);
+        let mut second = JumpingWindowUtc::new_aligned(1, period);
+
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_004);
+        first.reset(Some(now));
+        second.reset(Some(now + Duration::from_secs(3)));
+
+        assert_eq!(first.window_start(), second.window_start());
+        assert_eq!(first.next_reset(Some(now)), second.next_reset(Some(now)),);
+    }
+
+    #[test]
+    fn backwards_clock_stays_in_the_window() {
+        let period = Duration::from_secs(10);
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut window = JumpingWindowUtc::new(2, period);
+        window.reset(Some(start));
+        window.trigger(Some(start));
+
+        let earlier = start - Duration::from_secs(86_400);
+        assert_eq!(window.tokens(Some(earlier)), 1);
+        assert_eq!(window.next_reset(Some(earlier)), period);
+        assert_eq!(window.trigger(Some(earlier)), None);
+        assert_eq!(window.trigger(Some(earlier)), Some(period));
+        assert_eq!(window.window_start(), start);
+
+        // before the epoch, there is nothing to align to, but nothing panics either.
+        let mut aligned = JumpingWindowUtc::new_aligned(1, period);
+        aligned.reset(Some(UNIX_EPOCH - Duration::from_secs(5)));
+        assert_eq!(aligned.trigger(Some(UNIX_EPOCH)), None);
+        assert_eq!(
+            aligned.trigger(Some(UNIX_EPOCH)),
+            Some(Duration::from_secs(5))
+        );
+    }
+}
@@ -0,0 +1,323 @@
+use std::{
+    hash::{BuildHasher, Hash},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{Clock, FixedMapping, RateLimiter, SharedJumpingWindow};
+
+/// One layer of a `floodgate::LayeredMapping`: a cooldown that can be checked and triggered
+/// for a key, and refunded if another layer rejects the trigger.
+pub trait Layer<K: ?Sized> {
+    /// How long until `key` can be triggered, or `None` if it can be right now. Nothing is
+    /// taken.
+    fn check(&self, key: &K) -> Option<Duration>;
+
+    /// Take a token for `key`. Returns how long until `key` can be triggered again if it can't
+    /// be triggered now, like `floodgate::JumpingWindow::trigger`.
+    fn trigger(&self, key: &K) -> Option<Duration>;
+
+    /// A number identifying the current window of `key`, read before triggering it so that
+    /// the token can be given back with `Layer::refund`. See `floodgate::JumpingWindow::epoch`.
+    fn epoch(&self, key: &K) -> u64;
+
+    /// Give back the token taken by a successful `trigger` for `key`, unless the window
+    /// `epoch` identifies has ended since.
+    fn refund(&self, key: &K, epoch: u64);
+}
+
+/// A single, global window, shared by every key.
+impl<K: ?Sized> Layer<K> for SharedJumpingWindow {
+    fn check(&self, _key: &K) -> Option<Duration> {
+        waiting(SharedJumpingWindow::wait_for(self, 1, None))
+    }
+
+    fn trigger(&self, _key: &K) -> Option<Duration> {
+        SharedJumpingWindow::trigger(self, None)
+    }
+
+    fn epoch(&self, _key: &K) -> u64 {
+        SharedJumpingWindow::epoch(self, None)
+    }
+
+    fn refund(&self, _key: &K, epoch: u64) {
+        SharedJumpingWindow::refund(self, 1, epoch, None);
+    }
+}
+
+impl<K, L, C, S> Layer<K> for FixedMapping<K, L, C, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    L: RateLimiter,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    fn check(&self, key: &K) -> Option<Duration> {
+        waiting(FixedMapping::wait_for(self, key, 1))
+    }
+
+    fn trigger(&self, key: &K) -> Option<Duration> {
+        FixedMapping::trigger(self, key)
+    }
+
+    fn epoch(&self, key: &K) -> u64 {
+        FixedMapping::epoch(self, key)
+    }
+
+    fn refund(&self, key: &K, epoch: u64) {
+        FixedMapping::refund(self, key, 1, epoch);
+    }
+}
+
+impl<K: ?Sized, T: Layer<K> + ?Sized> Layer<K> for Arc<T> {
+    fn check(&self, key: &K) -> Option<Duration> {
+        (**self).check(key)
+    }
+
+    fn trigger(&self, key: &K) -> Option<Duration> {
+        (**self).trigger(key)
+    }
+
+    fn epoch(&self, key: &K) -> u64 {
+        (**self).epoch(key)
+    }
+
+    fn refund(&self, key: &K, epoch: u64) {
+        (**self).refund(key, epoch);
+    }
+}
+
+/// Turn the wait before a trigger into a retry-after, where a trigger that never fits waits
+/// forever.
+fn waiting(wait: Option<Duration>) -> Option<Duration> {
+    match wait {
+        Some(Duration::ZERO) => None,
+        wait => Some(wait.unwrap_or(Duration::MAX)),
+    }
+}
+
+/// A layer keyed by something derived from the `LayeredMapping`'s key.
+struct ByKey<T, F> {
+    layer: T,
+    key: F,
+}
+
+impl<K: ?Sized, K2, T: Layer<K2>, F: Fn(&K) -> K2> Layer<K> for ByKey<T, F> {
+    fn check(&self, key: &K) -> Option<Duration> {
+        self.layer.check(&(self.key)(key))
+    }
+
+    fn trigger(&self, key: &K) -> Option<Duration> {
+        self.layer.trigger(&(self.key)(key))
+    }
+
+    fn epoch(&self, key: &K) -> u64 {
+        self.layer.epoch(&(self.key)(key))
+    }
+
+    fn refund(&self, key: &K, epoch: u64) {
+        self.layer.refund(&(self.key)(key), epoch);
+    }
+}
+
+/// Returned by `floodgate::LayeredMapping::trigger` when one of the layers rejected the
+/// trigger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LayerRejected {
+    /// The index of the layer that rejected, in the order the layers were added.
+    pub layer: usize,
+    /// How long until that layer can be triggered again.
+    pub retry_after: Duration,
+}
+
+/// A stack of cooldowns that are all triggered together, such as a global limit, a limit per
+/// guild, and a limit per user.
+///
+/// A trigger first checks every layer without taking anything. If a layer would reject it,
+/// that layer is triggered first, so that its rejection is counted as usual while no other
+/// layer is touched: a trigger rejected by the per-user layer never holds a global token, even
+/// for a moment. Otherwise a token is taken from every layer in order. If a layer still
+/// rejects it, because of a concurrent trigger since the check, the tokens already taken are
+/// given back to the windows they were taken from.
+///
+/// # Examples
+/// ```
+/// use floodgate::{FixedMapping, LayeredMapping, SharedJumpingWindow};
+/// use std::time::Duration;
+///
+/// struct Event {
+///     guild: u64,
+///     user: u64,
+/// }
+///
+/// let minute = Duration::from_secs(60);
+/// let layers = LayeredMapping::new()
+///     .layer(SharedJumpingWindow::new(1000, minute))
+///     .layer_by(FixedMapping::new(100, minute), |event: &Event| event.guild)
+///     .layer_by(FixedMapping::new(1, minute), |event: &Event| event.user);
+///
+/// assert_eq!(layers.trigger(&Event { guild: 1, user: 1 }), Ok(()));
+/// assert_eq!(layers.trigger(&Event { guild: 1, user: 2 }), Ok(()));
+///
+/// let rejected = layers.trigger(&Event { guild: 1, user: 1 }).unwrap_err();
+/// assert_eq!(rejected.layer, 2);
+/// ```
+pub struct LayeredMapping<K: ?Sized> {
+    layers: Vec<Box<dyn Layer<K> + Send + Sync>>,
+}
+
+impl<K: ?Sized> LayeredMapping<K> {
+    /// Create a LayeredMapping without any layers.
+    pub fn new() -> Self {
+        Self { layers: Vec::new() }
+    }
+
+    /// Add a layer that is triggered with the mapping's key, or ignores it.
+    ///
+    /// # Arguments
+    /// * `layer` - The layer to add, such as a `floodgate::SharedJumpingWindow` or a
+    ///   `floodgate::FixedMapping`. To share a layer with other code, wrap it in an `Arc`.
+    pub fn layer(mut self, layer: impl Layer<K> + Send + Sync + 'static) -> Self {
+        self.layers.push(Box::new(layer));
+        self
+    }
+
+    /// Add a layer that is triggered with a key derived from the mapping's key.
+    ///
+    /// # Arguments
+    /// * `layer` - The layer to add.
+    /// * `key` - Returns the key to trigger the layer with.
+    pub fn layer_by<K2: 'static>(
+        mut self,
+        layer: impl Layer<K2> + Send + Sync + 'static,
+        key: impl Fn(&K) -> K2 + Send + Sync + 'static,
+    ) -> Self
+    where
+        K: 'static,
+    {
+        self.layers.push(Box::new(ByKey { layer, key }));
+        self
+    }
+
+    /// How many layers the mapping has.
+    pub fn layers(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// Trigger every layer for `key`, or none of them. Returns the first layer that rejected
+    /// the trigger, if any.
+    ///
+    /// # Arguments
+    /// * `key` - The key to trigger.
+    pub fn trigger(&self, key: &K) -> Result<(), LayerRejected> {
+        // the layer expected to reject is triggered first, so that a rejected trigger usually
+        // leaves the other layers untouched.
+        let mut order: Vec<usize> = (0..self.layers.len()).collect();
+        let failing = self
+            .layers
+            .iter()
+            .position(|layer| layer.check(key).is_some());
+        if let Some(failing) = failing {
+            order[..=failing].rotate_right(1);
+        }
+
+        let mut taken: Vec<(usize, u64)> = Vec::with_capacity(self.layers.len());
+        for i in order {
+            let layer = &self.layers[i];
+            // read before triggering, so that a window ending in between isn't refunded.
+            let epoch = layer.epoch(key);
+            if let Some(retry_after) = layer.trigger(key) {
+                for (j, epoch) in taken {
+                    self.layers[j].refund(key, epoch);
+                }
+                return Err(LayerRejected {
+                    layer: i,
+                    retry_after,
+                });
+            }
+            taken.push((i, epoch));
+        }
+        Ok(())
+    }
+}
+
+impl<K: ?Sized> Default for LayeredMapping<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use super::{Layer, LayeredMapping};
+    use crate::{FixedMapping, SharedJumpingWindow};
+
+    #[test]
+    fn rejected_triggers_give_back_earlier_layers() {
+        let global = SharedJumpingWindow::new(2, Duration::from_secs(60));
+        let users = Arc::new(FixedMapping::new(1, Duration::from_secs(60)));
+        let layers = LayeredMapping::new()
+            .layer(global.clone())
+            .layer(users.clone());
+
+        assert_eq!(layers.trigger(&1), Ok(()));
+        for _ in 0..3 {
+            assert_eq!(layers.trigger(&1).unwrap_err().layer, 1);
+        }
+        assert_eq!(global.tokens(None), 1);
+
+        assert_eq!(layers.trigger(&2), Ok(()));
+        let rejected = layers.trigger(&3).unwrap_err();
+        assert_eq!(rejected.layer, 0);
+        assert_eq!(users.tokens(&3), 1);
+    }
+
+    /// A layer that counts how often it is triggered.
+    struct Counted(SharedJumpingWindow, AtomicU64);
+
+    impl Layer<u64> for Counted {
+        fn check(&self, key: &u64) -> Option<Duration> {
+            self.0.check(key)
+        }
+
+        fn trigger(&self, key: &u64) -> Option<Duration> {
+            self.1.fetch_add(1, Ordering::Relaxed);
+            Layer::trigger(&self.0, key)
+        }
+
+        fn epoch(&self, key: &u64) -> u64 {
+            Layer::epoch(&self.0, key)
+        }
+
+        fn refund(&self, key: &u64, epoch: u64) {
+            Layer::refund(&self.0, key, epoch)
+        }
+    }
+
+    #[test]
+    fn rejected_triggers_never_take_a_global_token() {
+        let global = Arc::new(Counted(
+            SharedJumpingWindow::new(10, Duration::from_secs(60)),
+            AtomicU64::new(0),
+        ));
+        let users = Arc::new(FixedMapping::new(1, Duration::from_secs(60)).with_stats());
+        let layers = LayeredMapping::new()
+            .layer(global.clone())
+            .layer(users.clone());
+
+        assert_eq!(layers.trigger(&1), Ok(()));
+        for _ in 0..3 {
+            assert_eq!(layers.trigger(&1).unwrap_err().layer, 1);
+        }
+        assert_eq!(global.1.load(Ordering::Relaxed), 1);
+        assert_eq!(users.stats().rejected, 3);
+    }
+}
@@ -1,20 +1,226 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(all(feature = "tokio", target_arch = "wasm32", target_os = "unknown"))]
+compile_error!("the `tokio` feature isn't supported on wasm32-unknown-unknown, where tokio's timers can't read the time");
+
+#[cfg(feature = "tokio")]
+mod acquire;
+#[cfg(feature = "std")]
+mod adaptive_window;
+#[cfg(feature = "std")]
+mod atomic_jumping_window;
+#[cfg(feature = "axum")]
+pub mod axum;
+#[cfg(feature = "std")]
+mod backend;
+#[cfg(feature = "std")]
+mod bucket;
+#[cfg(all(
+    feature = "std",
+    not(all(target_arch = "wasm32", target_os = "unknown"))
+))]
+mod cleanup;
+#[cfg(feature = "std")]
+mod clock;
+#[cfg(feature = "std")]
+mod concurrency;
+#[cfg(feature = "tokio")]
+mod cycle_task;
+#[cfg(feature = "std")]
 mod dynamic_mapping;
+#[cfg(feature = "std")]
+mod entry;
+mod error;
+#[cfg(feature = "stream")]
+mod events;
+#[cfg(feature = "std")]
+mod eviction;
+#[cfg(feature = "std")]
 mod fixed_mapping;
+#[cfg(feature = "std")]
+mod gcra;
+#[cfg(feature = "http")]
+pub mod headers;
+#[cfg(feature = "std")]
+mod history;
+#[cfg(feature = "std")]
+mod hooks;
+#[cfg(feature = "std")]
+pub mod io;
+#[cfg(feature = "std")]
+pub mod iter;
+#[cfg(feature = "std")]
 mod jumping_window;
+mod jumping_window_core;
+#[cfg(feature = "std")]
+mod jumping_window_utc;
+#[cfg(feature = "std")]
+mod layered_mapping;
+#[cfg(feature = "std")]
+mod limiter;
+#[cfg(feature = "std")]
+mod log;
+#[cfg(feature = "std")]
 mod mapping;
+#[cfg(feature = "std")]
+mod mode;
+#[cfg(feature = "std")]
+mod multi_window;
+#[cfg(feature = "tokio")]
+mod notify;
+#[cfg(feature = "std")]
+mod policy;
+#[cfg(feature = "std")]
+mod priority;
+#[cfg(feature = "std")]
+mod rate;
+#[cfg(feature = "std")]
+mod rate_limit_info;
+#[cfg(feature = "std")]
+mod rate_limiter;
+#[cfg(feature = "redis")]
+pub mod redis;
+#[cfg(feature = "registry")]
+pub mod registry;
+#[cfg(feature = "reqwest")]
+pub mod reqwest;
+#[cfg(feature = "std")]
+mod reservation;
+#[cfg(feature = "std")]
+mod shared_jumping_window;
+#[cfg(feature = "std")]
+mod shedding;
+#[cfg(feature = "std")]
+mod sleeper;
+#[cfg(feature = "std")]
+mod sliding_counter;
+#[cfg(feature = "std")]
+mod sliding_window;
+#[cfg(feature = "std")]
+mod snapshot;
+#[cfg(feature = "std")]
+mod stats;
+#[cfg(feature = "std")]
+mod status;
+#[cfg(feature = "stream")]
+pub mod stream;
+#[cfg(feature = "std")]
+mod sweep;
+#[cfg(feature = "std")]
+mod token_bucket;
+#[cfg(feature = "tower")]
+pub mod tower;
+#[cfg(feature = "std")]
+mod trigger_guard;
+#[cfg(feature = "tokio")]
+mod wait_queue;
+#[cfg(feature = "std")]
+mod warmup;
 
-pub use dynamic_mapping::DynamicMapping;
+#[cfg(feature = "std")]
+pub use adaptive_window::AdaptiveWindow;
+#[cfg(feature = "std")]
+pub use atomic_jumping_window::AtomicJumpingWindow;
+#[cfg(feature = "std")]
+pub use backend::{Backend, BackendMapping, MemoryBackend, WindowState};
+#[cfg(feature = "std")]
+pub use bucket::{Bucket, BucketContext, BucketIds, BucketedCooldown};
+#[cfg(all(
+    feature = "std",
+    not(all(target_arch = "wasm32", target_os = "unknown"))
+))]
+pub use cleanup::CleanupHandle;
+#[cfg(feature = "std")]
+pub use clock::{CachedClock, Clock, ManualClock, MonotonicClock};
+#[cfg(feature = "std")]
+pub use concurrency::{ConcurrencyLimit, ConcurrencyMapping, ConcurrencyPermit, KeyedPermit};
+#[cfg(feature = "tokio")]
+pub use cycle_task::CleanupTask;
+#[cfg(feature = "std")]
+pub use dynamic_mapping::{DynamicMapping, KeyRate};
+#[cfg(feature = "std")]
+pub use entry::EntryGuard;
+#[cfg(feature = "std")]
+pub use error::CooldownError;
+#[cfg(feature = "std")]
+pub use error::Elapsed;
+#[cfg(feature = "std")]
+pub use error::InvalidPolicy;
+pub use error::InvalidWindow;
+#[cfg(feature = "std")]
+pub use error::ParseRateError;
+#[cfg(feature = "std")]
+pub use error::RateLimited;
+#[cfg(feature = "std")]
+pub use error::ReserveError;
+#[cfg(feature = "std")]
+pub use error::TriggerAllError;
+#[cfg(feature = "stream")]
+pub use events::{Events, RateLimitEvent};
+#[cfg(feature = "std")]
 pub use fixed_mapping::FixedMapping;
-pub use jumping_window::JumpingWindow;
+#[cfg(feature = "macros")]
+pub use floodgate_macros::cooldown;
+#[cfg(feature = "std")]
+pub use gcra::Gcra;
+#[cfg(feature = "std")]
+pub use history::TriggerRecord;
+#[cfg(feature = "std")]
+pub use jumping_window::{JumpingWindow, JumpingWindowBuilder, Reservation};
+pub use jumping_window_core::{Jitter, JumpingWindowCore, Penalty, TickDuration, TickInstant};
+#[cfg(feature = "std")]
+pub use jumping_window_utc::JumpingWindowUtc;
+#[cfg(feature = "std")]
+pub use layered_mapping::{Layer, LayerRejected, LayeredMapping};
+#[cfg(feature = "std")]
+pub use limiter::{Algorithm, Limiter};
+#[cfg(feature = "std")]
+pub use mode::EnforcementMode;
+#[cfg(feature = "std")]
+pub use multi_window::MultiWindow;
+#[cfg(feature = "std")]
+pub use policy::Policy;
+#[cfg(feature = "std")]
+pub use priority::Priority;
+#[cfg(feature = "std")]
+pub use rate::Rate;
+#[cfg(feature = "std")]
+pub use rate_limit_info::RateLimitInfo;
+#[cfg(feature = "std")]
+pub use rate_limiter::RateLimiter;
+#[cfg(feature = "std")]
+pub use reservation::KeyedReservation;
+#[cfg(feature = "std")]
+pub use shared_jumping_window::SharedJumpingWindow;
+#[cfg(feature = "std")]
+pub use shedding::Shedding;
+#[cfg(feature = "std")]
+pub use sleeper::Sleeper;
+#[cfg(feature = "std")]
+pub use sliding_counter::SlidingCounter;
+#[cfg(feature = "std")]
+pub use sliding_window::SlidingWindow;
+#[cfg(feature = "std")]
+pub use snapshot::{MappingSnapshot, MergeStrategy, SnapshotEntry};
+#[cfg(feature = "std")]
+pub use stats::{MappingStats, UtilizationStats};
+#[cfg(feature = "std")]
+pub use status::{snapshot, Named, NamedRateLimitInfo, StatusSource};
+#[cfg(feature = "std")]
+pub use token_bucket::TokenBucket;
+#[cfg(feature = "std")]
+pub use trigger_guard::TriggerGuard;
+#[cfg(feature = "std")]
+pub use warmup::Warmup;
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use std::{
         sync::Arc,
         time::{Duration, Instant},
     };
 
-    use crate::FixedMapping;
+    use crate::{FixedMapping, TriggerGuard};
 
     #[test]
     fn benchmark() {
@@ -27,4 +233,30 @@ mod tests {
         }
         println!("Elapsed: {:?}", start.elapsed());
     }
+
+    #[test]
+    fn trigger_guard_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<TriggerGuard<'static, u64, crate::JumpingWindow>>();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn fixed_mapping_serde_round_trip() {
+        let mapping = FixedMapping::<String>::new(2, Duration::from_secs(10));
+        mapping.trigger(&"a".to_owned());
+        mapping.trigger(&"a".to_owned());
+        mapping.trigger(&"b".to_owned());
+        mapping.set_override("c".to_owned(), 5, Duration::from_secs(10));
+
+        let saved = serde_json::to_string(&mapping).unwrap();
+        let restored: FixedMapping<String> = serde_json::from_str(&saved).unwrap();
+
+        assert_eq!(restored.capacity(), 2);
+        assert_eq!(restored.period(), Duration::from_secs(10));
+        assert!(restored.trigger(&"a".to_owned()).is_some());
+        assert_eq!(restored.tokens(&"b".to_owned()), 1);
+        assert_eq!(restored.tokens(&"c".to_owned()), 5);
+        assert_eq!(restored.tokens(&"d".to_owned()), 2);
+    }
 }
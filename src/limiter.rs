@@ -0,0 +1,210 @@
+use std::time::Duration;
+
+use crate::clock::Instant;
+use crate::{
+    Gcra, JumpingWindow, Rate, RateLimitInfo, RateLimiter, SlidingCounter, SlidingWindow,
+    TokenBucket,
+};
+
+/// The algorithms a `floodgate::Limiter` can use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Algorithm {
+    /// `floodgate::JumpingWindow`, the cheapest, whose windows start at the first trigger.
+    #[default]
+    Jumping,
+    /// `floodgate::SlidingWindow`, which counts the triggers of exactly the last period.
+    Sliding,
+    /// `floodgate::SlidingCounter`, an approximation of a sliding window in constant space.
+    SlidingCounter,
+    /// `floodgate::TokenBucket`, which gives tokens back one at a time.
+    TokenBucket,
+    /// `floodgate::Gcra`, which spaces triggers out evenly after the burst.
+    Gcra,
+}
+
+impl Algorithm {
+    /// A new limiter using this algorithm, allowing `capacity` triggers per `period`.
+    pub fn limiter(self, capacity: u64, period: Duration) -> Limiter {
+        match self {
+            Self::Jumping => Limiter::Jumping(RateLimiter::new(capacity, period)),
+            Self::Sliding => Limiter::Sliding(SlidingWindow::new(capacity, period)),
+            Self::SlidingCounter => Limiter::SlidingCounter(SlidingCounter::new(capacity, period)),
+            Self::TokenBucket => Limiter::TokenBucket(TokenBucket::new(capacity, period)),
+            Self::Gcra => Limiter::Gcra(Gcra::new(capacity, period)),
+        }
+    }
+
+    /// The constructor of this algorithm's limiters, for `floodgate::DynamicMapping`.
+    pub(crate) fn constructor(self) -> fn(u64, Duration) -> Limiter {
+        match self {
+            Self::Jumping => |capacity, period| Algorithm::Jumping.limiter(capacity, period),
+            Self::Sliding => |capacity, period| Algorithm::Sliding.limiter(capacity, period),
+            Self::SlidingCounter => {
+                |capacity, period| Algorithm::SlidingCounter.limiter(capacity, period)
+            }
+            Self::TokenBucket => {
+                |capacity, period| Algorithm::TokenBucket.limiter(capacity, period)
+            }
+            Self::Gcra => |capacity, period| Algorithm::Gcra.limiter(capacity, period),
+        }
+    }
+}
+
+/// A limiter using any of the crate's algorithms, so that the keys of one mapping can each use
+/// a different one without boxing them. Keys of a `floodgate::DynamicMapping` can be given
+/// their algorithm with `DynamicMapping::add_limiter`.
+///
+/// Created with `RateLimiter::new`, it is a `floodgate::JumpingWindow`.
+///
+/// For method documentation, please see `floodgate::JumpingWindow`.
+///
+/// # Examples
+/// ```
+/// use floodgate::{Algorithm, DynamicMapping, Limiter, Rate};
+/// use std::time::Duration;
+///
+/// let mapping = DynamicMapping::<String, Limiter>::with_limiter(Duration::from_secs(60));
+/// let period = Duration::from_secs(10);
+/// mapping.add_limiter("internal".into(), Algorithm::Sliding, 2, period);
+/// mapping.add_key("public".into(), 2, period);
+///
+/// assert_eq!(mapping.trigger_key("internal"), None);
+/// assert_eq!(mapping.trigger_key("public"), None);
+/// assert_eq!(mapping.algorithm("internal"), Some(Algorithm::Sliding));
+/// assert_eq!(mapping.algorithm("public"), Some(Algorithm::Jumping));
+///
+/// let limiter = Rate::per_second(5).limiter(Algorithm::Gcra);
+/// assert_eq!(limiter.algorithm(), Algorithm::Gcra);
+/// ```
+// a `JumpingWindow` is much larger than the others, but is what most keys use, so boxing it
+// would cost more than it saves.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub enum Limiter {
+    Jumping(JumpingWindow),
+    Sliding(SlidingWindow),
+    SlidingCounter(SlidingCounter),
+    TokenBucket(TokenBucket),
+    Gcra(Gcra),
+}
+
+/// Call `$method` on whichever limiter `$limiter` holds.
+macro_rules! dispatch {
+    ($limiter:expr, $method:ident($($arg:expr),*)) => {
+        match $limiter {
+            Limiter::Jumping(limiter) => RateLimiter::$method(limiter $(, $arg)*),
+            Limiter::Sliding(limiter) => RateLimiter::$method(limiter $(, $arg)*),
+            Limiter::SlidingCounter(limiter) => RateLimiter::$method(limiter $(, $arg)*),
+            Limiter::TokenBucket(limiter) => RateLimiter::$method(limiter $(, $arg)*),
+            Limiter::Gcra(limiter) => RateLimiter::$method(limiter $(, $arg)*),
+        }
+    };
+}
+
+impl Limiter {
+    /// Create a new Limiter.
+    ///
+    /// # Arguments
+    /// * `algorithm` - The algorithm to use.
+    /// * `capacity` - How many triggers can occur per period.
+    /// * `period` - How long the period is.
+    pub fn new(algorithm: Algorithm, capacity: u64, period: Duration) -> Self {
+        algorithm.limiter(capacity, period)
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        match self {
+            Self::Jumping(_) => Algorithm::Jumping,
+            Self::Sliding(_) => Algorithm::Sliding,
+            Self::SlidingCounter(_) => Algorithm::SlidingCounter,
+            Self::TokenBucket(_) => Algorithm::TokenBucket,
+            Self::Gcra(_) => Algorithm::Gcra,
+        }
+    }
+}
+
+impl Rate {
+    /// A new limiter allowing this rate with `algorithm`.
+    pub fn limiter(self, algorithm: Algorithm) -> Limiter {
+        algorithm.limiter(self.capacity(), self.period())
+    }
+}
+
+impl RateLimiter for Limiter {
+    fn new(capacity: u64, period: Duration) -> Self {
+        Algorithm::Jumping.limiter(capacity, period)
+    }
+
+    fn capacity(&self) -> u64 {
+        dispatch!(self, capacity())
+    }
+
+    fn period(&self) -> Duration {
+        dispatch!(self, period())
+    }
+
+    fn set_rate(&mut self, capacity: u64, period: Duration) {
+        dispatch!(self, set_rate(capacity, period))
+    }
+
+    fn tokens(&mut self, now: Option<Instant>) -> u64 {
+        dispatch!(self, tokens(now))
+    }
+
+    fn next_reset(&mut self, now: Option<Instant>) -> Duration {
+        dispatch!(self, next_reset(now))
+    }
+
+    fn retry_after(&mut self, now: Option<Instant>) -> Option<Duration> {
+        dispatch!(self, retry_after(now))
+    }
+
+    fn retry_at(&mut self, now: Option<Instant>) -> Option<Instant> {
+        dispatch!(self, retry_at(now))
+    }
+
+    fn wait_for(&mut self, n: u64, now: Option<Instant>) -> Option<Duration> {
+        dispatch!(self, wait_for(n, now))
+    }
+
+    fn can_trigger(&mut self, now: Option<Instant>) -> bool {
+        dispatch!(self, can_trigger(now))
+    }
+
+    fn trigger(&mut self, now: Option<Instant>) -> Option<Duration> {
+        dispatch!(self, trigger(now))
+    }
+
+    fn trigger_n(&mut self, cost: u64, now: Option<Instant>) -> Result<(), Duration> {
+        dispatch!(self, trigger_n(cost, now))
+    }
+
+    fn trigger_info(&mut self, now: Option<Instant>) -> RateLimitInfo {
+        dispatch!(self, trigger_info(now))
+    }
+
+    fn reset(&mut self, now: Option<Instant>) {
+        dispatch!(self, reset(now))
+    }
+
+    fn refund(&mut self, n: u64, epoch: u64, now: Option<Instant>) {
+        dispatch!(self, refund(n, epoch, now))
+    }
+
+    fn epoch(&mut self, now: Option<Instant>) -> u64 {
+        dispatch!(self, epoch(now))
+    }
+
+    fn utilization(&mut self, now: Option<Instant>) -> f64 {
+        dispatch!(self, utilization(now))
+    }
+
+    fn is_idle(&mut self, now: Option<Instant>) -> bool {
+        dispatch!(self, is_idle(now))
+    }
+}
@@ -0,0 +1,73 @@
+/// Log a message through any logging macro, at most `capacity` times per period for each key
+/// of a `floodgate::FixedMapping`.
+///
+/// Messages over the limit are dropped. The next message that is allowed through is preceded
+/// by a single `suppressed N similar messages` line, so a burst of errors is logged as a
+/// handful of lines per period.
+///
+/// The logging macro is given as its name followed by `!`, and the remaining arguments are
+/// passed to it unchanged.
+///
+/// # Examples
+/// ```
+/// use floodgate::{log_limited, FixedMapping};
+/// use std::time::Duration;
+///
+/// let limiter = FixedMapping::new(1, Duration::from_secs(10));
+///
+/// for attempt in 0..100 {
+///     // only the first attempt is printed.
+///     log_limited!(limiter, "db-error", eprintln!, "query failed on attempt {}", attempt);
+/// }
+/// assert_eq!(limiter.rejections(&"db-error"), 99);
+/// ```
+#[macro_export]
+macro_rules! log_limited {
+    ($limiter:expr, $key:expr, $($log:ident)::+!, $($arg:tt)+) => {
+        match $limiter.trigger_counted(&$key) {
+            Ok(0) => $($log)::+!($($arg)+),
+            Ok(suppressed) => {
+                $($log)::+!("suppressed {} similar messages", suppressed);
+                $($log)::+!($($arg)+);
+            }
+            Err(_) => {}
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, time::Duration};
+
+    use crate::FixedMapping;
+
+    #[test]
+    fn reports_suppressed_messages() {
+        let lines = RefCell::new(Vec::new());
+        macro_rules! record {
+            ($($arg:tt)+) => {
+                lines.borrow_mut().push(format!($($arg)+))
+            };
+        }
+
+        let period = Duration::from_millis(20);
+        let limiter = FixedMapping::new(2, period);
+        for i in 0..5 {
+            log_limited!(limiter, "a", record!, "a {}", i);
+        }
+        log_limited!(limiter, "b", record!, "b");
+        std::thread::sleep(period);
+        log_limited!(limiter, "a", record!, "a again");
+
+        assert_eq!(
+            lines.into_inner(),
+            [
+                "a 0",
+                "a 1",
+                "b",
+                "suppressed 3 similar messages",
+                "a again"
+            ]
+        );
+    }
+}
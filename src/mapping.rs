@@ -1,84 +1,990 @@
 use std::{
-    hash::Hash,
+    borrow::Borrow,
+    collections::{hash_map::RandomState, VecDeque},
+    hash::{BuildHasher, Hash},
     sync::{
-        atomic::{AtomicBool, Ordering},
-        RwLock,
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        MutexGuard, RwLock,
     },
-    time::{Duration, Instant},
+    time::Duration,
 };
 
-use dashmap::{mapref::one::RefMut, DashMap};
+use dashmap::{
+    mapref::{
+        entry::Entry,
+        one::{MappedRefMut, RefMut},
+    },
+    DashMap,
+};
 
-use crate::jumping_window::JumpingWindow;
+use crate::clock::Instant;
+use crate::{
+    eviction::{Candidate, EvictionQueue},
+    stats::Counters,
+    sweep::Sweep,
+    RateLimitInfo, RateLimiter, UtilizationStats,
+};
 
-pub(crate) struct Mapping<K: Eq + Hash + Clone + Send + Sync> {
-    right: DashMap<K, JumpingWindow>,
-    left: DashMap<K, JumpingWindow>,
+pub(crate) struct Mapping<K: Eq + Hash + Clone + Send + Sync, L, S = RandomState> {
+    right: DashMap<K, Slot<L>, S>,
+    left: DashMap<K, Slot<L>, S>,
     is_right_current: AtomicBool,
     last_cycle: RwLock<Instant>,
-    cycle_period: Duration,
+    cycle_period: AtomicU64,
+    idle_periods: AtomicU32,
+    /// Set once a bound is set with `set_max_keys`.
+    evictions: Option<EvictionQueue<K>>,
+    forced_evictions: AtomicU64,
+    counters: Option<Counters>,
+    #[allow(clippy::type_complexity)]
+    on_evict: Option<Box<dyn Fn(&K) + Send + Sync>>,
+    make_limiter: Option<Box<dyn Fn() -> L + Send + Sync>>,
+    /// Constructors for the limiters of particular keys, used instead of `make_limiter`.
+    templates: DashMap<K, fn(u64, Duration) -> L, S>,
+    /// Set once a template is added, so that new keys only look for one if there may be one.
+    has_templates: AtomicBool,
+    #[allow(clippy::type_complexity)]
+    configure: Option<Box<dyn Fn(&mut L) + Send + Sync>>,
+    sweep: Option<Sweep<K>>,
+    /// Whether the limiters may be triggered through a shared reference. See
+    /// `RateLimiter::shares_triggers`.
+    shared_triggers: bool,
+    /// While the mapping is paused, how to pause the limiters of new keys.
+    #[allow(clippy::type_complexity)]
+    pause: RwLock<Option<fn(&mut L, Instant)>>,
+    /// Set while the mapping is paused, so that new keys only look at `pause` if they may
+    /// have to.
+    paused: AtomicBool,
+    /// The id of the next slot, to tell slots of the same key apart.
+    next_id: AtomicU64,
 }
 
-impl<K: Eq + Hash + Clone + Send + Sync> Mapping<K> {
-    pub(crate) fn new(cycle_period: Duration) -> Self {
+/// One key of `Mapping::trigger_all`, to be charged `cost` tokens at `capacity` triggers per
+/// `period`.
+pub(crate) struct Charge<'a, K> {
+    pub(crate) key: &'a K,
+    pub(crate) cost: u64,
+    pub(crate) capacity: u64,
+    pub(crate) period: Duration,
+}
+
+/// How many times `Mapping::trigger_all` tries to lock a batch whose keys keep being taken
+/// out of the current map before giving up.
+const TRIGGER_ALL_ATTEMPTS: usize = 16;
+
+/// Why `Mapping::trigger_all` didn't charge a batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BatchError {
+    /// The charge at the index was ratelimited, with its retry-after.
+    Limited(usize, Duration),
+    /// The batch had more keys than the mapping's bound, which is given.
+    TooManyKeys(usize),
+    /// The batch's keys kept being taken out of the current map by concurrent changes.
+    Contended,
+}
+
+/// A stored limiter, with the last time it was used.
+pub(crate) struct Slot<L> {
+    limiter: L,
+    created: Instant,
+    /// How long after `created` the slot was last used, in nanoseconds. Atomic, so that shared
+    /// triggers can update it without exclusive access.
+    used: AtomicU64,
+    /// Which slot this is, to tell it apart from other slots of the same key in the sweep and
+    /// the eviction queue.
+    id: u64,
+    /// Set once the limiter needs exclusive access to be triggered, so that its shared trigger
+    /// isn't tried again.
+    exclusive: AtomicBool,
+}
+
+impl<L> Slot<L> {
+    fn new(limiter: L, now: Instant, id: u64) -> Self {
         Self {
-            left: DashMap::new(),
-            right: DashMap::new(),
+            limiter,
+            created: now,
+            used: AtomicU64::new(0),
+            id,
+            exclusive: AtomicBool::new(false),
+        }
+    }
+
+    fn last_used(&self) -> Instant {
+        let used = Duration::from_nanos(self.used.load(Ordering::Relaxed));
+        self.created.checked_add(used).unwrap_or(self.created)
+    }
+
+    /// Mark the slot as used at `now`, unless it has been used since.
+    fn mark_used(&self, now: Instant) {
+        let used = nanos(now.saturating_duration_since(self.created));
+        self.used.fetch_max(used, Ordering::Relaxed);
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync, L: RateLimiter> Mapping<K, L> {
+    pub(crate) fn new(cycle_period: Duration, now: Instant) -> Self {
+        Self::with_capacity_and_hasher(cycle_period, now, 0, RandomState::new())
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync, L: RateLimiter, S: BuildHasher + Clone> Mapping<K, L, S> {
+    /// Create a new Mapping whose maps hash keys with `hasher`, and have room for `capacity`
+    /// keys each before reallocating.
+    pub(crate) fn with_capacity_and_hasher(
+        cycle_period: Duration,
+        now: Instant,
+        capacity: usize,
+        hasher: S,
+    ) -> Self {
+        Self {
+            left: DashMap::with_capacity_and_hasher(capacity, hasher.clone()),
+            right: DashMap::with_capacity_and_hasher(capacity, hasher.clone()),
             is_right_current: AtomicBool::new(true),
-            last_cycle: RwLock::new(Instant::now()),
-            cycle_period: cycle_period.mul_f32(0.95),
+            last_cycle: RwLock::new(now),
+            cycle_period: AtomicU64::new(0),
+            idle_periods: AtomicU32::new(0),
+            evictions: None,
+            forced_evictions: AtomicU64::new(0),
+            counters: None,
+            on_evict: None,
+            make_limiter: None,
+            templates: DashMap::with_hasher(hasher),
+            has_templates: AtomicBool::new(false),
+            configure: None,
+            sweep: None,
+            shared_triggers: L::shares_triggers(),
+            pause: RwLock::new(None),
+            paused: AtomicBool::new(false),
+            next_id: AtomicU64::new(1),
+        }
+        .with_cycle_period(cycle_period)
+    }
+
+    /// Create limiters with `make_limiter` instead of `RateLimiter::new`.
+    pub(crate) fn with_factory(
+        mut self,
+        make_limiter: impl Fn() -> L + Send + Sync + 'static,
+    ) -> Self {
+        self.make_limiter = Some(Box::new(make_limiter));
+        self
+    }
+
+    /// Create the limiters of `key` with `make` from now on. A limiter it already has is kept.
+    pub(crate) fn set_template(&self, key: K, make: fn(u64, Duration) -> L) {
+        self.has_templates.store(true, Ordering::Relaxed);
+        self.templates.insert(key, make);
+    }
+
+    /// Stop creating the limiters of `key` with its own constructor.
+    pub(crate) fn remove_template<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.has_templates.load(Ordering::Relaxed) {
+            self.templates.remove(key);
+        }
+    }
+
+    /// Run `configure` on every new limiter, before it is stored, after anything added before.
+    pub(crate) fn add_configure(&mut self, configure: impl Fn(&mut L) + Send + Sync + 'static)
+    where
+        L: 'static,
+    {
+        self.configure = Some(match self.configure.take() {
+            Some(previous) => Box::new(move |limiter: &mut L| {
+                previous(limiter);
+                configure(limiter);
+            }),
+            None => Box::new(configure),
+        });
+    }
+
+    fn with_cycle_period(self, cycle_period: Duration) -> Self {
+        self.set_cycle_period(cycle_period);
+        self
+    }
+
+    pub(crate) fn set_cycle_period(&self, cycle_period: Duration) {
+        self.cycle_period
+            .store(nanos(cycle_period.mul_f32(0.95)), Ordering::Relaxed);
+    }
+
+    /// Keep limiters until they have been unused for `periods` times their own period, rather
+    /// than dropping them as soon as they are full.
+    pub(crate) fn set_idle_periods(&self, periods: u32) {
+        self.idle_periods.store(periods, Ordering::Relaxed);
+    }
+
+    pub(crate) fn idle_periods(&self) -> u32 {
+        self.idle_periods.load(Ordering::Relaxed)
+    }
+
+    /// Bound the number of stored limiters. See `Mapping::evict_for_insert`.
+    pub(crate) fn set_max_keys(&mut self, max_keys: usize) {
+        self.evictions = Some(EvictionQueue::new(max_keys));
+    }
+
+    /// Clean up incrementally, visiting at most `batch_size` keys at a time. See `Sweep`.
+    pub(crate) fn set_sweep(&mut self, batch_size: usize) {
+        self.sweep = Some(Sweep::new(batch_size));
+    }
+
+    /// How many limiters were dropped to stay under the bound set with `set_max_keys`.
+    pub(crate) fn forced_evictions(&self) -> u64 {
+        self.forced_evictions.load(Ordering::Relaxed)
+    }
+
+    /// Start keeping the counters behind `floodgate::MappingStats`.
+    pub(crate) fn enable_stats(&mut self) {
+        self.counters.get_or_insert_with(Counters::default);
+    }
+
+    /// Keep the counters behind `floodgate::MappingStats`, reporting them to `metrics` labeled
+    /// with `name`.
+    #[cfg(feature = "metrics")]
+    pub(crate) fn enable_metrics(&mut self, name: &str) {
+        self.counters = Some(Counters::with_metrics(name));
+    }
+
+    /// The mapping's counters, if stats are enabled.
+    pub(crate) fn counters(&self) -> Option<&Counters> {
+        self.counters.as_ref()
+    }
+
+    /// Call `on_evict` with every key whose limiter is dropped by cycling, cleaning up, or
+    /// `evict_for_insert`. It is called while holding a lock on the map, so it mustn't use the
+    /// mapping.
+    #[cfg(feature = "stream")]
+    pub(crate) fn set_on_evict(&mut self, on_evict: impl Fn(&K) + Send + Sync + 'static) {
+        self.on_evict = Some(Box::new(on_evict));
+    }
+
+    fn evicted(&self, key: &K) {
+        if let Some(on_evict) = &self.on_evict {
+            on_evict(key);
         }
     }
 
-    pub(crate) fn get_bucket(
+    /// The locked eviction queue of a bounded mapping, with the bound.
+    fn lock_evictions(&self) -> Option<(MutexGuard<'_, VecDeque<Candidate<K>>>, usize)> {
+        let evictions = self.evictions.as_ref()?;
+        Some((evictions.lock(), evictions.max_keys()))
+    }
+
+    /// Make room for a new limiter by dropping the least recently used ones, even if they are
+    /// still on cooldown. `queue` is the locked eviction queue, which must be held until the
+    /// new limiter is stored. See `EvictionQueue`.
+    ///
+    /// Keys for which `protected` returns true are passed over, and stay at the front of the
+    /// queue.
+    fn evict_for_insert(
+        &self,
+        queue: &mut VecDeque<Candidate<K>>,
+        max_keys: usize,
+        protected: impl Fn(&K) -> bool,
+    ) {
+        // entries of keys dropped by cycling or cleaning up are left in the queue, so once it
+        // is much longer than the bound, they are cleared out. This is done at most once every
+        // `max_keys` insertions, so it costs a constant amount per insertion on average.
+        if queue.len() > max_keys.saturating_mul(2) {
+            queue.retain(|candidate| {
+                [&self.right, &self.left].iter().any(|map| {
+                    map.get(&candidate.key)
+                        .is_some_and(|slot| slot.id == candidate.id)
+                })
+            });
+        }
+
+        let mut passed = Vec::new();
+        while self.len() >= max_keys {
+            let Some(candidate) = queue.pop_front() else {
+                break;
+            };
+            if protected(&candidate.key) {
+                passed.push(candidate);
+                continue;
+            }
+            let mut used = None;
+            let mut evicted = false;
+            for map in [&self.right, &self.left] {
+                evicted |= map
+                    .remove_if(&candidate.key, |_, slot| {
+                        // the key's limiter was replaced since it was queued.
+                        if slot.id != candidate.id {
+                            return false;
+                        }
+                        let last_used = slot.last_used();
+                        if last_used > candidate.used {
+                            used = Some(last_used);
+                            return false;
+                        }
+                        true
+                    })
+                    .is_some();
+            }
+            if evicted {
+                self.forced_evictions.fetch_add(1, Ordering::Relaxed);
+                self.evicted(&candidate.key);
+            } else if let Some(used) = used {
+                queue.push_back(Candidate { used, ..candidate });
+            }
+        }
+        for candidate in passed.into_iter().rev() {
+            queue.push_front(candidate);
+        }
+    }
+
+    /// Whether `slot` can be dropped at `now`: its limiter is idle, and hasn't been used for
+    /// long enough.
+    fn is_evictable(&self, slot: &mut Slot<L>, now: Instant) -> bool {
+        let idle = now.saturating_duration_since(slot.last_used());
+        let ttl = slot.limiter.period().saturating_mul(self.idle_periods());
+        idle >= ttl && slot.limiter.is_idle(Some(now))
+    }
+
+    /// Get the limiter for `key`, creating it if needed. New limiters start their first window
+    /// at `now`.
+    pub(crate) fn get_bucket<Q>(
+        &self,
+        key: &Q,
+        capacity: u64,
+        period: Duration,
+        now: Instant,
+    ) -> MappedRefMut<'_, K, Slot<L>, L, S>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.get_bucket_protecting(key, capacity, period, now, |_| false)
+    }
+
+    /// Like `get_bucket`, but never evicts a key for which `protected` returns true to make
+    /// room for `key`.
+    fn get_bucket_protecting<Q>(
         &self,
-        key: &K,
+        key: &Q,
         capacity: u64,
         period: Duration,
-    ) -> RefMut<K, JumpingWindow> {
+        now: Instant,
+        protected: impl Fn(&K) -> bool,
+    ) -> MappedRefMut<'_, K, Slot<L>, L, S>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        // without threads there is no background cycler, so cycle lazily instead.
+        #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+        self.cycle(now);
+
         let (current, previous) = match self.is_right_current.load(Ordering::Relaxed) {
             true => (&self.right, &self.left),
             false => (&self.left, &self.right),
         };
 
-        if let Some(mut bucket) = current.get_mut(key) {
-            if bucket.capacity != capacity || bucket.period != period {
-                bucket.capacity = capacity;
-                bucket.period = period;
+        if let Some(slot) = current.get_mut(key) {
+            return touch(slot, capacity, period, now);
+        }
+
+        // a bounded mapping makes room and inserts under the eviction queue's lock, moves from
+        // the previous map included, so that concurrent insertions can't overshoot the bound.
+        let mut evictions = self.lock_evictions();
+        // new keys are inserted through the entry, so that they are only hashed and locked once
+        // more, and a key inserted by another thread in the meantime isn't replaced.
+        let slot = match previous.remove(key) {
+            Some((key, slot)) => current.entry(key).or_insert(slot),
+            None => {
+                if let Some((queue, max_keys)) = &mut evictions {
+                    self.evict_for_insert(queue, *max_keys, protected);
+                }
+                match current.entry(key.to_owned()) {
+                    Entry::Occupied(entry) => entry.into_ref(),
+                    Entry::Vacant(entry) => {
+                        let slot = self.new_slot(entry.key(), capacity, period, now);
+                        self.track(entry.key(), &slot, now);
+                        if let Some((queue, _)) = &mut evictions {
+                            queue.push_back(Candidate {
+                                key: entry.key().clone(),
+                                id: slot.id,
+                                used: now,
+                            });
+                        }
+                        entry.insert(slot)
+                    }
+                }
+            }
+        };
+        drop(evictions);
+        touch(slot, capacity, period, now)
+    }
+
+    /// Trigger the limiter of every one of `charges` with its cost, or none of them, returning
+    /// the index of the charge that couldn't be made with its retry-after. The keys are held
+    /// locked together, so concurrent triggers of them see either every charge or none.
+    ///
+    /// The shards holding the keys are locked in ascending order, which keeps concurrent
+    /// batches from deadlocking. Nothing else holds more than one shard of a map at once.
+    ///
+    /// `charges` must hold each key at most once. A bounded mapping rejects a batch of more
+    /// keys than it can store with `BatchError::TooManyKeys`. The batch's keys aren't evicted to
+    /// make room for each other, but concurrent insertions, removals and cycles can still
+    /// take them out before they are locked, in which case the batch is tried again, up to
+    /// `TRIGGER_ALL_ATTEMPTS` times before giving up with `BatchError::Contended`.
+    pub(crate) fn trigger_all(
+        &self,
+        charges: &[Charge<'_, K>],
+        now: Instant,
+    ) -> Result<(), BatchError> {
+        if let Some(evictions) = &self.evictions {
+            if charges.len() > evictions.max_keys() {
+                return Err(BatchError::TooManyKeys(evictions.max_keys()));
+            }
+        }
+        let in_batch = |key: &K| charges.iter().any(|charge| charge.key == key);
+
+        for _ in 0..TRIGGER_ALL_ATTEMPTS {
+            let is_right_current = self.is_right_current.load(Ordering::Relaxed);
+            // bring every key into the current map at its rate first, as a trigger would.
+            for charge in charges {
+                drop(self.get_bucket_protecting(
+                    charge.key,
+                    charge.capacity,
+                    charge.period,
+                    now,
+                    in_batch,
+                ));
+            }
+
+            let current = match is_right_current {
+                true => &self.right,
+                false => &self.left,
+            };
+            let hashed: Vec<usize> = charges
+                .iter()
+                .map(|charge| current.determine_map(charge.key))
+                .collect();
+            let mut shards = hashed.clone();
+            shards.sort_unstable();
+            shards.dedup();
+            let mut guards: Vec<_> = shards
+                .iter()
+                .map(|&shard| current.shards()[shard].write())
+                .collect();
+            // a cycle in the meantime may have moved some of the keys to the other map.
+            if self.is_right_current.load(Ordering::Relaxed) != is_right_current {
+                continue;
+            }
+            let positions: Vec<usize> = hashed
+                .iter()
+                .map(|shard| shards.binary_search(shard).unwrap())
+                .collect();
+
+            // keys removed or evicted since they were brought in are created again, which
+            // can't be done while holding their shards, since it may have to evict others.
+            let missing = charges
+                .iter()
+                .zip(&positions)
+                .any(|(charge, &position)| !guards[position].contains_key(charge.key));
+            if missing {
+                continue;
+            }
+            macro_rules! limiter {
+                ($i:expr) => {
+                    guards[positions[$i]]
+                        .get_mut(charges[$i].key)
+                        .unwrap()
+                        .get_mut()
+                        .limiter
+                };
+            }
+
+            // the charge expected to fail is made first, so that a rejected batch usually
+            // leaves the other limiters untouched. The others are only given back if a limiter
+            // rejects a charge it expected to allow, such as with load shedding.
+            let mut order: Vec<usize> = (0..charges.len()).collect();
+            let failing = order.iter().position(|&i| {
+                limiter!(i).wait_for(charges[i].cost, Some(now)) != Some(Duration::ZERO)
+            });
+            if let Some(failing) = failing {
+                order[..=failing].rotate_right(1);
+            }
+            for (made, &i) in order.iter().enumerate() {
+                if let Err(retry_after) = limiter!(i).trigger_n(charges[i].cost, Some(now)) {
+                    for &j in &order[..made] {
+                        let epoch = limiter!(j).epoch(Some(now));
+                        limiter!(j).refund(charges[j].cost, epoch, Some(now));
+                    }
+                    return Err(BatchError::Limited(i, retry_after));
+                }
+            }
+            return Ok(());
+        }
+        Err(BatchError::Contended)
+    }
+
+    /// Trigger the limiter of `key` through a shared reference, so that concurrent triggers
+    /// of it don't wait for each other. Returns `None` if that isn't possible, because the
+    /// key has no limiter in the current map yet, it has another rate, or the limiter needs
+    /// exclusive access; `get_bucket` has to be used instead.
+    pub(crate) fn trigger_shared<Q>(
+        &self,
+        key: &Q,
+        capacity: u64,
+        period: Duration,
+        now: Instant,
+    ) -> Option<Option<Duration>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if !self.shared_triggers {
+            return None;
+        }
+        #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+        self.cycle(now);
+
+        let current = match self.is_right_current.load(Ordering::Relaxed) {
+            true => &self.right,
+            false => &self.left,
+        };
+        let slot = current.get(key)?;
+        if slot.exclusive.load(Ordering::Relaxed)
+            || slot.limiter.capacity() != capacity
+            || slot.limiter.period() != period
+        {
+            return None;
+        }
+        let Some(retry_after) = slot.limiter.trigger_shared(Some(now)) else {
+            slot.exclusive.store(true, Ordering::Relaxed);
+            return None;
+        };
+        slot.mark_used(now);
+        Some(retry_after)
+    }
+
+    /// Queue the new `slot` of `key` to be visited by the sweep, if there is one.
+    fn track(&self, key: &K, slot: &Slot<L>, now: Instant) {
+        if let Some(sweep) = &self.sweep {
+            let ttl = slot
+                .limiter
+                .period()
+                .saturating_mul(self.idle_periods().max(1));
+            sweep.track(key.clone(), slot.id, now.checked_add(ttl).unwrap_or(now));
+        }
+    }
+
+    /// When `slot` could next be evictable, at the earliest.
+    fn due(&self, slot: &mut Slot<L>, now: Instant) -> Instant {
+        let ttl = slot.limiter.period().saturating_mul(self.idle_periods());
+        let idle = slot.last_used().checked_add(ttl).unwrap_or(now);
+        let full = now
+            .checked_add(slot.limiter.next_reset(Some(now)))
+            .unwrap_or(now);
+        idle.max(full)
+    }
+
+    /// Visit the keys the sweep has due at `now`, a batch at a time, dropping the limiters
+    /// that `cleanup` would and queueing the others again. Each key is visited under its own
+    /// lock, so triggers are never held up for more than one key's check.
+    fn sweep(&self, sweep: &Sweep<K>, now: Instant) {
+        #[cfg(feature = "tracing")]
+        let (started, mut scanned) = (trace_start(), 0);
+        let mut evicted = 0;
+        // queued again only once the sweep is done, so that it always ends.
+        let mut requeue = Vec::new();
+        loop {
+            let due = sweep.take_due(now);
+            if due.is_empty() {
+                break;
+            }
+            #[cfg(feature = "tracing")]
+            {
+                scanned += due.len();
+            }
+            for (key, id) in due {
+                let mut next = None;
+                for map in [&self.right, &self.left] {
+                    map.remove_if_mut(&key, |key, slot| {
+                        // the key's limiter was replaced since it was queued.
+                        if slot.id != id {
+                            return false;
+                        }
+                        if self.is_evictable(slot, now) {
+                            evicted += 1;
+                            self.evicted(key);
+                            return true;
+                        }
+                        next = Some(self.due(slot, now));
+                        false
+                    });
+                }
+                if let Some(next) = next {
+                    requeue.push((key, id, next));
+                }
             }
-            return bucket;
         }
+        for (key, id, next) in requeue {
+            sweep.track(key, id, next);
+        }
+        if let Some(counters) = &self.counters {
+            counters.evicted(evicted);
+            #[cfg(feature = "metrics")]
+            counters.set_keys(self.len());
+        }
+        #[cfg(feature = "tracing")]
+        trace_cleanup(started, scanned, evicted);
+    }
 
-        if let Some((key2, bucket)) = previous.remove(key) {
-            current.insert(key2, bucket);
-        } else {
-            let bucket = JumpingWindow::new(capacity, period);
-            current.insert(key.clone(), bucket);
+    /// A slot with a new limiter, whose first window starts at `now`.
+    fn new_slot(&self, key: &K, capacity: u64, period: Duration, now: Instant) -> Slot<L> {
+        let limiter = self.new_limiter(key, capacity, period, now);
+        if let Some(counters) = &self.counters {
+            counters.key_created();
         }
+        Slot::new(limiter, now, self.next_id.fetch_add(1, Ordering::Relaxed))
+    }
 
-        self.get_bucket(key, capacity, period)
+    /// The limiter `key` would be given if it was created at `now`, without storing it.
+    pub(crate) fn new_limiter<Q>(&self, key: &Q, capacity: u64, period: Duration, now: Instant) -> L
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let template = match self.has_templates.load(Ordering::Relaxed) {
+            true => self.templates.get(key).map(|make| *make),
+            false => None,
+        };
+        let mut limiter = match (template, &self.make_limiter) {
+            (Some(make), _) => make(capacity, period),
+            (None, Some(make_limiter)) => make_limiter(),
+            (None, None) => L::new(capacity, period),
+        };
+        if let Some(configure) = &self.configure {
+            configure(&mut limiter);
+        }
+        limiter.reset(Some(now));
+        if self.paused.load(Ordering::Relaxed) {
+            if let Some(pause) = *self.pause.read().unwrap() {
+                pause(&mut limiter, now);
+            }
+        }
+        limiter
     }
 
-    pub(crate) fn cycle(&self, now: Option<Instant>) -> bool {
-        let now = now.unwrap_or_else(Instant::now);
-        if now.duration_since(*self.last_cycle.read().unwrap()) < self.cycle_period {
+    pub(crate) fn cycle(&self, now: Instant) -> bool {
+        let cycle_period = Duration::from_nanos(self.cycle_period.load(Ordering::Relaxed));
+        if now.duration_since(*self.last_cycle.read().unwrap()) < cycle_period {
             return false;
         }
 
+        // sweeping keeps every key in the current map, so there is nothing to swap.
+        if let Some(sweep) = &self.sweep {
+            self.sweep(sweep, now);
+            *self.last_cycle.write().unwrap() = now;
+            return true;
+        }
+
         let is_right_current = !self.is_right_current.load(Ordering::Relaxed);
         self.is_right_current
             .store(is_right_current, Ordering::Relaxed);
 
-        {
-            match is_right_current {
-                true => &self.left,
-                false => &self.right,
+        // the map becoming current holds the keys that went unused for a whole cycle. Those
+        // that are still on cooldown, or haven't been idle for long enough, are kept.
+        let stale = match is_right_current {
+            true => &self.right,
+            false => &self.left,
+        };
+        #[cfg(feature = "tracing")]
+        let (started, mut scanned) = (trace_start(), 0);
+        let mut evicted = 0;
+        stale.retain(|key, slot| {
+            #[cfg(feature = "tracing")]
+            {
+                scanned += 1;
             }
+            let evict = self.is_evictable(slot, now);
+            if evict {
+                evicted += 1;
+                self.evicted(key);
+            }
+            !evict
+        });
+        if let Some(counters) = &self.counters {
+            counters.evicted(evicted);
+            #[cfg(feature = "metrics")]
+            counters.set_keys(self.len());
         }
-        .clear();
+        #[cfg(feature = "tracing")]
+        trace_cleanup(started, scanned, evicted);
 
         *self.last_cycle.write().unwrap() = now;
 
         true
     }
+
+    /// How many keys currently have a limiter.
+    pub(crate) fn len(&self) -> usize {
+        self.right.len() + self.left.len()
+    }
+
+    /// Whether `key` has a limiter, without creating one.
+    pub(crate) fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.right.contains_key(key) || self.left.contains_key(key)
+    }
+
+    /// Run `f` on the limiter for `key` if it has one, without creating it.
+    pub(crate) fn with_existing<Q, T>(&self, key: &Q, f: impl FnOnce(&mut L) -> T) -> Option<T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if let Some(mut slot) = self.right.get_mut(key) {
+            return Some(f(&mut slot.limiter));
+        }
+        self.left.get_mut(key).map(|mut slot| f(&mut slot.limiter))
+    }
+
+    /// Run `f` on every stored limiter.
+    pub(crate) fn for_each_mut(&self, mut f: impl FnMut(&K, &mut L)) {
+        for mut entry in self.right.iter_mut().chain(self.left.iter_mut()) {
+            let (key, slot) = entry.pair_mut();
+            f(key, &mut slot.limiter);
+        }
+    }
+
+    /// Pause every stored limiter with `pause` at `now`, and the limiters of new keys as they
+    /// are created, until `Mapping::resume`. Returns `false` if the mapping was already paused.
+    pub(crate) fn pause(&self, pause: fn(&mut L, Instant), now: Instant) -> bool {
+        {
+            let mut current = self.pause.write().unwrap();
+            if current.is_some() {
+                return false;
+            }
+            *current = Some(pause);
+            self.paused.store(true, Ordering::Relaxed);
+        }
+        self.for_each_mut(|_, limiter| pause(limiter, now));
+        true
+    }
+
+    /// Resume every stored limiter with `resume` at `now`, and stop pausing new ones. Returns
+    /// `false` if the mapping wasn't paused.
+    pub(crate) fn resume(&self, resume: fn(&mut L, Instant), now: Instant) -> bool {
+        if self.pause.write().unwrap().take().is_none() {
+            return false;
+        }
+        self.paused.store(false, Ordering::Relaxed);
+        self.for_each_mut(|_, limiter| resume(limiter, now));
+        true
+    }
+
+    /// Drop every limiter for which `predicate` returns `true`, returning how many were
+    /// dropped.
+    pub(crate) fn remove_matching(&self, mut predicate: impl FnMut(&K, &mut L) -> bool) -> usize {
+        let mut removed = 0;
+        for map in [&self.right, &self.left] {
+            map.retain(|key, slot| {
+                let remove = predicate(key, &mut slot.limiter);
+                removed += remove as usize;
+                !remove
+            });
+        }
+        removed
+    }
+
+    /// The state of every limiter that isn't full, all computed at `now`. Checking a limiter
+    /// resets it if its window has expired, so expired windows are left out.
+    pub(crate) fn cooldowns(&self, now: Instant) -> Vec<(K, RateLimitInfo)> {
+        let mut cooldowns = Vec::new();
+        self.for_each_mut(|key, bucket| {
+            let info = status(bucket, now);
+            if info.remaining < info.limit {
+                cooldowns.push((key.clone(), info));
+            }
+        });
+        cooldowns
+    }
+
+    /// The mean and max utilization of every stored limiter at `now`. Like `cooldowns`, this
+    /// resets the limiters whose window has expired.
+    pub(crate) fn utilization(&self, now: Instant) -> UtilizationStats {
+        let mut stats = UtilizationStats::default();
+        let mut sum = 0.0;
+        self.for_each_mut(|_, bucket| {
+            let utilization = bucket.utilization(Some(now));
+            stats.keys += 1;
+            stats.max = stats.max.max(utilization);
+            sum += utilization;
+        });
+        if stats.keys > 0 {
+            stats.mean = sum / stats.keys as f64;
+        }
+        stats
+    }
+
+    /// The state of the limiter for `key` at `now`, if it has one. Like `cooldowns`, this
+    /// resets the limiter if its window has expired, but doesn't consume anything.
+    pub(crate) fn status<Q>(&self, key: &Q, now: Instant) -> Option<RateLimitInfo>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.with_existing(key, |bucket| status(bucket, now))
+    }
+
+    /// Drop every limiter that is full again at `now`, and has been idle for long enough,
+    /// returning how many were dropped.
+    pub(crate) fn cleanup(&self, now: Instant) -> usize {
+        self.evict_idle(now, |_| {})
+    }
+
+    /// Like `cleanup`, but returns the keys of the dropped limiters.
+    pub(crate) fn drain_expired(&self, now: Instant) -> Vec<K> {
+        let mut keys = Vec::new();
+        self.evict_idle(now, |key| keys.push(key.clone()));
+        keys
+    }
+
+    /// Drop every limiter that `cleanup` would, calling `f` with each of their keys.
+    fn evict_idle(&self, now: Instant, mut f: impl FnMut(&K)) -> usize {
+        #[cfg(feature = "tracing")]
+        let (started, mut scanned) = (trace_start(), 0);
+        let mut evicted = 0;
+        for map in [&self.right, &self.left] {
+            map.retain(|key, slot| {
+                #[cfg(feature = "tracing")]
+                {
+                    scanned += 1;
+                }
+                let evict = self.is_evictable(slot, now);
+                if evict {
+                    evicted += 1;
+                    self.evicted(key);
+                    f(key);
+                }
+                !evict
+            });
+        }
+        if let Some(counters) = &self.counters {
+            counters.evicted(evicted);
+            #[cfg(feature = "metrics")]
+            counters.set_keys(self.len());
+        }
+        #[cfg(feature = "tracing")]
+        trace_cleanup(started, scanned, evicted);
+        evicted
+    }
+
+    /// Drop the limiter for `key`. Returns whether it had one.
+    pub(crate) fn remove<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        // a key lives in only one of the maps, except while it is being moved between them.
+        let right = self.right.remove(key).is_some();
+        let left = self.left.remove(key).is_some();
+        right || left
+    }
+
+    /// Insert `limiter` for `key`, replacing any limiter it already had.
+    #[cfg(feature = "serde")]
+    pub(crate) fn insert(&self, key: K, limiter: L, now: Instant) {
+        let (current, previous) = match self.is_right_current.load(Ordering::Relaxed) {
+            true => (&self.right, &self.left),
+            false => (&self.left, &self.right),
+        };
+
+        let mut evictions = self.lock_evictions();
+        if previous.remove(&key).is_none() && !current.contains_key(&key) {
+            if let Some((queue, max_keys)) = &mut evictions {
+                self.evict_for_insert(queue, *max_keys, |_| false);
+            }
+        }
+        let slot = Slot::new(limiter, now, self.next_id.fetch_add(1, Ordering::Relaxed));
+        self.track(&key, &slot, now);
+        if let Some((queue, _)) = &mut evictions {
+            queue.push_back(Candidate {
+                key: key.clone(),
+                id: slot.id,
+                used: now,
+            });
+        }
+        current.insert(key, slot);
+    }
+}
+
+/// The state of `bucket` at `now`, where `allowed` is whether it could be triggered.
+fn status<L: RateLimiter>(bucket: &mut L, now: Instant) -> RateLimitInfo {
+    let remaining = bucket.tokens(Some(now));
+    RateLimitInfo {
+        allowed: remaining != 0,
+        limit: bucket.capacity(),
+        remaining,
+        retry_after: bucket.retry_after(Some(now)),
+        reset_after: bucket.next_reset(Some(now)),
+    }
+}
+
+/// Mark `slot` as used at `now`, moving its limiter to `capacity` and `period` if it had
+/// another rate.
+fn touch<'a, K: Eq + Hash, L: RateLimiter, S: BuildHasher + Clone>(
+    mut slot: RefMut<'a, K, Slot<L>, S>,
+    capacity: u64,
+    period: Duration,
+    now: Instant,
+) -> MappedRefMut<'a, K, Slot<L>, L, S> {
+    let used = nanos(now.saturating_duration_since(slot.created));
+    let last_used = slot.used.get_mut();
+    *last_used = (*last_used).max(used);
+    if slot.limiter.capacity() != capacity || slot.limiter.period() != period {
+        slot.limiter.set_rate(capacity, period);
+    }
+    slot.map(|slot| &mut slot.limiter)
+}
+
+/// `duration` in nanoseconds, saturating at `u64::MAX`.
+pub(crate) fn nanos(duration: Duration) -> u64 {
+    duration.as_nanos().min(u64::MAX as u128) as u64
+}
+
+/// When a cleanup of the limiters started, if it is traced.
+#[cfg(feature = "tracing")]
+fn trace_start() -> Option<Instant> {
+    tracing::enabled!(tracing::Level::DEBUG).then(Instant::now)
+}
+
+/// Emit a `tracing` event for a cleanup that started at `started`, looked at `scanned`
+/// limiters and dropped `evicted` of them.
+#[cfg(feature = "tracing")]
+fn trace_cleanup(started: Option<Instant>, scanned: usize, evicted: usize) {
+    if let Some(started) = started {
+        let elapsed = started.elapsed();
+        tracing::debug!(scanned, evicted, ?elapsed, "cleaned up limiters");
+    }
+}
+
+/// Serialized as a sequence of `(key, limiter)` pairs.
+#[cfg(feature = "serde")]
+impl<K, L, H> serde::Serialize for Mapping<K, L, H>
+where
+    K: Eq + Hash + Clone + Send + Sync + serde::Serialize,
+    L: serde::Serialize,
+    H: BuildHasher + Clone,
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeSeq;
+
+        // hold on to the entries, so that the length is known up front.
+        let entries: Vec<_> = self.right.iter().chain(self.left.iter()).collect();
+        let mut seq = serializer.serialize_seq(Some(entries.len()))?;
+        for entry in &entries {
+            seq.serialize_element(&(entry.key(), &entry.value().limiter))?;
+        }
+        seq.end()
+    }
 }
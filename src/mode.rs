@@ -0,0 +1,84 @@
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+use crate::stats::Counters;
+
+/// How a mapping or a `floodgate::SharedJumpingWindow` treats triggers. The mode can be
+/// switched at any time with `set_mode`, and applies to every trigger that starts afterwards.
+///
+/// # Examples
+/// ```
+/// use floodgate::{EnforcementMode, FixedMapping};
+/// use std::time::Duration;
+///
+/// let mapping = FixedMapping::new(1, Duration::from_secs(10));
+/// mapping.set_mode(EnforcementMode::DryRun);
+///
+/// assert_eq!(mapping.trigger(&1), None);
+/// assert_eq!(mapping.trigger(&1), None);
+/// assert!(!mapping.trigger_info(&1).allowed);
+/// assert_eq!(mapping.dry_run_rejections(), 2);
+///
+/// mapping.set_mode(EnforcementMode::Enforce);
+/// assert!(mapping.trigger(&1).is_some());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EnforcementMode {
+    /// Reject triggers that exceed the limit.
+    #[default]
+    Enforce,
+    /// Update the limiters as usual, but let every trigger through. Triggers that would have
+    /// been rejected are counted by `dry_run_rejections`, and `trigger_info` still reports them
+    /// as not allowed.
+    DryRun,
+    /// Let every trigger through without touching the limiters at all.
+    Bypass,
+}
+
+/// An `EnforcementMode` that can be switched atomically, along with the count of triggers
+/// that were only let through because of `EnforcementMode::DryRun`.
+#[derive(Debug, Default)]
+pub(crate) struct ModeCell {
+    mode: AtomicU8,
+    dry_run_rejections: AtomicU64,
+}
+
+impl ModeCell {
+    pub(crate) fn get(&self) -> EnforcementMode {
+        match self.mode.load(Ordering::Relaxed) {
+            0 => EnforcementMode::Enforce,
+            1 => EnforcementMode::DryRun,
+            _ => EnforcementMode::Bypass,
+        }
+    }
+
+    pub(crate) fn set(&self, mode: EnforcementMode) {
+        self.mode.store(mode as u8, Ordering::Relaxed);
+    }
+
+    /// Whether a trigger made in `mode` should be rejected, given whether the limiter
+    /// `rejected` it. The trigger is counted in `counters`, or as a dry-run rejection if it is
+    /// only let through because of `EnforcementMode::DryRun`.
+    pub(crate) fn rejects(
+        &self,
+        mode: EnforcementMode,
+        rejected: bool,
+        counters: Option<&Counters>,
+    ) -> bool {
+        if rejected && mode == EnforcementMode::DryRun {
+            self.dry_run_rejections.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        if let Some(counters) = counters {
+            counters.trigger(rejected);
+        }
+        rejected
+    }
+
+    pub(crate) fn dry_run_rejections(&self) -> u64 {
+        self.dry_run_rejections.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn reset_dry_run_rejections(&self) {
+        self.dry_run_rejections.store(0, Ordering::Relaxed);
+    }
+}
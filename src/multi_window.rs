@@ -0,0 +1,354 @@
+use std::time::Duration;
+
+use crate::{
+    clock::{self, Instant},
+    InvalidWindow, JumpingWindow, RateLimiter,
+};
+
+/// Several `floodgate::JumpingWindow`s stacked into a single policy, such as "5 per 10
+/// seconds and 30 per 10 minutes".
+///
+/// Triggers are all-or-nothing: tokens are only consumed if every window allows it. When used
+/// as a `floodgate::RateLimiter`, the capacity and period are those of the window with the
+/// longest period. To give each key of a `floodgate::FixedMapping` the full policy, use
+/// `FixedMapping::with_template`.
+#[derive(Debug, Clone)]
+pub struct MultiWindow {
+    windows: Vec<JumpingWindow>,
+}
+
+impl MultiWindow {
+    /// Create a new MultiWindow.
+    ///
+    /// # Arguments
+    /// * `limits` - The `(capacity, period)` of each window.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::MultiWindow;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = MultiWindow::new([
+    ///     (2, Duration::from_secs(10)),
+    ///     (3, Duration::from_secs(60)),
+    /// ]);
+    /// cooldown.reset(Some(now));
+    ///
+    /// assert_eq!(cooldown.trigger(Some(now)), None);
+    /// assert_eq!(cooldown.trigger(Some(now)), None);
+    /// // the burst window is exhausted.
+    /// assert_eq!(cooldown.trigger(Some(now)), Some(Duration::from_secs(10)));
+    ///
+    /// let later = now + Duration::from_secs(10);
+    /// assert_eq!(cooldown.trigger(Some(later)), None);
+    /// // now the sustained window is exhausted too.
+    /// assert_eq!(cooldown.trigger(Some(later)), Some(Duration::from_secs(50)));
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `limits` is empty, or if any capacity or period is zero. See
+    /// `MultiWindow::try_new`.
+    pub fn new(limits: impl IntoIterator<Item = (u64, Duration)>) -> Self {
+        Self::try_new(limits).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Create a new MultiWindow, returning an error if `limits` is empty, or if any capacity
+    /// or period is zero.
+    ///
+    /// # Arguments
+    /// * `limits` - The `(capacity, period)` of each window.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{InvalidWindow, MultiWindow};
+    ///
+    /// let result = MultiWindow::try_new([]);
+    /// assert_eq!(result.err(), Some(InvalidWindow::NoWindows));
+    /// ```
+    pub fn try_new(
+        limits: impl IntoIterator<Item = (u64, Duration)>,
+    ) -> Result<Self, InvalidWindow> {
+        let windows = limits
+            .into_iter()
+            .map(|(capacity, period)| JumpingWindow::try_new(capacity, period))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if windows.is_empty() {
+            return Err(InvalidWindow::NoWindows);
+        }
+
+        Ok(Self { windows })
+    }
+
+    /// The windows making up the policy, in the order they were given.
+    pub fn windows(&self) -> &[JumpingWindow] {
+        &self.windows
+    }
+
+    pub(crate) fn windows_mut(&mut self) -> &mut [JumpingWindow] {
+        &mut self.windows
+    }
+
+    /// How many triggers are left before any of the windows is exhausted.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    pub fn tokens(&mut self, now: Option<Instant>) -> u64 {
+        let now = now.unwrap_or_else(clock::now);
+        self.windows
+            .iter_mut()
+            .map(|window| window.tokens(Some(now)))
+            .min()
+            .unwrap_or_default()
+    }
+
+    /// Return the time until every window has been reset.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    pub fn next_reset(&mut self, now: Option<Instant>) -> Duration {
+        let now = now.unwrap_or_else(clock::now);
+        self.windows
+            .iter_mut()
+            .map(|window| window.next_reset(Some(now)))
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Return the longest wait among the exhausted windows, or `None` if none are exhausted.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    pub fn retry_after(&mut self, now: Option<Instant>) -> Option<Duration> {
+        let now = now.unwrap_or_else(clock::now);
+        self.windows
+            .iter_mut()
+            .filter_map(|window| window.retry_after(Some(now)))
+            .max()
+    }
+
+    /// How long until every window allows `n` triggers at once. Returns `None` if `n` exceeds
+    /// the capacity of any window, so that they never can.
+    ///
+    /// # Arguments
+    /// * `n` - How many triggers to wait for.
+    /// * `now` - Optionally specify the current time.
+    pub fn wait_for(&self, n: u64, now: Option<Instant>) -> Option<Duration> {
+        let now = now.unwrap_or_else(clock::now);
+        self.windows
+            .iter()
+            .map(|window| window.wait_for(n, Some(now)))
+            .try_fold(Duration::ZERO, |wait, window| Some(wait.max(window?)))
+    }
+
+    /// Returns whether or not every window still has available triggers.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    pub fn can_trigger(&mut self, now: Option<Instant>) -> bool {
+        self.tokens(now) != 0
+    }
+
+    /// Trigger every window, or none of them if any is exhausted.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    pub fn trigger(&mut self, now: Option<Instant>) -> Option<Duration> {
+        self.trigger_n(1, now).err()
+    }
+
+    /// Trigger every window, consuming `cost` tokens from each at once. If any window doesn't
+    /// have enough tokens, nothing is consumed and the longest wait among those windows is
+    /// returned.
+    ///
+    /// # Arguments
+    /// * `cost` - How many tokens to consume.
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::MultiWindow;
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = MultiWindow::new([
+    ///     (5, Duration::from_secs(10)),
+    ///     (8, Duration::from_secs(60)),
+    /// ]);
+    ///
+    /// assert_eq!(cooldown.trigger_n(4, None), Ok(()));
+    /// assert!(cooldown.trigger_n(4, None).is_err());
+    /// // nothing was consumed from the sustained window.
+    /// assert_eq!(cooldown.windows()[1].peek_tokens(None), 4);
+    /// ```
+    pub fn trigger_n(&mut self, cost: u64, now: Option<Instant>) -> Result<(), Duration> {
+        let now = now.unwrap_or_else(clock::now);
+
+        let mut wait = None;
+        for window in &mut self.windows {
+            if cost > window.burst_capacity() {
+                return Err(Duration::MAX);
+            }
+            if window.tokens(Some(now)) < cost {
+                // rejected by this window, which applies its penalty if it has one.
+                let retry_after = window.trigger_n(cost, Some(now)).unwrap_err();
+                wait = Some(wait.map_or(retry_after, |wait: Duration| wait.max(retry_after)));
+            }
+        }
+
+        if let Some(wait) = wait {
+            return Err(wait);
+        }
+
+        for window in &mut self.windows {
+            let _ = window.trigger_n(cost, Some(now));
+        }
+        Ok(())
+    }
+
+    /// Reset every window.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    pub fn reset(&mut self, now: Option<Instant>) {
+        let now = now.unwrap_or_else(clock::now);
+        for window in &mut self.windows {
+            window.reset(Some(now));
+        }
+    }
+
+    /// Give back `n` tokens to every window, if none of them has been reset since `epoch` was
+    /// read with `MultiWindow::epoch`. See `floodgate::JumpingWindow::refund`.
+    ///
+    /// Tokens are taken from every window at once, so they are only given back at once too:
+    /// once any window has ended, nothing is given back, even to the windows that haven't.
+    ///
+    /// # Arguments
+    /// * `n` - How many tokens to give back.
+    /// * `epoch` - The windows the tokens were taken from.
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::MultiWindow;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = MultiWindow::new([
+    ///     (1, Duration::from_secs(10)),
+    ///     (5, Duration::from_secs(60)),
+    /// ]);
+    /// cooldown.reset(Some(now));
+    ///
+    /// let epoch = cooldown.epoch(Some(now));
+    /// cooldown.trigger(Some(now));
+    /// cooldown.refund(1, epoch, Some(now));
+    /// assert_eq!(cooldown.windows()[1].peek_tokens(Some(now)), 5);
+    ///
+    /// // the burst window has been reset since, so nothing is given back.
+    /// let later = now + Duration::from_secs(10);
+    /// let epoch = cooldown.epoch(Some(now));
+    /// cooldown.trigger(Some(now));
+    /// cooldown.trigger(Some(later));
+    /// cooldown.refund(1, epoch, Some(later));
+    /// assert_eq!(cooldown.tokens(Some(later)), 0);
+    /// assert_eq!(cooldown.windows()[1].peek_tokens(Some(later)), 3);
+    /// ```
+    pub fn refund(&mut self, n: u64, epoch: u64, now: Option<Instant>) {
+        let now = now.unwrap_or_else(clock::now);
+        if self.epoch(Some(now)) != epoch {
+            return;
+        }
+        for window in &mut self.windows {
+            let epoch = window.epoch(Some(now));
+            window.refund(n, epoch, Some(now));
+        }
+    }
+
+    /// A number identifying the windows that are current at `now`, which changes whenever any
+    /// of them is reset. See `floodgate::JumpingWindow::epoch`.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    pub fn epoch(&self, now: Option<Instant>) -> u64 {
+        let now = now.unwrap_or_else(clock::now);
+        // every window's epoch only ever grows, so their sum changes whenever any of them does.
+        self.windows.iter().fold(0, |sum: u64, window| {
+            sum.wrapping_add(window.epoch(Some(now)))
+        })
+    }
+
+    /// The window with the longest period, which determines how long state must be kept.
+    fn longest(&self) -> &JumpingWindow {
+        self.windows
+            .iter()
+            .max_by_key(|window| window.period())
+            .expect("a MultiWindow always has at least one window")
+    }
+
+    fn longest_mut(&mut self) -> &mut JumpingWindow {
+        self.windows
+            .iter_mut()
+            .max_by_key(|window| window.period())
+            .expect("a MultiWindow always has at least one window")
+    }
+}
+
+impl RateLimiter for MultiWindow {
+    fn new(capacity: u64, period: Duration) -> Self {
+        Self::new([(capacity, period)])
+    }
+
+    fn capacity(&self) -> u64 {
+        self.longest().capacity()
+    }
+
+    fn period(&self) -> Duration {
+        self.longest().period()
+    }
+
+    /// Change the rate of the window with the longest period.
+    fn set_rate(&mut self, capacity: u64, period: Duration) {
+        self.longest_mut().set_rate(capacity, period);
+    }
+
+    fn tokens(&mut self, now: Option<Instant>) -> u64 {
+        self.tokens(now)
+    }
+
+    fn next_reset(&mut self, now: Option<Instant>) -> Duration {
+        self.next_reset(now)
+    }
+
+    fn retry_after(&mut self, now: Option<Instant>) -> Option<Duration> {
+        self.retry_after(now)
+    }
+
+    fn wait_for(&mut self, n: u64, now: Option<Instant>) -> Option<Duration> {
+        MultiWindow::wait_for(self, n, now)
+    }
+
+    fn can_trigger(&mut self, now: Option<Instant>) -> bool {
+        self.can_trigger(now)
+    }
+
+    fn trigger(&mut self, now: Option<Instant>) -> Option<Duration> {
+        self.trigger(now)
+    }
+
+    fn trigger_n(&mut self, cost: u64, now: Option<Instant>) -> Result<(), Duration> {
+        self.trigger_n(cost, now)
+    }
+
+    fn reset(&mut self, now: Option<Instant>) {
+        self.reset(now)
+    }
+
+    fn refund(&mut self, n: u64, epoch: u64, now: Option<Instant>) {
+        self.refund(n, epoch, now)
+    }
+
+    fn epoch(&mut self, now: Option<Instant>) -> u64 {
+        MultiWindow::epoch(self, now)
+    }
+}
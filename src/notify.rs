@@ -0,0 +1,122 @@
+use std::{future::Future, hash::Hash, time::Duration};
+
+use crate::{
+    clock::{self, Instant},
+    DynamicMapping, FixedMapping, RateLimiter, SharedJumpingWindow,
+};
+
+impl SharedJumpingWindow {
+    /// Wait until the window is reset, either explicitly with `reset` or by rolling over
+    /// into a new window.
+    ///
+    /// No reset is ever missed: the returned future completes after any reset that happens
+    /// once this method has been called, even if nothing triggers the window at the time it
+    /// rolls over. It may also complete spuriously, for example when tokens are refunded, so
+    /// check the window's state after waking up.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::SharedJumpingWindow;
+    /// use std::time::Duration;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let cooldown = SharedJumpingWindow::new(1, Duration::from_secs(3600));
+    /// cooldown.trigger(None);
+    ///
+    /// let notified = cooldown.reset_notified();
+    /// cooldown.reset(None);
+    /// notified.await;
+    /// assert!(cooldown.can_trigger(None));
+    /// # }
+    /// ```
+    pub fn reset_notified(&self) -> impl Future<Output = ()> + '_ {
+        let seen = self.waiters.resets();
+        let deadline = self.next_reset_at(None);
+
+        async move { self.waiters.reset_notified(seen, Some(deadline)).await }
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync + 'static, L: RateLimiter> FixedMapping<K, L> {
+    /// Wait until `key` is reset, either explicitly with `reset` or by its window rolling
+    /// over. See `floodgate::SharedJumpingWindow::reset_notified` for the guarantees.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::time::Duration;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let mapping = FixedMapping::new(1, Duration::from_millis(20));
+    /// mapping.trigger(&1);
+    ///
+    /// // completes when the window rolls over, without anyone triggering it.
+    /// mapping.notified(&1).await;
+    /// assert!(mapping.can_trigger(&1));
+    /// # }
+    /// ```
+    pub fn notified(&self, key: &K) -> impl Future<Output = ()> + '_ {
+        let waiters = self.waiters.get(key);
+        let seen = waiters.queue().resets();
+        let deadline = deadline(self.next_reset(key));
+
+        async move { waiters.queue().reset_notified(seen, deadline).await }
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync + 'static, L: RateLimiter> DynamicMapping<K, L> {
+    /// Wait until `key` is reset, either explicitly with `reset` or by its window rolling
+    /// over. See `floodgate::SharedJumpingWindow::reset_notified` for the guarantees.
+    pub fn notified(
+        &self,
+        key: &K,
+        capacity: u64,
+        period: Duration,
+    ) -> impl Future<Output = ()> + '_ {
+        let waiters = self.waiters.get(key);
+        let seen = waiters.queue().resets();
+        let deadline = deadline(self.next_reset(key, capacity, period));
+
+        async move { waiters.queue().reset_notified(seen, deadline).await }
+    }
+}
+
+fn deadline(next_reset: Duration) -> Option<Instant> {
+    clock::now().checked_add(next_reset)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::timeout;
+
+    use crate::{FixedMapping, SharedJumpingWindow};
+
+    #[tokio::test]
+    async fn lazy_rollover_notifies() {
+        let period = Duration::from_millis(50);
+        let cooldown = SharedJumpingWindow::new(1, period);
+        let start = cooldown.window_start();
+
+        let notified = cooldown.reset_notified();
+        // roll the window over by triggering it in the next window.
+        cooldown.trigger(Some(start + period));
+        timeout(Duration::from_millis(10), notified).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn explicit_reset_notifies_key() {
+        let mapping = FixedMapping::new(1, Duration::from_secs(3600));
+        mapping.trigger(&1);
+
+        let notified = mapping.notified(&1);
+        let other = mapping.notified(&2);
+        mapping.reset(&1);
+
+        timeout(Duration::from_millis(10), notified).await.unwrap();
+        assert!(timeout(Duration::from_millis(10), other).await.is_err());
+    }
+}
@@ -0,0 +1,205 @@
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use crate::rate::{optional_period, RateRepr};
+use crate::{InvalidPolicy, Penalty, Rate};
+
+/// A complete description of a keyed limit, to build a `floodgate::FixedMapping` from with
+/// `FixedMapping::from_policy`. With the `serde` feature, policies can be loaded from config.
+///
+/// Every key is limited by all of the rates at once, such as a burst rate and a sustained one.
+/// The other options apply to each of the rates' windows.
+///
+/// When deserializing, the rates use the forms accepted by `floodgate::Rate`, and `jitter` and
+/// `idle_ttl` are durations like `30s` or a number of seconds. The policy is validated as it
+/// is deserialized, and errors name the field that is wrong.
+///
+/// # Examples
+/// ```
+/// use floodgate::{FixedMapping, Policy, Rate};
+/// use std::time::Duration;
+///
+/// let policy = Policy {
+///     max_keys: Some(10_000),
+///     idle_ttl: Some(Duration::from_secs(600)),
+///     ..Policy::new([Rate::per_second(2), Rate::per_minute(30)])
+/// };
+///
+/// let mapping = FixedMapping::<u64, _>::from_policy(&policy).unwrap();
+/// assert_eq!(mapping.trigger(&1), None);
+/// assert_eq!(mapping.trigger(&1), None);
+/// assert!(mapping.trigger(&1).is_some());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "PolicyRepr"))]
+pub struct Policy {
+    /// The rates every key is limited by.
+    pub rates: Vec<Rate>,
+    /// Extend the windows of keys whose triggers are rejected. See
+    /// `floodgate::JumpingWindow::punitive`.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub penalty: Option<Penalty>,
+    /// Carry unused tokens over into the next window, up to this many. See
+    /// `floodgate::JumpingWindow::carry_over`.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub carry_over: Option<u64>,
+    /// Randomize the length of each window by up to this much. See
+    /// `floodgate::JumpingWindow::jittered`.
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "optional_period", skip_serializing_if = "Option::is_none")
+    )]
+    pub jitter: Option<Duration>,
+    /// The most keys to store a limiter for. See `floodgate::FixedMapping::max_keys`.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub max_keys: Option<usize>,
+    /// How long to keep the limiter of a key that has gone unused, rounded up to a whole number
+    /// of the longest rate's periods. See `floodgate::FixedMapping::set_idle_periods`.
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "optional_period", skip_serializing_if = "Option::is_none")
+    )]
+    pub idle_ttl: Option<Duration>,
+}
+
+impl Policy {
+    /// Create a new Policy with only `rates` set.
+    ///
+    /// # Arguments
+    /// * `rates` - The rates every key is limited by.
+    pub fn new(rates: impl IntoIterator<Item = Rate>) -> Self {
+        Self {
+            rates: rates.into_iter().collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Check that the policy can be turned into a limiter.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{InvalidPolicy, Policy, Rate};
+    ///
+    /// assert_eq!(Policy::new([]).validate(), Err(InvalidPolicy::NoRates));
+    /// assert_eq!(Policy::new([Rate::per_second(1)]).validate(), Ok(()));
+    /// ```
+    pub fn validate(&self) -> Result<(), InvalidPolicy> {
+        if self.rates.is_empty() {
+            Err(InvalidPolicy::NoRates)
+        } else if self.max_keys == Some(0) {
+            Err(InvalidPolicy::ZeroMaxKeys)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The `idle_ttl` in periods of `period`, rounded up.
+    pub(crate) fn idle_periods(&self, period: Duration) -> Option<u32> {
+        let periods = self.idle_ttl?.as_nanos().div_ceil(period.as_nanos());
+        Some(periods.try_into().unwrap_or(u32::MAX))
+    }
+}
+
+/// A `Policy` as it is deserialized, before it is validated.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyRepr {
+    rates: Vec<RateRepr>,
+    #[serde(default)]
+    penalty: Option<Penalty>,
+    #[serde(default)]
+    carry_over: Option<u64>,
+    #[serde(default, with = "optional_period")]
+    jitter: Option<Duration>,
+    #[serde(default)]
+    max_keys: Option<usize>,
+    #[serde(default, with = "optional_period")]
+    idle_ttl: Option<Duration>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<PolicyRepr> for Policy {
+    type Error = InvalidPolicy;
+
+    fn try_from(repr: PolicyRepr) -> Result<Self, Self::Error> {
+        let rates = repr
+            .rates
+            .into_iter()
+            .enumerate()
+            .map(|(index, rate)| {
+                rate.into_rate()
+                    .map_err(|error| InvalidPolicy::InvalidRate { index, error })
+            })
+            .collect::<Result<_, _>>()?;
+
+        let policy = Self {
+            rates,
+            penalty: repr.penalty,
+            carry_over: repr.carry_over,
+            jitter: repr.jitter,
+            max_keys: repr.max_keys,
+            idle_ttl: repr.idle_ttl,
+        };
+        policy.validate()?;
+        Ok(policy)
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use std::time::Duration;
+
+    use super::Policy;
+    use crate::{FixedMapping, Rate};
+
+    #[test]
+    fn deserialized_policies_build_stacked_mappings() {
+        let policy: Policy = serde_json::from_value(serde_json::json!({
+            "rates": ["2/10s", { "capacity": 3, "period_secs": 60 }],
+            "penalty": "RestartWindow",
+            "jitter": "1s",
+            "idle_ttl": 90,
+        }))
+        .unwrap();
+        assert_eq!(
+            policy.rates,
+            [Rate::new(2, Duration::from_secs(10)), Rate::per_minute(3)]
+        );
+        assert_eq!(policy.idle_periods(Duration::from_secs(60)), Some(2));
+        assert_eq!(
+            serde_json::from_value(serde_json::to_value(&policy).unwrap()).ok(),
+            Some(policy.clone())
+        );
+
+        let mapping = FixedMapping::<u64, _>::from_policy(&policy).unwrap();
+        assert_eq!(mapping.period(), Duration::from_secs(60));
+        assert_eq!(mapping.idle_periods(), 2);
+        assert_eq!(mapping.trigger(&1), None);
+        assert_eq!(mapping.trigger(&1), None);
+        assert!(mapping.trigger(&1).is_some());
+    }
+
+    #[test]
+    fn invalid_policies_name_the_field() {
+        let err = |value| {
+            serde_json::from_value::<Policy>(value)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            err(serde_json::json!({ "rates": [] })),
+            "`rates` must contain at least one rate"
+        );
+        assert_eq!(
+            err(serde_json::json!({ "rates": ["1/s", "0/10s"] })),
+            "`rates[1]`: capacity must be greater than zero"
+        );
+        assert_eq!(
+            err(serde_json::json!({ "rates": ["1/s"], "max_keys": 0 })),
+            "`max_keys` must be greater than zero"
+        );
+        assert!(err(serde_json::json!({ "rates": ["1/s"], "burst": 2 })).contains("burst"));
+    }
+}
@@ -0,0 +1,9 @@
+/// How important a trigger is, for limiters that keep part of their capacity for high priority
+/// triggers. See `floodgate::JumpingWindow::prioritized`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Priority {
+    /// May use every token.
+    High,
+    /// May only use the tokens above the reserved floor.
+    Low,
+}
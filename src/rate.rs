@@ -0,0 +1,364 @@
+use std::{fmt, str::FromStr, time::Duration};
+
+use crate::{error::validate, InvalidWindow, ParseRateError};
+
+/// A capacity per period, such as 5 triggers per 10 seconds.
+///
+/// Displays as the capacity and the period, like `5/10s` or `100/1m` - the period is written
+/// with the largest units that fit exactly, down to nanoseconds.
+///
+/// Rates can also be parsed from that form, where the period is one or more whole numbers
+/// with units (`ns`, `us`, `ms`, `s`, `m`, `h`, `d`, or names like `sec`, `min` and `hours`),
+/// like `2/1h30m`. A bare unit counts as one of it, so `100/min` is 100 per minute. With the
+/// `serde` feature, rates are serialized as strings, and can be deserialized from a string or a
+/// `{ capacity, period_secs }` map.
+///
+/// # Examples
+/// ```
+/// use floodgate::{JumpingWindow, Rate};
+/// use std::time::Duration;
+///
+/// let rate = Rate::new(5, Duration::from_secs(10));
+/// assert_eq!(rate.to_string(), "5/10s");
+/// assert_eq!(Rate::per_hour(2).to_string(), "2/1h");
+/// assert_eq!(Rate::new(1, Duration::from_millis(1500)).to_string(), "1/1s500ms");
+///
+/// let cooldown = JumpingWindow::from_rate(rate);
+/// assert_eq!(cooldown.rate(), rate);
+///
+/// assert_eq!("100/min".parse(), Ok(Rate::per_minute(100)));
+/// assert_eq!("2/1h30m".parse(), Ok(Rate::new(2, Duration::from_secs(90 * 60))));
+/// assert!("5 per second".parse::<Rate>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rate {
+    capacity: u64,
+    period: Duration,
+}
+
+impl Rate {
+    /// Create a new Rate.
+    ///
+    /// # Arguments
+    /// * `capacity` - How many triggers can occur per period.
+    /// * `period` - How long the period is.
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `period` is zero. See `Rate::try_new`.
+    pub fn new(capacity: u64, period: Duration) -> Self {
+        Self::try_new(capacity, period).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Create a new Rate, returning an error if `capacity` or `period` is zero.
+    ///
+    /// # Arguments
+    /// * `capacity` - How many triggers can occur per period.
+    /// * `period` - How long the period is.
+    pub fn try_new(capacity: u64, period: Duration) -> Result<Self, InvalidWindow> {
+        validate(capacity, period)?;
+        Ok(Self { capacity, period })
+    }
+
+    /// `capacity` triggers per second.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is zero.
+    pub fn per_second(capacity: u64) -> Self {
+        Self::new(capacity, Duration::from_secs(1))
+    }
+
+    /// `capacity` triggers per minute.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is zero.
+    pub fn per_minute(capacity: u64) -> Self {
+        Self::new(capacity, Duration::from_secs(60))
+    }
+
+    /// `capacity` triggers per hour.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is zero.
+    pub fn per_hour(capacity: u64) -> Self {
+        Self::new(capacity, Duration::from_secs(60 * 60))
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+}
+
+impl From<Rate> for (u64, Duration) {
+    fn from(rate: Rate) -> Self {
+        (rate.capacity, rate.period)
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/", self.capacity)?;
+        fmt_period(self.period, f)
+    }
+}
+
+impl FromStr for Rate {
+    type Err = ParseRateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (capacity, period) = s.split_once('/').ok_or(ParseRateError::MissingSlash)?;
+        let capacity = capacity.trim();
+        let capacity = capacity
+            .parse()
+            .map_err(|_| ParseRateError::InvalidCapacity(capacity.to_owned()))?;
+        let period = parse_period(period.trim())?;
+        Self::try_new(capacity, period).map_err(ParseRateError::Invalid)
+    }
+}
+
+/// Parse a period like `10s`, `1h30m` or `min`.
+fn parse_period(period: &str) -> Result<Duration, ParseRateError> {
+    if period.is_empty() {
+        return Err(ParseRateError::InvalidPeriod(period.to_owned()));
+    }
+    // a bare unit counts as one of it.
+    if period.chars().all(char::is_alphabetic) {
+        return period_unit(period).ok_or_else(|| ParseRateError::UnknownUnit(period.to_owned()));
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = period;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (count, after) = rest.split_at(digits);
+        let after = after.trim_start();
+        let units = after
+            .find(|c: char| !c.is_alphabetic())
+            .unwrap_or(after.len());
+        let (unit, after) = after.split_at(units);
+
+        let count: u32 = match count.parse() {
+            Ok(count) => count,
+            Err(_) if count.is_empty() => {
+                return Err(ParseRateError::InvalidPeriod(period.to_owned()))
+            }
+            Err(_) => return Err(ParseRateError::PeriodTooLong),
+        };
+        let unit = match unit {
+            "" if after.is_empty() => return Err(ParseRateError::MissingUnit(rest.to_owned())),
+            "" => return Err(ParseRateError::InvalidPeriod(period.to_owned())),
+            unit => {
+                period_unit(unit).ok_or_else(|| ParseRateError::UnknownUnit(unit.to_owned()))?
+            }
+        };
+        total = unit
+            .checked_mul(count)
+            .and_then(|part| total.checked_add(part))
+            .ok_or(ParseRateError::PeriodTooLong)?;
+
+        rest = after.trim_start();
+    }
+    Ok(total)
+}
+
+/// The length of one `unit` of a period.
+fn period_unit(unit: &str) -> Option<Duration> {
+    Some(match unit {
+        "ns" | "nanos" => Duration::from_nanos(1),
+        "us" | "µs" | "micros" => Duration::from_micros(1),
+        "ms" | "millis" => Duration::from_millis(1),
+        "s" | "sec" | "secs" | "second" | "seconds" => Duration::from_secs(1),
+        "m" | "min" | "mins" | "minute" | "minutes" => Duration::from_secs(60),
+        "h" | "hr" | "hrs" | "hour" | "hours" => Duration::from_secs(60 * 60),
+        "d" | "day" | "days" => Duration::from_secs(24 * 60 * 60),
+        _ => return None,
+    })
+}
+
+/// Write `period` with the largest units that fit exactly, like `1h30m` or `1s500ms`.
+fn fmt_period(period: Duration, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if period.is_zero() {
+        return write!(f, "0s");
+    }
+
+    let secs = period.as_secs();
+    let units = [
+        (secs / (24 * 60 * 60), "d"),
+        (secs / (60 * 60) % 24, "h"),
+        (secs / 60 % 60, "m"),
+        (secs % 60, "s"),
+    ];
+    for (count, unit) in units {
+        if count > 0 {
+            write!(f, "{count}{unit}")?;
+        }
+    }
+
+    let nanos = period.subsec_nanos();
+    match nanos {
+        0 => Ok(()),
+        _ if nanos.is_multiple_of(1_000_000) => write!(f, "{}ms", nanos / 1_000_000),
+        _ if nanos.is_multiple_of(1_000) => write!(f, "{}us", nanos / 1_000),
+        _ => write!(f, "{nanos}ns"),
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Rate {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Rate {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        RateRepr::deserialize(deserializer)?
+            .into_rate()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// The forms a `Rate` can be deserialized from, before they are validated.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+pub(crate) enum RateRepr {
+    Spec(String),
+    Parts { capacity: u64, period_secs: f64 },
+}
+
+#[cfg(feature = "serde")]
+impl RateRepr {
+    pub(crate) fn into_rate(self) -> Result<Rate, ParseRateError> {
+        match self {
+            Self::Spec(spec) => spec.parse(),
+            Self::Parts {
+                capacity,
+                period_secs,
+            } => {
+                let period = Duration::try_from_secs_f64(period_secs)
+                    .map_err(|_| ParseRateError::InvalidPeriod(period_secs.to_string()))?;
+                Rate::try_new(capacity, period).map_err(ParseRateError::Invalid)
+            }
+        }
+    }
+}
+
+/// (De)serializes an optional duration in the same form as the period of a `Rate`, like
+/// `30s`. Durations can also be deserialized from a number of seconds.
+#[cfg(feature = "serde")]
+pub(crate) mod optional_period {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::{fmt_period, parse_period};
+
+    pub(crate) fn serialize<S: Serializer>(
+        period: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        struct Period(Duration);
+
+        impl std::fmt::Display for Period {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                fmt_period(self.0, f)
+            }
+        }
+
+        match period {
+            Some(period) => serializer.collect_str(&Period(*period)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum PeriodRepr {
+            Spec(String),
+            Secs(f64),
+        }
+
+        let period = match Option::<PeriodRepr>::deserialize(deserializer)? {
+            Some(PeriodRepr::Spec(spec)) => parse_period(spec.trim()),
+            Some(PeriodRepr::Secs(secs)) => Duration::try_from_secs_f64(secs)
+                .map_err(|_| crate::ParseRateError::InvalidPeriod(secs.to_string())),
+            None => return Ok(None),
+        };
+        period.map(Some).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Rate;
+    use crate::{InvalidWindow, ParseRateError};
+
+    #[test]
+    fn parses_what_it_displays() {
+        let secs = Duration::from_secs;
+        for (spec, period) in [
+            ("5/10s", secs(10)),
+            ("100/min", secs(60)),
+            ("1 / 2 hours", secs(2 * 60 * 60)),
+            ("3/1h 30m", secs(90 * 60)),
+            ("7/1d2h3m4s5ms", secs(93_784) + Duration::from_millis(5)),
+            ("9/250us", Duration::from_micros(250)),
+        ] {
+            let rate: Rate = spec.parse().unwrap();
+            assert_eq!(rate.period(), period, "{spec}");
+            assert_eq!(rate.to_string().parse(), Ok(rate));
+        }
+    }
+
+    #[test]
+    fn reports_what_is_malformed() {
+        let err = |spec: &str| spec.parse::<Rate>().unwrap_err();
+        assert_eq!(err("5"), ParseRateError::MissingSlash);
+        assert_eq!(err("x/10s"), ParseRateError::InvalidCapacity("x".into()));
+        assert_eq!(err("5/"), ParseRateError::InvalidPeriod("".into()));
+        assert_eq!(err("5/1.5s"), ParseRateError::InvalidPeriod("1.5s".into()));
+        assert_eq!(err("5/10"), ParseRateError::MissingUnit("10".into()));
+        assert_eq!(
+            err("5/10 fortnights"),
+            ParseRateError::UnknownUnit("fortnights".into())
+        );
+        assert_eq!(err("5/99999999999s"), ParseRateError::PeriodTooLong);
+        assert_eq!(
+            err("0/10s"),
+            ParseRateError::Invalid(InvalidWindow::ZeroCapacity)
+        );
+        assert_eq!(
+            err("5/0s"),
+            ParseRateError::Invalid(InvalidWindow::ZeroPeriod)
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserializes_from_a_string_or_a_map() {
+        let rate = Rate::new(5, Duration::from_secs(10));
+        assert_eq!(serde_json::to_value(rate).unwrap(), "5/10s");
+        assert_eq!(
+            serde_json::from_value::<Rate>("5/10s".into()).unwrap(),
+            rate
+        );
+
+        let parts = serde_json::json!({ "capacity": 5, "period_secs": 10 });
+        assert_eq!(serde_json::from_value::<Rate>(parts).unwrap(), rate);
+
+        let err = serde_json::from_value::<Rate>("5/10".into()).unwrap_err();
+        assert!(err.to_string().contains("missing a unit"));
+    }
+}
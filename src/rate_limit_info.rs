@@ -0,0 +1,27 @@
+use std::time::Duration;
+
+/// The state of a limiter after a trigger, all computed from the same instant.
+///
+/// Created by `floodgate::JumpingWindow::trigger_info` and the mappings' `trigger_info`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitInfo {
+    /// Whether the trigger was allowed.
+    pub allowed: bool,
+    /// The capacity of the limiter.
+    pub limit: u64,
+    /// How many tokens are left after the trigger.
+    pub remaining: u64,
+    /// How long to wait before retrying, if the trigger wasn't allowed.
+    pub retry_after: Option<Duration>,
+    /// How long until the limiter resets.
+    pub reset_after: Duration,
+}
+
+impl RateLimitInfo {
+    /// Whether the trigger was rejected even though tokens are left, as with
+    /// `floodgate::JumpingWindow::load_shedding`. Retrying such a trigger soon may succeed,
+    /// while other rejections have to wait for tokens to come back.
+    pub fn is_soft_rejection(&self) -> bool {
+        !self.allowed && self.remaining > 0
+    }
+}
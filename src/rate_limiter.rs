@@ -0,0 +1,132 @@
+use std::time::Duration;
+
+use crate::clock::{self, Instant};
+use crate::{error, InvalidWindow, RateLimitInfo};
+
+/// The behaviour shared by every ratelimit implementation, allowing `floodgate::FixedMapping`
+/// and `floodgate::DynamicMapping` to be generic over the limiter they use.
+///
+/// For method documentation, please see `floodgate::JumpingWindow`.
+pub trait RateLimiter {
+    /// Create a new limiter allowing `capacity` triggers per `period`.
+    fn new(capacity: u64, period: Duration) -> Self
+    where
+        Self: Sized;
+
+    /// Check that `capacity` and `period` can be used to create a limiter with
+    /// `RateLimiter::new`, which panics otherwise. Mappings check the rates they are given with
+    /// this. The default only rejects zero; limiters that keep time more coarsely, such as
+    /// `floodgate::AtomicJumpingWindow`, also reject periods they can't represent.
+    fn validate(capacity: u64, period: Duration) -> Result<(), InvalidWindow>
+    where
+        Self: Sized,
+    {
+        error::validate(capacity, period)
+    }
+
+    /// The number of triggers allowed per period.
+    fn capacity(&self) -> u64;
+
+    /// The period of the limiter.
+    fn period(&self) -> Duration;
+
+    /// Change the capacity and period of the limiter, keeping its current state.
+    fn set_rate(&mut self, capacity: u64, period: Duration);
+
+    fn tokens(&mut self, now: Option<Instant>) -> u64;
+
+    fn next_reset(&mut self, now: Option<Instant>) -> Duration;
+
+    fn retry_after(&mut self, now: Option<Instant>) -> Option<Duration>;
+
+    /// Like `retry_after`, but returns the time at which a trigger will be allowed.
+    fn retry_at(&mut self, now: Option<Instant>) -> Option<Instant> {
+        let now = now.unwrap_or_else(clock::now);
+        self.retry_after(Some(now))
+            .map(|retry_after| now + retry_after)
+    }
+
+    /// How long until `n` triggers can be made at once, or `None` if they never can. The
+    /// default assumes every token comes back at the next reset, as with a jumping window.
+    fn wait_for(&mut self, n: u64, now: Option<Instant>) -> Option<Duration> {
+        let now = now.unwrap_or_else(clock::now);
+        if n > self.capacity() {
+            None
+        } else if self.tokens(Some(now)) >= n {
+            Some(Duration::ZERO)
+        } else {
+            Some(self.next_reset(Some(now)))
+        }
+    }
+
+    fn can_trigger(&mut self, now: Option<Instant>) -> bool {
+        self.tokens(now) != 0
+    }
+
+    fn trigger(&mut self, now: Option<Instant>) -> Option<Duration>;
+
+    fn trigger_n(&mut self, cost: u64, now: Option<Instant>) -> Result<(), Duration>;
+
+    /// Like `trigger`, but through a shared reference, for limiters that keep their state in
+    /// atomics. Mappings use it to trigger a limiter without locking it exclusively, so that
+    /// concurrent triggers of one key don't wait for each other. It is only tried for types
+    /// whose `RateLimiter::shares_triggers` is `true`.
+    ///
+    /// Returns `None` if the limiter needs exclusive access to be triggered, which the default
+    /// does. A mapping then stops trying it for that key's limiter only, so a type such as an
+    /// enum can support it for some of its limiters. `floodgate::AtomicJumpingWindow`
+    /// implements it.
+    fn trigger_shared(&self, _now: Option<Instant>) -> Option<Option<Duration>> {
+        None
+    }
+
+    /// Whether limiters of this type may support `RateLimiter::trigger_shared`. Mappings of
+    /// other types don't look for a shared trigger at all, which saves a lookup per trigger.
+    /// The default is `false`, so limiters that implement `trigger_shared` must return `true`.
+    fn shares_triggers() -> bool
+    where
+        Self: Sized,
+    {
+        false
+    }
+
+    /// Trigger the limiter, returning its state from a single snapshot of `now`.
+    fn trigger_info(&mut self, now: Option<Instant>) -> RateLimitInfo {
+        let now = now.unwrap_or_else(clock::now);
+        let retry_after = self.trigger(Some(now));
+
+        RateLimitInfo {
+            allowed: retry_after.is_none(),
+            limit: self.capacity(),
+            remaining: self.tokens(Some(now)),
+            retry_after,
+            reset_after: self.next_reset(Some(now)),
+        }
+    }
+
+    fn reset(&mut self, now: Option<Instant>);
+
+    /// Give back `n` tokens taken from the window `epoch` identifies, or nothing if it has
+    /// ended. See `floodgate::JumpingWindow::refund`. Limiters without windows ignore `epoch`.
+    fn refund(&mut self, n: u64, epoch: u64, now: Option<Instant>);
+
+    /// A number identifying the window that is current at `now`, for giving tokens back to it
+    /// with `RateLimiter::refund`. The default is always zero, for limiters whose refunds
+    /// don't depend on when the tokens were taken.
+    fn epoch(&mut self, _now: Option<Instant>) -> u64 {
+        0
+    }
+
+    /// Whether the limiter is in the same state as a new one, so that a mapping can drop it
+    /// without changing what it allows. The default is whether every token is available,
+    /// which suits limiters whose state is only their tokens.
+    fn is_idle(&mut self, now: Option<Instant>) -> bool {
+        self.tokens(now) >= self.capacity()
+    }
+
+    /// How much of the capacity is currently used, from `0.0` to `1.0`.
+    fn utilization(&mut self, now: Option<Instant>) -> f64 {
+        let capacity = self.capacity().max(1);
+        capacity.saturating_sub(self.tokens(now)) as f64 / capacity as f64
+    }
+}
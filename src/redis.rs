@@ -0,0 +1,354 @@
+//! A keyed cooldown whose windows are kept in Redis, so that several processes share them.
+//!
+//! Each trigger is a single round trip, running a Lua script that checks and takes the tokens
+//! of the key's window atomically on the server.
+
+use std::{fmt, time::Duration};
+
+use redis::{aio::ConnectionManager, RedisResult, Script};
+
+use crate::{
+    backend::millis,
+    clock::SystemTime,
+    error::{validate, validate_resolution},
+    InvalidWindow,
+};
+
+/// Check and take `ARGV[4]` tokens from the window stored at `KEYS[1]`, returning whether they
+/// were taken, how many are left, and how long until the window ends, in milliseconds.
+///
+/// Windows are aligned to multiples of the period since the unix epoch, and stored as
+/// `tokens:start`, expiring when they end. A window started by a process whose clock is ahead
+/// is kept until it ends rather than started over, so that processes with slightly different
+/// clocks never reset a window early. A cost of zero only reads the window.
+const SCRIPT: &str = r"
+local capacity = tonumber(ARGV[1])
+local period = tonumber(ARGV[2])
+local now = tonumber(ARGV[3])
+local cost = tonumber(ARGV[4])
+local start = now - now % period
+local tokens = capacity
+local state = redis.call('GET', KEYS[1])
+if state then
+  local stored_tokens, stored_start = string.match(state, '^(%d+):(%d+)$')
+  if stored_start and tonumber(stored_start) >= start then
+    start = tonumber(stored_start)
+    tokens = math.min(tonumber(stored_tokens), capacity)
+  end
+end
+local reset_after = start + period - now
+if cost > tokens then
+  return {0, tokens, reset_after}
+end
+if cost > 0 then
+  tokens = tokens - cost
+  redis.call('SET', KEYS[1], string.format('%d:%d', tokens, start), 'PX', reset_after)
+end
+return {1, tokens, reset_after}
+";
+
+/// The largest capacity a `RedisMapping` can have. Lua, which the script runs in, represents
+/// numbers as doubles, so larger ones lose precision.
+const MAX_CAPACITY: u64 = 1 << 53;
+
+/// What a `floodgate::redis::RedisMapping` does when Redis can't be reached, or fails to run
+/// its script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FailurePolicy {
+    /// Allow every trigger, as if there were no limit. This keeps the service up while Redis
+    /// is down, at the cost of not limiting anyone.
+    #[default]
+    Open,
+    /// Reject every trigger, asking the caller to retry after `retry_after`. This keeps the
+    /// limit, at the cost of turning everyone away while Redis is down.
+    Closed {
+        /// The retry-after to reject triggers with.
+        retry_after: Duration,
+    },
+}
+
+/// A keyed cooldown like `floodgate::BackendMapping`, whose windows are kept in Redis, so that
+/// they are shared by every process using the same Redis and mapping name.
+///
+/// A key's window is stored at `floodgate:{name}:{key}`, with the key formatted with its
+/// `Display` impl, and expires when the window ends, so Redis drops idle keys on its own.
+/// Windows are aligned to multiples of the period since the unix epoch, and times are rounded
+/// down to whole milliseconds, so that every process agrees on when a window starts and ends.
+///
+/// Commands are sent over a `redis::aio::ConnectionManager`, which reconnects when the
+/// connection fails; its config sets how long to wait for Redis. If Redis can't be reached,
+/// triggers are handled according to the mapping's `floodgate::redis::FailurePolicy`.
+///
+/// # Examples
+/// ```no_run
+/// use floodgate::redis::{FailurePolicy, RedisMapping};
+/// use redis::{aio::ConnectionManager, Client};
+/// use std::time::Duration;
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() -> redis::RedisResult<()> {
+/// let client = Client::open("redis://127.0.0.1/")?;
+/// let connection = ConnectionManager::new(client).await?;
+/// let mapping = RedisMapping::new(connection, "commands", 1, Duration::from_secs(10))
+///     .on_failure(FailurePolicy::Closed {
+///         retry_after: Duration::from_secs(1),
+///     });
+///
+/// assert_eq!(mapping.trigger("user", None).await, None);
+/// assert!(mapping.trigger("user", None).await.is_some());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct RedisMapping {
+    connection: ConnectionManager,
+    script: Script,
+    name: String,
+    capacity: u64,
+    period: u64,
+    policy: FailurePolicy,
+}
+
+impl RedisMapping {
+    /// Create a new RedisMapping.
+    ///
+    /// # Arguments
+    /// * `connection` - The connection to Redis.
+    /// * `name` - The name of the mapping, which its keys are stored under. Processes using
+    ///   the same name share their windows.
+    /// * `capacity` - How many triggers can occur per window.
+    /// * `period` - How long the window is, in whole milliseconds.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is zero or larger than 2^53, or if `period` isn't a whole number
+    /// of milliseconds. See `RedisMapping::try_new`.
+    pub fn new(connection: ConnectionManager, name: &str, capacity: u64, period: Duration) -> Self {
+        Self::try_new(connection, name, capacity, period).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Create a new RedisMapping, returning an error if `capacity` is zero or larger than
+    /// 2^53, or if `period` isn't a whole number of milliseconds. See `RedisMapping::new`.
+    pub fn try_new(
+        connection: ConnectionManager,
+        name: &str,
+        capacity: u64,
+        period: Duration,
+    ) -> Result<Self, InvalidWindow> {
+        validate(capacity, period)?;
+        validate_resolution(period, Duration::from_millis(1))?;
+        let period = period.as_millis().min(u64::MAX as u128) as u64;
+        if capacity > MAX_CAPACITY {
+            return Err(InvalidWindow::CapacityTooLarge {
+                capacity,
+                max: MAX_CAPACITY,
+            });
+        }
+        Ok(Self {
+            connection,
+            script: Script::new(SCRIPT),
+            name: name.to_owned(),
+            capacity,
+            period,
+            policy: FailurePolicy::default(),
+        })
+    }
+
+    /// Set what to do when Redis can't be reached. Defaults to `FailurePolicy::Open`.
+    pub fn on_failure(mut self, policy: FailurePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    /// The period, in whole milliseconds.
+    pub fn period(&self) -> Duration {
+        Duration::from_millis(self.period)
+    }
+
+    /// How many triggers `key` has left in its window. If Redis can't be reached, this is the
+    /// capacity when failing open, and zero when failing closed.
+    pub async fn tokens<Q: fmt::Display + ?Sized>(&self, key: &Q, now: Option<SystemTime>) -> u64 {
+        match (self.run(key, 0, now).await, self.policy) {
+            (Ok(window), _) => window.tokens,
+            (Err(_), FailurePolicy::Open) => self.capacity,
+            (Err(_), FailurePolicy::Closed { .. }) => 0,
+        }
+    }
+
+    /// How long until `key` can be triggered, or `None` if it can be now. If Redis can't be
+    /// reached, this follows the mapping's `FailurePolicy`.
+    pub async fn retry_after<Q: fmt::Display + ?Sized>(
+        &self,
+        key: &Q,
+        now: Option<SystemTime>,
+    ) -> Option<Duration> {
+        match self.run(key, 0, now).await {
+            Ok(window) => (window.tokens == 0).then_some(window.reset_after),
+            Err(_) => self.fail().err(),
+        }
+    }
+
+    /// Trigger the cooldown for `key`, returning how long until it can be triggered again if
+    /// it is on cooldown. If Redis can't be reached, this follows the mapping's
+    /// `FailurePolicy`.
+    pub async fn trigger<Q: fmt::Display + ?Sized>(
+        &self,
+        key: &Q,
+        now: Option<SystemTime>,
+    ) -> Option<Duration> {
+        self.trigger_n(key, 1, now).await.err()
+    }
+
+    /// Consume `cost` triggers for `key` at once. See `floodgate::JumpingWindow::trigger_n`.
+    pub async fn trigger_n<Q: fmt::Display + ?Sized>(
+        &self,
+        key: &Q,
+        cost: u64,
+        now: Option<SystemTime>,
+    ) -> Result<(), Duration> {
+        if cost > self.capacity {
+            return Err(Duration::MAX);
+        }
+        match self.run(key, cost, now).await {
+            Ok(window) if window.taken => Ok(()),
+            Ok(window) => Err(window.reset_after),
+            Err(_) => self.fail(),
+        }
+    }
+
+    /// Reset the cooldown for `key`. Since a key without a window has a full one, this is the
+    /// same as `RedisMapping::remove`.
+    pub async fn reset<Q: fmt::Display + ?Sized>(&self, key: &Q) -> RedisResult<()> {
+        self.remove(key).await.map(|_| ())
+    }
+
+    /// Drop the window of `key`. Returns whether it had one.
+    pub async fn remove<Q: fmt::Display + ?Sized>(&self, key: &Q) -> RedisResult<bool> {
+        let removed: u64 = redis::cmd("DEL")
+            .arg(self.key(key))
+            .query_async(&mut self.connection.clone())
+            .await?;
+        Ok(removed > 0)
+    }
+
+    /// Where the window of `key` is stored.
+    fn key<Q: fmt::Display + ?Sized>(&self, key: &Q) -> String {
+        format!("floodgate:{}:{key}", self.name)
+    }
+
+    /// The result of a trigger that couldn't reach Redis.
+    fn fail(&self) -> Result<(), Duration> {
+        match self.policy {
+            FailurePolicy::Open => Ok(()),
+            FailurePolicy::Closed { retry_after } => Err(retry_after),
+        }
+    }
+
+    /// Run the script for `key`, taking `cost` tokens if there are enough.
+    async fn run<Q: fmt::Display + ?Sized>(
+        &self,
+        key: &Q,
+        cost: u64,
+        now: Option<SystemTime>,
+    ) -> RedisResult<Window> {
+        let now = millis(now.unwrap_or_else(SystemTime::now));
+        let (taken, tokens, reset_after): (bool, u64, u64) = self
+            .script
+            .key(self.key(key))
+            .arg(self.capacity)
+            .arg(self.period)
+            .arg(now)
+            .arg(cost)
+            .invoke_async(&mut self.connection.clone())
+            .await?;
+        Ok(Window {
+            taken,
+            tokens,
+            reset_after: Duration::from_millis(reset_after),
+        })
+    }
+}
+
+impl fmt::Debug for RedisMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisMapping")
+            .field("name", &self.name)
+            .field("capacity", &self.capacity)
+            .field("period", &self.period())
+            .field("policy", &self.policy)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A key's window, as returned by the script.
+struct Window {
+    taken: bool,
+    tokens: u64,
+    reset_after: Duration,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use redis::{
+        aio::{ConnectionManager, ConnectionManagerConfig},
+        Client,
+    };
+    use tokio::net::TcpListener;
+
+    use super::{FailurePolicy, RedisMapping};
+    use crate::InvalidWindow;
+
+    #[tokio::test]
+    async fn unreachable_redis_follows_the_failure_policy() {
+        // nothing accepts connections on a port that was just freed.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+
+        let client = Client::open(format!("redis://{address}/")).unwrap();
+        let config = ConnectionManagerConfig::new().set_number_of_retries(0);
+        let connection = ConnectionManager::new_lazy_with_config(client, config).unwrap();
+
+        let period = Duration::from_secs(10);
+        let open = RedisMapping::new(connection, "test", 1, period);
+        assert_eq!(open.trigger("a", None).await, None);
+        assert_eq!(open.tokens("a", None).await, 1);
+        assert!(open.remove("a").await.is_err());
+
+        let retry_after = Duration::from_secs(3);
+        let closed = open.on_failure(FailurePolicy::Closed { retry_after });
+        assert_eq!(closed.trigger("a", None).await, Some(retry_after));
+        assert_eq!(closed.retry_after("a", None).await, Some(retry_after));
+        assert_eq!(closed.tokens("a", None).await, 0);
+    }
+
+    #[test]
+    fn periods_must_be_whole_milliseconds() {
+        let client = Client::open("redis://127.0.0.1/").unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let _guard = runtime.enter();
+        let connection =
+            ConnectionManager::new_lazy_with_config(client, ConnectionManagerConfig::new())
+                .unwrap();
+
+        let mapping = RedisMapping::new(connection.clone(), "a", 1, Duration::from_millis(2));
+        assert_eq!(mapping.period(), Duration::from_millis(2));
+        assert!(matches!(
+            RedisMapping::try_new(connection.clone(), "a", 1, Duration::from_micros(1500)),
+            Err(InvalidWindow::PeriodResolution { .. })
+        ));
+        assert!(
+            RedisMapping::try_new(connection.clone(), "a", 1, Duration::from_micros(999)).is_err()
+        );
+        assert!(
+            RedisMapping::try_new(connection, "a", (1 << 53) + 1, Duration::from_secs(1)).is_err()
+        );
+    }
+}
@@ -0,0 +1,253 @@
+//! Named cooldowns that can be shared across a program without passing them around.
+//!
+//! A `Registry` maps names such as `"login"` to the `floodgate::FixedMapping` built from a
+//! `floodgate::Policy`. Create one per key type, for example in a `static`, or use the global
+//! registry of `String` keys through the free functions of this module.
+//!
+//! # Examples
+//! ```
+//! use floodgate::{registry, Policy, Rate};
+//!
+//! registry::register("password-reset", &Policy::new([Rate::per_hour(3)])).unwrap();
+//!
+//! // anywhere else in the program:
+//! assert_eq!(registry::trigger("password-reset", "alice"), Ok(None));
+//! assert!(registry::trigger("email-send", "alice").is_err());
+//! ```
+
+use std::{
+    borrow::Borrow,
+    error::Error,
+    fmt,
+    hash::Hash,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+
+use crate::{FixedMapping, InvalidPolicy, MultiWindow, Policy};
+
+/// The mappings a `Registry` holds, as built by `floodgate::FixedMapping::from_policy`.
+pub type Cooldown<K> = FixedMapping<K, MultiWindow>;
+
+/// An error returned by a `Registry`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegistryError {
+    /// No cooldown was registered with the name.
+    Unknown(String),
+    /// A cooldown was already registered with the name.
+    AlreadyRegistered(String),
+    /// The policy couldn't be turned into a cooldown.
+    InvalidPolicy(InvalidPolicy),
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown(name) => write!(f, "no cooldown is registered as `{name}`"),
+            Self::AlreadyRegistered(name) => {
+                write!(f, "a cooldown is already registered as `{name}`")
+            }
+            Self::InvalidPolicy(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for RegistryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::InvalidPolicy(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Cooldowns for keys of type `K`, looked up by name.
+///
+/// # Examples
+/// ```
+/// use floodgate::{registry::Registry, Policy, Rate};
+/// use std::sync::LazyLock;
+///
+/// static COOLDOWNS: LazyLock<Registry<u64>> = LazyLock::new(Registry::new);
+///
+/// COOLDOWNS.register("login", &Policy::new([Rate::per_minute(1)])).unwrap();
+/// assert_eq!(COOLDOWNS.trigger("login", &1), Ok(None));
+/// assert!(COOLDOWNS.trigger("login", &1).unwrap().is_some());
+/// ```
+pub struct Registry<K: Eq + Hash + Clone + Send + Sync + 'static> {
+    cooldowns: DashMap<String, Arc<Cooldown<K>>>,
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync + 'static> Registry<K> {
+    /// Create a new, empty Registry.
+    pub fn new() -> Self {
+        Self {
+            cooldowns: DashMap::new(),
+        }
+    }
+
+    /// Build a cooldown from `policy` and register it as `name`, returning it.
+    ///
+    /// Names can only be registered once: registering a name again returns
+    /// `Err(RegistryError::AlreadyRegistered)` and leaves the existing cooldown as it is. To
+    /// replace it, `Registry::remove` it first.
+    ///
+    /// # Arguments
+    /// * `name` - The name to look the cooldown up by.
+    /// * `policy` - The limit to apply to each key.
+    pub fn register(
+        &self,
+        name: impl Into<String>,
+        policy: &Policy,
+    ) -> Result<Arc<Cooldown<K>>, RegistryError> {
+        match self.cooldowns.entry(name.into()) {
+            Entry::Occupied(entry) => Err(RegistryError::AlreadyRegistered(entry.key().clone())),
+            Entry::Vacant(entry) => {
+                let cooldown =
+                    FixedMapping::from_policy(policy).map_err(RegistryError::InvalidPolicy)?;
+                Ok(entry.insert(Arc::new(cooldown)).clone())
+            }
+        }
+    }
+
+    /// The cooldown registered as `name`, building it from `policy` and registering it first if
+    /// there is none. Whichever call registers the name first decides its policy.
+    ///
+    /// # Arguments
+    /// * `name` - The name to look the cooldown up by.
+    /// * `policy` - The limit to apply to each key, if the cooldown has to be built.
+    pub fn get_or_register(
+        &self,
+        name: impl Into<String>,
+        policy: &Policy,
+    ) -> Result<Arc<Cooldown<K>>, RegistryError> {
+        match self.cooldowns.entry(name.into()) {
+            Entry::Occupied(entry) => Ok(entry.get().clone()),
+            Entry::Vacant(entry) => {
+                let cooldown =
+                    FixedMapping::from_policy(policy).map_err(RegistryError::InvalidPolicy)?;
+                Ok(entry.insert(Arc::new(cooldown)).clone())
+            }
+        }
+    }
+
+    /// The cooldown registered as `name`.
+    pub fn get(&self, name: &str) -> Result<Arc<Cooldown<K>>, RegistryError> {
+        self.cooldowns
+            .get(name)
+            .map(|cooldown| cooldown.clone())
+            .ok_or_else(|| RegistryError::Unknown(name.to_owned()))
+    }
+
+    /// Trigger `key` in the cooldown registered as `name`, returning its retry-after if it is
+    /// on cooldown. See `floodgate::FixedMapping::trigger`.
+    pub fn trigger<Q>(&self, name: &str, key: &Q) -> Result<Option<Duration>, RegistryError>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        Ok(self.get(name)?.trigger(key))
+    }
+
+    /// Unregister the cooldown registered as `name`, returning it. Handles to it returned
+    /// before keep working.
+    pub fn remove(&self, name: &str) -> Option<Arc<Cooldown<K>>> {
+        self.cooldowns.remove(name).map(|(_, cooldown)| cooldown)
+    }
+
+    /// The names of every registered cooldown, in no particular order.
+    pub fn names(&self) -> Vec<String> {
+        self.cooldowns
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    /// Unregister every cooldown. This is mostly useful between tests.
+    pub fn clear(&self) {
+        self.cooldowns.clear();
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync + 'static> Default for Registry<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync + 'static> fmt::Debug for Registry<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Registry")
+            .field("names", &self.names())
+            .finish()
+    }
+}
+
+/// The process-wide registry of cooldowns keyed by `String`, used by the free functions of this
+/// module.
+pub fn global() -> &'static Registry<String> {
+    static GLOBAL: OnceLock<Registry<String>> = OnceLock::new();
+    GLOBAL.get_or_init(Registry::new)
+}
+
+/// Register a cooldown in the global registry. See `Registry::register`.
+pub fn register(
+    name: impl Into<String>,
+    policy: &Policy,
+) -> Result<Arc<Cooldown<String>>, RegistryError> {
+    global().register(name, policy)
+}
+
+/// Look up a cooldown in the global registry. See `Registry::get`.
+pub fn get(name: &str) -> Result<Arc<Cooldown<String>>, RegistryError> {
+    global().get(name)
+}
+
+/// Trigger `key` in a cooldown of the global registry. See `Registry::trigger`.
+pub fn trigger(name: &str, key: &str) -> Result<Option<Duration>, RegistryError> {
+    global().trigger(name, key)
+}
+
+/// Unregister every cooldown of the global registry. See `Registry::clear`.
+pub fn clear() {
+    global().clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Registry, RegistryError};
+    use crate::{InvalidPolicy, Policy, Rate};
+
+    #[test]
+    fn names_are_registered_once() {
+        let registry = Registry::<u64>::new();
+        let policy = Policy::new([Rate::per_minute(1)]);
+
+        let login = registry.register("login", &policy).unwrap();
+        login.trigger(&1);
+        assert_eq!(
+            registry
+                .register("login", &Policy::new([Rate::per_minute(5)]))
+                .err(),
+            Some(RegistryError::AlreadyRegistered("login".into()))
+        );
+        // the existing cooldown was kept.
+        assert!(registry.trigger("login", &1).unwrap().is_some());
+        let again = registry.get_or_register("login", &Policy::new([])).unwrap();
+        assert!(std::sync::Arc::ptr_eq(&login, &again));
+
+        assert_eq!(
+            registry.register("empty", &Policy::new([])).err(),
+            Some(RegistryError::InvalidPolicy(InvalidPolicy::NoRates))
+        );
+        assert_eq!(registry.names(), ["login"]);
+
+        registry.clear();
+        assert_eq!(
+            registry.trigger("login", &1),
+            Err(RegistryError::Unknown("login".into()))
+        );
+    }
+}
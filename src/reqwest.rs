@@ -0,0 +1,243 @@
+//! A `reqwest-middleware` middleware that paces the requests sent to each host, and backs off
+//! the hosts that answer with `429 Too Many Requests`.
+
+use std::{fmt, sync::Arc};
+
+use dashmap::DashMap;
+use http::{Extensions, StatusCode};
+use reqwest::{Request, Response};
+use reqwest_middleware::{Middleware, Next};
+use tokio::time::sleep;
+
+use crate::{clock::Instant, headers::retry_after, Clock, DynamicMapping};
+
+/// Paces the requests of a `reqwest_middleware::ClientWithMiddleware` with a
+/// `floodgate::DynamicMapping`, keyed by the host each request is sent to.
+///
+/// Before a request is sent, it waits for its key with `DynamicMapping::acquire`, at the rate
+/// returned by `DynamicMapping::get_rate`, so hosts can be given their own rate with
+/// `DynamicMapping::add_key`, falling back to the mapping's default rate or policy. Requests
+/// whose key has no rate are sent without waiting.
+///
+/// When a host answers with `429 Too Many Requests` and a `Retry-After` in seconds, the next
+/// requests to it wait that long before being paced, whether or not it has a rate. The
+/// response itself is returned as is, so the caller can retry it. Errors sending requests are
+/// passed through untouched.
+///
+/// # Examples
+/// ```no_run
+/// use floodgate::{reqwest::Pace, DynamicMapping};
+/// use reqwest_middleware::ClientBuilder;
+/// use std::{sync::Arc, time::Duration};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() -> reqwest_middleware::Result<()> {
+/// // 10 requests per second to every host, and 1 per second to the slow one.
+/// let second = Duration::from_secs(1);
+/// let hosts = Arc::new(DynamicMapping::new(second).with_default_rate(10, second));
+/// hosts.add_key("slow.example.com".to_owned(), 1, second);
+/// DynamicMapping::start(hosts.clone());
+///
+/// let client = ClientBuilder::new(reqwest::Client::new())
+///     .with(Pace::new(hosts))
+///     .build();
+/// client.get("http://slow.example.com/").send().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Pace {
+    mapping: Arc<DynamicMapping<String>>,
+    #[allow(clippy::type_complexity)]
+    key: Box<dyn Fn(&Request) -> Option<String> + Send + Sync>,
+    /// When the keys that were answered with a `Retry-After` can be sent requests again.
+    backoff: DashMap<String, Instant>,
+}
+
+impl Pace {
+    /// Create a new Pace, keying requests by their host. The mapping's cycler must be started
+    /// separately.
+    ///
+    /// # Arguments
+    /// * `mapping` - The mapping to acquire each request's key from.
+    pub fn new(mapping: Arc<DynamicMapping<String>>) -> Self {
+        Self {
+            mapping,
+            key: Box::new(|request| request.url().host_str().map(str::to_owned)),
+            backoff: DashMap::new(),
+        }
+    }
+
+    /// Set how to key requests, such as by host and path, or by the API token they are sent
+    /// with. Requests for which `key` returns `None` are sent without waiting. Defaults to
+    /// the request's host.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{reqwest::Pace, DynamicMapping};
+    /// use std::{sync::Arc, time::Duration};
+    ///
+    /// let second = Duration::from_secs(1);
+    /// let mapping = Arc::new(DynamicMapping::new(second).with_default_rate(5, second));
+    ///
+    /// // pace each host and port separately.
+    /// let pace = Pace::new(mapping).key(|request| {
+    ///     let url = request.url();
+    ///     Some(format!("{}:{}", url.host_str()?, url.port_or_known_default()?))
+    /// });
+    /// ```
+    pub fn key(mut self, key: impl Fn(&Request) -> Option<String> + Send + Sync + 'static) -> Self {
+        self.key = Box::new(key);
+        self
+    }
+
+    /// The mapping used by this middleware.
+    pub fn mapping(&self) -> &Arc<DynamicMapping<String>> {
+        &self.mapping
+    }
+
+    /// Wait out the backoff of `key`, if it has one, then for its rate.
+    async fn wait(&self, key: &String) {
+        let until = self.backoff.get(key).map(|until| *until);
+        if let Some(until) = until {
+            let now = self.mapping.clock().now();
+            if until > now {
+                sleep(until - now).await;
+            }
+            self.backoff.remove_if(key, |_, backoff| *backoff <= until);
+        }
+        if let Some(rate) = self.mapping.get_rate(key) {
+            let (capacity, period) = rate.rate();
+            self.mapping.acquire(key, capacity, period).await;
+        }
+    }
+
+    /// Make `key` wait for as long as `response` asks, if it is ratelimited.
+    fn learn(&self, key: String, response: &Response) {
+        if response.status() != StatusCode::TOO_MANY_REQUESTS {
+            return;
+        }
+        let Some(wait) = retry_after(response.headers()) else {
+            return;
+        };
+        let Some(until) = self.mapping.clock().now().checked_add(wait) else {
+            return;
+        };
+        let mut backoff = self.backoff.entry(key).or_insert(until);
+        *backoff = (*backoff).max(until);
+    }
+}
+
+impl fmt::Debug for Pace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pace").finish_non_exhaustive()
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for Pace {
+    async fn handle(
+        &self,
+        request: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        let Some(key) = (self.key)(&request) else {
+            return next.run(request, extensions).await;
+        };
+        self.wait(&key).await;
+        let response = next.run(request, extensions).await?;
+        self.learn(key, &response);
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::TcpListener,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::Pace;
+    use crate::DynamicMapping;
+
+    fn client(mapping: Arc<DynamicMapping<String>>) -> ClientWithMiddleware {
+        ClientBuilder::new(reqwest::Client::new())
+            .with(Pace::new(mapping))
+            .build()
+    }
+
+    #[tokio::test]
+    async fn requests_wait_for_the_retry_after_of_a_429() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/"))
+            .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "2"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+
+        // no rate at all, so only the backoff makes requests wait.
+        let client = client(Arc::new(DynamicMapping::new(Duration::from_secs(1))));
+        let response = client.get(server.uri()).send().await.unwrap();
+        assert_eq!(response.status(), 429);
+
+        let sent = Instant::now();
+        let response = client.get(server.uri()).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(sent.elapsed() >= Duration::from_secs(2));
+
+        // the backoff is over.
+        let sent = Instant::now();
+        client.get(server.uri()).send().await.unwrap();
+        assert!(sent.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn requests_wait_for_the_rate_of_their_host() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+
+        let period = Duration::from_millis(500);
+        let mapping = Arc::new(DynamicMapping::new(period));
+        mapping.add_key("127.0.0.1".to_owned(), 1, period);
+        let client = client(mapping);
+
+        let sent = Instant::now();
+        for _ in 0..2 {
+            client.get(server.uri()).send().await.unwrap();
+        }
+        assert!(sent.elapsed() >= period);
+    }
+
+    #[tokio::test]
+    async fn errors_are_passed_through() {
+        // nothing accepts connections on a port that was just freed.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+
+        let client = client(Arc::new(DynamicMapping::new(Duration::from_secs(1))));
+        let err = client
+            .get(format!("http://{address}/"))
+            .send()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, reqwest_middleware::Error::Reqwest(err) if err.is_connect()));
+    }
+}
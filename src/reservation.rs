@@ -0,0 +1,86 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hash},
+};
+
+use crate::{clock::Instant, Clock, FixedMapping, JumpingWindow, MonotonicClock, Reservation};
+
+/// A token reserved for a key of a `floodgate::FixedMapping`, which is released when the
+/// reservation is dropped unless `commit` is called first.
+///
+/// Like `floodgate::TriggerGuard`, the reservation only holds the key and a reference to the
+/// mapping, so it can be kept across `.await` points, such as while waiting for its slot.
+///
+/// Created by `floodgate::FixedMapping::reserve`.
+#[must_use = "dropping the reservation immediately releases the token"]
+pub struct KeyedReservation<
+    'a,
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    C: Clock = MonotonicClock,
+    S: BuildHasher + Clone = RandomState,
+> {
+    mapping: &'a FixedMapping<K, JumpingWindow, C, S>,
+    key: K,
+    at: Instant,
+    /// The reserved token, or `None` if the key didn't need one, or it was committed.
+    reservation: Option<Reservation>,
+}
+
+impl<'a, K, C, S> KeyedReservation<'a, K, C, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    pub(crate) fn new(
+        mapping: &'a FixedMapping<K, JumpingWindow, C, S>,
+        key: K,
+        at: Instant,
+        reservation: Option<Reservation>,
+    ) -> Self {
+        Self {
+            mapping,
+            key,
+            at,
+            reservation,
+        }
+    }
+
+    /// The key the token was reserved for.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// When the token may be used, according to the mapping's clock. If the mapping is paused
+    /// and resumed after the token was reserved, it may be used later than this, by how long
+    /// it was paused. See `floodgate::JumpingWindow::resume`.
+    pub fn at(&self) -> Instant {
+        self.at
+    }
+
+    /// Whether the token was booked out of a coming window, rather than taken from the
+    /// current one.
+    pub fn is_booked(&self) -> bool {
+        self.reservation
+            .as_ref()
+            .is_some_and(Reservation::is_booked)
+    }
+
+    /// Keep the token, so that it isn't released when the reservation is dropped.
+    pub fn commit(mut self) {
+        self.reservation = None;
+    }
+}
+
+impl<K, C, S> Drop for KeyedReservation<'_, K, C, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    fn drop(&mut self) {
+        if let Some(reservation) = self.reservation.take() {
+            self.mapping.cancel_reservation(&self.key, reservation);
+        }
+    }
+}
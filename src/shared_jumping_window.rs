@@ -0,0 +1,431 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+#[cfg(feature = "tokio")]
+use crate::wait_queue::WaitQueue;
+use crate::{
+    clock::Instant, mode::ModeCell, EnforcementMode, InvalidWindow, JumpingWindow, Rate,
+    RateLimitInfo, RateLimited, RateLimiter,
+};
+
+/// A `floodgate::JumpingWindow` that can be shared between threads and tasks.
+///
+/// Every method takes `&self`, and cloning is cheap: clones share the same window. This is the
+/// recommended way to use a single, un-keyed cooldown from many places at once. For keyed
+/// cooldowns, use `floodgate::FixedMapping` instead.
+///
+/// Clones also share the window's `floodgate::EnforcementMode`. See
+/// `SharedJumpingWindow::set_mode`.
+///
+/// For method documentation, please see `floodgate::JumpingWindow`.
+///
+/// # Examples
+/// ```
+/// use floodgate::SharedJumpingWindow;
+/// use std::{thread, time::Duration};
+///
+/// let cooldown = SharedJumpingWindow::new(1, Duration::from_secs(10));
+///
+/// let handle = thread::spawn({
+///     let cooldown = cooldown.clone();
+///     move || cooldown.trigger(None)
+/// });
+///
+/// assert_eq!(handle.join().unwrap(), None);
+/// assert!(cooldown.trigger(None).is_some());
+/// ```
+#[derive(Debug, Clone)]
+pub struct SharedJumpingWindow {
+    window: Arc<Mutex<JumpingWindow>>,
+    mode: Arc<ModeCell>,
+    #[cfg(feature = "tokio")]
+    pub(crate) waiters: Arc<WaitQueue>,
+}
+
+impl SharedJumpingWindow {
+    /// Create a new SharedJumpingWindow.
+    ///
+    /// # Arguments
+    /// * `capacity` - How many triggers can occur per window.
+    /// * `period` - How long the window is.
+    ///
+    /// # Panics
+    /// Panics if `capacity` or `period` is zero. See `SharedJumpingWindow::try_new`.
+    pub fn new(capacity: u64, period: Duration) -> Self {
+        JumpingWindow::new(capacity, period).into()
+    }
+
+    /// Create a new SharedJumpingWindow, returning an error if `capacity` or `period` is zero.
+    ///
+    /// # Arguments
+    /// * `capacity` - How many triggers can occur per window.
+    /// * `period` - How long the window is.
+    pub fn try_new(capacity: u64, period: Duration) -> Result<Self, InvalidWindow> {
+        JumpingWindow::try_new(capacity, period).map(Self::from)
+    }
+
+    /// Create a new SharedJumpingWindow that allows `rate`.
+    pub fn from_rate(rate: Rate) -> Self {
+        JumpingWindow::from_rate(rate).into()
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.lock().capacity()
+    }
+
+    pub fn period(&self) -> Duration {
+        self.lock().period()
+    }
+
+    pub fn rate(&self) -> Rate {
+        self.lock().rate()
+    }
+
+    pub fn window_start(&self) -> Instant {
+        self.lock().window_start()
+    }
+
+    pub fn tokens(&self, now: Option<Instant>) -> u64 {
+        self.update(|window| window.tokens(now))
+    }
+
+    pub fn next_reset(&self, now: Option<Instant>) -> Duration {
+        self.update(|window| window.next_reset(now))
+    }
+
+    pub fn next_reset_at(&self, now: Option<Instant>) -> Instant {
+        self.lock().next_reset_at(now)
+    }
+
+    pub fn retry_after(&self, now: Option<Instant>) -> Option<Duration> {
+        if self.mode.get() != EnforcementMode::Enforce {
+            return None;
+        }
+        self.update(|window| window.retry_after(now))
+    }
+
+    pub fn retry_at(&self, now: Option<Instant>) -> Option<Instant> {
+        if self.mode.get() != EnforcementMode::Enforce {
+            return None;
+        }
+        self.update(|window| window.retry_at(now))
+    }
+
+    /// How long until `n` triggers can be made at once. See
+    /// `floodgate::JumpingWindow::wait_for`. Outside of `EnforcementMode::Enforce`, triggers are
+    /// never held back, so this is zero as long as `n` doesn't exceed the capacity.
+    pub fn wait_for(&self, n: u64, now: Option<Instant>) -> Option<Duration> {
+        let window = self.lock();
+        if self.mode.get() != EnforcementMode::Enforce && n <= window.capacity() {
+            return Some(Duration::ZERO);
+        }
+        window.wait_for(n, now)
+    }
+
+    pub fn can_trigger(&self, now: Option<Instant>) -> bool {
+        if self.mode.get() != EnforcementMode::Enforce {
+            return true;
+        }
+        self.update(|window| window.can_trigger(now))
+    }
+
+    pub fn trigger(&self, now: Option<Instant>) -> Option<Duration> {
+        let mode = self.mode.get();
+        if mode == EnforcementMode::Bypass {
+            return None;
+        }
+        let retry_after = self.update(|window| window.trigger(now));
+        match self.mode.rejects(mode, retry_after.is_some(), None) {
+            true => retry_after,
+            false => None,
+        }
+    }
+
+    pub fn try_trigger(&self, now: Option<Instant>) -> Result<(), RateLimited> {
+        match self.trigger(now) {
+            Some(retry_after) => Err(RateLimited {
+                retry_after,
+                key: (),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    pub fn trigger_n(&self, cost: u64, now: Option<Instant>) -> Result<(), Duration> {
+        let mode = self.mode.get();
+        if mode == EnforcementMode::Bypass {
+            return Ok(());
+        }
+        let result = self.update(|window| window.trigger_n(cost, now));
+        match self.mode.rejects(mode, result.is_err(), None) {
+            true => result,
+            false => Ok(()),
+        }
+    }
+
+    /// Like `floodgate::JumpingWindow::trigger_info`. In `EnforcementMode::DryRun`, triggers
+    /// that are only let through because of the mode are reported as not allowed.
+    pub fn trigger_info(&self, now: Option<Instant>) -> RateLimitInfo {
+        let mode = self.mode.get();
+        if mode == EnforcementMode::Bypass {
+            let limit = self.capacity();
+            return RateLimitInfo {
+                allowed: true,
+                limit,
+                remaining: limit,
+                retry_after: None,
+                reset_after: Duration::ZERO,
+            };
+        }
+        let info = self.update(|window| window.trigger_info(now));
+        self.mode.rejects(mode, !info.allowed, None);
+        info
+    }
+
+    pub fn reset(&self, now: Option<Instant>) {
+        self.lock().reset(now);
+        #[cfg(feature = "tokio")]
+        self.waiters.notify_reset();
+    }
+
+    /// Give back `n` tokens taken from the window `epoch` identifies. See
+    /// `floodgate::JumpingWindow::refund`.
+    pub fn refund(&self, n: u64, epoch: u64, now: Option<Instant>) {
+        if self.mode.get() == EnforcementMode::Bypass {
+            return;
+        }
+        self.update(|window| window.refund(n, epoch, now))
+    }
+
+    /// A number identifying the current window. See `floodgate::JumpingWindow::epoch`.
+    pub fn epoch(&self, now: Option<Instant>) -> u64 {
+        self.lock().epoch(now)
+    }
+
+    /// How the window treats triggers. See `floodgate::EnforcementMode`.
+    pub fn mode(&self) -> EnforcementMode {
+        self.mode.get()
+    }
+
+    /// Switch how the window, and every clone of it, treats triggers. See
+    /// `floodgate::FixedMapping::set_mode`.
+    ///
+    /// # Arguments
+    /// * `mode` - The new mode.
+    pub fn set_mode(&self, mode: EnforcementMode) {
+        self.mode.set(mode);
+    }
+
+    /// How many triggers were let through in `EnforcementMode::DryRun` that would otherwise
+    /// have been rejected.
+    pub fn dry_run_rejections(&self) -> u64 {
+        self.mode.dry_run_rejections()
+    }
+
+    /// Run `f` with exclusive access to the underlying window, for combining several
+    /// operations atomically. The mode isn't applied to the window's own methods.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::SharedJumpingWindow;
+    /// use std::time::Duration;
+    ///
+    /// let cooldown = SharedJumpingWindow::new(2, Duration::from_secs(10));
+    ///
+    /// let triggered = cooldown.with(|window| {
+    ///     window.trigger(None);
+    ///     window.trigger(None);
+    ///     window.tokens(None)
+    /// });
+    /// assert_eq!(triggered, 0);
+    /// ```
+    pub fn with<T>(&self, f: impl FnOnce(&mut JumpingWindow) -> T) -> T {
+        self.update(f)
+    }
+
+    /// Run `f` on the window, signalling a reset to `reset_notified` waiters if the window
+    /// rolled over or gained tokens in the meantime.
+    fn update<T>(&self, f: impl FnOnce(&mut JumpingWindow) -> T) -> T {
+        let mut window = self.lock();
+        #[cfg(feature = "tokio")]
+        let (start, tokens) = (window.window_start(), window.peek_tokens(None));
+
+        let result = f(&mut window);
+
+        #[cfg(feature = "tokio")]
+        if window.window_start() != start || window.peek_tokens(None) > tokens {
+            self.waiters.notify_reset();
+        }
+        result
+    }
+
+    /// None of the window's methods can panic while holding the lock, so a poisoned lock
+    /// still holds a valid window.
+    fn lock(&self) -> MutexGuard<'_, JumpingWindow> {
+        self.window
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl From<JumpingWindow> for SharedJumpingWindow {
+    fn from(window: JumpingWindow) -> Self {
+        Self {
+            window: Arc::new(Mutex::new(window)),
+            mode: Arc::default(),
+            #[cfg(feature = "tokio")]
+            waiters: Arc::new(WaitQueue::new()),
+        }
+    }
+}
+
+impl fmt::Display for SharedJumpingWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.lock().fmt(f)
+    }
+}
+
+impl RateLimiter for SharedJumpingWindow {
+    fn new(capacity: u64, period: Duration) -> Self {
+        Self::new(capacity, period)
+    }
+
+    fn capacity(&self) -> u64 {
+        SharedJumpingWindow::capacity(self)
+    }
+
+    fn period(&self) -> Duration {
+        SharedJumpingWindow::period(self)
+    }
+
+    fn set_rate(&mut self, capacity: u64, period: Duration) {
+        self.with(|window| window.set_rate(capacity, period))
+    }
+
+    fn tokens(&mut self, now: Option<Instant>) -> u64 {
+        SharedJumpingWindow::tokens(self, now)
+    }
+
+    fn next_reset(&mut self, now: Option<Instant>) -> Duration {
+        SharedJumpingWindow::next_reset(self, now)
+    }
+
+    fn retry_after(&mut self, now: Option<Instant>) -> Option<Duration> {
+        SharedJumpingWindow::retry_after(self, now)
+    }
+
+    fn retry_at(&mut self, now: Option<Instant>) -> Option<Instant> {
+        SharedJumpingWindow::retry_at(self, now)
+    }
+
+    fn wait_for(&mut self, n: u64, now: Option<Instant>) -> Option<Duration> {
+        SharedJumpingWindow::wait_for(self, n, now)
+    }
+
+    fn can_trigger(&mut self, now: Option<Instant>) -> bool {
+        SharedJumpingWindow::can_trigger(self, now)
+    }
+
+    fn trigger(&mut self, now: Option<Instant>) -> Option<Duration> {
+        SharedJumpingWindow::trigger(self, now)
+    }
+
+    fn trigger_n(&mut self, cost: u64, now: Option<Instant>) -> Result<(), Duration> {
+        SharedJumpingWindow::trigger_n(self, cost, now)
+    }
+
+    fn trigger_info(&mut self, now: Option<Instant>) -> RateLimitInfo {
+        SharedJumpingWindow::trigger_info(self, now)
+    }
+
+    fn reset(&mut self, now: Option<Instant>) {
+        SharedJumpingWindow::reset(self, now)
+    }
+
+    fn refund(&mut self, n: u64, epoch: u64, now: Option<Instant>) {
+        SharedJumpingWindow::refund(self, n, epoch, now)
+    }
+
+    fn epoch(&mut self, now: Option<Instant>) -> u64 {
+        SharedJumpingWindow::epoch(self, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicU64, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    use super::SharedJumpingWindow;
+    use crate::EnforcementMode;
+
+    #[test]
+    fn concurrent_triggers_never_exceed_capacity() {
+        let cooldown = SharedJumpingWindow::new(100, Duration::from_secs(3600));
+        let accepted = AtomicU64::new(0);
+
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..1_000 {
+                        if cooldown.trigger(None).is_none() {
+                            accepted.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+
+        assert_eq!(accepted.load(Ordering::Relaxed), 100);
+        assert_eq!(cooldown.tokens(None), 0);
+    }
+
+    #[test]
+    fn bypass_leaves_the_window_untouched() {
+        let cooldown = SharedJumpingWindow::new(1, Duration::from_secs(3600));
+        cooldown.clone().set_mode(EnforcementMode::Bypass);
+
+        for _ in 0..3 {
+            assert_eq!(cooldown.trigger(None), None);
+            assert!(cooldown.trigger_info(None).allowed);
+        }
+        assert_eq!(cooldown.tokens(None), 1);
+
+        cooldown.set_mode(EnforcementMode::DryRun);
+        assert_eq!(cooldown.trigger(None), None);
+        assert_eq!(cooldown.trigger(None), None);
+        assert_eq!(cooldown.trigger_n(2, None), Ok(()));
+        assert_eq!(cooldown.tokens(None), 0);
+        assert_eq!(cooldown.dry_run_rejections(), 2);
+    }
+
+    #[test]
+    fn concurrent_triggers_across_windows() {
+        let period = Duration::from_millis(50);
+        let cooldown = SharedJumpingWindow::new(10, period);
+        let accepted = AtomicU64::new(0);
+
+        let start = std::time::Instant::now();
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    while start.elapsed() < period * 4 {
+                        if cooldown.trigger(None).is_none() {
+                            accepted.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+
+        // the loop spans at most 5 windows, each allowing 10 triggers.
+        let windows = start.elapsed().as_nanos() / period.as_nanos() + 1;
+        assert!(accepted.load(Ordering::Relaxed) as u128 <= windows * 10);
+    }
+}
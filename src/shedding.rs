@@ -0,0 +1,170 @@
+use crate::jumping_window_core::{random_seed, splitmix64};
+
+/// Rejects triggers at random once a window is nearly used up, set with
+/// `floodgate::JumpingWindow::load_shedding`, so that load is turned away gradually instead of
+/// all at once when the window is exhausted, like RED queue management.
+///
+/// Once more than `threshold` of the capacity has been used, each trigger is rejected with a
+/// probability rising from 0 to 1 as the last tokens are used up:
+/// `((used - threshold) / (1 - threshold)) ^ curve`. The curve is linear by default; a higher
+/// one sheds less at first and more near the end.
+///
+/// The random numbers come from the same kind of generator as `floodgate::Jitter`, seeded
+/// randomly. Use `Shedding::seed` to make the rejections reproducible, or `Shedding::rng` to
+/// draw the numbers some other way.
+///
+/// # Examples
+/// ```
+/// use floodgate::{JumpingWindow, Shedding};
+/// use std::time::Duration;
+///
+/// let shedding = Shedding::new(0.5).seed(3);
+/// let mut cooldown = JumpingWindow::new(10, Duration::from_secs(10)).load_shedding(shedding);
+///
+/// // the first half of the window is never shed.
+/// for _ in 0..5 {
+///     assert_eq!(cooldown.trigger(None), None);
+/// }
+/// let info = cooldown.trigger_info(None);
+/// if !info.allowed {
+///     assert!(info.is_soft_rejection());
+/// }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Shedding {
+    threshold: f64,
+    curve: f64,
+    state: u64,
+    next: fn(&mut u64) -> u64,
+}
+
+impl Shedding {
+    /// Create a new Shedding that starts rejecting triggers once more than `threshold` of the
+    /// capacity has been used.
+    ///
+    /// # Arguments
+    /// * `threshold` - The fraction of the capacity to allow freely, from `0.0` to `1.0`.
+    ///
+    /// # Panics
+    /// Panics if `threshold` isn't between `0.0` and `1.0`.
+    pub fn new(threshold: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&threshold),
+            "threshold must be between 0 and 1"
+        );
+        Self {
+            threshold,
+            curve: 1.0,
+            state: random_seed(),
+            next: splitmix64,
+        }
+    }
+
+    /// Raise the probability to the power of `curve`.
+    ///
+    /// # Panics
+    /// Panics if `curve` isn't a positive number.
+    pub fn curve(mut self, curve: f64) -> Self {
+        assert!(curve > 0.0 && curve.is_finite(), "curve must be positive");
+        self.curve = curve;
+        self
+    }
+
+    /// Seed the generator, so that the same seed always rejects the same triggers.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.state = seed;
+        self
+    }
+
+    /// Draw the random numbers with `next` instead of the built-in generator. `next` is given
+    /// the generator's state, which starts out as the seed, and returns the next number. A
+    /// trigger is rejected if the number, as a fraction of `u64::MAX`, is below the
+    /// probability.
+    pub fn rng(mut self, next: fn(&mut u64) -> u64) -> Self {
+        self.next = next;
+        self
+    }
+
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
+    /// The probability of rejecting a trigger once `used` of the capacity has been used.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::Shedding;
+    ///
+    /// let shedding = Shedding::new(0.8).curve(2.0);
+    /// assert_eq!(shedding.probability(0.5), 0.0);
+    /// assert!((shedding.probability(0.9) - 0.25).abs() < 1e-9);
+    /// assert_eq!(shedding.probability(1.0), 1.0);
+    /// ```
+    pub fn probability(&self, used: f64) -> f64 {
+        if used <= self.threshold {
+            return 0.0;
+        }
+        let over = (used - self.threshold) / (1.0 - self.threshold);
+        over.min(1.0).powf(self.curve)
+    }
+
+    /// A copy for the `n`th of several windows, so that they don't all draw the same numbers.
+    pub(crate) fn fork(&self, n: u64) -> Self {
+        Self {
+            state: self.state.wrapping_add(n),
+            ..*self
+        }
+    }
+
+    /// Whether to reject a trigger once `used` of the capacity has been used.
+    pub(crate) fn sheds(&mut self, used: f64) -> bool {
+        let probability = self.probability(used);
+        if probability <= 0.0 {
+            return false;
+        }
+        let random = (self.next)(&mut self.state);
+        (random as f64 / u64::MAX as f64) < probability
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Shedding;
+    use crate::{FixedMapping, JumpingWindow, ManualClock};
+
+    /// Which of 100 triggers made in a row were allowed.
+    fn pattern(mut trigger: impl FnMut() -> bool) -> Vec<bool> {
+        (0..100).map(|_| trigger()).collect()
+    }
+
+    #[test]
+    fn seeded_shedding_is_reproducible() {
+        let shedding = Shedding::new(0.5).curve(2.0).seed(42);
+        let period = Duration::from_secs(10);
+        let mut windows = [0, 1].map(|_| JumpingWindow::new(100, period).load_shedding(shedding));
+
+        let [first, second] = windows.each_mut().map(|window| {
+            pattern(|| {
+                let info = window.trigger_info(None);
+                assert!(info.allowed || info.is_soft_rejection() || info.remaining == 0);
+                info.allowed
+            })
+        });
+        assert_eq!(first, second);
+        assert!(first[..50].iter().all(|&allowed| allowed));
+        assert!(first[50..].contains(&false));
+    }
+
+    #[test]
+    fn keys_shed_independently() {
+        let mapping = FixedMapping::with_clock(100, Duration::from_secs(10), ManualClock::new())
+            .load_shedding(Shedding::new(0.0).seed(1));
+
+        let first = pattern(|| mapping.trigger(&1).is_none());
+        let second = pattern(|| mapping.trigger(&2).is_none());
+        assert_ne!(first, second);
+        assert_eq!(mapping.tokens(&3), 100);
+    }
+}
@@ -0,0 +1,236 @@
+use std::{
+    borrow::Borrow,
+    future::Future,
+    hash::{BuildHasher, Hash},
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use crate::{
+    clock::{self, Instant},
+    Clock, DynamicMapping, Elapsed, FixedMapping, JumpingWindow, RateLimiter, ReserveError,
+};
+
+/// A way to sleep in an async runtime, so that the crate's async helpers can run on any of
+/// them. With the `tokio` feature, the methods without a sleeper use `tokio::time::sleep`.
+///
+/// This is implemented for every function returning a future, such as
+/// `async_std::task::sleep`, or `|duration| async move { smol::Timer::after(duration).await; }`.
+pub trait Sleeper {
+    /// The future returned by `Sleeper::sleep`.
+    type Sleep: Future<Output = ()>;
+
+    /// A future that completes once `duration` has passed.
+    fn sleep(&self, duration: Duration) -> Self::Sleep;
+}
+
+impl<F, Fut> Sleeper for F
+where
+    F: Fn(Duration) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    type Sleep = Fut;
+
+    fn sleep(&self, duration: Duration) -> Fut {
+        self(duration)
+    }
+}
+
+/// Trigger with `trigger`, sleeping for the returned retry-after until it succeeds. Nothing is
+/// consumed while sleeping, so dropping the future is always safe.
+pub(crate) async fn acquire(
+    mut trigger: impl FnMut() -> Option<Duration>,
+    deadline: Option<Instant>,
+    sleeper: &impl Sleeper,
+) -> Result<(), Elapsed> {
+    loop {
+        let Some(retry_after) = trigger() else {
+            return Ok(());
+        };
+
+        if let Some(deadline) = deadline {
+            let ready_at = clock::now().checked_add(retry_after);
+            if ready_at.is_none_or(|ready_at| ready_at > deadline) {
+                return Err(Elapsed);
+            }
+        }
+
+        sleeper.sleep(retry_after).await;
+    }
+}
+
+/// Sleep for the duration returned by `wait`, then call `cycle`, for as long as `mapping` is
+/// alive.
+pub(crate) async fn cycles<M>(
+    mapping: Weak<M>,
+    wait: impl Fn(&M) -> Duration,
+    cycle: impl Fn(&M),
+    sleeper: impl Sleeper,
+) {
+    while let Some(duration) = mapping.upgrade().map(|mapping| wait(&mapping)) {
+        sleeper.sleep(duration).await;
+        match mapping.upgrade() {
+            Some(mapping) => cycle(&mapping),
+            None => break,
+        }
+    }
+}
+
+impl<C: Clock + Default> JumpingWindow<C> {
+    /// Like `JumpingWindow::acquire`, but sleeps with `sleeper`, so that it works in any async
+    /// runtime.
+    ///
+    /// # Arguments
+    /// * `sleeper` - How to sleep, such as `async_std::task::sleep`.
+    pub async fn acquire_with(&mut self, sleeper: impl Sleeper) {
+        let _ = acquire(|| self.trigger(None), None, &sleeper).await;
+    }
+}
+
+impl<K, L, C, S> FixedMapping<K, L, C, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    L: RateLimiter,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    /// Trigger the cooldown for `key`, sleeping with `sleeper` until it can be triggered, so
+    /// that it works in any async runtime. Unlike `FixedMapping::acquire`, waiters on the same
+    /// key aren't served in order.
+    ///
+    /// # Arguments
+    /// * `key` - The key to trigger.
+    /// * `sleeper` - How to sleep, such as `async_std::task::sleep`.
+    pub async fn acquire_with<Q>(&self, key: &Q, sleeper: impl Sleeper)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let _ = acquire(|| self.trigger(key), None, &sleeper).await;
+    }
+
+    /// Like `FixedMapping::acquire_with`, but gives up with `Err(Elapsed)` if the wait would
+    /// exceed `timeout`. See `floodgate::JumpingWindow::acquire_timeout`.
+    ///
+    /// # Arguments
+    /// * `key` - The key to trigger.
+    /// * `timeout` - The longest time to wait.
+    /// * `sleeper` - How to sleep, such as `async_std::task::sleep`.
+    pub async fn acquire_timeout_with<Q>(
+        &self,
+        key: &Q,
+        timeout: Duration,
+        sleeper: impl Sleeper,
+    ) -> Result<(), Elapsed>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let deadline = clock::now().checked_add(timeout);
+        acquire(|| self.trigger(key), deadline, &sleeper).await
+    }
+
+    /// A future that cycles the mapping like `FixedMapping::start`, sleeping with `sleeper`,
+    /// for spawning on any async runtime. It only holds a weak reference to the mapping, and
+    /// completes once the mapping is dropped; to stop it sooner, cancel its task.
+    ///
+    /// # Arguments
+    /// * `mapping` - The FixedMapping, wrapped in an Arc.
+    /// * `sleeper` - How to sleep, such as `async_std::task::sleep`.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::FixedMapping;
+    /// use std::{sync::Arc, time::Duration};
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let mapping = Arc::new(FixedMapping::<u64>::new(1, Duration::from_secs(10)));
+    /// // with async-std, this would be `async_std::task::spawn(FixedMapping::cycler(
+    /// // mapping.clone(), async_std::task::sleep))`.
+    /// let cycler = tokio::spawn(FixedMapping::cycler(mapping.clone(), tokio::time::sleep));
+    ///
+    /// drop(mapping);
+    /// cycler.await.unwrap();
+    /// # }
+    /// ```
+    pub fn cycler(mapping: Arc<Self>, sleeper: impl Sleeper) -> impl Future<Output = ()> {
+        let mapping = Arc::downgrade(&mapping);
+        cycles(mapping, Self::cycle_period, Self::cycle_or_warn, sleeper)
+    }
+}
+
+impl<K, C, S> FixedMapping<K, JumpingWindow, C, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    /// Like `FixedMapping::pace`, but sleeps with `sleeper`, so that it works in any async
+    /// runtime.
+    ///
+    /// # Arguments
+    /// * `key` - The key to reserve a token of.
+    /// * `sleeper` - How to sleep, such as `async_std::task::sleep`.
+    pub async fn pace_with<Q>(&self, key: &Q, sleeper: impl Sleeper) -> Result<(), ReserveError>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let reservation = self.reserve(key)?;
+        let wait = reservation
+            .at()
+            .saturating_duration_since(self.clock().now());
+        sleeper.sleep(wait).await;
+        reservation.commit();
+        Ok(())
+    }
+}
+
+impl<K, L, C, S> DynamicMapping<K, L, C, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    L: RateLimiter,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    /// Trigger the cooldown for `key`, sleeping with `sleeper` until it can be triggered. See
+    /// `floodgate::FixedMapping::acquire_with`.
+    ///
+    /// # Arguments
+    /// * `key` - The key to trigger.
+    /// * `capacity` - The capacity of `key`.
+    /// * `period` - The period of `key`.
+    /// * `sleeper` - How to sleep, such as `async_std::task::sleep`.
+    pub async fn acquire_with<Q>(
+        &self,
+        key: &Q,
+        capacity: u64,
+        period: Duration,
+        sleeper: impl Sleeper,
+    ) where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let trigger = || self.trigger(key, capacity, period);
+        let _ = acquire(trigger, None, &sleeper).await;
+    }
+
+    /// A future that cycles the mapping, sleeping with `sleeper`, for spawning on any async
+    /// runtime. See `floodgate::FixedMapping::cycler`.
+    ///
+    /// # Arguments
+    /// * `mapping` - The DynamicMapping, wrapped in an Arc.
+    /// * `sleeper` - How to sleep, such as `async_std::task::sleep`.
+    pub fn cycler(mapping: Arc<Self>, sleeper: impl Sleeper) -> impl Future<Output = ()> {
+        let mapping = Arc::downgrade(&mapping);
+        cycles(
+            mapping,
+            Self::cycle_period,
+            |mapping| {
+                mapping.cycle();
+            },
+            sleeper,
+        )
+    }
+}
@@ -0,0 +1,441 @@
+use std::time::Duration;
+
+use crate::clock::{self, Instant};
+use crate::RateLimiter;
+
+/// An approximation of `floodgate::SlidingWindow` that only keeps two counters.
+///
+/// Triggers are counted in fixed windows, like `floodgate::JumpingWindow`, but the count of the
+/// previous window is also kept, weighted by how much of it still overlaps the trailing period.
+/// For example, 30% of the way into the current window, 70% of the previous window's triggers
+/// are counted against the capacity.
+///
+/// This assumes the previous window's triggers were spread evenly. In the worst case, where
+/// all of them happened at the very end of the previous window, a span of length `d` can contain
+/// up to `capacity * (1 + d / period)` triggers, so a full trailing period can contain just
+/// under `2 * capacity`. Compare this to `floodgate::JumpingWindow`, where `2 * capacity`
+/// triggers can happen almost at once, and `floodgate::SlidingWindow`, which is exact but needs
+/// `capacity` timestamps of memory.
+#[derive(Debug)]
+pub struct SlidingCounter {
+    pub(crate) capacity: u64,
+    pub(crate) period: Duration,
+
+    window_start: Instant,
+    current: u64,
+    previous: u64,
+}
+
+impl SlidingCounter {
+    /// Create a new SlidingCounter.
+    ///
+    /// # Arguments
+    /// * `capacity` - How many triggers can occur (approximately) in any trailing period.
+    /// * `period` - How long the trailing period is.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::SlidingCounter;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = SlidingCounter::new(4, Duration::from_secs(10));
+    /// cooldown.reset(Some(now));
+    ///
+    /// for _ in 0..4 {
+    ///     assert_eq!(cooldown.trigger(Some(now)), None);
+    /// }
+    ///
+    /// // halfway into the next window, half of the previous triggers still count.
+    /// let later = now + Duration::from_secs(15);
+    /// assert_eq!(cooldown.tokens(Some(later)), 2);
+    /// ```
+    pub fn new(capacity: u64, period: Duration) -> Self {
+        Self {
+            capacity,
+            period,
+            window_start: clock::now(),
+            current: 0,
+            previous: 0,
+        }
+    }
+
+    /// How many triggers (tokens) are left.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::SlidingCounter;
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = SlidingCounter::new(1, Duration::from_secs(10));
+    ///
+    /// assert_eq!(cooldown.tokens(None), 1);
+    /// cooldown.trigger(None);
+    /// assert_eq!(cooldown.tokens(None), 0);
+    /// ```
+    pub fn tokens(&mut self, now: Option<Instant>) -> u64 {
+        let now = now.unwrap_or_else(clock::now);
+        self.roll(now);
+
+        let period = self.period.as_nanos();
+        let used = self.previous_weight(now) + self.current as u128 * period;
+        let available = (self.capacity as u128 * period).saturating_sub(used) / period;
+        available as u64
+    }
+
+    /// Return the time until neither window has any triggers counted against the capacity.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::SlidingCounter;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = SlidingCounter::new(1, Duration::from_secs(10));
+    /// cooldown.reset(Some(now));
+    ///
+    /// assert_eq!(cooldown.next_reset(Some(now)), Duration::ZERO);
+    /// cooldown.trigger(Some(now));
+    /// assert_eq!(cooldown.next_reset(Some(now)), Duration::from_secs(20));
+    /// ```
+    pub fn next_reset(&mut self, now: Option<Instant>) -> Duration {
+        let now = now.unwrap_or_else(clock::now);
+        self.roll(now);
+
+        let remaining = self.period - self.elapsed_in_window(now);
+        if self.current != 0 {
+            remaining + self.period
+        } else if self.previous != 0 {
+            remaining
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    /// Return the time until a trigger is allowed, or `None` if one is allowed now.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::SlidingCounter;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = SlidingCounter::new(2, Duration::from_secs(10));
+    /// cooldown.reset(Some(now));
+    ///
+    /// cooldown.trigger(Some(now));
+    /// assert_eq!(cooldown.retry_after(Some(now)), None);
+    /// cooldown.trigger(Some(now));
+    ///
+    /// // the previous window must be half over before one of its triggers stops counting.
+    /// assert_eq!(cooldown.retry_after(Some(now)), Some(Duration::from_secs(15)));
+    /// ```
+    pub fn retry_after(&mut self, now: Option<Instant>) -> Option<Duration> {
+        let now = now.unwrap_or_else(clock::now);
+        self.check(1, now).err()
+    }
+
+    /// How long until the weighted count leaves room for `n` triggers at once. Returns `None`
+    /// if `n` exceeds the capacity, so that they never can.
+    ///
+    /// # Arguments
+    /// * `n` - How many triggers to wait for.
+    /// * `now` - Optionally specify the current time.
+    pub fn wait_for(&mut self, n: u64, now: Option<Instant>) -> Option<Duration> {
+        if n > self.capacity {
+            return None;
+        }
+
+        let now = now.unwrap_or_else(clock::now);
+        Some(self.check(n, now).err().unwrap_or_default())
+    }
+
+    /// Returns whether or not there are still available triggers.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::SlidingCounter;
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = SlidingCounter::new(1, Duration::from_secs(10));
+    ///
+    /// assert!(cooldown.can_trigger(None));
+    /// cooldown.trigger(None);
+    /// assert!(!cooldown.can_trigger(None));
+    /// ```
+    pub fn can_trigger(&mut self, now: Option<Instant>) -> bool {
+        self.tokens(now) != 0
+    }
+
+    /// Trigger the cooldown.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::SlidingCounter;
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = SlidingCounter::new(1, Duration::from_secs(10));
+    ///
+    /// assert_eq!(cooldown.trigger(None), None);
+    /// assert!(matches!(cooldown.trigger(None), Some(_)));
+    /// ```
+    pub fn trigger(&mut self, now: Option<Instant>) -> Option<Duration> {
+        self.trigger_n(1, now).err()
+    }
+
+    /// Trigger the cooldown, consuming `cost` tokens at once.
+    ///
+    /// Either all `cost` tokens are consumed, or none are. If `cost` is greater than the
+    /// capacity, the trigger can never succeed, so `Duration::MAX` is returned immediately.
+    ///
+    /// # Arguments
+    /// * `cost` - How many tokens to consume.
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::SlidingCounter;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = SlidingCounter::new(4, Duration::from_secs(10));
+    /// cooldown.reset(Some(now));
+    ///
+    /// assert_eq!(cooldown.trigger_n(3, Some(now)), Ok(()));
+    /// assert!(cooldown.trigger_n(3, Some(now)).is_err());
+    /// assert_eq!(cooldown.trigger_n(5, Some(now)), Err(Duration::MAX));
+    /// ```
+    pub fn trigger_n(&mut self, cost: u64, now: Option<Instant>) -> Result<(), Duration> {
+        if cost > self.capacity {
+            return Err(Duration::MAX);
+        }
+
+        let now = now.unwrap_or_else(clock::now);
+        self.check(cost, now)?;
+        self.current += cost;
+        Ok(())
+    }
+
+    /// Reset the cooldown, forgetting the counts of both windows.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::SlidingCounter;
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = SlidingCounter::new(1, Duration::from_secs(10));
+    /// cooldown.trigger(None);
+    ///
+    /// assert!(!cooldown.can_trigger(None));
+    /// cooldown.reset(None);
+    /// assert!(cooldown.can_trigger(None));
+    /// ```
+    pub fn reset(&mut self, now: Option<Instant>) {
+        self.window_start = now.unwrap_or_else(clock::now);
+        self.current = 0;
+        self.previous = 0;
+    }
+
+    /// Give back `n` tokens to the current window. Triggers that have already moved into the
+    /// previous window can't be refunded.
+    ///
+    /// # Arguments
+    /// * `n` - How many tokens to give back.
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::SlidingCounter;
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = SlidingCounter::new(1, Duration::from_secs(10));
+    ///
+    /// assert_eq!(cooldown.trigger(None), None);
+    /// cooldown.refund(1, None);
+    /// assert_eq!(cooldown.trigger(None), None);
+    /// ```
+    pub fn refund(&mut self, n: u64, now: Option<Instant>) {
+        let now = now.unwrap_or_else(clock::now);
+        self.roll(now);
+        self.current = self.current.saturating_sub(n);
+    }
+
+    /// Advance the windows so that `now` falls within the current one.
+    fn roll(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed < self.period {
+            return;
+        }
+
+        if elapsed < self.period * 2 {
+            self.previous = self.current;
+            self.window_start += self.period;
+        } else {
+            self.previous = 0;
+            let windows = elapsed.as_nanos() / self.period.as_nanos().max(1);
+            self.window_start += nanos(windows * self.period.as_nanos());
+        }
+        self.current = 0;
+    }
+
+    fn elapsed_in_window(&self, now: Instant) -> Duration {
+        now.duration_since(self.window_start).min(self.period)
+    }
+
+    /// The previous window's count, weighted by the remaining overlap, in units of
+    /// triggers * period nanoseconds.
+    fn previous_weight(&self, now: Instant) -> u128 {
+        let remaining = self.period - self.elapsed_in_window(now);
+        self.previous as u128 * remaining.as_nanos()
+    }
+
+    /// Check whether `cost` triggers are allowed at `now`, returning the time until they are
+    /// if not. Assumes `cost <= capacity`.
+    fn check(&mut self, cost: u64, now: Instant) -> Result<(), Duration> {
+        self.roll(now);
+
+        let period = self.period.as_nanos();
+        let remaining = period - self.elapsed_in_window(now).as_nanos();
+
+        if self.current + cost <= self.capacity {
+            // the triggers fit in the current window, once enough of the previous one expires.
+            let slack = (self.capacity - self.current - cost) as u128 * period;
+            if self.previous as u128 * remaining <= slack {
+                return Ok(());
+            }
+            Err(nanos(remaining - slack / self.previous as u128))
+        } else {
+            // they only fit once the current window becomes the previous one.
+            let slack = (self.capacity - cost) as u128 * period;
+            let into_next = period - slack / self.current as u128;
+            Err(nanos(remaining + into_next))
+        }
+    }
+}
+
+fn nanos(nanos: u128) -> Duration {
+    Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
+}
+
+impl RateLimiter for SlidingCounter {
+    fn new(capacity: u64, period: Duration) -> Self {
+        Self::new(capacity, period)
+    }
+
+    fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    fn period(&self) -> Duration {
+        self.period
+    }
+
+    fn set_rate(&mut self, capacity: u64, period: Duration) {
+        self.capacity = capacity;
+        self.period = period;
+    }
+
+    fn tokens(&mut self, now: Option<Instant>) -> u64 {
+        self.tokens(now)
+    }
+
+    fn next_reset(&mut self, now: Option<Instant>) -> Duration {
+        self.next_reset(now)
+    }
+
+    fn retry_after(&mut self, now: Option<Instant>) -> Option<Duration> {
+        self.retry_after(now)
+    }
+
+    fn wait_for(&mut self, n: u64, now: Option<Instant>) -> Option<Duration> {
+        self.wait_for(n, now)
+    }
+
+    fn can_trigger(&mut self, now: Option<Instant>) -> bool {
+        self.can_trigger(now)
+    }
+
+    fn trigger(&mut self, now: Option<Instant>) -> Option<Duration> {
+        self.trigger(now)
+    }
+
+    fn trigger_n(&mut self, cost: u64, now: Option<Instant>) -> Result<(), Duration> {
+        self.trigger_n(cost, now)
+    }
+
+    fn reset(&mut self, now: Option<Instant>) {
+        self.reset(now)
+    }
+
+    fn refund(&mut self, n: u64, _epoch: u64, now: Option<Instant>) {
+        self.refund(n, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::SlidingCounter;
+
+    fn accepted(counter: &mut SlidingCounter, now: Instant) -> u64 {
+        let mut accepted = 0;
+        while counter.trigger(Some(now)).is_none() {
+            accepted += 1;
+        }
+        accepted
+    }
+
+    #[test]
+    fn worst_case_overshoot() {
+        let start = Instant::now();
+        let mut counter = SlidingCounter::new(10, Duration::from_secs(10));
+        counter.reset(Some(start));
+
+        // the whole capacity is used right at the end of the first window...
+        assert_eq!(
+            accepted(&mut counter, start + Duration::from_millis(9_999)),
+            10
+        );
+
+        // ...but halfway into the next window, half of it is assumed to have expired. The
+        // trailing period (5s, 15s] now contains 15 triggers, 1.5x the capacity.
+        assert_eq!(accepted(&mut counter, start + Duration::from_secs(15)), 5);
+    }
+
+    #[test]
+    fn retry_after_is_honest() {
+        let start = Instant::now();
+        let mut counter = SlidingCounter::new(10, Duration::from_secs(10));
+        counter.reset(Some(start));
+
+        let mut now = start;
+        for _ in 0..100 {
+            match counter.trigger(Some(now)) {
+                None => now += Duration::from_millis(250),
+                Some(retry_after) => {
+                    now += retry_after;
+                    assert_eq!(counter.trigger(Some(now)), None);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,367 @@
+use std::{collections::VecDeque, time::Duration};
+
+use crate::clock::{self, Instant};
+use crate::RateLimiter;
+
+/// A strict ratelimit implementation, allowing at most `capacity` triggers in any trailing
+/// `period`.
+///
+/// Unlike `floodgate::JumpingWindow`, a burst at the end of one window can't be followed by
+/// another burst at the start of the next. This is done by keeping the timestamps of recent
+/// triggers, so memory usage grows with the capacity (at most `capacity` timestamps are kept).
+#[derive(Debug)]
+pub struct SlidingWindow {
+    pub(crate) capacity: u64,
+    pub(crate) period: Duration,
+
+    triggers: VecDeque<Instant>,
+}
+
+impl SlidingWindow {
+    /// Create a new SlidingWindow.
+    ///
+    /// # Arguments
+    /// * `capacity` - How many triggers can occur in any trailing period.
+    /// * `period` - How long the trailing period is.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::SlidingWindow;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = SlidingWindow::new(2, Duration::from_secs(10));
+    ///
+    /// assert_eq!(cooldown.trigger(Some(now)), None);
+    /// assert_eq!(cooldown.trigger(Some(now + Duration::from_secs(9))), None);
+    ///
+    /// // the first trigger is still within the trailing 10 seconds...
+    /// let later = now + Duration::from_secs(9);
+    /// assert_eq!(cooldown.trigger(Some(later)), Some(Duration::from_secs(1)));
+    ///
+    /// // ...until it ages out.
+    /// let later = now + Duration::from_secs(10);
+    /// assert_eq!(cooldown.trigger(Some(later)), None);
+    /// ```
+    pub fn new(capacity: u64, period: Duration) -> Self {
+        Self {
+            capacity,
+            period,
+            triggers: VecDeque::new(),
+        }
+    }
+
+    /// How many triggers (tokens) are left in the trailing period.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::SlidingWindow;
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = SlidingWindow::new(1, Duration::from_secs(10));
+    ///
+    /// assert_eq!(cooldown.tokens(None), 1);
+    /// cooldown.trigger(None);
+    /// assert_eq!(cooldown.tokens(None), 0);
+    /// ```
+    pub fn tokens(&mut self, now: Option<Instant>) -> u64 {
+        let now = now.unwrap_or_else(clock::now);
+        self.prune(now);
+        self.capacity.saturating_sub(self.triggers.len() as u64)
+    }
+
+    /// Return the time until every recorded trigger has aged out, so that the full capacity is
+    /// available again.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::SlidingWindow;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = SlidingWindow::new(2, Duration::from_secs(10));
+    ///
+    /// assert_eq!(cooldown.next_reset(Some(now)), Duration::ZERO);
+    /// cooldown.trigger(Some(now));
+    /// cooldown.trigger(Some(now + Duration::from_secs(4)));
+    /// assert_eq!(cooldown.next_reset(Some(now + Duration::from_secs(5))), Duration::from_secs(9));
+    /// ```
+    pub fn next_reset(&mut self, now: Option<Instant>) -> Duration {
+        let now = now.unwrap_or_else(clock::now);
+        self.prune(now);
+
+        match self.triggers.back() {
+            Some(&newest) => self.age_out(newest, now),
+            None => Duration::ZERO,
+        }
+    }
+
+    /// Return the time until the oldest recorded trigger ages out, or `None` if there are still
+    /// triggers available.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::SlidingWindow;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = SlidingWindow::new(1, Duration::from_secs(10));
+    ///
+    /// assert_eq!(cooldown.retry_after(Some(now)), None);
+    /// cooldown.trigger(Some(now));
+    /// let later = now + Duration::from_secs(3);
+    /// assert_eq!(cooldown.retry_after(Some(later)), Some(Duration::from_secs(7)));
+    /// ```
+    pub fn retry_after(&mut self, now: Option<Instant>) -> Option<Duration> {
+        let now = now.unwrap_or_else(clock::now);
+        self.check(1, now).err()
+    }
+
+    /// How long until enough recorded triggers have aged out for `n` more to be made at once.
+    /// Returns `None` if `n` exceeds the capacity, so that they never can.
+    ///
+    /// # Arguments
+    /// * `n` - How many triggers to wait for.
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::SlidingWindow;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let later = now + Duration::from_secs(3);
+    /// let mut cooldown = SlidingWindow::new(2, Duration::from_secs(10));
+    /// cooldown.trigger(Some(now));
+    /// cooldown.trigger(Some(later));
+    ///
+    /// assert_eq!(cooldown.wait_for(1, Some(later)), Some(Duration::from_secs(7)));
+    /// assert_eq!(cooldown.wait_for(2, Some(later)), Some(Duration::from_secs(10)));
+    /// ```
+    pub fn wait_for(&mut self, n: u64, now: Option<Instant>) -> Option<Duration> {
+        if n > self.capacity {
+            return None;
+        }
+
+        let now = now.unwrap_or_else(clock::now);
+        Some(self.check(n, now).err().unwrap_or_default())
+    }
+
+    /// Returns whether or not there are still available triggers.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::SlidingWindow;
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = SlidingWindow::new(1, Duration::from_secs(10));
+    ///
+    /// assert!(cooldown.can_trigger(None));
+    /// cooldown.trigger(None);
+    /// assert!(!cooldown.can_trigger(None));
+    /// ```
+    pub fn can_trigger(&mut self, now: Option<Instant>) -> bool {
+        self.tokens(now) != 0
+    }
+
+    /// Trigger the cooldown.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::SlidingWindow;
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = SlidingWindow::new(1, Duration::from_secs(10));
+    ///
+    /// assert_eq!(cooldown.trigger(None), None);
+    /// assert!(matches!(cooldown.trigger(None), Some(_)));
+    /// ```
+    pub fn trigger(&mut self, now: Option<Instant>) -> Option<Duration> {
+        self.trigger_n(1, now).err()
+    }
+
+    /// Trigger the cooldown, consuming `cost` tokens at once.
+    ///
+    /// Either all `cost` tokens are consumed, or none are. If there aren't enough tokens left,
+    /// the time until enough triggers age out is returned. If `cost` is greater than the
+    /// capacity, the trigger can never succeed, so `Duration::MAX` is returned immediately.
+    ///
+    /// # Arguments
+    /// * `cost` - How many tokens to consume.
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::SlidingWindow;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut cooldown = SlidingWindow::new(3, Duration::from_secs(10));
+    ///
+    /// cooldown.trigger(Some(now));
+    /// cooldown.trigger(Some(now + Duration::from_secs(2)));
+    ///
+    /// // two of the recorded triggers must age out first.
+    /// let later = now + Duration::from_secs(5);
+    /// assert_eq!(cooldown.trigger_n(3, Some(later)), Err(Duration::from_secs(7)));
+    /// assert_eq!(cooldown.trigger_n(1, Some(later)), Ok(()));
+    /// assert_eq!(cooldown.trigger_n(4, Some(later)), Err(Duration::MAX));
+    /// ```
+    pub fn trigger_n(&mut self, cost: u64, now: Option<Instant>) -> Result<(), Duration> {
+        if cost > self.capacity {
+            return Err(Duration::MAX);
+        }
+
+        let now = now.unwrap_or_else(clock::now);
+        self.check(cost, now)?;
+        self.triggers.extend((0..cost).map(|_| now));
+        Ok(())
+    }
+
+    /// Reset the cooldown, forgetting every recorded trigger.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::SlidingWindow;
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = SlidingWindow::new(1, Duration::from_secs(10));
+    /// cooldown.trigger(None);
+    ///
+    /// assert!(!cooldown.can_trigger(None));
+    /// cooldown.reset(None);
+    /// assert!(cooldown.can_trigger(None));
+    /// ```
+    pub fn reset(&mut self, _now: Option<Instant>) {
+        self.triggers.clear();
+    }
+
+    /// Give back `n` tokens by forgetting the `n` most recent triggers.
+    ///
+    /// # Arguments
+    /// * `n` - How many tokens to give back.
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::SlidingWindow;
+    /// use std::time::Duration;
+    ///
+    /// let mut cooldown = SlidingWindow::new(1, Duration::from_secs(10));
+    ///
+    /// assert_eq!(cooldown.trigger(None), None);
+    /// cooldown.refund(1, None);
+    /// assert_eq!(cooldown.trigger(None), None);
+    /// ```
+    pub fn refund(&mut self, n: u64, now: Option<Instant>) {
+        let now = now.unwrap_or_else(clock::now);
+        self.prune(now);
+        let len = self.triggers.len().saturating_sub(n as usize);
+        self.triggers.truncate(len);
+    }
+
+    /// Remove the triggers that are no longer within the trailing period.
+    fn prune(&mut self, now: Instant) {
+        while let Some(&oldest) = self.triggers.front() {
+            if now.duration_since(oldest) < self.period {
+                break;
+            }
+            self.triggers.pop_front();
+        }
+    }
+
+    /// The time until a trigger recorded at `at` ages out.
+    fn age_out(&self, at: Instant, now: Instant) -> Duration {
+        self.period.saturating_sub(now.duration_since(at))
+    }
+
+    /// Check whether `cost` triggers are allowed at `now`, returning the time until they are
+    /// if not. Assumes `cost <= capacity`.
+    fn check(&mut self, cost: u64, now: Instant) -> Result<(), Duration> {
+        self.prune(now);
+
+        let len = self.triggers.len() as u64;
+        if len + cost <= self.capacity {
+            return Ok(());
+        }
+
+        let blocking = (len + cost - self.capacity - 1) as usize;
+        Err(self.age_out(self.triggers[blocking], now))
+    }
+}
+
+impl RateLimiter for SlidingWindow {
+    fn new(capacity: u64, period: Duration) -> Self {
+        Self::new(capacity, period)
+    }
+
+    fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    fn period(&self) -> Duration {
+        self.period
+    }
+
+    fn set_rate(&mut self, capacity: u64, period: Duration) {
+        self.capacity = capacity;
+        self.period = period;
+        while self.triggers.len() as u64 > capacity {
+            self.triggers.pop_front();
+        }
+    }
+
+    fn tokens(&mut self, now: Option<Instant>) -> u64 {
+        self.tokens(now)
+    }
+
+    fn next_reset(&mut self, now: Option<Instant>) -> Duration {
+        self.next_reset(now)
+    }
+
+    fn retry_after(&mut self, now: Option<Instant>) -> Option<Duration> {
+        self.retry_after(now)
+    }
+
+    fn wait_for(&mut self, n: u64, now: Option<Instant>) -> Option<Duration> {
+        self.wait_for(n, now)
+    }
+
+    fn can_trigger(&mut self, now: Option<Instant>) -> bool {
+        self.can_trigger(now)
+    }
+
+    fn trigger(&mut self, now: Option<Instant>) -> Option<Duration> {
+        self.trigger(now)
+    }
+
+    fn trigger_n(&mut self, cost: u64, now: Option<Instant>) -> Result<(), Duration> {
+        self.trigger_n(cost, now)
+    }
+
+    fn reset(&mut self, now: Option<Instant>) {
+        self.reset(now)
+    }
+
+    fn refund(&mut self, n: u64, _epoch: u64, now: Option<Instant>) {
+        self.refund(n, now)
+    }
+}
@@ -0,0 +1,59 @@
+use std::time::Duration;
+
+use crate::clock::SystemTime;
+
+/// The state of every key of a `floodgate::FixedMapping` that is on cooldown, returned by
+/// `floodgate::FixedMapping::export` and restored by `floodgate::FixedMapping::import`.
+///
+/// The snapshot records the wall-clock time it was taken at, so the time it spends being
+/// handed to another process still counts towards the windows. Keys whose limiter is full
+/// aren't included, since they have no state worth restoring.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MappingSnapshot<K> {
+    /// When the snapshot was taken.
+    pub taken_at: SystemTime,
+    /// The state of each key.
+    pub entries: Vec<SnapshotEntry<K>>,
+}
+
+/// The state of one key in a `floodgate::MappingSnapshot`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SnapshotEntry<K> {
+    pub key: K,
+    /// How many triggers were left.
+    pub tokens: u64,
+    /// How long the key's window had been running.
+    pub elapsed: Duration,
+}
+
+/// What `floodgate::FixedMapping::import` does with a key that already has a limiter in the
+/// mapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MergeStrategy {
+    /// Always use the state from the snapshot.
+    #[default]
+    Overwrite,
+    /// Only use the state from the snapshot for keys that have none in the mapping.
+    KeepExisting,
+    /// Use whichever state has fewer triggers left, or, if they have as many, whichever window
+    /// ends later.
+    KeepMoreRestrictive,
+    /// Use whichever state's window started later.
+    KeepNewer,
+}
+
+impl MergeStrategy {
+    /// Whether the `imported` state replaces the `existing` one. Both are `(tokens, elapsed)`.
+    pub(crate) fn replaces(self, imported: (u64, Duration), existing: (u64, Duration)) -> bool {
+        match self {
+            Self::Overwrite => true,
+            Self::KeepExisting => false,
+            Self::KeepMoreRestrictive => {
+                imported.0 < existing.0 || (imported.0 == existing.0 && imported.1 < existing.1)
+            }
+            Self::KeepNewer => imported.1 < existing.1,
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A snapshot of a mapping's counters, returned by `floodgate::FixedMapping::stats` and
+/// `floodgate::DynamicMapping::stats`.
+///
+/// Every counter only ever increases, until the mapping's `reset_stats` is called. Apart from
+/// `dry_run_rejected`, they are only kept once stats have been enabled with the mapping's
+/// `with_stats`, and are zero otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MappingStats {
+    /// How many triggers were allowed.
+    pub accepted: u64,
+    /// How many triggers were rejected.
+    pub rejected: u64,
+    /// How many triggers were let through by `floodgate::EnforcementMode::DryRun` that would
+    /// otherwise have been rejected. They aren't counted as accepted.
+    pub dry_run_rejected: u64,
+    /// How many limiters were created. A key is counted again if its limiter was dropped and
+    /// it is then used again.
+    pub keys_created: u64,
+    /// How many limiters were dropped by cycling or cleaning up the mapping. Limiters dropped
+    /// to stay under `max_keys` are counted by the mapping's `forced_evictions` instead.
+    pub evicted: u64,
+}
+
+/// How much of their capacity a mapping's limiters are using, returned by
+/// `floodgate::FixedMapping::utilization` and `floodgate::DynamicMapping::utilization`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct UtilizationStats {
+    /// How many limiters were checked.
+    pub keys: usize,
+    /// The average utilization of the limiters, from `0.0` to `1.0`.
+    pub mean: f64,
+    /// The utilization of the busiest limiter.
+    pub max: f64,
+}
+
+/// The counters behind `MappingStats`, kept by a `Mapping` once stats are enabled.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    accepted: AtomicU64,
+    rejected: AtomicU64,
+    keys_created: AtomicU64,
+    evicted: AtomicU64,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Metrics>,
+}
+
+/// The `metrics` a named mapping reports, labeled with its name. Registered once, when the
+/// mapping is created, so that reporting is as cheap as counting.
+#[cfg(feature = "metrics")]
+struct Metrics {
+    accepted: metrics::Counter,
+    rejected: metrics::Counter,
+    evicted: metrics::Counter,
+    keys: metrics::Gauge,
+}
+
+#[cfg(feature = "metrics")]
+impl Metrics {
+    fn new(name: &str) -> Self {
+        metrics::describe_counter!("floodgate_accepted_total", "Triggers that were allowed.");
+        metrics::describe_counter!("floodgate_rejected_total", "Triggers that were rejected.");
+        metrics::describe_counter!(
+            "floodgate_evicted_total",
+            "Limiters dropped by cycling or cleaning up the mapping."
+        );
+        metrics::describe_gauge!(
+            "floodgate_keys",
+            "Keys with a limiter, as of the last cycle or cleanup."
+        );
+        let label = [("mapping", name.to_owned())];
+        Self {
+            accepted: metrics::counter!("floodgate_accepted_total", &label),
+            rejected: metrics::counter!("floodgate_rejected_total", &label),
+            evicted: metrics::counter!("floodgate_evicted_total", &label),
+            keys: metrics::gauge!("floodgate_keys", &label),
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl std::fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
+
+impl Counters {
+    /// Counters that also report to `metrics`, labeled with `name`.
+    #[cfg(feature = "metrics")]
+    pub(crate) fn with_metrics(name: &str) -> Self {
+        Self {
+            metrics: Some(Metrics::new(name)),
+            ..Self::default()
+        }
+    }
+
+    /// Count a trigger that was allowed, or rejected.
+    pub(crate) fn trigger(&self, rejected: bool) {
+        match rejected {
+            true => self.rejected.fetch_add(1, Ordering::Relaxed),
+            false => self.accepted.fetch_add(1, Ordering::Relaxed),
+        };
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &self.metrics {
+            match rejected {
+                true => metrics.rejected.increment(1),
+                false => metrics.accepted.increment(1),
+            }
+        }
+    }
+
+    pub(crate) fn key_created(&self) {
+        self.keys_created.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn evicted(&self, n: usize) {
+        if n > 0 {
+            self.evicted.fetch_add(n as u64, Ordering::Relaxed);
+            #[cfg(feature = "metrics")]
+            if let Some(metrics) = &self.metrics {
+                metrics.evicted.increment(n as u64);
+            }
+        }
+    }
+
+    /// Report how many keys have a limiter, after a cycle or cleanup.
+    #[cfg(feature = "metrics")]
+    pub(crate) fn set_keys(&self, keys: usize) {
+        if let Some(metrics) = &self.metrics {
+            metrics.keys.set(keys as f64);
+        }
+    }
+
+    pub(crate) fn snapshot(&self, dry_run_rejected: u64) -> MappingStats {
+        MappingStats {
+            accepted: self.accepted.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            dry_run_rejected,
+            keys_created: self.keys_created.load(Ordering::Relaxed),
+            evicted: self.evicted.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        for counter in [
+            &self.accepted,
+            &self.rejected,
+            &self.keys_created,
+            &self.evicted,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
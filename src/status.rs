@@ -0,0 +1,222 @@
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+};
+
+use crate::{
+    clock::{self, Instant},
+    AtomicJumpingWindow, Clock, DynamicMapping, FixedMapping, JumpingWindow, RateLimitInfo,
+    RateLimiter, SharedJumpingWindow,
+};
+
+/// The state of one of the limits in a `floodgate::snapshot`, under its name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamedRateLimitInfo {
+    /// The name the limit was reported under, such as `"per-ip"`.
+    pub name: String,
+    /// The state of the limit.
+    pub info: RateLimitInfo,
+}
+
+/// A limit that can report its state for a `floodgate::snapshot`, such as a window, or one key
+/// of a mapping. See `floodgate::Named`.
+///
+/// Implement it to include your own limiters in the same report.
+pub trait StatusSource {
+    /// The state of the limit at `now`, without consuming anything, or `None` if it has
+    /// nothing to report, like a key that has no limiter.
+    fn status(&self, now: Instant) -> Option<NamedRateLimitInfo>;
+}
+
+/// The state of every one of `sources` at the same instant, in order, without consuming
+/// anything. Sources with nothing to report are left out.
+///
+/// This is meant for responses listing every limit a caller is subject to, such as the body
+/// of a 429.
+///
+/// # Arguments
+/// * `now` - Optionally specify the current time. It is given to every source, so they should
+///   all read the same clock.
+/// * `sources` - The limits to report.
+///
+/// # Examples
+/// ```
+/// use floodgate::{snapshot, FixedMapping, JumpingWindow, Named};
+/// use std::time::{Duration, Instant};
+///
+/// let per_ip = FixedMapping::new(2, Duration::from_secs(10));
+/// let per_token = FixedMapping::<String>::new(5, Duration::from_secs(60));
+/// let global = JumpingWindow::new(100, Duration::from_secs(1));
+/// per_ip.trigger("10.0.0.1");
+///
+/// let ip = Named::keyed("per-ip", &per_ip, "10.0.0.1");
+/// let token = Named::keyed("per-token", &per_token, "abc");
+/// let global = Named::new("global", &global);
+/// let report = snapshot(Some(Instant::now()), &[&ip, &token, &global]);
+///
+/// // the token hasn't been used, so it has no limiter to report.
+/// assert_eq!(report.len(), 2);
+/// assert_eq!(report[0].name, "per-ip");
+/// assert_eq!(report[0].info.remaining, 1);
+/// assert_eq!(report[1].info.remaining, 100);
+/// ```
+pub fn snapshot(now: Option<Instant>, sources: &[&dyn StatusSource]) -> Vec<NamedRateLimitInfo> {
+    let now = now.unwrap_or_else(clock::now);
+    sources
+        .iter()
+        .filter_map(|source| source.status(now))
+        .collect()
+}
+
+/// A limiter reported under `name`, or, for mappings, one of its keys. Created with
+/// `Named::new` or `Named::keyed`.
+#[derive(Debug)]
+pub struct Named<'a, T: ?Sized, Q: ?Sized = ()> {
+    name: &'a str,
+    limiter: &'a T,
+    key: &'a Q,
+}
+
+impl<'a, T: ?Sized> Named<'a, T> {
+    /// Report `limiter`, such as a `floodgate::JumpingWindow`, under `name`.
+    pub fn new(name: &'a str, limiter: &'a T) -> Self {
+        Self {
+            name,
+            limiter,
+            key: &(),
+        }
+    }
+}
+
+impl<'a, T: ?Sized, Q: ?Sized> Named<'a, T, Q> {
+    /// Report the limiter of `key` in `mapping`, such as a `floodgate::FixedMapping`, under
+    /// `name`.
+    pub fn keyed(name: &'a str, mapping: &'a T, key: &'a Q) -> Self {
+        Self {
+            name,
+            limiter: mapping,
+            key,
+        }
+    }
+
+    fn named(&self, info: RateLimitInfo) -> NamedRateLimitInfo {
+        NamedRateLimitInfo {
+            name: self.name.to_owned(),
+            info,
+        }
+    }
+}
+
+impl<C: Clock> StatusSource for Named<'_, JumpingWindow<C>> {
+    fn status(&self, now: Instant) -> Option<NamedRateLimitInfo> {
+        let window = self.limiter;
+        let remaining = window.peek_tokens(Some(now));
+        Some(self.named(RateLimitInfo {
+            allowed: remaining != 0,
+            limit: window.effective_capacity(Some(now)),
+            remaining,
+            retry_after: window.peek_retry_after(Some(now)),
+            reset_after: window.peek_next_reset(Some(now)),
+        }))
+    }
+}
+
+impl StatusSource for Named<'_, SharedJumpingWindow> {
+    fn status(&self, now: Instant) -> Option<NamedRateLimitInfo> {
+        self.limiter
+            .with(|window| Named::new(self.name, &*window).status(now))
+    }
+}
+
+impl StatusSource for Named<'_, AtomicJumpingWindow> {
+    fn status(&self, now: Instant) -> Option<NamedRateLimitInfo> {
+        let window = self.limiter;
+        let remaining = window.tokens(Some(now));
+        Some(self.named(RateLimitInfo {
+            allowed: remaining != 0,
+            limit: window.capacity(),
+            remaining,
+            retry_after: window.retry_after(Some(now)),
+            reset_after: window.next_reset(Some(now)),
+        }))
+    }
+}
+
+impl<K, L, C, S, Q> StatusSource for Named<'_, FixedMapping<K, L, C, S>, Q>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Borrow<Q>,
+    L: RateLimiter,
+    C: Clock,
+    S: BuildHasher + Clone,
+    Q: Hash + Eq + ?Sized,
+{
+    fn status(&self, now: Instant) -> Option<NamedRateLimitInfo> {
+        let info = self.limiter.status(self.key, Some(now))?;
+        Some(self.named(info))
+    }
+}
+
+impl<K, L, C, S, Q> StatusSource for Named<'_, DynamicMapping<K, L, C, S>, Q>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Borrow<Q>,
+    L: RateLimiter,
+    C: Clock,
+    S: BuildHasher + Clone,
+    Q: Hash + Eq + ?Sized,
+{
+    fn status(&self, now: Instant) -> Option<NamedRateLimitInfo> {
+        let info = self.limiter.status(self.key, Some(now))?;
+        Some(self.named(info))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{snapshot, Named, NamedRateLimitInfo, StatusSource};
+    use crate::{
+        clock::Instant, Clock, FixedMapping, ManualClock, RateLimitInfo, SharedJumpingWindow,
+    };
+
+    /// A limiter of the caller's own, which is always on its last token.
+    struct LastToken;
+
+    impl StatusSource for LastToken {
+        fn status(&self, _now: Instant) -> Option<NamedRateLimitInfo> {
+            Some(NamedRateLimitInfo {
+                name: "custom".into(),
+                info: RateLimitInfo {
+                    allowed: true,
+                    limit: 10,
+                    remaining: 1,
+                    retry_after: None,
+                    reset_after: Duration::from_secs(1),
+                },
+            })
+        }
+    }
+
+    #[test]
+    fn snapshot_reads_every_source_at_once_without_consuming() {
+        let clock = ManualClock::new();
+        let period = Duration::from_secs(10);
+        let mapping = FixedMapping::with_clock(1, period, clock.clone());
+        let global = SharedJumpingWindow::new(3, period);
+        mapping.trigger(&1);
+        global.trigger(None);
+
+        clock.advance(Duration::from_secs(4));
+        let key = Named::keyed("per-key", &mapping, &1);
+        let global = Named::new("global", &global);
+        let sources: [&dyn StatusSource; 3] = [&key, &global, &LastToken];
+        let now = clock.now();
+        let report = snapshot(Some(now), &sources);
+
+        let names: Vec<_> = report.iter().map(|status| status.name.as_str()).collect();
+        assert_eq!(names, ["per-key", "global", "custom"]);
+        assert_eq!(report[0].info.retry_after, Some(Duration::from_secs(6)));
+        assert_eq!(report[1].info.remaining, 2);
+        assert_eq!(snapshot(Some(now), &sources), report);
+    }
+}
@@ -0,0 +1,351 @@
+//! Adapters for throttling a `Stream` with a cooldown.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    hash::Hash,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use futures_core::Stream;
+use pin_project_lite::pin_project;
+use tokio::time::{sleep, Instant, Sleep};
+
+use crate::{FixedMapping, RateLimiter};
+
+/// The default for `RateLimitedBy::max_pending`.
+const DEFAULT_MAX_PENDING: usize = 64;
+
+/// An extension trait for throttling any `Stream` with a cooldown.
+pub trait RateLimitStreamExt: Stream + Sized {
+    /// Only yield an item once `limiter` can be triggered for it, waiting for the next reset
+    /// otherwise. While waiting, the inner stream is not polled.
+    ///
+    /// # Arguments
+    /// * `limiter` - The limiter to trigger for each item.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{stream::RateLimitStreamExt, JumpingWindow};
+    /// use futures::{stream, StreamExt};
+    /// use std::time::{Duration, Instant};
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let start = Instant::now();
+    /// let window = JumpingWindow::new(2, Duration::from_millis(20));
+    ///
+    /// let items: Vec<_> = stream::iter(0..5).ratelimit(window).collect().await;
+    /// assert_eq!(items, [0, 1, 2, 3, 4]);
+    /// // the last item had to wait for the third window.
+    /// assert!(start.elapsed() >= Duration::from_millis(40));
+    /// # }
+    /// ```
+    fn ratelimit<L: RateLimiter>(self, limiter: L) -> RateLimited<Self, L> {
+        RateLimited {
+            stream: self,
+            limiter,
+            item: None,
+            sleep: None,
+        }
+    }
+
+    /// Throttle the items per key, using `mapping` and the key returned by `key`.
+    ///
+    /// Items with the same key are yielded in order, but an item whose key isn't limited is
+    /// not held back by items waiting on other keys. Up to `RateLimitedBy::max_pending` items
+    /// are buffered while waiting; after that, the inner stream is not polled until one of
+    /// them is yielded.
+    ///
+    /// # Arguments
+    /// * `mapping` - The mapping to trigger for each item.
+    /// * `key` - A function returning the key of an item.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::{stream::RateLimitStreamExt, FixedMapping};
+    /// use futures::{stream, StreamExt};
+    /// use std::{sync::Arc, time::Duration};
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let mapping = Arc::new(FixedMapping::new(1, Duration::from_millis(20)));
+    ///
+    /// let jobs = stream::iter([("a", 1), ("a", 2), ("b", 3)]);
+    /// let done: Vec<_> = jobs.ratelimit_by(mapping, |job| job.0).collect().await;
+    /// // "b" doesn't wait behind the second "a".
+    /// assert_eq!(done, [("a", 1), ("b", 3), ("a", 2)]);
+    /// # }
+    /// ```
+    fn ratelimit_by<K, L, F>(
+        self,
+        mapping: Arc<FixedMapping<K, L>>,
+        key: F,
+    ) -> RateLimitedBy<Self, K, L, F>
+    where
+        K: Eq + Hash + Clone + Send + Sync + 'static,
+        L: RateLimiter,
+        F: FnMut(&Self::Item) -> K,
+    {
+        RateLimitedBy {
+            stream: self,
+            mapping,
+            key,
+            blocked: HashMap::new(),
+            pending: 0,
+            max_pending: DEFAULT_MAX_PENDING,
+            done: false,
+        }
+    }
+}
+
+impl<S: Stream> RateLimitStreamExt for S {}
+
+pin_project! {
+    /// A stream throttled by a single limiter, created by `RateLimitStreamExt::ratelimit`.
+    #[must_use = "streams do nothing unless polled"]
+    pub struct RateLimited<S: Stream, L> {
+        #[pin]
+        stream: S,
+        limiter: L,
+        item: Option<S::Item>,
+        sleep: Option<Pin<Box<Sleep>>>,
+    }
+}
+
+impl<S: Stream, L> RateLimited<S, L> {
+    /// The limiter throttling the stream.
+    pub fn limiter(&self) -> &L {
+        &self.limiter
+    }
+
+    /// Consume the adapter, returning the inner stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S: Stream, L: RateLimiter> Stream for RateLimited<S, L> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        loop {
+            if this.item.is_none() {
+                match ready!(this.stream.as_mut().poll_next(cx)) {
+                    Some(item) => *this.item = Some(item),
+                    None => return Poll::Ready(None),
+                }
+            }
+
+            if let Some(sleep) = this.sleep {
+                ready!(sleep.as_mut().poll(cx));
+                *this.sleep = None;
+            }
+
+            match this.limiter.trigger(None) {
+                None => return Poll::Ready(this.item.take()),
+                Some(retry_after) => *this.sleep = Some(Box::pin(sleep(retry_after))),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let buffered = self.item.is_some() as usize;
+        let (lower, upper) = self.stream.size_hint();
+        (
+            lower.saturating_add(buffered),
+            upper.and_then(|upper| upper.checked_add(buffered)),
+        )
+    }
+}
+
+/// The items of a key that is waiting for its cooldown.
+struct Blocked<T> {
+    items: VecDeque<T>,
+    sleep: Pin<Box<Sleep>>,
+}
+
+pin_project! {
+    /// A stream throttled per key, created by `RateLimitStreamExt::ratelimit_by`.
+    #[must_use = "streams do nothing unless polled"]
+    pub struct RateLimitedBy<S, K, L, F>
+    where
+        S: Stream,
+        K: Eq,
+        K: Hash,
+        K: Clone,
+        K: Send,
+        K: Sync,
+        K: 'static,
+    {
+        #[pin]
+        stream: S,
+        mapping: Arc<FixedMapping<K, L>>,
+        key: F,
+        blocked: HashMap<K, Blocked<S::Item>>,
+        pending: usize,
+        max_pending: usize,
+        done: bool,
+    }
+}
+
+impl<S, K, L, F> RateLimitedBy<S, K, L, F>
+where
+    S: Stream,
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+{
+    /// Set how many items can be buffered while waiting for their keys. Defaults to 64.
+    ///
+    /// # Panics
+    /// Panics if `max_pending` is zero.
+    pub fn max_pending(mut self, max_pending: usize) -> Self {
+        assert!(max_pending != 0, "max_pending must be greater than zero");
+        self.max_pending = max_pending;
+        self
+    }
+
+    /// The mapping throttling the stream.
+    pub fn mapping(&self) -> &Arc<FixedMapping<K, L>> {
+        &self.mapping
+    }
+}
+
+impl<S, K, L, F> Stream for RateLimitedBy<S, K, L, F>
+where
+    S: Stream,
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    L: RateLimiter,
+    F: FnMut(&S::Item) -> K,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        // items that have been waiting come first.
+        let mut ready_key = None;
+        for (key, blocked) in this.blocked.iter_mut() {
+            if blocked.sleep.as_mut().poll(cx).is_pending() {
+                continue;
+            }
+            match this.mapping.trigger(key) {
+                None => {
+                    ready_key = Some(key.clone());
+                    break;
+                }
+                Some(retry_after) => reset(&mut blocked.sleep, retry_after, cx),
+            }
+        }
+
+        if let Some(key) = ready_key {
+            let blocked = this.blocked.get_mut(&key).expect("the key was just found");
+            let item = blocked.items.pop_front();
+            if blocked.items.is_empty() {
+                this.blocked.remove(&key);
+            } else {
+                // try the next item straight away.
+                blocked.sleep.as_mut().reset(Instant::now());
+            }
+            *this.pending -= 1;
+            return Poll::Ready(item);
+        }
+
+        while !*this.done && *this.pending < *this.max_pending {
+            let Poll::Ready(item) = this.stream.as_mut().poll_next(cx) else {
+                break;
+            };
+            let Some(item) = item else {
+                *this.done = true;
+                break;
+            };
+
+            let key = (this.key)(&item);
+            if let Some(blocked) = this.blocked.get_mut(&key) {
+                blocked.items.push_back(item);
+                *this.pending += 1;
+                continue;
+            }
+
+            match this.mapping.trigger(&key) {
+                None => return Poll::Ready(Some(item)),
+                Some(retry_after) => {
+                    let mut sleep = Box::pin(sleep(retry_after));
+                    // register the timer with this task.
+                    let _ = sleep.as_mut().poll(cx);
+                    this.blocked.insert(
+                        key,
+                        Blocked {
+                            items: VecDeque::from([item]),
+                            sleep,
+                        },
+                    );
+                    *this.pending += 1;
+                }
+            }
+        }
+
+        if *this.done && *this.pending == 0 {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Reset `sleep` to fire after `retry_after`, and register it with the current task.
+fn reset(sleep: &mut Pin<Box<Sleep>>, retry_after: Duration, cx: &mut Context<'_>) {
+    sleep.as_mut().reset(Instant::now() + retry_after);
+    let _ = sleep.as_mut().poll(cx);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use futures::{stream, StreamExt};
+
+    use super::RateLimitStreamExt;
+    use crate::FixedMapping;
+
+    #[tokio::test]
+    async fn keys_keep_their_order() {
+        let mapping = Arc::new(FixedMapping::new(1, Duration::from_millis(20)));
+        let items = [(1, 0), (1, 1), (2, 2), (1, 3), (2, 4), (3, 5)];
+
+        let done: Vec<_> = stream::iter(items)
+            .ratelimit_by(mapping, |item| item.0)
+            .collect()
+            .await;
+
+        assert_eq!(done.len(), items.len());
+        for key in 1..=3 {
+            let order: Vec<_> = done.iter().filter(|item| item.0 == key).collect();
+            let expected: Vec<_> = items.iter().filter(|item| item.0 == key).collect();
+            assert_eq!(order, expected);
+        }
+        // the un-limited keys came first.
+        assert_eq!(done[..3], [(1, 0), (2, 2), (3, 5)]);
+    }
+
+    #[tokio::test]
+    async fn pending_items_are_bounded() {
+        let mapping = Arc::new(FixedMapping::new(1, Duration::from_secs(3600)));
+        let polled = std::cell::Cell::new(0);
+
+        let mut limited = stream::iter(0..100)
+            .inspect(|_| polled.set(polled.get() + 1))
+            .ratelimit_by(mapping, |_| 1)
+            .max_pending(4);
+
+        assert_eq!(limited.next().await, Some(0));
+        let next = tokio::time::timeout(Duration::from_millis(10), limited.next()).await;
+        assert!(next.is_err());
+        // the first item, plus the 4 waiting ones.
+        assert_eq!(polled.get(), 5);
+    }
+}
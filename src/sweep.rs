@@ -0,0 +1,62 @@
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::clock::Instant;
+
+/// The keys of a mapping that cleans up incrementally, in roughly the order they could become
+/// evictable. See `floodgate::FixedMapping::incremental_cleanup`.
+///
+/// Each key is queued with the id of its slot, so that an entry left behind by a removed key
+/// doesn't apply to a new slot for the same key.
+pub(crate) struct Sweep<K> {
+    queue: Mutex<VecDeque<Visit<K>>>,
+    batch_size: usize,
+}
+
+/// A key to check once `due` has passed.
+struct Visit<K> {
+    key: K,
+    id: u64,
+    due: Instant,
+}
+
+impl<K> Sweep<K> {
+    pub(crate) fn new(batch_size: usize) -> Self {
+        Self {
+            queue: Mutex::new(VecDeque::new()),
+            batch_size,
+        }
+    }
+
+    /// Check `key`, whose slot has `id`, once `due` has passed.
+    pub(crate) fn track(&self, key: K, id: u64, due: Instant) {
+        self.lock().push_back(Visit { key, id, due });
+    }
+
+    /// Take up to a batch of the keys that are due at `now`, with the ids of their slots.
+    /// Keys are only taken from the front of the queue, so a key that isn't due yet holds
+    /// back those behind it.
+    pub(crate) fn take_due(&self, now: Instant) -> Vec<(K, u64)> {
+        let mut queue = self.lock();
+        let mut due = Vec::new();
+        while due.len() < self.batch_size {
+            match queue.front() {
+                Some(visit) if visit.due <= now => {}
+                _ => break,
+            }
+            if let Some(visit) = queue.pop_front() {
+                due.push((visit.key, visit.id));
+            }
+        }
+        due
+    }
+
+    /// Nothing panics while holding the lock, so a poisoned lock still holds a valid queue.
+    fn lock(&self) -> MutexGuard<'_, VecDeque<Visit<K>>> {
+        self.queue
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
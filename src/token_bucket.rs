@@ -0,0 +1,391 @@
+use std::time::Duration;
+
+use crate::clock::{self, Instant};
+use crate::RateLimiter;
+
+/// A ratelimit implementation where tokens refill continuously, at a rate of
+/// `capacity / period`, instead of all at once.
+///
+/// This avoids the bursts that `floodgate::JumpingWindow` allows at the start of every window.
+#[derive(Debug)]
+pub struct TokenBucket {
+    pub(crate) capacity: u64,
+    pub(crate) period: Duration,
+
+    last_refill: Instant,
+    tokens: u64,
+}
+
+impl TokenBucket {
+    /// Create a new TokenBucket. The bucket starts full.
+    ///
+    /// # Arguments
+    /// * `capacity` - The maximum number of tokens the bucket can hold.
+    /// * `period` - How long it takes for an empty bucket to refill completely.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::TokenBucket;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// // refill one token every second, holding at most 2.
+    /// let now = Instant::now();
+    /// let mut bucket = TokenBucket::new(2, Duration::from_secs(2));
+    /// bucket.reset(Some(now));
+    ///
+    /// assert_eq!(bucket.trigger(Some(now)), None);
+    /// assert_eq!(bucket.trigger(Some(now)), None);
+    /// assert_eq!(bucket.trigger(Some(now)), Some(Duration::from_secs(1)));
+    ///
+    /// // half a second later, the next token is half a second away.
+    /// let later = now + Duration::from_millis(500);
+    /// assert_eq!(bucket.trigger(Some(later)), Some(Duration::from_millis(500)));
+    ///
+    /// let later = now + Duration::from_secs(1);
+    /// assert_eq!(bucket.trigger(Some(later)), None);
+    /// ```
+    pub fn new(capacity: u64, period: Duration) -> Self {
+        Self {
+            capacity,
+            period,
+            last_refill: clock::now(),
+            tokens: capacity,
+        }
+    }
+
+    /// How many tokens are currently in the bucket.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::TokenBucket;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut bucket = TokenBucket::new(1, Duration::from_secs(1));
+    /// bucket.reset(Some(now));
+    ///
+    /// bucket.trigger(Some(now));
+    /// assert_eq!(bucket.tokens(Some(now)), 0);
+    ///
+    /// // fractional progress is kept between calls.
+    /// assert_eq!(bucket.tokens(Some(now + Duration::from_millis(300))), 0);
+    /// assert_eq!(bucket.tokens(Some(now + Duration::from_millis(700))), 0);
+    /// assert_eq!(bucket.tokens(Some(now + Duration::from_millis(1000))), 1);
+    /// ```
+    pub fn tokens(&mut self, now: Option<Instant>) -> u64 {
+        let now = now.unwrap_or_else(clock::now);
+        self.refill(now);
+        self.tokens
+    }
+
+    /// Return the time until the bucket is full again.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::TokenBucket;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut bucket = TokenBucket::new(2, Duration::from_secs(10));
+    /// bucket.reset(Some(now));
+    ///
+    /// assert_eq!(bucket.next_reset(Some(now)), Duration::ZERO);
+    /// bucket.trigger(Some(now));
+    /// bucket.trigger(Some(now));
+    /// assert_eq!(bucket.next_reset(Some(now)), Duration::from_secs(10));
+    /// ```
+    pub fn next_reset(&mut self, now: Option<Instant>) -> Duration {
+        let now = now.unwrap_or_else(clock::now);
+        self.refill(now);
+        self.time_until(self.capacity, now)
+    }
+
+    /// Return the time until a token is available, or `None` if there already is one.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::TokenBucket;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut bucket = TokenBucket::new(2, Duration::from_secs(10));
+    /// bucket.reset(Some(now));
+    ///
+    /// bucket.trigger(Some(now));
+    /// assert_eq!(bucket.retry_after(Some(now)), None);
+    /// bucket.trigger(Some(now));
+    /// assert_eq!(bucket.retry_after(Some(now)), Some(Duration::from_secs(5)));
+    /// ```
+    pub fn retry_after(&mut self, now: Option<Instant>) -> Option<Duration> {
+        let now = now.unwrap_or_else(clock::now);
+        self.refill(now);
+
+        if self.tokens == 0 {
+            Some(self.time_until(1, now))
+        } else {
+            None
+        }
+    }
+
+    /// How long until `n` tokens will have been refilled, so that they can be consumed at
+    /// once. Returns `None` if `n` exceeds the capacity, so that they never can.
+    ///
+    /// # Arguments
+    /// * `n` - How many tokens to wait for.
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::TokenBucket;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut bucket = TokenBucket::new(4, Duration::from_secs(8));
+    /// bucket.trigger_n(4, Some(now)).unwrap();
+    ///
+    /// assert_eq!(bucket.wait_for(1, Some(now)), Some(Duration::from_secs(2)));
+    /// assert_eq!(bucket.wait_for(3, Some(now)), Some(Duration::from_secs(6)));
+    /// assert_eq!(bucket.wait_for(5, Some(now)), None);
+    /// ```
+    pub fn wait_for(&mut self, n: u64, now: Option<Instant>) -> Option<Duration> {
+        if n > self.capacity {
+            return None;
+        }
+
+        let now = now.unwrap_or_else(clock::now);
+        self.refill(now);
+        Some(self.time_until(n, now))
+    }
+
+    /// Returns whether or not there is a token available.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::TokenBucket;
+    /// use std::time::Duration;
+    ///
+    /// let mut bucket = TokenBucket::new(1, Duration::from_secs(10));
+    ///
+    /// assert!(bucket.can_trigger(None));
+    /// bucket.trigger(None);
+    /// assert!(!bucket.can_trigger(None));
+    /// ```
+    pub fn can_trigger(&mut self, now: Option<Instant>) -> bool {
+        self.tokens(now) != 0
+    }
+
+    /// Take a token from the bucket. If the bucket is empty, returns the time until the next
+    /// token is available.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::TokenBucket;
+    /// use std::time::Duration;
+    ///
+    /// let mut bucket = TokenBucket::new(1, Duration::from_secs(10));
+    ///
+    /// assert_eq!(bucket.trigger(None), None);
+    /// assert!(matches!(bucket.trigger(None), Some(_)));
+    /// ```
+    pub fn trigger(&mut self, now: Option<Instant>) -> Option<Duration> {
+        self.trigger_n(1, now).err()
+    }
+
+    /// Take `cost` tokens from the bucket at once.
+    ///
+    /// Either all `cost` tokens are taken, or none are. If there aren't enough tokens, the time
+    /// until there will be is returned. If `cost` is greater than the capacity, the trigger can
+    /// never succeed, so `Duration::MAX` is returned immediately.
+    ///
+    /// # Arguments
+    /// * `cost` - How many tokens to take.
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::TokenBucket;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let now = Instant::now();
+    /// let mut bucket = TokenBucket::new(4, Duration::from_secs(4));
+    /// bucket.reset(Some(now));
+    ///
+    /// assert_eq!(bucket.trigger_n(3, Some(now)), Ok(()));
+    /// assert_eq!(bucket.trigger_n(3, Some(now)), Err(Duration::from_secs(2)));
+    /// assert_eq!(bucket.trigger_n(5, Some(now)), Err(Duration::MAX));
+    /// ```
+    pub fn trigger_n(&mut self, cost: u64, now: Option<Instant>) -> Result<(), Duration> {
+        if cost > self.capacity {
+            return Err(Duration::MAX);
+        }
+
+        let now = now.unwrap_or_else(clock::now);
+        self.refill(now);
+
+        if self.tokens < cost {
+            Err(self.time_until(cost, now))
+        } else {
+            self.tokens -= cost;
+            Ok(())
+        }
+    }
+
+    /// Fill the bucket back up.
+    ///
+    /// # Arguments
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::TokenBucket;
+    /// use std::time::Duration;
+    ///
+    /// let mut bucket = TokenBucket::new(1, Duration::from_secs(10));
+    /// bucket.trigger(None);
+    ///
+    /// assert!(!bucket.can_trigger(None));
+    /// bucket.reset(None);
+    /// assert!(bucket.can_trigger(None));
+    /// ```
+    pub fn reset(&mut self, now: Option<Instant>) {
+        self.tokens = self.capacity;
+        self.last_refill = now.unwrap_or_else(clock::now);
+    }
+
+    /// Put `n` tokens back into the bucket, without exceeding the capacity.
+    ///
+    /// # Arguments
+    /// * `n` - How many tokens to give back.
+    /// * `now` - Optionally specify the current time.
+    ///
+    /// # Examples
+    /// ```
+    /// use floodgate::TokenBucket;
+    /// use std::time::Duration;
+    ///
+    /// let mut bucket = TokenBucket::new(1, Duration::from_secs(10));
+    ///
+    /// assert_eq!(bucket.trigger(None), None);
+    /// bucket.refund(1, None);
+    /// assert_eq!(bucket.trigger(None), None);
+    /// ```
+    pub fn refund(&mut self, n: u64, now: Option<Instant>) {
+        let now = now.unwrap_or_else(clock::now);
+        self.refill(now);
+        self.tokens = self.tokens.saturating_add(n).min(self.capacity);
+    }
+
+    /// Add the tokens that have accumulated since the last refill. Only whole tokens are
+    /// added; `last_refill` is advanced by exactly the time they took to accumulate, so any
+    /// fractional progress carries over to the next call.
+    fn refill(&mut self, now: Instant) {
+        if self.tokens >= self.capacity || self.period.is_zero() {
+            self.tokens = self.capacity;
+            self.last_refill = now;
+            return;
+        }
+
+        let elapsed = now.duration_since(self.last_refill).as_nanos();
+        let period = self.period.as_nanos();
+        let gained = elapsed * self.capacity as u128 / period;
+        if gained == 0 {
+            return;
+        }
+
+        let tokens = self.tokens as u128 + gained;
+        if tokens >= self.capacity as u128 {
+            self.tokens = self.capacity;
+            self.last_refill = now;
+        } else {
+            self.tokens = tokens as u64;
+            let spent = (gained * period).div_ceil(self.capacity as u128);
+            self.last_refill += nanos(spent);
+        }
+    }
+
+    /// The time until the bucket holds `n` tokens. Assumes `refill` was just called.
+    fn time_until(&self, n: u64, now: Instant) -> Duration {
+        if self.tokens >= n {
+            return Duration::ZERO;
+        }
+
+        let needed = (n - self.tokens) as u128 * self.period.as_nanos();
+        let needed = needed.div_ceil(self.capacity as u128);
+        nanos(needed).saturating_sub(now.duration_since(self.last_refill))
+    }
+}
+
+fn nanos(nanos: u128) -> Duration {
+    Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
+}
+
+impl RateLimiter for TokenBucket {
+    fn new(capacity: u64, period: Duration) -> Self {
+        Self::new(capacity, period)
+    }
+
+    fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    fn period(&self) -> Duration {
+        self.period
+    }
+
+    fn set_rate(&mut self, capacity: u64, period: Duration) {
+        self.capacity = capacity;
+        self.period = period;
+        self.tokens = self.tokens.min(capacity);
+    }
+
+    fn tokens(&mut self, now: Option<Instant>) -> u64 {
+        self.tokens(now)
+    }
+
+    fn next_reset(&mut self, now: Option<Instant>) -> Duration {
+        self.next_reset(now)
+    }
+
+    fn retry_after(&mut self, now: Option<Instant>) -> Option<Duration> {
+        self.retry_after(now)
+    }
+
+    fn wait_for(&mut self, n: u64, now: Option<Instant>) -> Option<Duration> {
+        self.wait_for(n, now)
+    }
+
+    fn can_trigger(&mut self, now: Option<Instant>) -> bool {
+        self.can_trigger(now)
+    }
+
+    fn trigger(&mut self, now: Option<Instant>) -> Option<Duration> {
+        self.trigger(now)
+    }
+
+    fn trigger_n(&mut self, cost: u64, now: Option<Instant>) -> Result<(), Duration> {
+        self.trigger_n(cost, now)
+    }
+
+    fn reset(&mut self, now: Option<Instant>) {
+        self.reset(now)
+    }
+
+    fn refund(&mut self, n: u64, _epoch: u64, now: Option<Instant>) {
+        self.refund(n, now)
+    }
+}
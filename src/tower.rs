@@ -0,0 +1,327 @@
+//! A `tower` middleware that ratelimits requests by key.
+
+use std::{
+    future::Future,
+    hash::Hash,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{EnforcementMode, FixedMapping, JumpingWindow, RateLimitInfo, RateLimiter};
+
+/// What to do with a request whose key is ratelimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    /// Respond immediately with the rejection response.
+    #[default]
+    Reject,
+    /// Wait until the key can be triggered, then forward the request.
+    Wait,
+}
+
+/// A `tower_layer::Layer` that wraps services in a `floodgate::tower::RateLimit`.
+///
+/// # Examples
+/// ```
+/// use floodgate::{tower::RateLimitLayer, FixedMapping};
+/// use std::{sync::Arc, time::Duration};
+/// use tower::{service_fn, ServiceBuilder, ServiceExt};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let mapping = Arc::new(FixedMapping::new(1, Duration::from_secs(10)));
+/// let layer = RateLimitLayer::new(mapping, |user: &u64| *user, |_info| "slow down");
+///
+/// let service = ServiceBuilder::new()
+///     .layer(layer)
+///     .service(service_fn(|_user: u64| async { Ok::<_, ()>("hello") }));
+///
+/// assert_eq!(service.clone().oneshot(1).await, Ok("hello"));
+/// assert_eq!(service.clone().oneshot(1).await, Ok("slow down"));
+/// assert_eq!(service.oneshot(2).await, Ok("hello"));
+/// # }
+/// ```
+pub struct RateLimitLayer<K: Eq + Hash + Clone + Send + Sync + 'static, F, R, L = JumpingWindow> {
+    mapping: Arc<FixedMapping<K, L>>,
+    key: F,
+    reject: R,
+    mode: Mode,
+}
+
+impl<K, F, R, L> RateLimitLayer<K, F, R, L>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+{
+    /// Create a new RateLimitLayer in `Mode::Reject`.
+    ///
+    /// # Arguments
+    /// * `mapping` - The mapping to trigger for each request.
+    /// * `key` - A function returning the key of a request.
+    /// * `reject` - A function creating the response for a ratelimited request.
+    pub fn new(mapping: Arc<FixedMapping<K, L>>, key: F, reject: R) -> Self {
+        Self {
+            mapping,
+            key,
+            reject,
+            mode: Mode::Reject,
+        }
+    }
+
+    /// Set what to do with ratelimited requests.
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+}
+
+#[cfg(feature = "http")]
+impl<K, F, B, L> RateLimitLayer<K, F, fn(RateLimitInfo) -> http::Response<B>, L>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    B: From<&'static str>,
+{
+    /// Create a new RateLimitLayer for HTTP services, rejecting requests with
+    /// `floodgate::tower::too_many_requests`.
+    ///
+    /// # Arguments
+    /// * `mapping` - The mapping to trigger for each request.
+    /// * `key` - A function returning the key of a request.
+    pub fn http(mapping: Arc<FixedMapping<K, L>>, key: F) -> Self {
+        Self::new(mapping, key, too_many_requests)
+    }
+}
+
+impl<K, F: Clone, R: Clone, L> Clone for RateLimitLayer<K, F, R, L>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+{
+    fn clone(&self) -> Self {
+        Self {
+            mapping: self.mapping.clone(),
+            key: self.key.clone(),
+            reject: self.reject.clone(),
+            mode: self.mode,
+        }
+    }
+}
+
+impl<S, K, F: Clone, R: Clone, L> Layer<S> for RateLimitLayer<K, F, R, L>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+{
+    type Service = RateLimit<S, K, F, R, L>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// A service that ratelimits requests by key before forwarding them to the inner service.
+///
+/// `poll_ready` only checks the inner service; the key is triggered when the request arrives
+/// in `call`.
+pub struct RateLimit<S, K: Eq + Hash + Clone + Send + Sync + 'static, F, R, L = JumpingWindow> {
+    inner: S,
+    layer: RateLimitLayer<K, F, R, L>,
+}
+
+impl<S: Clone, K, F: Clone, R: Clone, L> Clone for RateLimit<S, K, F, R, L>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            layer: self.layer.clone(),
+        }
+    }
+}
+
+impl<S, K, F, R, L> RateLimit<S, K, F, R, L>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+{
+    /// The inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Consume the middleware, returning the inner service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, K, F, R, L, Req> Service<Req> for RateLimit<S, K, F, R, L>
+where
+    S: Service<Req> + Clone + Send + 'static,
+    S::Future: Send,
+    S::Response: Send + 'static,
+    S::Error: 'static,
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    F: Fn(&Req) -> K,
+    R: Fn(RateLimitInfo) -> S::Response,
+    L: RateLimiter + Send + Sync + 'static,
+    Req: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Req) -> Self::Future {
+        let key = (self.layer.key)(&request);
+
+        match self.layer.mode {
+            Mode::Reject => {
+                let enforce = self.layer.mapping.mode() == EnforcementMode::Enforce;
+                let info = self.layer.mapping.trigger_info(&key);
+                if !info.allowed && enforce {
+                    let response = (self.layer.reject)(info);
+                    return Box::pin(async move { Ok(response) });
+                }
+                Box::pin(self.inner.call(request))
+            }
+            Mode::Wait => {
+                // the inner service was made ready for this call, so take it and leave a clone
+                // in its place.
+                let clone = self.inner.clone();
+                let mut inner = std::mem::replace(&mut self.inner, clone);
+                let mapping = self.layer.mapping.clone();
+
+                Box::pin(async move {
+                    mapping.acquire(&key).await;
+                    inner.call(request).await
+                })
+            }
+        }
+    }
+}
+
+/// Create a `429 Too Many Requests` response, with the headers from
+/// `floodgate::RateLimitInfo::to_headers`.
+///
+/// # Examples
+/// ```
+/// use floodgate::{tower::too_many_requests, RateLimitInfo};
+/// use std::time::Duration;
+///
+/// let response = too_many_requests::<String>(RateLimitInfo {
+///     allowed: false,
+///     limit: 5,
+///     remaining: 0,
+///     retry_after: Some(Duration::from_secs(3)),
+///     reset_after: Duration::from_secs(3),
+/// });
+///
+/// assert_eq!(response.status(), 429);
+/// assert_eq!(response.headers()["retry-after"], "3");
+/// assert_eq!(response.body(), "Too Many Requests");
+/// ```
+#[cfg(feature = "http")]
+pub fn too_many_requests<B: From<&'static str>>(info: RateLimitInfo) -> http::Response<B> {
+    let mut response = http::Response::new(B::from("Too Many Requests"));
+    *response.status_mut() = http::StatusCode::TOO_MANY_REQUESTS;
+    *response.headers_mut() = info.to_headers();
+    response
+}
+
+#[cfg(all(test, feature = "http"))]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use ::tower::{service_fn, ServiceBuilder, ServiceExt};
+    use http::{Request, Response, StatusCode};
+
+    use super::{Mode, RateLimitLayer};
+    use crate::{EnforcementMode, FixedMapping};
+
+    fn client_id(request: &Request<()>) -> String {
+        request.headers()["client-id"].to_str().unwrap().to_owned()
+    }
+
+    fn request(client_id: &str) -> Request<()> {
+        Request::builder()
+            .header("client-id", client_id)
+            .body(())
+            .unwrap()
+    }
+
+    async fn ok(_: Request<()>) -> Result<Response<String>, ()> {
+        Ok(Response::new("ok".to_owned()))
+    }
+
+    #[tokio::test]
+    async fn rejects_with_429() {
+        let mapping = Arc::new(FixedMapping::new(1, Duration::from_secs(10)));
+        let service = ServiceBuilder::new()
+            .layer(RateLimitLayer::http(mapping, client_id))
+            .service(service_fn(ok));
+
+        let response = service.clone().oneshot(request("a")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = service.clone().oneshot(request("a")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "10");
+        assert_eq!(response.headers()["x-ratelimit-remaining"], "0");
+        assert_eq!(response.body(), "Too Many Requests");
+
+        let response = service.oneshot(request("b")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn dry_run_lets_rejected_requests_through() {
+        let mapping = Arc::new(FixedMapping::new(1, Duration::from_secs(10)));
+        mapping.set_mode(EnforcementMode::DryRun);
+        let service = ServiceBuilder::new()
+            .layer(RateLimitLayer::http(mapping.clone(), client_id))
+            .service(service_fn(ok));
+
+        for _ in 0..3 {
+            let response = service.clone().oneshot(request("a")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        assert_eq!(mapping.dry_run_rejections(), 2);
+    }
+
+    #[tokio::test]
+    async fn waits_for_the_key() {
+        let period = Duration::from_millis(30);
+        let mapping = Arc::new(FixedMapping::new(1, period));
+        let service = ServiceBuilder::new()
+            .layer(RateLimitLayer::http(mapping.clone(), client_id).mode(Mode::Wait))
+            .service(service_fn(ok));
+
+        let start = std::time::Instant::now();
+        for _ in 0..3 {
+            let response = service.clone().oneshot(request("a")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        assert!(start.elapsed() >= period * 2);
+    }
+
+    #[tokio::test]
+    async fn poll_ready_consumes_nothing() {
+        let mapping = Arc::new(FixedMapping::new(1, Duration::from_secs(10)));
+        let mut service = ServiceBuilder::new()
+            .layer(RateLimitLayer::http(mapping.clone(), client_id))
+            .service(service_fn(ok));
+
+        for _ in 0..3 {
+            service.ready().await.unwrap();
+        }
+        assert_eq!(mapping.tokens(&"a".to_owned()), 1);
+    }
+}
@@ -0,0 +1,71 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hash},
+};
+
+use crate::{Clock, FixedMapping, MonotonicClock, RateLimiter};
+
+/// A token taken from a `floodgate::FixedMapping`, which is given back when the guard is
+/// dropped unless `commit` is called first.
+///
+/// The guard only holds the key and a reference to the mapping, not a lock, so it can be kept
+/// across `.await` points. It also records the window the token was taken from: if the key's
+/// window resets before the guard is dropped, the token isn't refunded, since it would go to a
+/// window it wasn't taken from. See `floodgate::FixedMapping::refund`.
+///
+/// Created by `floodgate::FixedMapping::trigger_guard`.
+#[must_use = "dropping the guard immediately refunds the token"]
+pub struct TriggerGuard<
+    'a,
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    L: RateLimiter,
+    C: Clock = MonotonicClock,
+    S: BuildHasher + Clone = RandomState,
+> {
+    mapping: &'a FixedMapping<K, L, C, S>,
+    key: K,
+    /// The window the token was taken from.
+    epoch: u64,
+    committed: bool,
+}
+
+impl<'a, K, L, C, S> TriggerGuard<'a, K, L, C, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    L: RateLimiter,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    pub(crate) fn new(mapping: &'a FixedMapping<K, L, C, S>, key: K, epoch: u64) -> Self {
+        Self {
+            mapping,
+            key,
+            epoch,
+            committed: false,
+        }
+    }
+
+    /// The key this guard took a token from.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Keep the token, so that it isn't refunded when the guard is dropped.
+    pub fn commit(mut self) {
+        self.committed = true;
+    }
+}
+
+impl<K, L, C, S> Drop for TriggerGuard<'_, K, L, C, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    L: RateLimiter,
+    C: Clock,
+    S: BuildHasher + Clone,
+{
+    fn drop(&mut self) {
+        if !self.committed {
+            self.mapping.refund(&self.key, 1, self.epoch);
+        }
+    }
+}
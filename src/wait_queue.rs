@@ -0,0 +1,160 @@
+use std::{
+    borrow::Borrow,
+    hash::Hash,
+    pin::pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use dashmap::DashMap;
+use tokio::{
+    sync::{Notify, Semaphore, SemaphorePermit},
+    time::timeout_at,
+};
+
+use crate::clock::Instant;
+use crate::Elapsed;
+
+/// A FIFO queue of async waiters. Only the waiter at the front of the queue may wait for a
+/// trigger, so tokens are handed out in arrival order. Waiters leave the queue when they are
+/// dropped, whether or not they got to the front.
+///
+/// The queue also signals resets of the window to anyone waiting for one.
+#[derive(Debug)]
+pub(crate) struct WaitQueue {
+    turn: Semaphore,
+    resets: AtomicU64,
+    reset_notify: Notify,
+}
+
+impl WaitQueue {
+    pub(crate) fn new() -> Self {
+        Self {
+            turn: Semaphore::new(1),
+            resets: AtomicU64::new(0),
+            reset_notify: Notify::new(),
+        }
+    }
+
+    /// How many resets have been signalled so far.
+    pub(crate) fn resets(&self) -> u64 {
+        self.resets.load(Ordering::Acquire)
+    }
+
+    /// Signal that the window was reset.
+    pub(crate) fn notify_reset(&self) {
+        self.resets.fetch_add(1, Ordering::AcqRel);
+        self.reset_notify.notify_waiters();
+    }
+
+    /// Wait until a reset is signalled after the first `seen` resets, or until `deadline` (the
+    /// time at which the window resets by itself).
+    pub(crate) async fn reset_notified(&self, seen: u64, deadline: Option<Instant>) {
+        let mut notified = pin!(self.reset_notify.notified());
+        notified.as_mut().enable();
+
+        if self.resets() != seen {
+            return;
+        }
+
+        match deadline {
+            Some(deadline) => {
+                let _ = timeout_at(deadline.into(), notified).await;
+            }
+            None => notified.await,
+        }
+    }
+
+    /// Wait until it is this waiter's turn, giving up at `deadline`.
+    pub(crate) async fn enter(
+        &self,
+        deadline: Option<Instant>,
+    ) -> Result<SemaphorePermit<'_>, Elapsed> {
+        let permit = match deadline {
+            Some(deadline) => timeout_at(deadline.into(), self.turn.acquire())
+                .await
+                .map_err(|_| Elapsed)?,
+            None => self.turn.acquire().await,
+        };
+
+        Ok(permit.expect("the semaphore is never closed"))
+    }
+}
+
+/// A `WaitQueue` for each key that currently has waiters.
+pub(crate) struct WaitQueues<K: Eq + Hash> {
+    queues: DashMap<K, Arc<WaitQueue>>,
+}
+
+impl<K: Eq + Hash + Clone> WaitQueues<K> {
+    pub(crate) fn new() -> Self {
+        Self {
+            queues: DashMap::new(),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.queues.is_empty()
+    }
+
+    /// Signal a reset of `key` to its waiters, if it has any.
+    pub(crate) fn notify_reset<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if let Some(queue) = self.queues.get(key) {
+            queue.notify_reset();
+        }
+    }
+
+    /// Signal a reset to every waiter.
+    pub(crate) fn notify_reset_all(&self) {
+        for queue in self.queues.iter() {
+            queue.notify_reset();
+        }
+    }
+
+    /// Get the queue for `key`, creating it if needed. The queue is removed once the last
+    /// handle to it is dropped.
+    pub(crate) fn get(&self, key: &K) -> QueueHandle<'_, K> {
+        let queue = self
+            .queues
+            .entry(key.clone())
+            .or_insert_with(|| Arc::new(WaitQueue::new()))
+            .clone();
+
+        QueueHandle {
+            queues: &self.queues,
+            key: key.clone(),
+            queue: Some(queue),
+        }
+    }
+}
+
+pub(crate) struct QueueHandle<'a, K: Eq + Hash> {
+    queues: &'a DashMap<K, Arc<WaitQueue>>,
+    key: K,
+    queue: Option<Arc<WaitQueue>>,
+}
+
+impl<K: Eq + Hash> QueueHandle<'_, K> {
+    pub(crate) fn queue(&self) -> &WaitQueue {
+        self.queue
+            .as_ref()
+            .expect("the queue is only taken on drop")
+    }
+}
+
+impl<K: Eq + Hash> Drop for QueueHandle<'_, K> {
+    fn drop(&mut self) {
+        // new handles are only created while holding the shard lock, so if the map holds the
+        // only reference, nobody else is waiting on this key.
+        drop(self.queue.take());
+        self.queues
+            .remove_if(&self.key, |_, queue| Arc::strong_count(queue) == 1);
+    }
+}
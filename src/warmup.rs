@@ -0,0 +1,99 @@
+use std::{sync::RwLock, time::Duration};
+
+use crate::clock::Instant;
+
+/// A slow start, set with `floodgate::JumpingWindow::slow_start` or
+/// `floodgate::FixedMapping::slow_start`: the capacity starts out as a fraction of the configured
+/// one, and grows linearly to all of it over the warm-up's duration.
+///
+/// While warming up, `tokens`, `trigger` and `floodgate::RateLimitInfo::limit` all go by the
+/// reduced capacity. The capacity never drops below one.
+///
+/// # Examples
+/// ```
+/// use floodgate::Warmup;
+/// use std::time::Duration;
+///
+/// // 10% to 100% over 5 minutes.
+/// let warmup = Warmup::new(Duration::from_secs(300), 0.1);
+///
+/// assert_eq!(warmup.capacity(100, Duration::ZERO), 10);
+/// assert_eq!(warmup.capacity(100, Duration::from_secs(150)), 55);
+/// assert_eq!(warmup.capacity(100, Duration::from_secs(300)), 100);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Warmup {
+    duration: Duration,
+    initial: f64,
+}
+
+impl Warmup {
+    /// Create a new Warmup.
+    ///
+    /// # Arguments
+    /// * `duration` - How long it takes to reach the full capacity.
+    /// * `initial` - The fraction of the capacity to start with, from 0 to 1.
+    ///
+    /// # Panics
+    /// Panics if `initial` isn't between 0 and 1.
+    pub fn new(duration: Duration, initial: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&initial),
+            "the initial fraction must be between 0 and 1"
+        );
+        Self { duration, initial }
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    pub fn initial(&self) -> f64 {
+        self.initial
+    }
+
+    /// The effective capacity `elapsed` into the warm-up, out of the configured `capacity`.
+    pub fn capacity(&self, capacity: u64, elapsed: Duration) -> u64 {
+        if elapsed >= self.duration {
+            return capacity;
+        }
+        let progress = elapsed.as_secs_f64() / self.duration.as_secs_f64();
+        let fraction = self.initial + (1.0 - self.initial) * progress;
+        ((capacity as f64 * fraction) as u64).clamp(1.min(capacity), capacity)
+    }
+
+    /// Whether the warm-up is over `elapsed` into it.
+    pub(crate) fn is_over(&self, elapsed: Duration) -> bool {
+        elapsed >= self.duration
+    }
+}
+
+/// A warm-up shared by every key of a mapping, which can be restarted through a shared
+/// reference.
+#[derive(Debug)]
+pub(crate) struct SharedWarmup {
+    pub(crate) warmup: Warmup,
+    since: RwLock<Instant>,
+}
+
+impl SharedWarmup {
+    pub(crate) fn new(warmup: Warmup, now: Instant) -> Self {
+        Self {
+            warmup,
+            since: RwLock::new(now),
+        }
+    }
+
+    /// Start warming up again from `now`.
+    pub(crate) fn begin(&self, now: Instant) {
+        *self.since.write().unwrap() = now;
+    }
+
+    /// The effective capacity at `now`, out of the configured `capacity`.
+    pub(crate) fn capacity(&self, capacity: u64, now: Instant) -> u64 {
+        let since = *self.since.read().unwrap();
+        self.warmup
+            .capacity(capacity, now.saturating_duration_since(since))
+    }
+}
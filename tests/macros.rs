@@ -0,0 +1,32 @@
+#![cfg(feature = "macros")]
+
+use floodgate::{cooldown, CooldownError};
+
+#[cooldown(rate = "2/1m", key = "channel")]
+fn send(channel: &str, message: &str) -> Result<String, CooldownError> {
+    Ok(format!("#{channel}: {message}"))
+}
+
+#[test]
+fn reference_keys_are_limited_separately() {
+    assert!(send("general", "hi").is_ok());
+    assert!(send("general", "hi again").is_ok());
+    let err = send("general", "hello?").unwrap_err();
+    assert!(err.retry_after.as_secs() <= 60);
+    assert!(send("random", "hi").is_ok());
+}
+
+#[cfg(feature = "tokio")]
+#[cooldown(rate = "1/50ms", key = "user", wait = true)]
+async fn poll(user: u64) -> u64 {
+    user
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn waiting_functions_wait_instead_of_failing() {
+    let start = std::time::Instant::now();
+    assert_eq!(poll(1).await, 1);
+    assert_eq!(poll(1).await, 1);
+    assert!(start.elapsed() >= std::time::Duration::from_millis(50));
+}
@@ -0,0 +1,88 @@
+//! Runs the Redis mapping's script against a real Redis, at the url in `FLOODGATE_REDIS`.
+//! Without it, the tests pass without doing anything.
+
+#![cfg(feature = "redis")]
+
+use std::{
+    env,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use floodgate::redis::RedisMapping;
+use redis::{aio::ConnectionManager, Client};
+
+/// A mapping with a name of its own, so that runs don't share windows.
+async fn mapping(capacity: u64, period: Duration) -> Option<RedisMapping> {
+    let url = env::var("FLOODGATE_REDIS").ok()?;
+    let client = Client::open(url).unwrap();
+    let connection = ConnectionManager::new(client).await.unwrap();
+    let run = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let name = format!("test-{}", run.as_nanos());
+    Some(RedisMapping::new(connection, &name, capacity, period))
+}
+
+/// A time `offset` into a window of `period`, which started a while after the epoch.
+fn at(period: Duration, offset: Duration) -> Option<SystemTime> {
+    Some(UNIX_EPOCH + period * 1_000_000 + offset)
+}
+
+#[tokio::test]
+async fn triggers_share_a_window_until_it_ends() {
+    let period = Duration::from_secs(10);
+    let Some(mapping) = mapping(2, period).await else {
+        return;
+    };
+
+    let now = at(period, Duration::from_secs(4));
+    assert_eq!(mapping.trigger("a", now).await, None);
+    assert_eq!(mapping.tokens("a", now).await, 1);
+    assert_eq!(mapping.trigger("a", now).await, None);
+    assert_eq!(
+        mapping.trigger("a", now).await,
+        Some(Duration::from_secs(6))
+    );
+    assert_eq!(
+        mapping.retry_after("a", now).await,
+        Some(Duration::from_secs(6))
+    );
+
+    // other keys have windows of their own.
+    assert_eq!(mapping.trigger_n("b", 2, now).await, Ok(()));
+    assert_eq!(mapping.trigger_n("c", 3, now).await, Err(Duration::MAX));
+
+    // the next window starts full.
+    let later = at(period, period);
+    assert_eq!(mapping.tokens("a", later).await, 2);
+}
+
+#[tokio::test]
+async fn earlier_clocks_keep_the_current_window() {
+    let period = Duration::from_secs(10);
+    let Some(mapping) = mapping(1, period).await else {
+        return;
+    };
+
+    // a process whose clock is behind, still in the previous window, doesn't reset it.
+    let now = at(period, Duration::from_secs(1));
+    assert_eq!(mapping.trigger("a", now).await, None);
+    let behind = at(period, Duration::ZERO).map(|time| time - Duration::from_secs(2));
+    assert_eq!(
+        mapping.trigger("a", behind).await,
+        Some(Duration::from_secs(12))
+    );
+}
+
+#[tokio::test]
+async fn resets_and_removals_drop_the_window() {
+    let period = Duration::from_secs(10);
+    let Some(mapping) = mapping(1, period).await else {
+        return;
+    };
+
+    assert!(!mapping.remove("a").await.unwrap());
+    assert_eq!(mapping.trigger("a", None).await, None);
+    assert!(mapping.remove("a").await.unwrap());
+    assert_eq!(mapping.trigger("a", None).await, None);
+    mapping.reset("a").await.unwrap();
+    assert_eq!(mapping.tokens("a", None).await, 1);
+}
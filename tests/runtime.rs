@@ -0,0 +1,65 @@
+//! The async helpers taking a `Sleeper` don't depend on tokio, so they are driven here by a
+//! plain executor and a sleeper built on threads, the way another runtime would drive them.
+
+#![cfg(all(
+    feature = "std",
+    not(all(target_arch = "wasm32", target_os = "unknown"))
+))]
+
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use floodgate::{FixedMapping, JumpingWindow};
+use futures::{channel::oneshot, executor::block_on};
+
+/// Sleep on a thread of its own, without any runtime's timer.
+async fn sleep(duration: Duration) {
+    let (done, slept) = oneshot::channel();
+    thread::spawn(move || {
+        thread::sleep(duration);
+        let _ = done.send(());
+    });
+    let _ = slept.await;
+}
+
+#[test]
+fn acquire_waits_without_a_runtime() {
+    let period = Duration::from_millis(20);
+    let mut cooldown = JumpingWindow::new(1, period);
+    let mapping = FixedMapping::new(1, period);
+
+    let start = Instant::now();
+    block_on(async {
+        cooldown.acquire_with(sleep).await;
+        cooldown.acquire_with(sleep).await;
+        mapping.acquire_with(&1, sleep).await;
+        mapping.acquire_with(&1, sleep).await;
+        mapping.pace_with(&2, sleep).await.unwrap();
+        mapping.pace_with(&2, sleep).await.unwrap();
+    });
+    assert!(start.elapsed() >= period);
+
+    mapping.trigger(&3);
+    let timed_out = block_on(mapping.acquire_timeout_with(&3, Duration::ZERO, sleep));
+    assert!(timed_out.is_err());
+}
+
+#[test]
+fn cycler_runs_on_any_executor() {
+    let mapping = Arc::new(FixedMapping::new(1, Duration::from_millis(10)));
+    mapping.trigger(&1);
+
+    let cycler = thread::spawn({
+        let cycler = FixedMapping::cycler(mapping.clone(), sleep);
+        move || block_on(cycler)
+    });
+    thread::sleep(Duration::from_millis(100));
+    assert!(mapping.is_empty());
+
+    // the cycler finishes once the mapping is dropped.
+    drop(mapping);
+    cycler.join().unwrap();
+}
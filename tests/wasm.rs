@@ -0,0 +1,52 @@
+//! Run with `wasm-pack test --headless --firefox -- --test wasm` (or `--chrome`). The unit
+//! tests use threads, so they aren't built for wasm32-unknown-unknown.
+#![cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+
+use std::time::Duration;
+
+use floodgate::{FixedMapping, JumpingWindow, ManualClock};
+use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+#[wasm_bindgen_test]
+fn jumping_window_trigger_and_reset() {
+    let period = Duration::from_secs(60);
+    let mut cooldown = JumpingWindow::new(2, period);
+
+    assert_eq!(cooldown.trigger(None), None);
+    assert_eq!(cooldown.trigger(None), None);
+    assert!(cooldown.trigger(None).is_some());
+
+    cooldown.reset(None);
+    assert_eq!(cooldown.tokens(None), 2);
+    assert_eq!(cooldown.trigger(None), None);
+}
+
+#[wasm_bindgen_test]
+fn fixed_mapping_trigger_and_reset() {
+    let mapping = FixedMapping::new(1, Duration::from_secs(60));
+
+    assert_eq!(mapping.trigger(&"a"), None);
+    assert!(mapping.trigger(&"a").is_some());
+    assert_eq!(mapping.trigger(&"b"), None);
+
+    mapping.reset(&"a");
+    assert_eq!(mapping.trigger(&"a"), None);
+}
+
+#[wasm_bindgen_test]
+fn fixed_mapping_cycles_without_a_cycler() {
+    let clock = ManualClock::new();
+    let period = Duration::from_secs(60);
+    let mapping = FixedMapping::with_clock(1, period, clock.clone());
+
+    assert_eq!(mapping.trigger(&"a"), None);
+    for _ in 0..3 {
+        clock.advance(period);
+        assert_eq!(mapping.trigger(&"b"), None);
+    }
+    // "a" was dropped by the lazy cycles, so it starts with a fresh window.
+    assert_eq!(mapping.trigger(&"a"), None);
+    assert!(!mapping.cycle());
+}